@@ -0,0 +1,10 @@
+CREATE TABLE IF NOT EXISTS device_status_log (
+    id            UUID PRIMARY KEY NOT NULL DEFAULT gen_random_uuid(),
+    device_id     TEXT NOT NULL,
+    battery_level INTEGER,                 -- 0-100%, NULL if unchanged
+    online        BOOLEAN,                 -- NULL if unchanged
+    source        TEXT NOT NULL,           -- 'webhook'
+    created_at    TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX idx_device_status_log_device_id_created ON device_status_log (device_id, created_at DESC);
@@ -0,0 +1,12 @@
+CREATE TABLE IF NOT EXISTS device_alerts (
+    id          UUID PRIMARY KEY NOT NULL DEFAULT gen_random_uuid(),
+    device_id   TEXT NOT NULL,
+    kind        TEXT NOT NULL,              -- 'low_battery', 'offline'
+    message     TEXT NOT NULL,
+    started_at  TIMESTAMPTZ NOT NULL DEFAULT now(),
+    resolved_at TIMESTAMPTZ
+);
+
+-- At most one open alert per device and kind (one notification per incident).
+CREATE UNIQUE INDEX idx_device_alerts_open ON device_alerts (device_id, kind) WHERE resolved_at IS NULL;
+CREATE INDEX idx_device_alerts_started_at ON device_alerts (started_at);
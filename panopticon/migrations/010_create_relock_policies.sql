@@ -0,0 +1,6 @@
+CREATE TABLE IF NOT EXISTS relock_policies (
+    device_id  TEXT PRIMARY KEY NOT NULL,
+    enabled    BOOLEAN NOT NULL DEFAULT TRUE,
+    delay_secs INTEGER NOT NULL,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
@@ -0,0 +1,17 @@
+CREATE TABLE IF NOT EXISTS lock_commands (
+    id              UUID PRIMARY KEY NOT NULL DEFAULT gen_random_uuid(),
+    device_id       TEXT NOT NULL,
+    command         TEXT NOT NULL,              -- 'lock', 'unlock'
+    status          TEXT NOT NULL DEFAULT 'pending', -- 'pending', 'confirmed', 'failed', 'timed_out'
+    source          TEXT NOT NULL,              -- 'api', 'mqtt', 'rfid', 'auto_relock'
+    user_id         UUID REFERENCES users(id) ON DELETE SET NULL,
+    idempotency_key TEXT,
+    lock_state      TEXT,                       -- state reported when the command completed
+    error           TEXT,
+    created_at      TIMESTAMPTZ NOT NULL DEFAULT now(),
+    completed_at    TIMESTAMPTZ
+);
+
+CREATE UNIQUE INDEX idx_lock_commands_idempotency ON lock_commands (user_id, idempotency_key)
+    WHERE idempotency_key IS NOT NULL;
+CREATE INDEX idx_lock_commands_device_id_created ON lock_commands (device_id, created_at DESC);
@@ -0,0 +1,8 @@
+-- Commands merged into an identical queued/running command complete with its
+-- outcome and point at it here.
+ALTER TABLE lock_commands
+    ADD COLUMN coalesced_into UUID REFERENCES lock_commands(id) ON DELETE SET NULL;
+
+-- The command whose response (or deferred poll) reported this state.
+ALTER TABLE lock_state_log
+    ADD COLUMN command_id UUID REFERENCES lock_commands(id) ON DELETE SET NULL;
@@ -0,0 +1,6 @@
+-- 'admin', 'operator', 'resident', 'viewer'. Newly approved users are residents.
+ALTER TABLE users ADD COLUMN role TEXT NOT NULL DEFAULT 'resident'
+    CHECK (role IN ('admin', 'operator', 'resident', 'viewer'));
+
+-- Until now every approved user could do everything.
+UPDATE users SET role = 'admin' WHERE is_approved = TRUE;
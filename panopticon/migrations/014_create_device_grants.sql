@@ -0,0 +1,28 @@
+CREATE TABLE IF NOT EXISTS device_grants (
+    user_id        UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    device_id      TEXT NOT NULL,
+    can_lock       BOOLEAN NOT NULL DEFAULT FALSE,
+    can_unlock     BOOLEAN NOT NULL DEFAULT FALSE,
+    -- Optional weekly window; all four are set or all are NULL.
+    schedule_days  SMALLINT[],                 -- ISO weekdays, 1 = Monday; empty = every day
+    schedule_start TIME,
+    schedule_end   TIME,                       -- before start = window runs past midnight
+    schedule_tz    TEXT,                       -- IANA time zone name
+    created_at     TIMESTAMPTZ NOT NULL DEFAULT now(),
+    updated_at     TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY (user_id, device_id)
+);
+
+CREATE INDEX idx_device_grants_device_id ON device_grants (device_id);
+
+CREATE TABLE IF NOT EXISTS access_denials (
+    id         UUID PRIMARY KEY NOT NULL DEFAULT gen_random_uuid(),
+    user_id    UUID REFERENCES users(id) ON DELETE SET NULL,
+    device_id  TEXT NOT NULL,
+    command    TEXT NOT NULL,              -- 'lock', 'unlock'
+    source     TEXT NOT NULL,              -- 'api', 'mqtt'
+    reason     TEXT NOT NULL,              -- 'role', 'no_grant', 'action_not_granted', 'outside_schedule', 'unknown_user'
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX idx_access_denials_created_at ON access_denials (created_at);
@@ -0,0 +1,30 @@
+CREATE TABLE IF NOT EXISTS user_totp (
+    user_id        UUID PRIMARY KEY NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    secret         TEXT NOT NULL,              -- base32
+    enabled        BOOLEAN NOT NULL DEFAULT FALSE,  -- FALSE until the first code is confirmed
+    last_used_step BIGINT,                     -- rejects replay of an accepted code
+    created_at     TIMESTAMPTZ NOT NULL DEFAULT now(),
+    enabled_at     TIMESTAMPTZ
+);
+
+CREATE TABLE IF NOT EXISTS totp_recovery_codes (
+    id         UUID PRIMARY KEY NOT NULL DEFAULT gen_random_uuid(),
+    user_id    UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    code_hash  TEXT NOT NULL,                  -- SHA-256 hex of the normalized code
+    used_at    TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX idx_totp_recovery_codes_user_id ON totp_recovery_codes (user_id);
+
+-- Password accepted, waiting for the second factor.
+CREATE TABLE IF NOT EXISTS login_challenges (
+    id         TEXT PRIMARY KEY NOT NULL,
+    user_id    UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    attempts   INTEGER NOT NULL DEFAULT 0,
+    expires_at TIMESTAMPTZ NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+INSERT INTO system_config (key, value) VALUES ('require_2fa_for_unlock', 'false')
+    ON CONFLICT (key) DO NOTHING;
@@ -0,0 +1,22 @@
+CREATE TABLE IF NOT EXISTS webauthn_credentials (
+    id            UUID PRIMARY KEY NOT NULL DEFAULT gen_random_uuid(),
+    user_id       UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    credential_id BYTEA NOT NULL UNIQUE,
+    public_key    BYTEA NOT NULL,              -- DER SubjectPublicKeyInfo
+    alg           INTEGER NOT NULL,            -- COSE algorithm (-7 ES256, -257 RS256)
+    sign_count    BIGINT NOT NULL DEFAULT 0,
+    name          TEXT NOT NULL,
+    created_at    TIMESTAMPTZ NOT NULL DEFAULT now(),
+    last_used_at  TIMESTAMPTZ
+);
+
+CREATE INDEX idx_webauthn_credentials_user_id ON webauthn_credentials (user_id);
+
+-- Outstanding registration / sign-in ceremonies.
+CREATE TABLE IF NOT EXISTS webauthn_challenges (
+    id         TEXT PRIMARY KEY NOT NULL,
+    user_id    UUID REFERENCES users(id) ON DELETE CASCADE,  -- NULL for sign-in
+    kind       TEXT NOT NULL CHECK (kind IN ('register', 'login')),
+    challenge  BYTEA NOT NULL,
+    expires_at TIMESTAMPTZ NOT NULL
+);
@@ -0,0 +1,13 @@
+CREATE TABLE IF NOT EXISTS api_tokens (
+    id           UUID PRIMARY KEY NOT NULL DEFAULT gen_random_uuid(),
+    user_id      UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    name         TEXT NOT NULL,
+    token_hash   TEXT NOT NULL UNIQUE,         -- SHA-256 hex of the full token
+    prefix       TEXT NOT NULL,                -- first characters, shown in listings
+    scopes       TEXT[] NOT NULL,
+    expires_at   TIMESTAMPTZ,                  -- NULL = never expires
+    last_used_at TIMESTAMPTZ,
+    created_at   TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX idx_api_tokens_user_id ON api_tokens (user_id);
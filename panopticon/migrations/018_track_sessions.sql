@@ -0,0 +1,8 @@
+-- Sessions get a public identifier (the primary key is the cookie value and
+-- must never be exposed) and client details for the session list.
+ALTER TABLE sessions ADD COLUMN public_id UUID NOT NULL DEFAULT gen_random_uuid() UNIQUE;
+ALTER TABLE sessions ADD COLUMN last_seen_at TIMESTAMPTZ;
+ALTER TABLE sessions ADD COLUMN ip TEXT;
+ALTER TABLE sessions ADD COLUMN user_agent TEXT;
+
+CREATE INDEX IF NOT EXISTS idx_sessions_user_id ON sessions (user_id);
@@ -0,0 +1,9 @@
+-- Failed login and password-reset attempts, per account and per client IP.
+CREATE TABLE IF NOT EXISTS auth_throttle (
+    bucket          TEXT NOT NULL,             -- login_account, login_ip, reset_account, reset_ip
+    subject         TEXT NOT NULL,             -- email address or IP
+    failures        INTEGER NOT NULL DEFAULT 0,
+    last_failure_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    locked_until    TIMESTAMPTZ,
+    PRIMARY KEY (bucket, subject)
+);
@@ -0,0 +1,2 @@
+-- Pending address for 'email_change' tokens.
+ALTER TABLE email_tokens ADD COLUMN new_email TEXT;
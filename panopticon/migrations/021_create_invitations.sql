@@ -0,0 +1,13 @@
+CREATE TABLE IF NOT EXISTS invitations (
+    id          UUID PRIMARY KEY NOT NULL DEFAULT gen_random_uuid(),
+    email       TEXT NOT NULL,
+    role        TEXT NOT NULL CHECK (role IN ('admin', 'operator', 'resident', 'viewer')),
+    token_hash  TEXT NOT NULL UNIQUE,          -- SHA-256 hex of the emailed token
+    invited_by  UUID REFERENCES users(id) ON DELETE SET NULL,
+    created_at  TIMESTAMPTZ NOT NULL DEFAULT now(),
+    expires_at  TIMESTAMPTZ NOT NULL,
+    accepted_at TIMESTAMPTZ,
+    user_id     UUID REFERENCES users(id) ON DELETE SET NULL  -- account created on acceptance
+);
+
+CREATE INDEX idx_invitations_email ON invitations (email);
@@ -0,0 +1,19 @@
+-- Accounts linked to an external OpenID Connect provider.
+CREATE TABLE IF NOT EXISTS user_identities (
+    issuer        TEXT NOT NULL,
+    subject       TEXT NOT NULL,               -- the provider's `sub` claim
+    user_id       UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    created_at    TIMESTAMPTZ NOT NULL DEFAULT now(),
+    last_login_at TIMESTAMPTZ,
+    PRIMARY KEY (issuer, subject)
+);
+
+CREATE INDEX idx_user_identities_user_id ON user_identities (user_id);
+
+-- Authorization requests in flight (state, nonce and PKCE verifier).
+CREATE TABLE IF NOT EXISTS oidc_login_states (
+    state         TEXT PRIMARY KEY NOT NULL,
+    nonce         TEXT NOT NULL,
+    code_verifier TEXT NOT NULL,
+    expires_at    TIMESTAMPTZ NOT NULL
+);
@@ -0,0 +1,24 @@
+-- Per-user, per-channel notification preferences. A user without a row for a
+-- channel gets the defaults: push on for everything, email off.
+CREATE TABLE notification_preferences (
+    user_id     UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    channel     TEXT NOT NULL,              -- 'email', 'push'
+    enabled     BOOLEAN NOT NULL DEFAULT TRUE,
+    event_types TEXT[] NOT NULL,            -- 'scan_denied', 'scan_granted', 'lock_change', 'device_alert', 'sentinel_offline'
+    tag_ids     TEXT[],                     -- NULL = all cards
+    device_ids  TEXT[],                     -- NULL = all devices
+    -- Optional quiet hours; all four are set or all are NULL.
+    quiet_days  SMALLINT[],                 -- ISO weekdays, 1 = Monday; empty = every day
+    quiet_start TIME,
+    quiet_end   TIME,                       -- before start = runs past midnight
+    quiet_tz    TEXT,                       -- IANA time zone name
+    updated_at  TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY (user_id, channel)
+);
+
+-- notify_email used to send every event except sentinel ones.
+INSERT INTO notification_preferences (user_id, channel, enabled, event_types)
+SELECT id, 'email', TRUE, ARRAY['scan_denied', 'scan_granted', 'lock_change', 'device_alert']
+FROM users WHERE notify_email = TRUE;
+
+ALTER TABLE users DROP COLUMN notify_email;
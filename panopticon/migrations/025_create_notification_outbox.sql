@@ -0,0 +1,32 @@
+-- Events that produce notifications, kept so deliveries can be inspected and
+-- re-sent. Pruned after 30 days (deliveries cascade).
+CREATE TABLE notification_outbox (
+    id         UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    event_type TEXT NOT NULL,                  -- see notification_preferences.event_types
+    event      JSONB NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX idx_notification_outbox_created_at ON notification_outbox (created_at);
+
+-- One row per recipient and channel, rendered when the event happened.
+CREATE TABLE notification_deliveries (
+    id                   UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    outbox_id            UUID NOT NULL REFERENCES notification_outbox(id) ON DELETE CASCADE,
+    user_id              UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    channel              TEXT NOT NULL,        -- 'email', 'push'
+    email                TEXT,                 -- email: recipient address
+    push_subscription_id UUID REFERENCES push_subscriptions(id) ON DELETE SET NULL,
+    subject              TEXT NOT NULL,
+    body                 TEXT NOT NULL,
+    status               TEXT NOT NULL DEFAULT 'pending',  -- 'pending', 'sent', 'dead'
+    attempts             INTEGER NOT NULL DEFAULT 0,
+    next_attempt_at      TIMESTAMPTZ NOT NULL DEFAULT now(),
+    last_error           TEXT,
+    sent_at              TIMESTAMPTZ,
+    created_at           TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX idx_notification_deliveries_due ON notification_deliveries (next_attempt_at)
+    WHERE status = 'pending';
+CREATE INDEX idx_notification_deliveries_status ON notification_deliveries (status, created_at DESC);
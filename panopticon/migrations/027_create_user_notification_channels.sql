@@ -0,0 +1,12 @@
+-- Per-user ntfy / Gotify / Matrix destinations. Which events they receive is
+-- set in notification_preferences under the same channel name.
+CREATE TABLE user_notification_channels (
+    user_id    UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    channel    TEXT NOT NULL,       -- 'ntfy', 'gotify', 'matrix'
+    server_url TEXT NOT NULL,       -- ntfy server, Gotify server or Matrix homeserver
+    target     TEXT,                -- ntfy topic or Matrix room ID
+    token      TEXT,                -- ntfy access token (optional), Gotify app token, Matrix access token
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY (user_id, channel)
+);
@@ -0,0 +1,22 @@
+-- Email can be sent per event or collected into hourly / daily digests.
+ALTER TABLE notification_preferences
+    ADD COLUMN digest      TEXT NOT NULL DEFAULT 'instant',  -- 'instant', 'hourly', 'daily'
+    ADD COLUMN digest_hour SMALLINT,                         -- daily: local hour the digest goes out
+    ADD COLUMN digest_tz   TEXT;                             -- daily: IANA time zone name
+
+-- Events waiting for a user's next digest.
+CREATE TABLE notification_digest_items (
+    id         BIGSERIAL PRIMARY KEY,
+    user_id    UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    outbox_id  UUID NOT NULL REFERENCES notification_outbox(id) ON DELETE CASCADE,
+    event_type TEXT NOT NULL,
+    tag_id     TEXT,
+    device_id  TEXT,
+    summary    TEXT NOT NULL,                                -- the event's email subject
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX idx_notification_digest_items_user ON notification_digest_items (user_id, created_at);
+
+-- A digest delivery covers many events, so it has no single outbox row.
+ALTER TABLE notification_deliveries ALTER COLUMN outbox_id DROP NOT NULL;
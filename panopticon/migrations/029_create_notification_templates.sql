@@ -0,0 +1,14 @@
+-- Admin overrides of the built-in notification texts, one row per kind
+-- ('scan_granted', 'lock_change', …). Missing rows use the defaults.
+CREATE TABLE notification_templates (
+    kind       TEXT PRIMARY KEY,
+    subject    TEXT NOT NULL,
+    body       TEXT NOT NULL,               -- plain text with {placeholders}
+    updated_by UUID REFERENCES users(id) ON DELETE SET NULL,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+-- Display names at the time of the event, so digests don't show raw IDs.
+ALTER TABLE notification_digest_items
+    ADD COLUMN card TEXT,                   -- card label, or the tag ID
+    ADD COLUMN door TEXT;                   -- lock or sentinel name
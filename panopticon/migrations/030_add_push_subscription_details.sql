@@ -0,0 +1,10 @@
+-- Which browser each push subscription belongs to, how delivery to it is
+-- going, and which VAPID key it was made with (for key rotation).
+ALTER TABLE push_subscriptions ADD COLUMN name TEXT;
+ALTER TABLE push_subscriptions ADD COLUMN user_agent TEXT;
+ALTER TABLE push_subscriptions ADD COLUMN vapid_key TEXT;  -- NULL = made before rotation support
+ALTER TABLE push_subscriptions ADD COLUMN last_success_at TIMESTAMPTZ;
+ALTER TABLE push_subscriptions ADD COLUMN last_failure_at TIMESTAMPTZ;
+ALTER TABLE push_subscriptions ADD COLUMN last_error TEXT;
+-- Consecutive failed sends; the subscription is removed when it gets too high.
+ALTER TABLE push_subscriptions ADD COLUMN failure_count INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,13 @@
+-- Who added a card, when it was added by a person rather than enrolment.
+ALTER TABLE access_cards
+    ADD COLUMN created_by UUID REFERENCES users(id) ON DELETE SET NULL;
+
+-- Push notification actions that have been performed. Each action on an
+-- event can be used once, so a replayed token can't undo a later change.
+CREATE TABLE push_action_uses (
+    outbox_id UUID NOT NULL REFERENCES notification_outbox(id) ON DELETE CASCADE,
+    action    TEXT NOT NULL,                -- 'lock', 'grant_card'
+    user_id   UUID REFERENCES users(id) ON DELETE SET NULL,
+    used_at   TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY (outbox_id, action)
+);
@@ -0,0 +1,2 @@
+-- The card whose scan issued an RFID unlock, so notifications can name it.
+ALTER TABLE lock_commands ADD COLUMN tag_id TEXT;
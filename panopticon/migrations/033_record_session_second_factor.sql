@@ -0,0 +1,3 @@
+-- The second factor a session's login was completed with (totp or passkey),
+-- so the "require 2FA for unlock" policy can check the session itself.
+ALTER TABLE sessions ADD COLUMN second_factor TEXT;
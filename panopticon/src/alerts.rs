@@ -0,0 +1,327 @@
+//! Low-battery and lock-offline alerting.
+//!
+//! A background task polls U-Tec for device status (feeding the same
+//! `device_status` history as webhooks), then compares the latest recorded
+//! values against the configured thresholds. Each incident opens exactly one
+//! row in `device_alerts` and broadcasts a `WsEvent::DeviceAlert`; when the
+//! condition clears, the row is resolved and a recovery event is broadcast.
+//! The email and push notifiers pick both up from the event bus.
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use axum::{extract::State, http::StatusCode, routing::get, Json, Router};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+use crate::device_status;
+use crate::middleware::Authorized;
+use crate::permissions::perm;
+use crate::ws::WsEvent;
+use crate::AppState;
+
+type ApiError = (StatusCode, &'static str);
+
+const LOW_BATTERY: &str = "low_battery";
+const OFFLINE: &str = "offline";
+
+// ── Config ──────────────────────────────────────────────────────────────────
+
+#[derive(Clone)]
+pub struct AlertConfig {
+    /// Alert when battery falls below this percentage.
+    pub battery_threshold: u64,
+    /// Alert when a device has been offline for longer than this.
+    pub offline_after: Duration,
+    /// How often to poll U-Tec and evaluate alerts.
+    pub poll_interval: Duration,
+}
+
+impl AlertConfig {
+    pub fn from_env() -> Self {
+        let battery_threshold = std::env::var("ALERT_BATTERY_THRESHOLD")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(20);
+        let offline_minutes: u64 = std::env::var("ALERT_OFFLINE_MINUTES")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(10);
+        let poll_secs: u64 = std::env::var("ALERT_POLL_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(60);
+
+        info!(
+            battery_threshold,
+            offline_minutes, poll_secs, "Device alerting configured"
+        );
+
+        Self {
+            battery_threshold,
+            offline_after: Duration::from_secs(offline_minutes * 60),
+            poll_interval: Duration::from_secs(poll_secs.max(10)),
+        }
+    }
+}
+
+// ── API ─────────────────────────────────────────────────────────────────────
+
+pub fn router() -> Router<AppState> {
+    Router::new().route("/alerts", get(list_alerts))
+}
+
+#[derive(Serialize)]
+struct AlertEntry {
+    id: Uuid,
+    device_id: String,
+    kind: String,
+    message: String,
+    started_at: String,
+}
+
+#[derive(Serialize)]
+struct AlertsResponse {
+    battery_threshold: u64,
+    offline_minutes: u64,
+    alerts: Vec<AlertEntry>,
+}
+
+async fn list_alerts(
+    _user: Authorized<perm::ViewDevices>,
+    State(state): State<AppState>,
+) -> Result<Json<AlertsResponse>, ApiError> {
+    let rows: Vec<(Uuid, String, String, String, DateTime<Utc>)> = sqlx::query_as(
+        "SELECT id, device_id, kind, message, started_at FROM device_alerts \
+         WHERE resolved_at IS NULL ORDER BY started_at DESC",
+    )
+    .fetch_all(&state.db)
+    .await
+    .map_err(|e| {
+        error!("Failed to list alerts: {e:#}");
+        (StatusCode::INTERNAL_SERVER_ERROR, "Database error")
+    })?;
+
+    let alerts = rows
+        .into_iter()
+        .map(|(id, device_id, kind, message, started_at)| AlertEntry {
+            id,
+            device_id,
+            kind,
+            message,
+            started_at: started_at.to_rfc3339(),
+        })
+        .collect();
+
+    Ok(Json(AlertsResponse {
+        battery_threshold: state.alert_config.battery_threshold,
+        offline_minutes: state.alert_config.offline_after.as_secs() / 60,
+        alerts,
+    }))
+}
+
+// ── Evaluation ──────────────────────────────────────────────────────────────
+
+/// Whether a battery reading should raise a low-battery alert.
+fn is_low_battery(level: i32, threshold: u64) -> bool {
+    (level.max(0) as u64) < threshold
+}
+
+/// Whether a device that went offline at `since` has been offline long enough
+/// to alert.
+fn is_offline_too_long(since: DateTime<Utc>, now: DateTime<Utc>, offline_after: Duration) -> bool {
+    now.signed_duration_since(since)
+        .to_std()
+        .is_ok_and(|elapsed| elapsed >= offline_after)
+}
+
+/// Open an alert for this device/kind unless one is already open.
+async fn open_alert(state: &AppState, device_id: &str, kind: &str, message: String) {
+    let opened: Result<Option<Uuid>, _> = sqlx::query_scalar(
+        "INSERT INTO device_alerts (device_id, kind, message) VALUES ($1, $2, $3) \
+         ON CONFLICT (device_id, kind) WHERE resolved_at IS NULL DO NOTHING RETURNING id",
+    )
+    .bind(device_id)
+    .bind(kind)
+    .bind(&message)
+    .fetch_optional(&state.db)
+    .await;
+
+    match opened {
+        Ok(Some(_)) => {
+            warn!(device_id, kind, "Device alert raised: {message}");
+            let _ = state.events.send(WsEvent::DeviceAlert {
+                device_id: device_id.to_string(),
+                kind: kind.to_string(),
+                active: true,
+                message,
+            });
+        }
+        Ok(None) => {} // already open — notified once for this incident
+        Err(e) => error!(device_id, kind, "Failed to open device alert: {e:#}"),
+    }
+}
+
+/// Resolve the open alert for this device/kind, if any.
+async fn resolve_alert(state: &AppState, device_id: &str, kind: &str, message: String) {
+    let resolved: Result<Option<Uuid>, _> = sqlx::query_scalar(
+        "UPDATE device_alerts SET resolved_at = now() \
+         WHERE device_id = $1 AND kind = $2 AND resolved_at IS NULL RETURNING id",
+    )
+    .bind(device_id)
+    .bind(kind)
+    .fetch_optional(&state.db)
+    .await;
+
+    match resolved {
+        Ok(Some(_)) => {
+            info!(device_id, kind, "Device alert resolved: {message}");
+            let _ = state.events.send(WsEvent::DeviceAlert {
+                device_id: device_id.to_string(),
+                kind: kind.to_string(),
+                active: false,
+                message,
+            });
+        }
+        Ok(None) => {}
+        Err(e) => error!(device_id, kind, "Failed to resolve device alert: {e:#}"),
+    }
+}
+
+/// Compare the latest recorded status of every device against the thresholds.
+async fn evaluate(state: &AppState, config: &AlertConfig, names: &HashMap<String, String>) {
+    let name_of = |id: &str| names.get(id).cloned().unwrap_or_else(|| id.to_string());
+
+    let battery: Vec<(String, i32)> = match sqlx::query_as(
+        "SELECT DISTINCT ON (device_id) device_id, battery_level FROM device_status_log \
+         WHERE battery_level IS NOT NULL ORDER BY device_id, created_at DESC",
+    )
+    .fetch_all(&state.db)
+    .await
+    {
+        Ok(rows) => rows,
+        Err(e) => {
+            error!("Failed to read battery status: {e:#}");
+            Vec::new()
+        }
+    };
+
+    for (device_id, level) in battery {
+        let name = name_of(&device_id);
+        if is_low_battery(level, config.battery_threshold) {
+            let message = format!(
+                "{name} battery is at {level}% (below {}%)",
+                config.battery_threshold
+            );
+            open_alert(state, &device_id, LOW_BATTERY, message).await;
+        } else {
+            let message = format!("{name} battery is back at {level}%");
+            resolve_alert(state, &device_id, LOW_BATTERY, message).await;
+        }
+    }
+
+    let connectivity: Vec<(String, bool, DateTime<Utc>)> = match sqlx::query_as(
+        "SELECT DISTINCT ON (device_id) device_id, online, created_at FROM device_status_log \
+         WHERE online IS NOT NULL ORDER BY device_id, created_at DESC",
+    )
+    .fetch_all(&state.db)
+    .await
+    {
+        Ok(rows) => rows,
+        Err(e) => {
+            error!("Failed to read connectivity status: {e:#}");
+            Vec::new()
+        }
+    };
+
+    let now = Utc::now();
+    for (device_id, online, since) in connectivity {
+        let name = name_of(&device_id);
+        if online {
+            resolve_alert(state, &device_id, OFFLINE, format!("{name} is back online")).await;
+        } else if is_offline_too_long(since, now, config.offline_after) {
+            let minutes = now.signed_duration_since(since).num_minutes();
+            let message = format!("{name} has been offline for {minutes} minutes");
+            open_alert(state, &device_id, OFFLINE, message).await;
+        }
+    }
+}
+
+/// Query all locks and feed their status into the device status history.
+/// Returns device names keyed by ID for alert messages.
+async fn poll_devices(state: &AppState) -> HashMap<String, String> {
+    let Some(client) = state.auth_store.client().await else {
+        return HashMap::new();
+    };
+
+    let locks = match client.discover_locks().await {
+        Ok(l) => l,
+        Err(e) => {
+            error!("Alerts: failed to discover locks: {e:#}");
+            return HashMap::new();
+        }
+    };
+
+    let lock_refs: Vec<&_> = locks.iter().collect();
+    match client.query_devices(&lock_refs).await {
+        Ok(states) => {
+            for lock in &locks {
+                if let Some(ds) = states.iter().find(|s| s.id == lock.id) {
+                    let battery = ds.battery_level().map(|raw| lock.battery_percent(raw));
+                    device_status::observe(state, &lock.id, battery, ds.online(), "poll").await;
+                }
+            }
+        }
+        Err(e) => error!("Alerts: failed to query device states: {e:#}"),
+    }
+
+    locks.into_iter().map(|l| (l.id, l.name)).collect()
+}
+
+// ── Entry point ─────────────────────────────────────────────────────────────
+
+pub async fn spawn_alert_monitor(state: AppState, config: AlertConfig) {
+    let mut interval = tokio::time::interval(config.poll_interval);
+    info!("Alert monitor started");
+    loop {
+        interval.tick().await;
+        let names = poll_devices(&state).await;
+        evaluate(&state, &config, &names).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn low_battery_below_threshold() {
+        assert!(is_low_battery(19, 20));
+        assert!(!is_low_battery(20, 20));
+        assert!(!is_low_battery(75, 20));
+    }
+
+    #[test]
+    fn offline_duration() {
+        let now = Utc::now();
+        let ten = Duration::from_secs(600);
+        assert!(!is_offline_too_long(
+            now - chrono::Duration::minutes(9),
+            now,
+            ten
+        ));
+        assert!(is_offline_too_long(
+            now - chrono::Duration::minutes(10),
+            now,
+            ten
+        ));
+        // Clock skew: a transition recorded in the future is not "too long".
+        assert!(!is_offline_too_long(
+            now + chrono::Duration::minutes(1),
+            now,
+            ten
+        ));
+    }
+}
@@ -1,18 +1,19 @@
 use axum::{
-    extract::{Path, State},
-    http::StatusCode,
-    routing::{delete, get, post},
+    extract::{Path, Query, State},
+    http::{HeaderMap, StatusCode},
+    routing::{delete, get, post, put},
     Json, Router,
 };
 use serde::{Deserialize, Serialize};
-use std::time::Duration;
-use tracing::{debug, error, warn};
+use tracing::{error, info};
 use uuid::Uuid;
 
-use crate::lock_log;
-use crate::middleware::AuthUser;
-use crate::utec::{Device, DeviceWithStates, LockUser, UTec};
-use crate::ws::WsEvent;
+use crate::commands::{self, CommandError, CommandRecord, CommandStatus, LockAction};
+use crate::grants::{self, Denial};
+use crate::middleware::{AuthUser, Authorized};
+use crate::permissions::{perm, Role, Scope};
+use crate::totp;
+use crate::utec::{LockUser, UTec};
 use crate::AppState;
 
 type ApiError = (StatusCode, &'static str);
@@ -30,16 +31,8 @@ struct DeviceResponse {
 struct LockActionResponse {
     success: bool,
     lock_state: Option<String>,
-}
-
-#[derive(Serialize)]
-struct NotificationPrefs {
-    email: bool,
-}
-
-#[derive(Deserialize)]
-struct UpdateNotificationPrefs {
-    email: bool,
+    command_id: Uuid,
+    status: String,
 }
 
 pub fn router() -> Router<AppState> {
@@ -48,13 +41,13 @@ pub fn router() -> Router<AppState> {
         .route("/devices/{id}/lock", post(lock_device))
         .route("/devices/{id}/unlock", post(unlock_device))
         .route("/devices/{id}/users", get(list_lock_users))
-        .route(
-            "/notifications",
-            get(get_notifications).put(update_notifications),
-        )
         .route("/admin/pending-users", get(list_pending_users))
+        .route("/admin/users", get(list_users))
+        .route("/admin/users/{id}/suspend", post(suspend_user))
+        .route("/admin/users/{id}/unsuspend", post(unsuspend_user))
         .route("/admin/users/{id}/approve", post(approve_user))
         .route("/admin/users/{id}", delete(delete_user))
+        .route("/admin/users/{id}/role", put(set_user_role))
 }
 
 async fn get_client(state: &AppState) -> Result<UTec, ApiError> {
@@ -66,7 +59,7 @@ async fn get_client(state: &AppState) -> Result<UTec, ApiError> {
 }
 
 async fn list_devices(
-    _user: AuthUser,
+    _user: Authorized<perm::ViewDevices>,
     State(state): State<AppState>,
 ) -> Result<Json<Vec<DeviceResponse>>, ApiError> {
     let client = get_client(&state).await?;
@@ -86,24 +79,9 @@ async fn list_devices(
         .iter()
         .map(|lock| {
             let device_states = states.iter().find(|s| s.id == lock.id);
-            let battery_level = device_states.and_then(|s| s.battery_level()).map(|raw| {
-                // Normalize battery level to 0-100% using the device's
-                // batteryLevelRange from discovery (e.g. min=1, max=5).
-                let (min, max) = lock
-                    .attributes
-                    .as_ref()
-                    .and_then(|a| a.get("batteryLevelRange"))
-                    .map(|r| {
-                        let min = r.get("min").and_then(|v| v.as_u64()).unwrap_or(0);
-                        let max = r.get("max").and_then(|v| v.as_u64()).unwrap_or(100);
-                        (min, max)
-                    })
-                    .unwrap_or((0, 100));
-                if max <= min {
-                    return raw;
-                }
-                ((raw.saturating_sub(min)) * 100) / (max - min)
-            });
+            let battery_level = device_states
+                .and_then(|s| s.battery_level())
+                .map(|raw| lock.battery_percent(raw));
             DeviceResponse {
                 id: lock.id.clone(),
                 name: lock.name.clone(),
@@ -118,42 +96,85 @@ async fn list_devices(
 }
 
 async fn lock_device(
-    user: AuthUser,
+    user: Authorized<perm::OperateLocks>,
     State(state): State<AppState>,
     Path(id): Path<String>,
+    headers: HeaderMap,
 ) -> Result<Json<LockActionResponse>, ApiError> {
-    let client = get_client(&state).await?;
-
-    let locks = client.discover_locks().await.map_err(|e| {
-        error!("Failed to discover locks: {e:#}");
-        (StatusCode::BAD_GATEWAY, "Failed to discover locks")
-    })?;
+    run_lock_command(&state, &user, &id, &headers, LockAction::Lock).await
+}
 
-    let device = locks
-        .iter()
-        .find(|d| d.id == id)
-        .ok_or((StatusCode::NOT_FOUND, "Device not found"))?;
+async fn unlock_device(
+    user: Authorized<perm::OperateLocks>,
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+) -> Result<Json<LockActionResponse>, ApiError> {
+    run_lock_command(&state, &user, &id, &headers, LockAction::Unlock).await
+}
 
-    let results = client.lock(device).await.map_err(|e| {
-        error!("Failed to lock device {id}: {e:#}");
-        (StatusCode::BAD_GATEWAY, "Failed to lock device")
-    })?;
+/// Send a lock/unlock command on behalf of an API user, honouring the
+/// client's `Idempotency-Key` header.
+async fn run_lock_command(
+    state: &AppState,
+    user: &AuthUser,
+    id: &str,
+    headers: &HeaderMap,
+    action: LockAction,
+) -> Result<Json<LockActionResponse>, ApiError> {
+    let scope = match action {
+        LockAction::Lock => Scope::LocksLock,
+        LockAction::Unlock => Scope::LocksUnlock,
+    };
+    if !user.has_scope(scope) {
+        return Err((StatusCode::FORBIDDEN, "Token lacks the required scope"));
+    }
 
-    let lock_state =
-        handle_lock_response(&state, &id, device, &results, "api", Some(user.id)).await;
+    let idempotency_key = match headers.get("idempotency-key") {
+        Some(v) => {
+            let key = v
+                .to_str()
+                .ok()
+                .map(str::trim)
+                .filter(|k| !k.is_empty() && k.len() <= commands::MAX_IDEMPOTENCY_KEY_LEN)
+                .ok_or((StatusCode::BAD_REQUEST, "Invalid Idempotency-Key header"))?;
+            Some(key.to_string())
+        }
+        None => None,
+    };
+
+    let record =
+        send_lock_command(state, user, id, action, "api", idempotency_key.as_deref()).await?;
 
     Ok(Json(LockActionResponse {
         success: true,
-        lock_state,
+        lock_state: record.lock_state,
+        command_id: record.id,
+        status: record.status,
     }))
 }
 
-async fn unlock_device(
-    user: AuthUser,
-    State(state): State<AppState>,
-    Path(id): Path<String>,
-) -> Result<Json<LockActionResponse>, ApiError> {
-    let client = get_client(&state).await?;
+/// Check two-factor and grants for `user`, then lock or unlock the device.
+/// `source` is recorded with the command and any denial.
+pub(crate) async fn send_lock_command(
+    state: &AppState,
+    user: &AuthUser,
+    id: &str,
+    action: LockAction,
+    source: &str,
+    idempotency_key: Option<&str>,
+) -> Result<CommandRecord, ApiError> {
+    if action == LockAction::Unlock && !totp::unlock_allowed(&state.db, user).await {
+        let denial = Denial::TwoFactorRequired;
+        grants::record_denial(&state.db, Some(user.id), id, action, source, denial).await;
+        return Err((StatusCode::FORBIDDEN, denial.message()));
+    }
+
+    grants::check(&state.db, user.id, user.role, id, action, source)
+        .await
+        .map_err(|denial| (StatusCode::FORBIDDEN, denial.message()))?;
+
+    let client = get_client(state).await?;
 
     let locks = client.discover_locks().await.map_err(|e| {
         error!("Failed to discover locks: {e:#}");
@@ -165,105 +186,39 @@ async fn unlock_device(
         .find(|d| d.id == id)
         .ok_or((StatusCode::NOT_FOUND, "Device not found"))?;
 
-    let results = client.unlock(device).await.map_err(|e| {
-        error!("Failed to unlock device {id}: {e:#}");
-        (StatusCode::BAD_GATEWAY, "Failed to unlock device")
+    let record = commands::execute(
+        state,
+        &client,
+        device,
+        action,
+        source,
+        Some(user.id),
+        idempotency_key,
+    )
+    .await
+    .map_err(|e| match e {
+        CommandError::KeyReused => (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "Idempotency-Key already used for a different command",
+        ),
+        CommandError::Database(e) => {
+            error!("Failed to record lock command: {e:#}");
+            (StatusCode::INTERNAL_SERVER_ERROR, "Database error")
+        }
     })?;
 
-    let lock_state =
-        handle_lock_response(&state, &id, device, &results, "api", Some(user.id)).await;
-
-    Ok(Json(LockActionResponse {
-        success: true,
-        lock_state,
-    }))
-}
-
-/// Maximum seconds we'll wait for a deferred lock response before giving up.
-const MAX_DEFERRED_WAIT_SECS: u64 = 60;
-
-/// Handle a lock/unlock command response: if the lock state is immediately
-/// available, broadcast it via WebSocket. If the API returns a deferred
-/// response (st.deferredResponse), spawn a background task that waits the
-/// indicated number of seconds, then queries the device and broadcasts the
-/// resulting lock state.
-pub async fn handle_lock_response(
-    state: &AppState,
-    device_id: &str,
-    device: &Device,
-    results: &[DeviceWithStates],
-    source: &str,
-    user_id: Option<uuid::Uuid>,
-) -> Option<String> {
-    let device_result = results.iter().find(|s| s.id == device_id);
-    let lock_state = device_result.and_then(|s| s.lock_state());
-
-    if let Some(ref ls) = lock_state {
-        lock_log::record(&state.db, device_id, ls, source, user_id).await;
-        let _ = state.events.send(WsEvent::LockState {
-            device_id: device_id.to_string(),
-            lock_state: ls.clone(),
+    if record.status == CommandStatus::Failed.as_str() {
+        return Err(match action {
+            LockAction::Lock => (StatusCode::BAD_GATEWAY, "Failed to lock device"),
+            LockAction::Unlock => (StatusCode::BAD_GATEWAY, "Failed to unlock device"),
         });
-    } else if let Some(seconds) = device_result
-        .and_then(|s| s.get_state("st.deferredResponse", "seconds"))
-        .and_then(|s| s.value.as_u64())
-    {
-        let seconds = if seconds > MAX_DEFERRED_WAIT_SECS {
-            warn!(
-                device_id,
-                seconds, "Deferred wait exceeds maximum, capping at {MAX_DEFERRED_WAIT_SECS}s"
-            );
-            MAX_DEFERRED_WAIT_SECS
-        } else {
-            seconds
-        };
-
-        let state = state.clone();
-        let device_id = device_id.to_string();
-        let device = device.clone();
-        let deferred_source = format!("{source}_deferred");
-        tokio::spawn(async move {
-            debug!(device_id, seconds, "Waiting for deferred lock response");
-            tokio::time::sleep(Duration::from_secs(seconds)).await;
-
-            let Some(client) = state.auth_store.client().await else {
-                error!(device_id, "No U-Tec client available for deferred poll");
-                return;
-            };
-            match client.query_device(&device).await {
-                Ok(device_states) => {
-                    if let Some(ls) = device_states.lock_state() {
-                        debug!(device_id, lock_state = %ls, "Deferred lock state resolved");
-                        lock_log::record(&state.db, &device_id, &ls, &deferred_source, user_id)
-                            .await;
-                        let _ = state.events.send(WsEvent::LockState {
-                            device_id,
-                            lock_state: ls,
-                        });
-                    } else {
-                        warn!(device_id, "Deferred query returned no lock state");
-                    }
-                }
-                Err(e) => {
-                    error!(
-                        device_id,
-                        "Failed to query device after deferred wait: {e:#}"
-                    );
-                }
-            }
-        });
-    } else {
-        warn!(
-            device_id,
-            "Lock command response contained neither lock state nor deferred response"
-        );
     }
 
-    lock_state
+    Ok(record)
 }
 
 async fn list_lock_users(
-    _user: AuthUser,
+    _user: Authorized<perm::ManageLocks>,
     State(state): State<AppState>,
     Path(id): Path<String>,
 ) -> Result<Json<Vec<LockUser>>, ApiError> {
@@ -287,46 +242,6 @@ async fn list_lock_users(
     Ok(Json(users))
 }
 
-async fn get_notifications(
-    user: AuthUser,
-    State(state): State<AppState>,
-) -> Result<Json<NotificationPrefs>, ApiError> {
-    let email: bool = sqlx::query_scalar("SELECT notify_email FROM users WHERE id = $1")
-        .bind(user.id)
-        .fetch_one(&state.db)
-        .await
-        .map_err(|e| {
-            error!("Failed to fetch notification prefs: {e}");
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "Failed to fetch preferences",
-            )
-        })?;
-
-    Ok(Json(NotificationPrefs { email }))
-}
-
-async fn update_notifications(
-    user: AuthUser,
-    State(state): State<AppState>,
-    Json(body): Json<UpdateNotificationPrefs>,
-) -> Result<Json<NotificationPrefs>, ApiError> {
-    sqlx::query("UPDATE users SET notify_email = $1 WHERE id = $2")
-        .bind(body.email)
-        .bind(user.id)
-        .execute(&state.db)
-        .await
-        .map_err(|e| {
-            error!("Failed to update notification prefs: {e}");
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "Failed to update preferences",
-            )
-        })?;
-
-    Ok(Json(NotificationPrefs { email: body.email }))
-}
-
 // ── Admin: pending users ─────────────────────────────────────────────
 
 #[derive(Serialize)]
@@ -337,19 +252,10 @@ struct PendingUser {
     created_at: String,
 }
 
-fn require_approved(user: &AuthUser) -> Result<(), ApiError> {
-    if !user.is_approved {
-        return Err((StatusCode::FORBIDDEN, "Not authorized"));
-    }
-    Ok(())
-}
-
 async fn list_pending_users(
-    user: AuthUser,
+    _user: Authorized<perm::ManageUsers>,
     State(state): State<AppState>,
 ) -> Result<Json<Vec<PendingUser>>, ApiError> {
-    require_approved(&user)?;
-
     let rows: Vec<(Uuid, String, bool, chrono::DateTime<chrono::Utc>)> = sqlx::query_as(
         "SELECT id, email, email_confirmed, created_at FROM users WHERE is_approved = FALSE ORDER BY created_at ASC",
     )
@@ -377,12 +283,10 @@ async fn list_pending_users(
 }
 
 async fn approve_user(
-    user: AuthUser,
+    _user: Authorized<perm::ManageUsers>,
     State(state): State<AppState>,
     Path(id): Path<Uuid>,
 ) -> Result<StatusCode, ApiError> {
-    require_approved(&user)?;
-
     let email: Option<String> = sqlx::query_scalar(
         "UPDATE users SET is_approved = TRUE WHERE id = $1 AND is_approved = FALSE RETURNING email",
     )
@@ -403,26 +307,319 @@ async fn approve_user(
     Ok(StatusCode::NO_CONTENT)
 }
 
+#[derive(Deserialize)]
+struct DeleteUserParams {
+    /// Move the user's audit trail to this user instead of anonymizing it.
+    reassign_to: Option<Uuid>,
+}
+
+/// Audit tables whose rows name the acting user. Their foreign keys are
+/// `ON DELETE SET NULL`, so a deleted user's history stays but is anonymized
+/// unless it is reassigned first.
+const AUDIT_TABLES: [(&str, &str); 8] = [
+    ("lock_commands", "user_id"),
+    ("lock_state_log", "user_id"),
+    ("access_denials", "user_id"),
+    ("invitations", "invited_by"),
+    ("access_cards", "created_by"),
+    ("push_action_uses", "user_id"),
+    ("outgoing_webhooks", "created_by"),
+    ("notification_templates", "updated_by"),
+];
+
 async fn delete_user(
-    user: AuthUser,
+    user: Authorized<perm::ManageUsers>,
     State(state): State<AppState>,
     Path(id): Path<Uuid>,
+    Query(params): Query<DeleteUserParams>,
 ) -> Result<StatusCode, ApiError> {
-    require_approved(&user)?;
+    if id == user.id {
+        return Err((StatusCode::CONFLICT, "You cannot delete your own account"));
+    }
+    if params.reassign_to == Some(id) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "Cannot reassign to the deleted user",
+        ));
+    }
 
-    // Sessions are cleaned up via ON DELETE CASCADE.
-    let result = sqlx::query("DELETE FROM users WHERE id = $1 AND is_approved = FALSE")
+    let db_err = |e: sqlx::Error| {
+        error!("Failed to delete user: {e}");
+        (StatusCode::INTERNAL_SERVER_ERROR, "Failed to delete user")
+    };
+
+    let mut tx = state.db.begin().await.map_err(db_err)?;
+    lock_users(&mut tx).await.map_err(db_err)?;
+
+    let email = ensure_not_last_admin(&mut tx, id).await?;
+
+    if let Some(target) = params.reassign_to {
+        let exists: bool = sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM users WHERE id = $1)")
+            .bind(target)
+            .fetch_one(&mut *tx)
+            .await
+            .map_err(db_err)?;
+        if !exists {
+            return Err((StatusCode::BAD_REQUEST, "Reassignment target not found"));
+        }
+        for (table, column) in AUDIT_TABLES {
+            sqlx::query(&format!(
+                "UPDATE {table} SET {column} = $2 WHERE {column} = $1"
+            ))
+            .bind(id)
+            .bind(target)
+            .execute(&mut *tx)
+            .await
+            .map_err(db_err)?;
+        }
+    }
+
+    // Sessions, tokens, grants and second factors go via ON DELETE CASCADE.
+    sqlx::query("DELETE FROM users WHERE id = $1")
         .bind(id)
-        .execute(&state.db)
+        .execute(&mut *tx)
         .await
-        .map_err(|e| {
-            error!("Failed to delete user: {e}");
-            (StatusCode::INTERNAL_SERVER_ERROR, "Failed to delete user")
-        })?;
+        .map_err(db_err)?;
+
+    tx.commit().await.map_err(db_err)?;
+
+    info!(
+        by = %user.email,
+        email = %email,
+        reassigned_to = ?params.reassign_to,
+        "User deleted"
+    );
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// ── Admin: all users ─────────────────────────────────────────────────
 
-    if result.rows_affected() == 0 {
-        return Err((StatusCode::NOT_FOUND, "User not found or already approved"));
+#[derive(Serialize)]
+struct UserSummary {
+    id: Uuid,
+    email: String,
+    role: String,
+    email_confirmed: bool,
+    is_approved: bool,
+    suspended_at: Option<String>,
+    last_login_at: Option<String>,
+    created_at: String,
+}
+
+type UserRow = (
+    Uuid,
+    String,
+    String,
+    bool,
+    bool,
+    Option<chrono::DateTime<chrono::Utc>>,
+    Option<chrono::DateTime<chrono::Utc>>,
+    chrono::DateTime<chrono::Utc>,
+);
+
+async fn list_users(
+    _user: Authorized<perm::ManageUsers>,
+    State(state): State<AppState>,
+) -> Result<Json<Vec<UserSummary>>, ApiError> {
+    let rows: Vec<UserRow> = sqlx::query_as(
+        "SELECT id, email, role, email_confirmed, is_approved, suspended_at, last_login_at, created_at \
+         FROM users ORDER BY email",
+    )
+    .fetch_all(&state.db)
+    .await
+    .map_err(|e| {
+        error!("Failed to fetch users: {e}");
+        (StatusCode::INTERNAL_SERVER_ERROR, "Failed to fetch users")
+    })?;
+
+    let users = rows
+        .into_iter()
+        .map(
+            |(
+                id,
+                email,
+                role,
+                email_confirmed,
+                is_approved,
+                suspended_at,
+                last_login_at,
+                created_at,
+            )| {
+                UserSummary {
+                    id,
+                    email,
+                    role,
+                    email_confirmed,
+                    is_approved,
+                    suspended_at: suspended_at.map(|t| t.to_rfc3339()),
+                    last_login_at: last_login_at.map(|t| t.to_rfc3339()),
+                    created_at: created_at.to_rfc3339(),
+                }
+            },
+        )
+        .collect();
+
+    Ok(Json(users))
+}
+
+/// Suspend a user: they can't sign in, their sessions and push
+/// subscriptions are removed and their API tokens stop working until they
+/// are unsuspended.
+async fn suspend_user(
+    user: Authorized<perm::ManageUsers>,
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Result<StatusCode, ApiError> {
+    if id == user.id {
+        return Err((StatusCode::CONFLICT, "You cannot suspend your own account"));
+    }
+
+    let db_err = |e: sqlx::Error| {
+        error!("Failed to suspend user: {e}");
+        (StatusCode::INTERNAL_SERVER_ERROR, "Failed to suspend user")
+    };
+
+    let mut tx = state.db.begin().await.map_err(db_err)?;
+    lock_users(&mut tx).await.map_err(db_err)?;
+
+    let email = ensure_not_last_admin(&mut tx, id).await?;
+
+    sqlx::query(
+        "UPDATE users SET suspended_at = now(), updated_at = now() \
+         WHERE id = $1 AND suspended_at IS NULL",
+    )
+    .bind(id)
+    .execute(&mut *tx)
+    .await
+    .map_err(db_err)?;
+    for table in ["sessions", "push_subscriptions"] {
+        sqlx::query(&format!("DELETE FROM {table} WHERE user_id = $1"))
+            .bind(id)
+            .execute(&mut *tx)
+            .await
+            .map_err(db_err)?;
     }
 
+    tx.commit().await.map_err(db_err)?;
+
+    info!(by = %user.email, email = %email, "User suspended");
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn unsuspend_user(
+    user: Authorized<perm::ManageUsers>,
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Result<StatusCode, ApiError> {
+    let email: Option<String> = sqlx::query_scalar(
+        "UPDATE users SET suspended_at = NULL, updated_at = now() \
+         WHERE id = $1 AND suspended_at IS NOT NULL RETURNING email",
+    )
+    .bind(id)
+    .fetch_optional(&state.db)
+    .await
+    .map_err(|e| {
+        error!("Failed to unsuspend user: {e}");
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Failed to unsuspend user",
+        )
+    })?;
+
+    let email = email.ok_or((StatusCode::NOT_FOUND, "User not found or not suspended"))?;
+    info!(by = %user.email, email = %email, "User unsuspended");
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Serialize changes that could leave the system without an active admin.
+async fn lock_users(tx: &mut sqlx::PgConnection) -> Result<(), sqlx::Error> {
+    sqlx::query("LOCK TABLE users IN SHARE ROW EXCLUSIVE MODE")
+        .execute(tx)
+        .await
+        .map(|_| ())
+}
+
+/// Refuse to remove the last active admin. Returns the user's email.
+async fn ensure_not_last_admin(tx: &mut sqlx::PgConnection, id: Uuid) -> Result<String, ApiError> {
+    let db_err = |e: sqlx::Error| {
+        error!("Failed to check admins: {e}");
+        (StatusCode::INTERNAL_SERVER_ERROR, "Database error")
+    };
+
+    let row: Option<(String, String)> =
+        sqlx::query_as("SELECT email, role FROM users WHERE id = $1")
+            .bind(id)
+            .fetch_optional(&mut *tx)
+            .await
+            .map_err(db_err)?;
+    let (email, role) = row.ok_or((StatusCode::NOT_FOUND, "User not found"))?;
+
+    if role == Role::Admin.as_str() {
+        let admins: i64 = sqlx::query_scalar(
+            "SELECT COUNT(*) FROM users \
+             WHERE role = 'admin' AND is_approved = TRUE AND suspended_at IS NULL AND id <> $1",
+        )
+        .bind(id)
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(db_err)?;
+        if admins == 0 {
+            return Err((StatusCode::CONFLICT, "Cannot remove the last admin"));
+        }
+    }
+
+    Ok(email)
+}
+
+// ── Admin: roles ─────────────────────────────────────────────────────
+
+#[derive(Deserialize)]
+struct SetRoleRequest {
+    role: Role,
+}
+
+async fn set_user_role(
+    user: Authorized<perm::ManageUsers>,
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    Json(body): Json<SetRoleRequest>,
+) -> Result<StatusCode, ApiError> {
+    let db_err = |e: sqlx::Error| {
+        error!("Failed to update user role: {e}");
+        (StatusCode::INTERNAL_SERVER_ERROR, "Failed to update role")
+    };
+
+    let mut tx = state.db.begin().await.map_err(db_err)?;
+
+    // Serialize role changes so two admins can't demote each other at once.
+    lock_users(&mut tx).await.map_err(db_err)?;
+
+    if body.role != Role::Admin {
+        ensure_not_last_admin(&mut tx, id).await?;
+    } else {
+        let exists: bool = sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM users WHERE id = $1)")
+            .bind(id)
+            .fetch_one(&mut *tx)
+            .await
+            .map_err(db_err)?;
+        if !exists {
+            return Err((StatusCode::NOT_FOUND, "User not found"));
+        }
+    }
+
+    sqlx::query("UPDATE users SET role = $1, updated_at = now() WHERE id = $2")
+        .bind(body.role.as_str())
+        .bind(id)
+        .execute(&mut *tx)
+        .await
+        .map_err(db_err)?;
+
+    tx.commit().await.map_err(db_err)?;
+
+    info!(by = %user.email, user_id = %id, role = body.role.as_str(), "User role changed");
+
     Ok(StatusCode::NO_CONTENT)
 }
@@ -0,0 +1,239 @@
+//! Personal API tokens for scripts and automations.
+//!
+//! A token is sent as `Authorization: Bearer pan_…` and authenticates as its
+//! owner, limited to the token's scopes (see `permissions::Scope`). Only a
+//! SHA-256 hash is stored; the token itself is shown once, at creation.
+//! Managing tokens needs a browser session, so a token can't mint more.
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::{delete, get},
+    Json, Router,
+};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use tracing::{error, info};
+use uuid::Uuid;
+
+use crate::email_auth::{generate_token, json_error};
+use crate::middleware::{AuthUser, SessionUser};
+use crate::permissions::{Role, Scope};
+use crate::AppState;
+
+const TOKEN_PREFIX: &str = "pan_";
+/// Characters of the token kept in clear so users can tell tokens apart.
+const DISPLAY_PREFIX_LEN: usize = 12;
+const MAX_EXPIRY_DAYS: i64 = 3650;
+
+fn hash_token(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+type TokenUserRow = (Uuid, Uuid, String, bool, bool, String, Vec<String>);
+
+/// Resolve a bearer token to its owner, recording when it was last used.
+pub(crate) async fn get_user_by_token(pool: &PgPool, token: &str) -> Option<AuthUser> {
+    if !token.starts_with(TOKEN_PREFIX) {
+        return None;
+    }
+
+    let row: Option<TokenUserRow> = sqlx::query_as(
+        "SELECT t.id, u.id, u.email, u.email_confirmed, u.is_approved, u.role, t.scopes \
+         FROM api_tokens t JOIN users u ON u.id = t.user_id \
+         WHERE t.token_hash = $1 AND (t.expires_at IS NULL OR t.expires_at > now()) \
+           AND u.suspended_at IS NULL",
+    )
+    .bind(hash_token(token))
+    .fetch_optional(pool)
+    .await
+    .ok()?;
+
+    let (token_id, id, email, email_confirmed, is_approved, role, scopes) = row?;
+
+    // Coarse granularity keeps this from writing on every request.
+    if let Err(e) = sqlx::query(
+        "UPDATE api_tokens SET last_used_at = now() WHERE id = $1 \
+         AND (last_used_at IS NULL OR last_used_at < now() - interval '1 minute')",
+    )
+    .bind(token_id)
+    .execute(pool)
+    .await
+    {
+        error!("Failed to update API token last use: {e}");
+    }
+
+    Some(AuthUser {
+        id,
+        email,
+        email_confirmed,
+        is_approved,
+        role: Role::parse(&role).unwrap_or(Role::Viewer),
+        token_scopes: Some(scopes.iter().filter_map(|s| Scope::parse(s)).collect()),
+        second_factor: None,
+    })
+}
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/tokens", get(list_tokens).post(create_token))
+        .route("/tokens/{id}", delete(revoke_token))
+}
+
+#[derive(Serialize)]
+struct TokenInfo {
+    id: Uuid,
+    name: String,
+    prefix: String,
+    scopes: Vec<String>,
+    expires_at: Option<String>,
+    last_used_at: Option<String>,
+    created_at: String,
+}
+
+type TokenRow = (
+    Uuid,
+    String,
+    String,
+    Vec<String>,
+    Option<DateTime<Utc>>,
+    Option<DateTime<Utc>>,
+    DateTime<Utc>,
+);
+
+async fn list_tokens(user: SessionUser, State(state): State<AppState>) -> Response {
+    let rows: Vec<TokenRow> = match sqlx::query_as(
+        "SELECT id, name, prefix, scopes, expires_at, last_used_at, created_at \
+         FROM api_tokens WHERE user_id = $1 ORDER BY created_at DESC",
+    )
+    .bind(user.id)
+    .fetch_all(&state.db)
+    .await
+    {
+        Ok(rows) => rows,
+        Err(e) => {
+            error!("Failed to list API tokens: {e}");
+            return json_error(StatusCode::INTERNAL_SERVER_ERROR, "Database error");
+        }
+    };
+
+    let tokens: Vec<TokenInfo> = rows
+        .into_iter()
+        .map(
+            |(id, name, prefix, scopes, expires_at, last_used_at, created_at)| TokenInfo {
+                id,
+                name,
+                prefix,
+                scopes,
+                expires_at: expires_at.map(|t| t.to_rfc3339()),
+                last_used_at: last_used_at.map(|t| t.to_rfc3339()),
+                created_at: created_at.to_rfc3339(),
+            },
+        )
+        .collect();
+
+    Json(tokens).into_response()
+}
+
+#[derive(Deserialize)]
+struct CreateTokenRequest {
+    name: String,
+    scopes: Vec<String>,
+    /// Omit for a token that never expires.
+    expires_in_days: Option<i64>,
+}
+
+async fn create_token(
+    user: SessionUser,
+    State(state): State<AppState>,
+    Json(body): Json<CreateTokenRequest>,
+) -> Response {
+    let name: String = body.name.trim().chars().take(64).collect();
+    if name.is_empty() {
+        return json_error(StatusCode::BAD_REQUEST, "Name must not be empty");
+    }
+
+    let mut scopes = Vec::new();
+    for s in &body.scopes {
+        match Scope::parse(s) {
+            Some(scope) if !scopes.contains(&scope) => scopes.push(scope),
+            Some(_) => {}
+            None => return json_error(StatusCode::BAD_REQUEST, "Unknown scope"),
+        }
+    }
+    if scopes.is_empty() {
+        return json_error(StatusCode::BAD_REQUEST, "At least one scope is required");
+    }
+
+    let expires_at = match body.expires_in_days {
+        Some(days) if !(1..=MAX_EXPIRY_DAYS).contains(&days) => {
+            return json_error(StatusCode::BAD_REQUEST, "Invalid expiry");
+        }
+        Some(days) => Some(Utc::now() + Duration::days(days)),
+        None => None,
+    };
+
+    let token = format!("{TOKEN_PREFIX}{}", generate_token());
+    let prefix = token[..DISPLAY_PREFIX_LEN].to_string();
+    let scope_names: Vec<&str> = scopes.iter().map(|s| s.as_str()).collect();
+
+    let id: Uuid = match sqlx::query_scalar(
+        "INSERT INTO api_tokens (user_id, name, token_hash, prefix, scopes, expires_at) \
+         VALUES ($1, $2, $3, $4, $5, $6) RETURNING id",
+    )
+    .bind(user.id)
+    .bind(&name)
+    .bind(hash_token(&token))
+    .bind(&prefix)
+    .bind(&scope_names)
+    .bind(expires_at)
+    .fetch_one(&state.db)
+    .await
+    {
+        Ok(id) => id,
+        Err(e) => {
+            error!("Failed to create API token: {e}");
+            return json_error(StatusCode::INTERNAL_SERVER_ERROR, "Database error");
+        }
+    };
+
+    info!(email = %user.email, name, scopes = ?scope_names, "API token created");
+
+    (
+        StatusCode::CREATED,
+        Json(serde_json::json!({
+            "id": id,
+            "name": name,
+            "token": token,
+            "scopes": scope_names,
+            "expires_at": expires_at.map(|t| t.to_rfc3339()),
+        })),
+    )
+        .into_response()
+}
+
+async fn revoke_token(
+    user: SessionUser,
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Response {
+    match sqlx::query("DELETE FROM api_tokens WHERE id = $1 AND user_id = $2")
+        .bind(id)
+        .bind(user.id)
+        .execute(&state.db)
+        .await
+    {
+        Ok(r) if r.rows_affected() == 0 => json_error(StatusCode::NOT_FOUND, "Token not found"),
+        Ok(_) => {
+            info!(email = %user.email, token = %id, "API token revoked");
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Err(e) => {
+            error!("Failed to revoke API token: {e}");
+            json_error(StatusCode::INTERNAL_SERVER_ERROR, "Database error")
+        }
+    }
+}
@@ -0,0 +1,1009 @@
+//! Lock command tracking.
+//!
+//! Every lock/unlock sent to U-Tec — from the REST API, MQTT, RFID grants or
+//! auto-relock — creates a row in `lock_commands` that moves from `pending`
+//! to `confirmed`, `failed` or `timed_out` once the lock reports its state
+//! (immediately, or after the `st.deferredResponse` wait). Each transition is
+//! broadcast as `WsEvent::CommandStatus`.
+//!
+//! API clients may pass an `Idempotency-Key` header: a retried request with
+//! the same key returns the original command instead of sending another one.
+//!
+//! Commands for one device are serialized through a [`CommandQueue`], and
+//! redundant ones are coalesced, so concurrent callers can't interleave their
+//! commands or deferred polls.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    routing::get,
+    Json, Router,
+};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::PgPool;
+use tokio::sync::{mpsc, oneshot};
+use tracing::{debug, error, info, warn};
+use uuid::Uuid;
+
+use crate::lock_log;
+use crate::middleware::Authorized;
+use crate::permissions::perm;
+use crate::utec::{Device, UTec};
+use crate::ws::WsEvent;
+use crate::AppState;
+
+type ApiError = (StatusCode, &'static str);
+
+/// Maximum seconds we'll wait for a deferred lock response before giving up.
+const MAX_DEFERRED_WAIT_SECS: u64 = 60;
+
+/// Maximum length accepted for a client-supplied idempotency key.
+pub const MAX_IDEMPOTENCY_KEY_LEN: usize = 128;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LockAction {
+    Lock,
+    Unlock,
+}
+
+impl LockAction {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Lock => "lock",
+            Self::Unlock => "unlock",
+        }
+    }
+
+    /// The lock state that confirms this command.
+    pub fn expected_state(self) -> &'static str {
+        match self {
+            Self::Lock => "locked",
+            Self::Unlock => "unlocked",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CommandStatus {
+    Pending,
+    Confirmed,
+    Failed,
+    TimedOut,
+}
+
+impl CommandStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Pending => "pending",
+            Self::Confirmed => "confirmed",
+            Self::Failed => "failed",
+            Self::TimedOut => "timed_out",
+        }
+    }
+}
+
+/// A lock command as stored in `lock_commands`.
+#[derive(Clone, Debug, Serialize)]
+pub struct CommandRecord {
+    pub id: Uuid,
+    pub device_id: String,
+    pub command: String,
+    pub status: String,
+    pub source: String,
+    pub user_id: Option<Uuid>,
+    pub lock_state: Option<String>,
+    pub error: Option<String>,
+    /// The command this one was merged into, if it was coalesced.
+    pub coalesced_into: Option<Uuid>,
+    pub created_at: String,
+    pub completed_at: Option<String>,
+}
+
+type CommandRow = (
+    Uuid,
+    String,
+    String,
+    String,
+    String,
+    Option<Uuid>,
+    Option<String>,
+    Option<String>,
+    Option<Uuid>,
+    DateTime<Utc>,
+    Option<DateTime<Utc>>,
+);
+
+const COMMAND_COLUMNS: &str = "id, device_id, command, status, source, user_id, lock_state, \
+     error, coalesced_into, created_at, completed_at";
+
+impl CommandRecord {
+    fn from_row(row: CommandRow) -> Self {
+        let (
+            id,
+            device_id,
+            command,
+            status,
+            source,
+            user_id,
+            lock_state,
+            error,
+            coalesced_into,
+            created_at,
+            completed_at,
+        ) = row;
+        Self {
+            id,
+            device_id,
+            command,
+            status,
+            source,
+            user_id,
+            lock_state,
+            error,
+            coalesced_into,
+            created_at: created_at.to_rfc3339(),
+            completed_at: completed_at.map(|t| t.to_rfc3339()),
+        }
+    }
+}
+
+/// Why a command could not be executed.
+#[derive(Debug)]
+pub enum CommandError {
+    /// The idempotency key was already used for a different device/command.
+    KeyReused,
+    Database(sqlx::Error),
+}
+
+impl std::fmt::Display for CommandError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::KeyReused => write!(f, "idempotency key reused for a different command"),
+            Self::Database(e) => write!(f, "database error: {e}"),
+        }
+    }
+}
+
+impl From<sqlx::Error> for CommandError {
+    fn from(e: sqlx::Error) -> Self {
+        Self::Database(e)
+    }
+}
+
+// ── API ─────────────────────────────────────────────────────────────────────
+
+pub fn router() -> Router<AppState> {
+    Router::new().route("/commands/{id}", get(get_command))
+}
+
+async fn get_command(
+    _user: Authorized<perm::ViewDevices>,
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<CommandRecord>, ApiError> {
+    let record = fetch(&state.db, id)
+        .await
+        .map_err(|e| {
+            error!("Failed to fetch command: {e:#}");
+            (StatusCode::INTERNAL_SERVER_ERROR, "Database error")
+        })?
+        .ok_or((StatusCode::NOT_FOUND, "Command not found"))?;
+
+    Ok(Json(record))
+}
+
+// ── Persistence ─────────────────────────────────────────────────────────────
+
+pub async fn fetch(db: &PgPool, id: Uuid) -> Result<Option<CommandRecord>, sqlx::Error> {
+    let row: Option<CommandRow> = sqlx::query_as(&format!(
+        "SELECT {COMMAND_COLUMNS} FROM lock_commands WHERE id = $1"
+    ))
+    .bind(id)
+    .fetch_optional(db)
+    .await?;
+    Ok(row.map(CommandRecord::from_row))
+}
+
+async fn fetch_by_key(
+    db: &PgPool,
+    user_id: Option<Uuid>,
+    key: &str,
+) -> Result<Option<CommandRecord>, sqlx::Error> {
+    let row: Option<CommandRow> = sqlx::query_as(&format!(
+        "SELECT {COMMAND_COLUMNS} FROM lock_commands \
+         WHERE user_id IS NOT DISTINCT FROM $1 AND idempotency_key = $2"
+    ))
+    .bind(user_id)
+    .bind(key)
+    .fetch_optional(db)
+    .await?;
+    Ok(row.map(CommandRecord::from_row))
+}
+
+/// Create a pending command. Returns `None` if the idempotency key was taken
+/// by a concurrent request.
+async fn create(
+    db: &PgPool,
+    device_id: &str,
+    action: LockAction,
+    source: &str,
+    user_id: Option<Uuid>,
+    idempotency_key: Option<&str>,
+    tag_id: Option<&str>,
+) -> Result<Option<CommandRecord>, sqlx::Error> {
+    let row: Option<CommandRow> = sqlx::query_as(&format!(
+        "INSERT INTO lock_commands (device_id, command, source, user_id, idempotency_key, tag_id) \
+         VALUES ($1, $2, $3, $4, $5, $6) ON CONFLICT DO NOTHING RETURNING {COMMAND_COLUMNS}"
+    ))
+    .bind(device_id)
+    .bind(action.as_str())
+    .bind(source)
+    .bind(user_id)
+    .bind(idempotency_key)
+    .bind(tag_id)
+    .fetch_optional(db)
+    .await?;
+    Ok(row.map(CommandRecord::from_row))
+}
+
+/// Move a pending command to a final status and broadcast the change.
+async fn complete(
+    state: &AppState,
+    id: Uuid,
+    status: CommandStatus,
+    lock_state: Option<&str>,
+    error: Option<&str>,
+) -> Option<CommandRecord> {
+    update_status(state, id, status.as_str(), lock_state, error, None).await
+}
+
+/// Complete a coalesced command with the outcome of the command it was
+/// merged into.
+async fn complete_coalesced(
+    state: &AppState,
+    id: Uuid,
+    into: &CommandRecord,
+) -> Option<CommandRecord> {
+    if into.status == CommandStatus::Pending.as_str() {
+        return None;
+    }
+    update_status(
+        state,
+        id,
+        &into.status,
+        into.lock_state.as_deref(),
+        into.error.as_deref(),
+        Some(into.id),
+    )
+    .await
+}
+
+async fn update_status(
+    state: &AppState,
+    id: Uuid,
+    status: &str,
+    lock_state: Option<&str>,
+    error: Option<&str>,
+    coalesced_into: Option<Uuid>,
+) -> Option<CommandRecord> {
+    let row: Result<Option<CommandRow>, _> = sqlx::query_as(&format!(
+        "UPDATE lock_commands SET status = $2, lock_state = $3, error = $4, coalesced_into = $5, \
+         completed_at = now() WHERE id = $1 AND status = $6 RETURNING {COMMAND_COLUMNS}"
+    ))
+    .bind(id)
+    .bind(status)
+    .bind(lock_state)
+    .bind(error)
+    .bind(coalesced_into)
+    .bind(CommandStatus::Pending.as_str())
+    .fetch_optional(&state.db)
+    .await;
+
+    match row {
+        Ok(Some(row)) => {
+            let record = CommandRecord::from_row(row);
+            broadcast(state, &record);
+            Some(record)
+        }
+        Ok(None) => None, // already completed
+        Err(e) => {
+            error!(command_id = %id, "Failed to update command status: {e:#}");
+            None
+        }
+    }
+}
+
+fn broadcast(state: &AppState, record: &CommandRecord) {
+    let _ = state.events.send(WsEvent::CommandStatus {
+        id: record.id,
+        device_id: record.device_id.clone(),
+        command: record.command.clone(),
+        status: record.status.clone(),
+        lock_state: record.lock_state.clone(),
+        error: record.error.clone(),
+    });
+}
+
+/// Mark commands left pending by a previous run as timed out.
+pub async fn expire_stale(db: &PgPool) {
+    match sqlx::query(
+        "UPDATE lock_commands SET status = $1, error = 'Server restarted', \
+         completed_at = now() WHERE status = $2",
+    )
+    .bind(CommandStatus::TimedOut.as_str())
+    .bind(CommandStatus::Pending.as_str())
+    .execute(db)
+    .await
+    {
+        Ok(r) if r.rows_affected() > 0 => {
+            warn!(
+                count = r.rows_affected(),
+                "Expired lock commands left pending by a previous run"
+            );
+        }
+        Ok(_) => {}
+        Err(e) => error!("Failed to expire stale lock commands: {e:#}"),
+    }
+}
+
+// ── Execution ───────────────────────────────────────────────────────────────
+
+/// Send a lock/unlock command to a device and track it.
+///
+/// If `idempotency_key` matches an earlier command from the same user, that
+/// command is returned and nothing is sent. Otherwise a pending record is
+/// created and handed to the device's [`CommandQueue`]. This returns once the
+/// command has been sent; if the lock defers its answer, the returned record
+/// is still `pending` and is completed in the background.
+pub async fn execute(
+    state: &AppState,
+    client: &UTec,
+    device: &Device,
+    action: LockAction,
+    source: &str,
+    user_id: Option<Uuid>,
+    idempotency_key: Option<&str>,
+) -> Result<CommandRecord, CommandError> {
+    if let Some(key) = idempotency_key {
+        if let Some(existing) = fetch_by_key(&state.db, user_id, key).await? {
+            if existing.device_id != device.id || existing.command != action.as_str() {
+                return Err(CommandError::KeyReused);
+            }
+            debug!(command_id = %existing.id, "Idempotent replay of lock command");
+            return Ok(existing);
+        }
+    }
+
+    let record = match create(
+        &state.db,
+        &device.id,
+        action,
+        source,
+        user_id,
+        idempotency_key,
+        None,
+    )
+    .await?
+    {
+        Some(r) => r,
+        // Lost a race with a concurrent request using the same key.
+        None => {
+            let key = idempotency_key.unwrap_or_default();
+            return fetch_by_key(&state.db, user_id, key)
+                .await?
+                .ok_or(CommandError::KeyReused);
+        }
+    };
+    broadcast(state, &record);
+
+    Ok(state
+        .command_queue
+        .submit(state, client, device, action, record)
+        .await)
+}
+
+/// Send an unlock for a granted card scan and track it, recording the card
+/// on the command so notifications can say whose card opened the door.
+pub async fn execute_for_card(
+    state: &AppState,
+    client: &UTec,
+    device: &Device,
+    tag_id: &str,
+) -> Result<CommandRecord, CommandError> {
+    let action = LockAction::Unlock;
+    let record = create(
+        &state.db,
+        &device.id,
+        action,
+        "rfid",
+        None,
+        None,
+        Some(tag_id),
+    )
+    .await?
+    .ok_or(CommandError::Database(sqlx::Error::RowNotFound))?;
+    broadcast(state, &record);
+
+    Ok(state
+        .command_queue
+        .submit(state, client, device, action, record)
+        .await)
+}
+
+/// What happened after a command was sent to the lock.
+enum Dispatch {
+    /// The command completed (or failed) immediately.
+    Done(Box<CommandRecord>),
+    /// The lock will report its state after this many seconds.
+    Deferred(u64),
+}
+
+/// Send a queued command to the lock and handle the immediate response.
+async fn dispatch(state: &AppState, job: &Job) -> Dispatch {
+    let device_id = job.device.id.as_str();
+    let record = &job.lead;
+
+    let result = match job.action {
+        LockAction::Lock => job.client.lock(&job.device).await,
+        LockAction::Unlock => job.client.unlock(&job.device).await,
+    };
+
+    let results = match result {
+        Ok(r) => r,
+        Err(e) => {
+            error!(
+                device_id,
+                command = job.action.as_str(),
+                "Lock command failed: {e:#}"
+            );
+            let msg = format!("{e:#}");
+            return Dispatch::Done(Box::new(
+                complete(state, record.id, CommandStatus::Failed, None, Some(&msg))
+                    .await
+                    .unwrap_or_else(|| record.clone()),
+            ));
+        }
+    };
+
+    let device_result = results.iter().find(|s| s.id == device_id);
+
+    if let Some(ls) = device_result.and_then(|s| s.lock_state()) {
+        record_lock_state(state, record, &ls, &record.source).await;
+        Dispatch::Done(Box::new(
+            resolve(state, record, job.action, &ls)
+                .await
+                .unwrap_or_else(|| record.clone()),
+        ))
+    } else if let Some(seconds) = device_result
+        .and_then(|s| s.get_state("st.deferredResponse", "seconds"))
+        .and_then(|s| s.value.as_u64())
+    {
+        if seconds > MAX_DEFERRED_WAIT_SECS {
+            warn!(
+                device_id,
+                seconds, "Deferred wait exceeds maximum, capping at {MAX_DEFERRED_WAIT_SECS}s"
+            );
+            Dispatch::Deferred(MAX_DEFERRED_WAIT_SECS)
+        } else {
+            Dispatch::Deferred(seconds)
+        }
+    } else {
+        warn!(
+            device_id,
+            "Lock command response contained neither lock state nor deferred response"
+        );
+        Dispatch::Done(Box::new(
+            complete(
+                state,
+                record.id,
+                CommandStatus::TimedOut,
+                None,
+                Some("No lock state in command response"),
+            )
+            .await
+            .unwrap_or_else(|| record.clone()),
+        ))
+    }
+}
+
+/// Log and broadcast a lock state reported in response to `record`.
+async fn record_lock_state(
+    state: &AppState,
+    record: &CommandRecord,
+    lock_state: &str,
+    source: &str,
+) {
+    let log_id = lock_log::record(
+        &state.db,
+        &record.device_id,
+        lock_state,
+        source,
+        record.user_id,
+        Some(record.id),
+    )
+    .await;
+    let _ = state.events.send(WsEvent::LockState {
+        device_id: record.device_id.clone(),
+        lock_state: lock_state.to_string(),
+        log_id,
+    });
+}
+
+/// Complete a command from a lock state report: `confirmed` if the lock
+/// reached the expected state, `failed` otherwise.
+async fn resolve(
+    state: &AppState,
+    record: &CommandRecord,
+    action: LockAction,
+    lock_state: &str,
+) -> Option<CommandRecord> {
+    if lock_state == action.expected_state() {
+        complete(
+            state,
+            record.id,
+            CommandStatus::Confirmed,
+            Some(lock_state),
+            None,
+        )
+        .await
+    } else {
+        let msg = format!("Lock reported {lock_state}");
+        complete(
+            state,
+            record.id,
+            CommandStatus::Failed,
+            Some(lock_state),
+            Some(&msg),
+        )
+        .await
+    }
+}
+
+/// Wait out a deferred response, then query the device and complete the
+/// command from its lock state. Runs on the device's queue worker, so no
+/// other command can reach the lock before the state is read.
+async fn poll_deferred(state: &AppState, job: &Job, seconds: u64) -> CommandRecord {
+    let device_id = job.device.id.as_str();
+    let record = &job.lead;
+    let deferred_source = format!("{}_deferred", record.source);
+
+    debug!(device_id, seconds, "Waiting for deferred lock response");
+    tokio::time::sleep(Duration::from_secs(seconds)).await;
+
+    let (status, error) = match job.client.query_device(&job.device).await {
+        Ok(device_states) => match device_states.lock_state() {
+            Some(ls) => {
+                debug!(device_id, lock_state = %ls, "Deferred lock state resolved");
+                record_lock_state(state, record, &ls, &deferred_source).await;
+                let done = resolve(state, record, job.action, &ls).await;
+                if let Some(ref done) = done {
+                    info!(device_id, command_id = %done.id, status = %done.status, "Lock command completed");
+                }
+                return done.unwrap_or_else(|| record.clone());
+            }
+            None => {
+                warn!(device_id, "Deferred query returned no lock state");
+                (
+                    CommandStatus::TimedOut,
+                    "Deferred query returned no lock state".to_string(),
+                )
+            }
+        },
+        Err(e) => {
+            error!(
+                device_id,
+                "Failed to query device after deferred wait: {e:#}"
+            );
+            (CommandStatus::TimedOut, format!("{e:#}"))
+        }
+    };
+
+    complete(state, record.id, status, None, Some(&error))
+        .await
+        .unwrap_or_else(|| record.clone())
+}
+
+// ── Queue ───────────────────────────────────────────────────────────────────
+
+/// Per-device command queues.
+///
+/// Each device gets a worker task that runs its commands one at a time,
+/// including any deferred wait, so the state read after a command can only
+/// have been caused by that command. A command submitted while the last
+/// queued (or running) command for the device is the same action is
+/// coalesced into it: nothing extra is sent, and the new record is completed
+/// with the same outcome and `coalesced_into` pointing at the command that
+/// actually ran.
+#[derive(Clone, Default)]
+pub struct CommandQueue {
+    devices: Arc<Mutex<HashMap<String, DeviceQueue>>>,
+}
+
+struct DeviceQueue {
+    tx: mpsc::UnboundedSender<Arc<Job>>,
+    /// The most recently submitted job, until it finishes.
+    tail: Option<Arc<Job>>,
+}
+
+struct Job {
+    client: UTec,
+    device: Device,
+    action: LockAction,
+    /// The command that is actually sent to the lock.
+    lead: CommandRecord,
+    inner: Mutex<JobInner>,
+}
+
+#[derive(Default)]
+struct JobInner {
+    /// Commands coalesced into this one.
+    followers: Vec<CommandRecord>,
+    /// Callers waiting for the command to be sent, with their own command id.
+    waiters: Vec<(Uuid, oneshot::Sender<CommandRecord>)>,
+    /// The command was sent and is waiting out a deferred response.
+    dispatched: bool,
+    finished: bool,
+}
+
+/// How a submitted command joined the queue.
+enum Joined {
+    Wait(oneshot::Receiver<CommandRecord>),
+    /// Coalesced into a command that was already sent.
+    Dispatched,
+}
+
+/// How the queue sends commands and records their outcomes. `AppState` talks
+/// to U-Tec and the database; tests use a stand-in.
+trait Runner: Clone + Send + Sync + 'static {
+    fn dispatch(&self, job: &Job) -> impl Future<Output = Dispatch> + Send;
+    fn poll_deferred(&self, job: &Job, seconds: u64) -> impl Future<Output = CommandRecord> + Send;
+    fn complete_coalesced(
+        &self,
+        id: Uuid,
+        into: &CommandRecord,
+    ) -> impl Future<Output = Option<CommandRecord>> + Send;
+}
+
+impl Runner for AppState {
+    async fn dispatch(&self, job: &Job) -> Dispatch {
+        dispatch(self, job).await
+    }
+
+    async fn poll_deferred(&self, job: &Job, seconds: u64) -> CommandRecord {
+        poll_deferred(self, job, seconds).await
+    }
+
+    async fn complete_coalesced(&self, id: Uuid, into: &CommandRecord) -> Option<CommandRecord> {
+        complete_coalesced(self, id, into).await
+    }
+}
+
+impl CommandQueue {
+    async fn submit<R: Runner>(
+        &self,
+        runner: &R,
+        client: &UTec,
+        device: &Device,
+        action: LockAction,
+        record: CommandRecord,
+    ) -> CommandRecord {
+        match self.enqueue(runner, client, device, action, &record) {
+            Joined::Wait(rx) => rx.await.unwrap_or(record),
+            Joined::Dispatched => record,
+        }
+    }
+
+    fn enqueue<R: Runner>(
+        &self,
+        runner: &R,
+        client: &UTec,
+        device: &Device,
+        action: LockAction,
+        record: &CommandRecord,
+    ) -> Joined {
+        let (tx, rx) = oneshot::channel();
+        let mut devices = self.devices.lock().unwrap();
+        let queue = devices
+            .entry(device.id.clone())
+            .or_insert_with(|| spawn_worker(self.clone(), runner.clone(), &device.id));
+
+        if let Some(tail) = queue.tail.as_ref().filter(|t| t.action == action) {
+            let mut inner = tail.inner.lock().unwrap();
+            if !inner.finished {
+                debug!(
+                    device_id = %device.id,
+                    command_id = %record.id,
+                    into = %tail.lead.id,
+                    "Coalescing lock command"
+                );
+                inner.followers.push(record.clone());
+                if inner.dispatched {
+                    return Joined::Dispatched;
+                }
+                inner.waiters.push((record.id, tx));
+                return Joined::Wait(rx);
+            }
+        }
+
+        let job = Arc::new(Job {
+            client: client.clone(),
+            device: device.clone(),
+            action,
+            lead: record.clone(),
+            inner: Mutex::new(JobInner {
+                waiters: vec![(record.id, tx)],
+                ..Default::default()
+            }),
+        });
+        queue.tail = Some(job.clone());
+        // The worker never drops its receiver, so this can't fail.
+        let _ = queue.tx.send(job);
+        Joined::Wait(rx)
+    }
+
+    /// Mark a job as sent but still waiting for the lock, releasing its
+    /// callers with their pending records.
+    fn mark_dispatched(&self, job: &Job) {
+        let mut inner = job.inner.lock().unwrap();
+        inner.dispatched = true;
+        let waiters = std::mem::take(&mut inner.waiters);
+        for (id, tx) in waiters {
+            let record = if id == job.lead.id {
+                Some(job.lead.clone())
+            } else {
+                inner.followers.iter().find(|f| f.id == id).cloned()
+            };
+            if let Some(record) = record {
+                let _ = tx.send(record);
+            }
+        }
+    }
+
+    /// Complete a job's coalesced commands with its outcome and release any
+    /// remaining callers.
+    async fn finish<R: Runner>(&self, runner: &R, job: &Arc<Job>, outcome: CommandRecord) {
+        let (followers, waiters) = {
+            let mut devices = self.devices.lock().unwrap();
+            let mut inner = job.inner.lock().unwrap();
+            inner.finished = true;
+            if let Some(queue) = devices.get_mut(&job.device.id) {
+                if queue.tail.as_ref().is_some_and(|t| Arc::ptr_eq(t, job)) {
+                    queue.tail = None;
+                }
+            }
+            (
+                std::mem::take(&mut inner.followers),
+                std::mem::take(&mut inner.waiters),
+            )
+        };
+
+        let mut completed = Vec::with_capacity(followers.len());
+        for follower in followers {
+            let record = runner
+                .complete_coalesced(follower.id, &outcome)
+                .await
+                .unwrap_or(follower);
+            completed.push(record);
+        }
+
+        for (id, tx) in waiters {
+            let record = if id == outcome.id {
+                Some(outcome.clone())
+            } else {
+                completed.iter().find(|r| r.id == id).cloned()
+            };
+            if let Some(record) = record {
+                let _ = tx.send(record);
+            }
+        }
+    }
+}
+
+fn spawn_worker<R: Runner>(queue: CommandQueue, runner: R, device_id: &str) -> DeviceQueue {
+    let (tx, mut rx) = mpsc::unbounded_channel::<Arc<Job>>();
+    let device_id = device_id.to_string();
+    tokio::spawn(async move {
+        debug!(device_id, "Command queue worker started");
+        while let Some(job) = rx.recv().await {
+            let outcome = match runner.dispatch(&job).await {
+                Dispatch::Done(record) => *record,
+                Dispatch::Deferred(seconds) => {
+                    queue.mark_dispatched(&job);
+                    runner.poll_deferred(&job, seconds).await
+                }
+            };
+            queue.finish(&runner, &job, outcome).await;
+        }
+    });
+    DeviceQueue { tx, tail: None }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::sync::Semaphore;
+
+    const HELD: &str = "front-door";
+
+    /// Completes every command as confirmed. Commands for `HELD` wait for a
+    /// `release` permit first, so tests can queue more behind them.
+    #[derive(Clone)]
+    struct StandIn {
+        sent: Arc<Mutex<Vec<(String, LockAction)>>>,
+        release: Arc<Semaphore>,
+    }
+
+    impl StandIn {
+        fn new() -> Self {
+            Self {
+                sent: Arc::default(),
+                release: Arc::new(Semaphore::new(0)),
+            }
+        }
+
+        fn sent(&self) -> Vec<(String, LockAction)> {
+            self.sent.lock().unwrap().clone()
+        }
+
+        /// Wait until `n` commands have reached the lock.
+        async fn until_sent(&self, n: usize) {
+            while self.sent.lock().unwrap().len() < n {
+                tokio::task::yield_now().await;
+            }
+        }
+    }
+
+    impl Runner for StandIn {
+        async fn dispatch(&self, job: &Job) -> Dispatch {
+            self.sent
+                .lock()
+                .unwrap()
+                .push((job.device.id.clone(), job.action));
+            if job.device.id == HELD {
+                self.release.acquire().await.unwrap().forget();
+            }
+            Dispatch::Done(Box::new(CommandRecord {
+                status: CommandStatus::Confirmed.as_str().to_string(),
+                lock_state: Some(job.action.expected_state().to_string()),
+                ..job.lead.clone()
+            }))
+        }
+
+        async fn poll_deferred(&self, job: &Job, _seconds: u64) -> CommandRecord {
+            job.lead.clone()
+        }
+
+        async fn complete_coalesced(
+            &self,
+            id: Uuid,
+            into: &CommandRecord,
+        ) -> Option<CommandRecord> {
+            Some(CommandRecord {
+                id,
+                coalesced_into: Some(into.id),
+                ..into.clone()
+            })
+        }
+    }
+
+    fn device(id: &str) -> Device {
+        serde_json::from_value(serde_json::json!({ "id": id, "name": id })).unwrap()
+    }
+
+    fn record(device_id: &str, action: LockAction) -> CommandRecord {
+        CommandRecord {
+            id: Uuid::new_v4(),
+            device_id: device_id.to_string(),
+            command: action.as_str().to_string(),
+            status: CommandStatus::Pending.as_str().to_string(),
+            source: "api".to_string(),
+            user_id: None,
+            lock_state: None,
+            error: None,
+            coalesced_into: None,
+            created_at: Utc::now().to_rfc3339(),
+            completed_at: None,
+        }
+    }
+
+    fn submit(
+        queue: &CommandQueue,
+        runner: &StandIn,
+        device_id: &str,
+        action: LockAction,
+    ) -> tokio::task::JoinHandle<CommandRecord> {
+        let (queue, runner) = (queue.clone(), runner.clone());
+        let device = device(device_id);
+        let record = record(device_id, action);
+        tokio::spawn(async move {
+            let client = UTec::new("test".to_string());
+            queue
+                .submit(&runner, &client, &device, action, record)
+                .await
+        })
+    }
+
+    /// Wait until the held device's last queued job matches `ready`.
+    async fn until_tail(queue: &CommandQueue, ready: impl Fn(&Job) -> bool) {
+        while !queue.devices.lock().unwrap()[HELD]
+            .tail
+            .as_deref()
+            .is_some_and(&ready)
+        {
+            tokio::task::yield_now().await;
+        }
+    }
+
+    #[tokio::test]
+    async fn concurrent_locks_share_one_command() {
+        let (queue, runner) = (CommandQueue::default(), StandIn::new());
+
+        let first = submit(&queue, &runner, HELD, LockAction::Lock);
+        runner.until_sent(1).await;
+        let second = submit(&queue, &runner, HELD, LockAction::Lock);
+        // Let the second join the queue before the first finishes.
+        until_tail(&queue, |tail| {
+            tail.inner.lock().unwrap().followers.len() == 1
+        })
+        .await;
+        runner.release.add_permits(1);
+
+        let (first, second) = (first.await.unwrap(), second.await.unwrap());
+        assert_eq!(runner.sent(), vec![(HELD.to_string(), LockAction::Lock)]);
+        assert_eq!(first.status, "confirmed");
+        assert_eq!(first.coalesced_into, None);
+        assert_eq!(second.status, "confirmed");
+        assert_eq!(second.coalesced_into, Some(first.id));
+    }
+
+    #[tokio::test]
+    async fn unlock_behind_lock_is_not_merged() {
+        let (queue, runner) = (CommandQueue::default(), StandIn::new());
+
+        let lock = submit(&queue, &runner, HELD, LockAction::Lock);
+        runner.until_sent(1).await;
+        let unlock = submit(&queue, &runner, HELD, LockAction::Unlock);
+        until_tail(&queue, |tail| tail.action == LockAction::Unlock).await;
+        runner.release.add_permits(2);
+
+        let (lock, unlock) = (lock.await.unwrap(), unlock.await.unwrap());
+        assert_eq!(
+            runner.sent(),
+            vec![
+                (HELD.to_string(), LockAction::Lock),
+                (HELD.to_string(), LockAction::Unlock),
+            ]
+        );
+        assert_eq!(lock.coalesced_into, None);
+        assert_eq!(unlock.coalesced_into, None);
+        assert_eq!(unlock.lock_state.as_deref(), Some("unlocked"));
+    }
+
+    #[tokio::test]
+    async fn devices_run_in_parallel() {
+        let (queue, runner) = (CommandQueue::default(), StandIn::new());
+
+        let held = submit(&queue, &runner, HELD, LockAction::Lock);
+        runner.until_sent(1).await;
+
+        // Another device isn't stuck behind the held one.
+        let other = tokio::time::timeout(
+            Duration::from_secs(5),
+            submit(&queue, &runner, "back-door", LockAction::Lock),
+        )
+        .await
+        .expect("back-door command waited for front-door")
+        .unwrap();
+        assert_eq!(other.status, "confirmed");
+        assert!(!held.is_finished());
+
+        runner.release.add_permits(1);
+        assert_eq!(held.await.unwrap().status, "confirmed");
+    }
+}
@@ -0,0 +1,87 @@
+//! Device status history (battery level and connectivity).
+//!
+//! U-Tec reports battery and online/offline state alongside lock state.
+//! Only changes are recorded: each observation is compared against the
+//! last recorded value for the device, and a `WsEvent` is broadcast for
+//! anything that differs.
+
+use sqlx::PgPool;
+
+use crate::ws::WsEvent;
+use crate::AppState;
+
+/// Last recorded battery level and connectivity for a device.
+async fn latest(db: &PgPool, device_id: &str) -> Result<(Option<i32>, Option<bool>), sqlx::Error> {
+    sqlx::query_as(
+        "SELECT \
+           (SELECT battery_level FROM device_status_log \
+            WHERE device_id = $1 AND battery_level IS NOT NULL \
+            ORDER BY created_at DESC LIMIT 1), \
+           (SELECT online FROM device_status_log \
+            WHERE device_id = $1 AND online IS NOT NULL \
+            ORDER BY created_at DESC LIMIT 1)",
+    )
+    .bind(device_id)
+    .fetch_one(db)
+    .await
+}
+
+/// Record an observed battery level (already normalized to 0-100%) and/or
+/// connectivity for a device. Values equal to the last recorded ones are
+/// ignored; changes are persisted and broadcast.
+pub async fn observe(
+    state: &AppState,
+    device_id: &str,
+    battery_level: Option<u64>,
+    online: Option<bool>,
+    source: &str,
+) {
+    let (last_battery, last_online) = match latest(&state.db, device_id).await {
+        Ok(row) => row,
+        Err(e) => {
+            tracing::error!(device_id, "Failed to read device status history: {e:#}");
+            return;
+        }
+    };
+
+    let battery_level = battery_level
+        .map(|b| b.min(100) as i32)
+        .filter(|b| Some(*b) != last_battery);
+    let online = online.filter(|o| Some(*o) != last_online);
+
+    if battery_level.is_none() && online.is_none() {
+        return;
+    }
+
+    if let Err(e) = sqlx::query(
+        "INSERT INTO device_status_log (device_id, battery_level, online, source) VALUES ($1, $2, $3, $4)",
+    )
+    .bind(device_id)
+    .bind(battery_level)
+    .bind(online)
+    .bind(source)
+    .execute(&state.db)
+    .await
+    {
+        tracing::error!(
+            device_id,
+            ?battery_level,
+            ?online,
+            source,
+            "Failed to log device status change: {e:#}"
+        );
+    }
+
+    if let Some(level) = battery_level {
+        let _ = state.events.send(WsEvent::BatteryLevel {
+            device_id: device_id.to_string(),
+            battery_level: level as u64,
+        });
+    }
+    if let Some(online) = online {
+        let _ = state.events.send(WsEvent::DeviceOnline {
+            device_id: device_id.to_string(),
+            online,
+        });
+    }
+}
@@ -0,0 +1,425 @@
+//! Email digests.
+//!
+//! Users can have email collected into hourly or daily digests instead of
+//! one message per event. The outbox writer parks their events in
+//! `notification_digest_items`; [`compile_due`] runs in the delivery worker
+//! and turns each user's items into a single email delivery once the digest
+//! period has closed, so digests are retried like any other notification.
+
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Duration, DurationRound, TimeZone, Timelike, Utc};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use tracing::{error, info};
+use uuid::Uuid;
+
+use crate::notification_prefs::{self, Channel, EventType};
+use crate::AppState;
+
+/// How a user's email notifications are batched.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum Digest {
+    /// One email per event.
+    #[default]
+    Instant,
+    /// Sent at the top of every hour.
+    Hourly,
+    /// Sent once a day at `hour` o'clock in `timezone`.
+    Daily { hour: u32, timezone: String },
+}
+
+impl Digest {
+    pub fn from_columns(mode: &str, hour: Option<i16>, timezone: Option<String>) -> Self {
+        match mode {
+            "hourly" => Self::Hourly,
+            "daily" => Self::Daily {
+                hour: hour.and_then(|h| u32::try_from(h).ok()).unwrap_or(7),
+                timezone: timezone.unwrap_or_else(|| "UTC".to_string()),
+            },
+            _ => Self::Instant,
+        }
+    }
+
+    /// `(digest, digest_hour, digest_tz)` column values.
+    pub fn columns(&self) -> (&'static str, Option<i16>, Option<&str>) {
+        match self {
+            Self::Instant => ("instant", None, None),
+            Self::Hourly => ("hourly", None, None),
+            Self::Daily { hour, timezone } => ("daily", Some(*hour as i16), Some(timezone)),
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), &'static str> {
+        match self {
+            Self::Daily { hour, .. } if *hour > 23 => Err("Digest hour must be 0–23"),
+            Self::Daily { timezone, .. } if timezone.parse::<Tz>().is_err() => {
+                Err("Unknown time zone")
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn tz(&self) -> Tz {
+        match self {
+            Self::Daily { timezone, .. } => timezone.parse().unwrap_or(chrono_tz::UTC),
+            _ => chrono_tz::UTC,
+        }
+    }
+
+    /// Start of the current digest period: events before it are due. For
+    /// instant delivery everything is due, which flushes leftovers after a
+    /// user switches back.
+    pub fn cutoff(&self, now: DateTime<Utc>) -> DateTime<Utc> {
+        match self {
+            Self::Instant => now,
+            Self::Hourly => now.duration_trunc(Duration::hours(1)).unwrap_or(now),
+            Self::Daily { hour, .. } => {
+                let tz = self.tz();
+                let local = now.with_timezone(&tz);
+                let mut date = local.date_naive();
+                if local.hour() < *hour {
+                    date = date.pred_opt().unwrap_or(date);
+                }
+                let at = date.and_hms_opt(*hour, 0, 0).unwrap_or_default();
+                // If a DST change skips that hour, send at the next one.
+                tz.from_local_datetime(&at)
+                    .earliest()
+                    .or_else(|| {
+                        tz.from_local_datetime(&(at + Duration::hours(1)))
+                            .earliest()
+                    })
+                    .map_or(now, |t| t.with_timezone(&Utc))
+            }
+        }
+    }
+
+    fn title(&self) -> &'static str {
+        match self {
+            Self::Instant => "Panopticon notifications",
+            Self::Hourly => "Panopticon hourly digest",
+            Self::Daily { .. } => "Panopticon daily digest",
+        }
+    }
+}
+
+/// One event waiting in a digest.
+#[derive(Debug)]
+pub struct DigestItem {
+    pub event_type: EventType,
+    pub tag_id: Option<String>,
+    pub device_id: Option<String>,
+    /// Card and door names from [`crate::notification_content::Message`].
+    pub card: Option<String>,
+    pub door: Option<String>,
+    /// The event's subject, e.g. "Front door locked".
+    pub summary: String,
+    pub created_at: DateTime<Utc>,
+}
+
+fn plural(n: usize, one: &str, many: &str) -> String {
+    format!("{n} {}", if n == 1 { one } else { many })
+}
+
+/// Subject and plain-text body of a digest. Denied scans are listed
+/// first, then per-card and per-door counts, then anything else (e.g.
+/// sentinels going offline).
+pub fn render(digest: &Digest, items: &[DigestItem]) -> (String, String) {
+    let tz = digest.tz();
+    let time = |t: &DateTime<Utc>| t.with_timezone(&tz).format("%b %-d %H:%M %Z").to_string();
+
+    let mut denied: BTreeMap<String, Vec<&DateTime<Utc>>> = BTreeMap::new();
+    let mut cards: BTreeMap<String, (usize, usize)> = BTreeMap::new();
+    let mut doors: BTreeMap<String, (usize, usize, &str)> = BTreeMap::new();
+    let mut other = Vec::new();
+
+    for item in items {
+        let card = || {
+            item.card
+                .clone()
+                .or_else(|| item.tag_id.as_ref().map(|t| format!("Card {t}")))
+        };
+        let door = item.door.clone().or_else(|| item.device_id.clone());
+        match (item.event_type, card(), &item.device_id) {
+            (EventType::ScanGranted, Some(card), _) => cards.entry(card).or_default().0 += 1,
+            (EventType::ScanDenied, Some(card), _) => {
+                cards.entry(card.clone()).or_default().1 += 1;
+                denied.entry(card).or_default().push(&item.created_at);
+            }
+            (event_type, _, Some(_)) => {
+                let door = doors.entry(door.unwrap_or_default()).or_insert((0, 0, ""));
+                if event_type == EventType::LockChange {
+                    door.0 += 1;
+                } else {
+                    door.1 += 1;
+                }
+                door.2 = &item.summary;
+            }
+            _ => other.push(item),
+        }
+    }
+
+    let denied_count: usize = denied.values().map(Vec::len).sum();
+    let mut subject = format!(
+        "{}: {}",
+        digest.title(),
+        plural(items.len(), "event", "events")
+    );
+    if denied_count > 0 {
+        subject.push_str(&format!(
+            ", {}",
+            plural(denied_count, "denied scan", "denied scans")
+        ));
+    }
+
+    let mut sections = Vec::new();
+    if !denied.is_empty() {
+        let mut lines = vec!["Denied scans".to_string()];
+        for (card, times) in &denied {
+            let shown: Vec<String> = times.iter().take(5).map(|t| time(t)).collect();
+            let more = if times.len() > 5 { ", …" } else { "" };
+            lines.push(format!(
+                "- {card} denied {}× ({}{more})",
+                times.len(),
+                shown.join(", ")
+            ));
+        }
+        sections.push(lines.join("\n"));
+    }
+    if !cards.is_empty() {
+        let mut lines = vec!["Cards".to_string()];
+        for (card, (granted, denied)) in &cards {
+            lines.push(format!("- {card}: {granted} granted, {denied} denied"));
+        }
+        sections.push(lines.join("\n"));
+    }
+    if !doors.is_empty() {
+        let mut lines = vec!["Doors".to_string()];
+        for (door, (changes, alerts, latest)) in &doors {
+            let mut counts = Vec::new();
+            if *changes > 0 {
+                counts.push(plural(*changes, "lock change", "lock changes"));
+            }
+            if *alerts > 0 {
+                counts.push(plural(*alerts, "alert", "alerts"));
+            }
+            lines.push(format!(
+                "- {door}: {} (latest: {latest})",
+                counts.join(", ")
+            ));
+        }
+        sections.push(lines.join("\n"));
+    }
+    if !other.is_empty() {
+        let mut lines = vec!["Other".to_string()];
+        for item in other {
+            lines.push(format!("- {} — {}", time(&item.created_at), item.summary));
+        }
+        sections.push(lines.join("\n"));
+    }
+
+    (subject, sections.join("\n\n"))
+}
+
+type ItemRow = (
+    String,
+    Option<String>,
+    Option<String>,
+    Option<String>,
+    Option<String>,
+    String,
+    DateTime<Utc>,
+);
+
+/// Turn every closed digest into an email delivery. Returns how many
+/// digests were queued.
+pub async fn compile_due(state: &AppState) -> usize {
+    // Suspended or unapproved users get nothing; drop what was collected.
+    if let Err(e) = sqlx::query(
+        "DELETE FROM notification_digest_items i USING users u \
+         WHERE u.id = i.user_id AND (u.is_approved = FALSE OR u.suspended_at IS NOT NULL)",
+    )
+    .execute(&state.db)
+    .await
+    {
+        error!("Failed to drop digests of inactive users: {e}");
+    }
+
+    let users: Vec<(Uuid, String)> = match sqlx::query_as(
+        "SELECT DISTINCT i.user_id, u.email FROM notification_digest_items i \
+         JOIN users u ON u.id = i.user_id \
+         WHERE u.is_approved = TRUE AND u.suspended_at IS NULL",
+    )
+    .fetch_all(&state.db)
+    .await
+    {
+        Ok(users) => users,
+        Err(e) => {
+            error!("Failed to load pending digests: {e}");
+            return 0;
+        }
+    };
+
+    let now = Utc::now();
+    let mut queued = 0;
+    for (user_id, email) in users {
+        match compile_for(state, user_id, &email, now).await {
+            Ok(true) => queued += 1,
+            Ok(false) => {}
+            Err(e) => error!(user = %user_id, "Failed to compile digest: {e}"),
+        }
+    }
+    queued
+}
+
+async fn compile_for(
+    state: &AppState,
+    user_id: Uuid,
+    email: &str,
+    now: DateTime<Utc>,
+) -> Result<bool, sqlx::Error> {
+    let prefs = notification_prefs::load(&state.db, user_id, Channel::Email).await?;
+    // Turning email off drops whatever was waiting.
+    let cutoff = if prefs.enabled {
+        prefs.digest.cutoff(now)
+    } else {
+        now
+    };
+
+    let mut tx = state.db.begin().await?;
+    let rows: Vec<ItemRow> = sqlx::query_as(
+        "DELETE FROM notification_digest_items WHERE user_id = $1 AND created_at < $2 \
+         RETURNING event_type, tag_id, device_id, card, door, summary, created_at",
+    )
+    .bind(user_id)
+    .bind(cutoff)
+    .fetch_all(&mut *tx)
+    .await?;
+    if rows.is_empty() || !prefs.enabled {
+        tx.commit().await?;
+        return Ok(false);
+    }
+
+    let mut items: Vec<DigestItem> = rows
+        .into_iter()
+        .filter_map(
+            |(event_type, tag_id, device_id, card, door, summary, created_at)| {
+                Some(DigestItem {
+                    event_type: EventType::parse(&event_type)?,
+                    tag_id,
+                    device_id,
+                    card,
+                    door,
+                    summary,
+                    created_at,
+                })
+            },
+        )
+        .collect();
+    items.sort_by_key(|i| i.created_at);
+
+    let (subject, body) = render(&prefs.digest, &items);
+    sqlx::query(
+        "INSERT INTO notification_deliveries (user_id, channel, email, subject, body) \
+         VALUES ($1, 'email', $2, $3, $4)",
+    )
+    .bind(user_id)
+    .bind(email)
+    .bind(&subject)
+    .bind(&body)
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+
+    info!(user = %user_id, events = items.len(), "Digest queued");
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(h: u32, m: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2026, 3, 2, h, m, 0).unwrap()
+    }
+
+    fn item(event_type: EventType, tag: Option<&str>, device: Option<&str>) -> DigestItem {
+        DigestItem {
+            event_type,
+            tag_id: tag.map(str::to_string),
+            device_id: device.map(str::to_string),
+            card: None,
+            door: None,
+            summary: format!("{} event", event_type.as_str()),
+            created_at: at(9, 30),
+        }
+    }
+
+    #[test]
+    fn hourly_cutoff_is_top_of_hour() {
+        assert_eq!(Digest::Hourly.cutoff(at(10, 42)), at(10, 0));
+        assert_eq!(Digest::Instant.cutoff(at(10, 42)), at(10, 42));
+    }
+
+    #[test]
+    fn daily_cutoff_uses_time_zone() {
+        let daily = Digest::Daily {
+            hour: 8,
+            timezone: "Europe/Berlin".to_string(),
+        };
+        // 08:00 in Berlin is 07:00 UTC in winter.
+        assert_eq!(daily.cutoff(at(10, 0)), at(7, 0));
+        // Before today's send time, the previous day's is the latest.
+        assert_eq!(
+            daily.cutoff(at(6, 0)),
+            Utc.with_ymd_and_hms(2026, 3, 1, 7, 0, 0).unwrap()
+        );
+    }
+
+    #[test]
+    fn validates_daily_settings() {
+        let daily = |hour, tz: &str| Digest::Daily {
+            hour,
+            timezone: tz.to_string(),
+        };
+        assert!(daily(7, "America/New_York").validate().is_ok());
+        assert!(daily(24, "UTC").validate().is_err());
+        assert!(daily(7, "Mars/Olympus").validate().is_err());
+        let utc = daily(7, "UTC");
+        let (mode, hour, tz) = utc.columns();
+        assert_eq!(
+            Digest::from_columns(mode, hour, tz.map(str::to_string)),
+            utc
+        );
+    }
+
+    #[test]
+    fn groups_by_card_and_door() {
+        let named = |mut item: DigestItem| {
+            item.card = item.tag_id.as_ref().map(|t| format!("Ada's fob ({t})"));
+            item.door = Some("Front door".to_string());
+            item
+        };
+        let items = vec![
+            named(item(EventType::ScanDenied, Some("80:00:48:23:4C"), None)),
+            named(item(EventType::ScanDenied, Some("80:00:48:23:4C"), None)),
+            named(item(EventType::ScanGranted, Some("80:00:48:23:4C"), None)),
+            item(EventType::ScanGranted, Some("11:22:33:44:55"), None),
+            named(item(EventType::LockChange, None, Some("front-door"))),
+            named(item(EventType::DeviceAlert, None, Some("front-door"))),
+            item(EventType::SentinelOffline, None, None),
+        ];
+        let (subject, body) = render(&Digest::Hourly, &items);
+
+        assert_eq!(
+            subject,
+            "Panopticon hourly digest: 7 events, 2 denied scans"
+        );
+        assert!(body.starts_with("Denied scans\n- Ada's fob (80:00:48:23:4C) denied 2×"));
+        assert!(body.contains("- Card 11:22:33:44:55: 1 granted, 0 denied"));
+        assert!(body.contains("- Ada's fob (80:00:48:23:4C): 1 granted, 2 denied"));
+        assert!(body.contains("- Front door: 1 lock change, 1 alert (latest: device_alert event)"));
+        assert!(body.contains("- Mar 2 09:30 UTC — sentinel_offline event"));
+    }
+}
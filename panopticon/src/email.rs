@@ -1,46 +1,121 @@
-use anyhow::{Context, Result};
+use std::net::IpAddr;
+use std::sync::Arc;
+
+use anyhow::{bail, Context, Result};
 use lettre::{
-    message::{header::ContentType, Mailbox},
+    message::{
+        dkim::{DkimConfig, DkimSigningAlgorithm, DkimSigningKey},
+        Mailbox, MultiPart,
+    },
     transport::smtp::authentication::Credentials,
-    AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor,
+    AsyncFileTransport, AsyncSendmailTransport, AsyncSmtpTransport, AsyncTransport, Message,
+    Tokio1Executor,
 };
-use sqlx::PgPool;
-use tokio::sync::broadcast;
-use tracing::{error, info, warn};
+use tracing::{error, info};
+
+/// Where outgoing mail goes, chosen with `MAIL_TRANSPORT`.
+enum Transport {
+    Smtp(AsyncSmtpTransport<Tokio1Executor>),
+    /// A local `sendmail`-compatible command.
+    Sendmail(AsyncSendmailTransport<Tokio1Executor>),
+    /// One `.eml` file per message, for development without a relay.
+    File(AsyncFileTransport<Tokio1Executor>),
+}
+
+impl Transport {
+    /// The transport and a description for the startup log.
+    fn from_env() -> Result<(Self, String)> {
+        match std::env::var("MAIL_TRANSPORT").as_deref() {
+            Err(_) | Ok("smtp") => {
+                let smtp_host = std::env::var("SMTP_HOST").context("SMTP_HOST must be set")?;
+                let smtp_username =
+                    std::env::var("SMTP_USERNAME").context("SMTP_USERNAME must be set")?;
+                let smtp_password =
+                    std::env::var("SMTP_PASSWORD").context("SMTP_PASSWORD must be set")?;
+
+                let creds = Credentials::new(smtp_username, smtp_password);
+                let transport = AsyncSmtpTransport::<Tokio1Executor>::relay(&smtp_host)
+                    .context("Failed to create SMTP transport")?
+                    .credentials(creds)
+                    .build();
+                Ok((Self::Smtp(transport), format!("SMTP: {smtp_host}")))
+            }
+            Ok("sendmail") => {
+                let command =
+                    std::env::var("SENDMAIL_COMMAND").unwrap_or_else(|_| "sendmail".into());
+                let transport =
+                    AsyncSendmailTransport::<Tokio1Executor>::new_with_command(&command);
+                Ok((Self::Sendmail(transport), format!("sendmail: {command}")))
+            }
+            Ok("file") => {
+                let dir = std::env::var("MAIL_DIR").unwrap_or_else(|_| "mail".into());
+                std::fs::create_dir_all(&dir)
+                    .with_context(|| format!("Failed to create MAIL_DIR {dir}"))?;
+                let transport = AsyncFileTransport::<Tokio1Executor>::new(&dir);
+                Ok((Self::File(transport), format!("files in {dir}")))
+            }
+            Ok(other) => bail!("Unknown MAIL_TRANSPORT: {other} (expected smtp, sendmail or file)"),
+        }
+    }
+
+    async fn send(&self, message: Message) -> Result<()> {
+        match self {
+            Self::Smtp(t) => t.send(message).await.map(drop)?,
+            Self::Sendmail(t) => t.send(message).await?,
+            Self::File(t) => t.send(message).await.map(drop)?,
+        }
+        Ok(())
+    }
+}
 
-use crate::ws::WsEvent;
+/// DKIM signing, enabled by setting `DKIM_DOMAIN`.
+fn dkim_from_env() -> Result<Option<DkimConfig>> {
+    let Ok(domain) = std::env::var("DKIM_DOMAIN") else {
+        return Ok(None);
+    };
+    let selector =
+        std::env::var("DKIM_SELECTOR").context("DKIM_SELECTOR must be set with DKIM_DOMAIN")?;
+    let key_file = std::env::var("DKIM_PRIVATE_KEY_FILE")
+        .context("DKIM_PRIVATE_KEY_FILE must be set with DKIM_DOMAIN")?;
+    // RSA keys are PKCS#1 PEM, Ed25519 keys the base64 of the 32-byte seed.
+    let algorithm = match std::env::var("DKIM_ALGORITHM").as_deref() {
+        Err(_) | Ok("rsa") => DkimSigningAlgorithm::Rsa,
+        Ok("ed25519") => DkimSigningAlgorithm::Ed25519,
+        Ok(other) => bail!("Unknown DKIM_ALGORITHM: {other} (expected rsa or ed25519)"),
+    };
+
+    let key = std::fs::read_to_string(&key_file)
+        .with_context(|| format!("Failed to read DKIM key {key_file}"))?;
+    let key = DkimSigningKey::new(key.trim(), algorithm).context("Invalid DKIM private key")?;
+    Ok(Some(DkimConfig::default_config(selector, domain, key)))
+}
 
 #[derive(Clone)]
 pub struct Mailer {
-    transport: AsyncSmtpTransport<Tokio1Executor>,
+    transport: Arc<Transport>,
+    dkim: Option<Arc<DkimConfig>>,
     from: Mailbox,
     base_url: String,
 }
 
 impl Mailer {
     pub fn new() -> Result<Self> {
-        let smtp_host = std::env::var("SMTP_HOST").context("SMTP_HOST must be set")?;
-        let smtp_username = std::env::var("SMTP_USERNAME").context("SMTP_USERNAME must be set")?;
-        let smtp_password = std::env::var("SMTP_PASSWORD").context("SMTP_PASSWORD must be set")?;
         let smtp_from =
             std::env::var("SMTP_FROM").unwrap_or_else(|_| "changeme@example.com".into());
         let base_url = std::env::var("BASE_URL").unwrap_or_else(|_| "http://localhost:5173".into());
 
-        let creds = Credentials::new(smtp_username, smtp_password);
-
-        let transport = AsyncSmtpTransport::<Tokio1Executor>::relay(&smtp_host)
-            .context("Failed to create SMTP transport")?
-            .credentials(creds)
-            .build();
+        let (transport, description) = Transport::from_env()?;
+        let dkim = dkim_from_env()?.map(Arc::new);
 
         let from: Mailbox = format!("Panopticon <{smtp_from}>")
             .parse()
             .context("Invalid SMTP_FROM address")?;
 
-        info!("Mailer initialized (SMTP: {smtp_host})");
+        info!(dkim = dkim.is_some(), "Mailer initialized ({description})");
 
         Ok(Self {
-            transport,
+            transport: Arc::new(transport),
+            dkim,
             from,
             base_url,
         })
@@ -49,23 +124,92 @@ impl Mailer {
     pub async fn send_confirmation_email(&self, to_email: &str, token: &str) -> Result<()> {
         let confirm_url = format!("{}/api/auth/confirm-email?token={}", self.base_url, token);
         let subject = "Confirm your Panopticon account";
-        let html = confirmation_template(&confirm_url);
+        let body = confirmation_template(&confirm_url);
 
-        self.send(to_email, subject, &html).await
+        self.send(to_email, subject, body).await
     }
 
     pub async fn send_password_reset_email(&self, to_email: &str, token: &str) -> Result<()> {
         let reset_url = format!("{}/reset-password?token={}", self.base_url, token);
         let subject = "Reset your Panopticon password";
-        let html = password_reset_template(&reset_url);
+        let body = password_reset_template(&reset_url);
+
+        self.send(to_email, subject, body).await
+    }
+
+    pub async fn send_email_change_email(&self, to_email: &str, token: &str) -> Result<()> {
+        let confirm_url = format!(
+            "{}/api/auth/confirm-email-change?token={}",
+            self.base_url, token
+        );
+        let subject = "Confirm your new Panopticon email address";
+        let body = email_change_template(&confirm_url);
+
+        self.send(to_email, subject, body).await
+    }
+
+    pub async fn send_email_changed_email(&self, to_email: &str, new_email: &str) -> Result<()> {
+        let dashboard_url = format!("{}/", self.base_url);
+        let subject = "Your Panopticon email address was changed";
+        let new_email = escape_html(new_email);
+        let body = email_template(
+            "Email address changed",
+            &format!(
+                "The email address on your Panopticon account was changed to <strong>{new_email}</strong>. \
+                 Future emails will go to the new address."
+            ),
+            "Go to Dashboard",
+            &dashboard_url,
+            "If you didn't make this change, contact your Panopticon administrator immediately.",
+        );
+
+        self.send(to_email, subject, body).await
+    }
+
+    pub async fn send_passkey_added_email(&self, to_email: &str, name: &str) -> Result<()> {
+        let dashboard_url = format!("{}/", self.base_url);
+        let subject = "A passkey was added to your Panopticon account";
+        let name = escape_html(name);
+        let body = email_template(
+            "Passkey added",
+            &format!(
+                "A passkey named <strong>{name}</strong> was added to your Panopticon account. \
+                 It can be used to sign in without a password."
+            ),
+            "Go to Dashboard",
+            &dashboard_url,
+            "If you didn't add it, remove it from your account settings and contact your Panopticon administrator immediately.",
+        );
 
-        self.send(to_email, subject, &html).await
+        self.send(to_email, subject, body).await
+    }
+
+    pub async fn send_invitation_email(
+        &self,
+        to_email: &str,
+        token: &str,
+        invited_by: &str,
+    ) -> Result<()> {
+        let accept_url = format!("{}/invite?token={}", self.base_url, token);
+        let subject = "You're invited to Panopticon";
+        let invited_by = escape_html(invited_by);
+        let body = email_template(
+            "You're invited",
+            &format!(
+                "{invited_by} has invited you to Panopticon. Click the button below to choose a password and sign in."
+            ),
+            "Accept Invitation",
+            &accept_url,
+            "This link expires in 7 days and can only be used once. If you weren't expecting it, you can ignore this email.",
+        );
+
+        self.send(to_email, subject, body).await
     }
 
     pub async fn send_approval_email(&self, to_email: &str) -> Result<()> {
         let dashboard_url = format!("{}/", self.base_url);
         let subject = "Account approved";
-        let html = email_template(
+        let body = email_template(
             "Account approved",
             "Your Panopticon account has been approved. You now have full access.",
             "Go to Dashboard",
@@ -73,32 +217,49 @@ impl Mailer {
             "You are receiving this because your Panopticon account was approved by an administrator.",
         );
 
-        self.send(to_email, subject, &html).await
+        self.send(to_email, subject, body).await
     }
 
+    pub async fn send_failed_login_email(
+        &self,
+        to_email: &str,
+        attempts: i32,
+        ip: Option<IpAddr>,
+    ) -> Result<()> {
+        let reset_url = format!("{}/forgot-password", self.base_url);
+        let subject = "Failed sign-in attempts on your account";
+        let body = failed_login_template(attempts, ip, &reset_url);
+
+        self.send(to_email, subject, body).await
+    }
+
+    /// Send a notification. `text` is plain text, as rendered by
+    /// [`crate::notification_content`].
     pub async fn send_access_event_email(
         &self,
         to_email: &str,
         subject: &str,
-        event_body: &str,
+        text: &str,
     ) -> Result<()> {
         let dashboard_url = format!("{}/", self.base_url);
-        let html = access_event_template(subject, event_body, &dashboard_url);
-        self.send(to_email, subject, &html).await
+        let body = access_event_template(subject, text, &dashboard_url);
+        self.send(to_email, subject, body).await
     }
 
-    async fn send(&self, to_email: &str, subject: &str, html_body: &str) -> Result<()> {
+    async fn send(&self, to_email: &str, subject: &str, body: EmailBody) -> Result<()> {
         let to: Mailbox = to_email
             .parse()
             .with_context(|| format!("Invalid recipient address: {to_email}"))?;
 
-        let message = Message::builder()
+        let mut message = Message::builder()
             .from(self.from.clone())
             .to(to)
             .subject(subject)
-            .header(ContentType::TEXT_HTML)
-            .body(html_body.to_string())
+            .multipart(MultiPart::alternative_plain_html(body.text, body.html))
             .context("Failed to build email message")?;
+        if let Some(dkim) = &self.dkim {
+            message.sign(dkim);
+        }
 
         match self.transport.send(message).await {
             Ok(_) => {
@@ -106,14 +267,14 @@ impl Mailer {
                 Ok(())
             }
             Err(e) => {
-                error!(to = to_email, subject, error = %e, "Failed to send email");
+                error!(to = to_email, subject, error = %format!("{e:#}"), "Failed to send email");
                 Err(e).context("Failed to send email")
             }
         }
     }
 }
 
-fn confirmation_template(confirm_url: &str) -> String {
+fn confirmation_template(confirm_url: &str) -> EmailBody {
     email_template(
         "Confirm your email",
         "Thanks for signing up for Panopticon. Click the button below to confirm your email address.",
@@ -123,7 +284,7 @@ fn confirmation_template(confirm_url: &str) -> String {
     )
 }
 
-fn password_reset_template(reset_url: &str) -> String {
+fn password_reset_template(reset_url: &str) -> EmailBody {
     email_template(
         "Reset your password",
         "We received a request to reset your Panopticon password. Click the button below to choose a new password.",
@@ -133,24 +294,89 @@ fn password_reset_template(reset_url: &str) -> String {
     )
 }
 
-fn access_event_template(heading: &str, body: &str, dashboard_url: &str) -> String {
+fn email_change_template(confirm_url: &str) -> EmailBody {
+    email_template(
+        "Confirm your new email",
+        "Someone asked to move a Panopticon account to this address. Click the button below to confirm it.",
+        "Confirm Email",
+        confirm_url,
+        "This link expires in 24 hours. If you didn't ask for this, you can ignore this email.",
+    )
+}
+
+fn failed_login_template(attempts: i32, ip: Option<IpAddr>, reset_url: &str) -> EmailBody {
+    let source = ip
+        .map(|ip| format!(" from {}", escape_html(&ip.to_string())))
+        .unwrap_or_default();
     email_template(
-        heading,
-        body,
+        "Failed sign-in attempts",
+        &format!(
+            "There have been {attempts} failed attempts to sign in to your Panopticon account{source}. \
+             Further attempts are being slowed down. If this wasn't you, consider changing your password."
+        ),
+        "Reset Password",
+        reset_url,
+        "You are receiving this because someone tried to sign in to your Panopticon account.",
+    )
+}
+
+fn access_event_template(heading: &str, text: &str, dashboard_url: &str) -> EmailBody {
+    email_template(
+        &escape_html(heading),
+        &escape_html(text).replace('\n', "<br>"),
         "View Dashboard",
         dashboard_url,
         "You are receiving this because you enabled email notifications in Panopticon.",
     )
 }
 
+/// An email's HTML and plain-text versions.
+struct EmailBody {
+    html: String,
+    text: String,
+}
+
+fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Plain-text rendering of a template fragment: line breaks kept, tags
+/// dropped, entities decoded.
+fn html_to_text(html: &str) -> String {
+    let html = html.replace("<br>", "\n");
+    let mut text = String::with_capacity(html.len());
+    let mut in_tag = false;
+    for c in html.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' if in_tag => in_tag = false,
+            _ if !in_tag => text.push(c),
+            _ => {}
+        }
+    }
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&amp;", "&")
+}
+
+/// `heading` and `body` are HTML fragments.
 fn email_template(
     heading: &str,
     body: &str,
     button_text: &str,
     button_url: &str,
     footer: &str,
-) -> String {
-    format!(
+) -> EmailBody {
+    let text = format!(
+        "{}\n\n{}\n\n{button_text}: {button_url}\n\n{footer}\n",
+        html_to_text(heading),
+        html_to_text(body)
+    );
+    let html = format!(
         r#"<!DOCTYPE html>
 <html>
 <head><meta charset="utf-8"><meta name="viewport" content="width=device-width, initial-scale=1.0"></head>
@@ -173,79 +399,78 @@ fn email_template(
 </table>
 </body>
 </html>"#
-    )
+    );
+    EmailBody { html, text }
 }
 
-pub async fn spawn_email_notifier(
-    mut rx: broadcast::Receiver<WsEvent>,
-    pool: PgPool,
-    mailer: Mailer,
-) {
-    info!("Email notifier started");
-    loop {
-        let event = match rx.recv().await {
-            Ok(e) => e,
-            Err(broadcast::error::RecvError::Lagged(n)) => {
-                warn!("Email notifier lagged, skipped {n} events");
-                continue;
-            }
-            Err(broadcast::error::RecvError::Closed) => {
-                info!("Email notifier shutting down (channel closed)");
-                return;
-            }
-        };
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        let (subject, body) = match &event {
-            WsEvent::Scan { tag_id, action, .. } => {
-                let subject = format!(
-                    "Access {}: {}",
-                    if action == "granted" {
-                        "Granted"
-                    } else {
-                        "Denied"
-                    },
-                    tag_id
-                );
-                let body = format!(
-                    "Card <strong>{}</strong> was <strong>{}</strong>.",
-                    tag_id, action
-                );
-                (subject, body)
-            }
-            WsEvent::LockState {
-                device_id,
-                lock_state,
-            } => {
-                let subject = format!("Lock {}: {}", lock_state, device_id);
-                let body = format!(
-                    "Device <strong>{}</strong> is now <strong>{}</strong>.",
-                    device_id, lock_state
-                );
-                (subject, body)
-            }
-            _ => continue,
-        };
+    #[test]
+    fn notification_email_has_plain_text_part() {
+        let body = access_event_template(
+            "Front door unlocked",
+            "Front door was unlocked by <ada@example.org>.\nSecond line",
+            "https://panopticon.example.org/",
+        );
+        assert!(body
+            .html
+            .contains("unlocked by &lt;ada@example.org&gt;.<br>Second line"));
+        assert!(body.text.starts_with(
+            "Front door unlocked\n\nFront door was unlocked by <ada@example.org>.\nSecond line\n\n"
+        ));
+        assert!(body
+            .text
+            .contains("View Dashboard: https://panopticon.example.org/"));
+    }
 
-        let recipients: Vec<String> = match sqlx::query_scalar(
-            "SELECT email FROM users WHERE notify_email = TRUE AND is_approved = TRUE",
-        )
-        .fetch_all(&pool)
-        .await
-        {
-            Ok(rows) => rows,
-            Err(e) => {
-                error!("Failed to query notification recipients: {e}");
-                continue;
-            }
+    #[test]
+    fn failed_login_email_names_the_address() {
+        let body = failed_login_template(
+            5,
+            Some("2001:db8::1".parse().unwrap()),
+            "https://panopticon.example.org/forgot-password",
+        );
+        assert!(body.html.contains("5 failed attempts"));
+        assert!(body.html.contains(" from 2001:db8::1."));
+        assert!(body.text.contains(" from 2001:db8::1."));
+    }
+
+    #[tokio::test]
+    async fn file_transport_writes_signed_eml() {
+        use base64::{engine::general_purpose::STANDARD, Engine};
+
+        let dir = std::env::temp_dir().join(format!("panopticon-mail-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let key = DkimSigningKey::new(&STANDARD.encode([7u8; 32]), DkimSigningAlgorithm::Ed25519)
+            .unwrap();
+        let mailer = Mailer {
+            transport: Arc::new(Transport::File(AsyncFileTransport::new(&dir))),
+            dkim: Some(Arc::new(DkimConfig::default_config(
+                "mail".into(),
+                "example.org".into(),
+                key,
+            ))),
+            from: "Panopticon <door@example.org>".parse().unwrap(),
+            base_url: "https://panopticon.example.org".into(),
         };
 
-        for email in recipients {
-            if let Err(e) = mailer
-                .send_access_event_email(&email, &subject, &body)
-                .await
-            {
-                error!(to = %email, "Failed to send access event email: {e}");
-            }
-        }
+        mailer
+            .send_password_reset_email("ada@example.org", "abc123")
+            .await
+            .unwrap();
+
+        let files: Vec<_> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().path())
+            .collect();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].extension().unwrap(), "eml");
+        let eml = std::fs::read_to_string(&files[0]).unwrap();
+        assert!(eml.contains("DKIM-Signature: "));
+        assert!(eml.contains("d=example.org"));
+        assert!(eml.contains("To: ada@example.org"));
+        std::fs::remove_dir_all(&dir).unwrap();
     }
 }
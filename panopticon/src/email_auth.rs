@@ -10,11 +10,15 @@ use rand::Rng;
 use serde::Deserialize;
 use tracing::{error, info, warn};
 
-use crate::middleware::AuthUser;
+use crate::login_throttle::{self, Bucket};
+use crate::middleware::{AuthUser, SessionUser};
+use crate::permissions::Role;
 use crate::session::{
-    clear_session_cookie, create_session, delete_session, extract_session_id_from_cookies,
-    set_session_cookie,
+    clear_session_cookie, create_session, current_session_id, delete_other_sessions,
+    delete_session, delete_user_sessions, extract_session_id_from_cookies, set_session_cookie,
+    ClientInfo, SecondFactor,
 };
+use crate::totp;
 use crate::AppState;
 
 pub fn router() -> Router<AppState> {
@@ -27,20 +31,23 @@ pub fn router() -> Router<AppState> {
         .route("/resend-confirmation", post(resend_confirmation))
         .route("/forgot-password", post(forgot_password))
         .route("/reset-password", post(reset_password))
+        .route("/change-password", post(change_password))
+        .route("/change-email", post(change_email))
+        .route("/confirm-email-change", get(confirm_email_change))
 }
 
-fn is_secure() -> bool {
+pub(crate) fn is_secure() -> bool {
     std::env::var("BASE_URL")
         .map(|u| u.starts_with("https://"))
         .unwrap_or(false)
 }
 
-fn generate_token() -> String {
+pub(crate) fn generate_token() -> String {
     let bytes: [u8; 32] = rand::thread_rng().r#gen();
     bytes.iter().map(|b| format!("{b:02x}")).collect()
 }
 
-fn json_error(status: StatusCode, msg: &str) -> Response {
+pub(crate) fn json_error(status: StatusCode, msg: &str) -> Response {
     (status, Json(serde_json::json!({"error": msg}))).into_response()
 }
 
@@ -52,7 +59,11 @@ struct RegisterRequest {
     password: String,
 }
 
-async fn register(State(state): State<AppState>, Json(body): Json<RegisterRequest>) -> Response {
+async fn register(
+    State(state): State<AppState>,
+    client: ClientInfo,
+    Json(body): Json<RegisterRequest>,
+) -> Response {
     let email = body.email.trim().to_lowercase();
     if email.is_empty() || !email.contains('@') {
         return json_error(StatusCode::BAD_REQUEST, "Invalid email address");
@@ -72,16 +83,7 @@ async fn register(State(state): State<AppState>, Json(body): Json<RegisterReques
         }
     };
 
-    let user_id: Option<(uuid::Uuid,)> = match sqlx::query_as(
-        "INSERT INTO users (email, password_hash) VALUES ($1, $2) \
-         ON CONFLICT (email) DO NOTHING \
-         RETURNING id",
-    )
-    .bind(&email)
-    .bind(&password_hash)
-    .fetch_optional(&state.db)
-    .await
-    {
+    let inserted = match insert_user(&state.db, &email, &password_hash).await {
         Ok(row) => row,
         Err(e) => {
             error!("Database error during registration: {e}");
@@ -89,8 +91,8 @@ async fn register(State(state): State<AppState>, Json(body): Json<RegisterReques
         }
     };
 
-    let user_id = match user_id {
-        Some((id,)) => id,
+    let (user_id, role) = match inserted {
+        Some(row) => row,
         None => {
             return json_error(
                 StatusCode::CONFLICT,
@@ -99,6 +101,9 @@ async fn register(State(state): State<AppState>, Json(body): Json<RegisterReques
         }
     };
 
+    if role == Role::Admin {
+        info!(email = %email, "First user registered, granted admin");
+    }
     info!(email = %email, "New user registered");
 
     // Send confirmation email
@@ -119,7 +124,7 @@ async fn register(State(state): State<AppState>, Json(body): Json<RegisterReques
     }
 
     // Create session
-    let session_id = match create_session(&state.db, user_id).await {
+    let session_id = match create_session(&state.db, user_id, &client, None).await {
         Ok(id) => id,
         Err(e) => {
             error!("Failed to create session: {e}");
@@ -131,7 +136,9 @@ async fn register(State(state): State<AppState>, Json(body): Json<RegisterReques
         "id": user_id,
         "email": email,
         "email_confirmed": false,
-        "is_approved": false,
+        "is_approved": role == Role::Admin,
+        "role": role,
+        "permissions": role.permissions(),
     }))
     .into_response();
 
@@ -153,11 +160,25 @@ struct LoginRequest {
     password: String,
 }
 
-async fn login(State(state): State<AppState>, Json(body): Json<LoginRequest>) -> Response {
+async fn login(
+    State(state): State<AppState>,
+    client: ClientInfo,
+    Json(body): Json<LoginRequest>,
+) -> Response {
     let email = body.email.trim().to_lowercase();
 
-    let row: Option<(uuid::Uuid, String, bool, bool)> = match sqlx::query_as(
-        "SELECT id, password_hash, email_confirmed, is_approved FROM users WHERE email = $1",
+    let ip = client.ip.map(|ip| ip.to_string());
+    let mut throttle_keys = vec![(Bucket::LoginAccount, email.as_str())];
+    if let Some(ip) = ip.as_deref() {
+        throttle_keys.push((Bucket::LoginIp, ip));
+    }
+    let attempt = match login_throttle::reserve(&state.db, &throttle_keys).await {
+        Ok(attempts) => attempts[0],
+        Err(refused) => return refused.into_response(),
+    };
+
+    let row: Option<(uuid::Uuid, String, bool, bool, String)> = match sqlx::query_as(
+        "SELECT id, password_hash, email_confirmed, is_approved, role FROM users WHERE email = $1",
     )
     .bind(&email)
     .fetch_optional(&state.db)
@@ -170,20 +191,101 @@ async fn login(State(state): State<AppState>, Json(body): Json<LoginRequest>) ->
         }
     };
 
-    let (user_id, password_hash, email_confirmed, is_approved) = match row {
+    let (user_id, password_hash, email_confirmed, is_approved, role) = match row {
         Some(r) => r,
         None => {
+            login_throttle::login_failed(&state, &email, attempt, client.ip).await;
             return json_error(StatusCode::UNAUTHORIZED, "Invalid email or password");
         }
     };
 
     if !verify_password(&body.password, &password_hash) {
+        login_throttle::login_failed(&state, &email, attempt, client.ip).await;
         return json_error(StatusCode::UNAUTHORIZED, "Invalid email or password");
     }
 
+    if let Some(ip) = ip.as_deref() {
+        login_throttle::release(&state.db, Bucket::LoginIp, ip).await;
+    }
+
+    // With two-factor enabled, earlier failures are only forgotten once the
+    // code is accepted too, so the code can't be guessed across fresh
+    // challenges.
+    match totp::is_enabled(&state.db, user_id).await {
+        Ok(true) => {
+            login_throttle::release(&state.db, Bucket::LoginAccount, &email).await;
+            return totp::start_challenge(&state, user_id).await;
+        }
+        Ok(false) => {}
+        Err(e) => {
+            error!("Failed to check two-factor status: {e}");
+            return json_error(StatusCode::INTERNAL_SERVER_ERROR, "Login failed");
+        }
+    }
+
+    if let Err(e) = login_throttle::clear(&state.db, Bucket::LoginAccount, &email).await {
+        error!("Failed to reset login throttle: {e}");
+    }
+
     info!(email = %email, "User logged in");
 
-    let session_id = match create_session(&state.db, user_id).await {
+    let user = LoginUser {
+        id: user_id,
+        email: &email,
+        email_confirmed,
+        is_approved,
+        role: &role,
+    };
+    start_session(&state, &client, &user, None).await
+}
+
+/// An account that has passed every login step, as echoed in the login
+/// response.
+pub(crate) struct LoginUser<'a> {
+    pub id: uuid::Uuid,
+    pub email: &'a str,
+    pub email_confirmed: bool,
+    pub is_approved: bool,
+    pub role: &'a str,
+}
+
+/// Create a session for a user who has passed every login step and return
+/// the login response with the session cookie set. `second_factor` is how
+/// the login proved one, if it did.
+pub(crate) async fn start_session(
+    state: &AppState,
+    client: &ClientInfo,
+    user: &LoginUser<'_>,
+    second_factor: Option<SecondFactor>,
+) -> Response {
+    let LoginUser {
+        id: user_id,
+        email,
+        email_confirmed,
+        is_approved,
+        role,
+    } = *user;
+
+    let suspended = match sqlx::query_scalar::<_, bool>(
+        "UPDATE users SET last_login_at = now() WHERE id = $1 AND suspended_at IS NULL \
+         RETURNING FALSE",
+    )
+    .bind(user_id)
+    .fetch_optional(&state.db)
+    .await
+    {
+        Ok(row) => row.is_none(),
+        Err(e) => {
+            error!("Failed to record login: {e}");
+            return json_error(StatusCode::INTERNAL_SERVER_ERROR, "Login failed");
+        }
+    };
+    if suspended {
+        warn!(email, "Login refused for suspended account");
+        return json_error(StatusCode::FORBIDDEN, "This account has been suspended");
+    }
+
+    let session_id = match create_session(&state.db, user_id, client, second_factor).await {
         Ok(id) => id,
         Err(e) => {
             error!("Failed to create session: {e}");
@@ -196,6 +298,7 @@ async fn login(State(state): State<AppState>, Json(body): Json<LoginRequest>) ->
         "email": email,
         "email_confirmed": email_confirmed,
         "is_approved": is_approved,
+        "role": role,
     }))
     .into_response();
 
@@ -226,6 +329,38 @@ async fn logout(State(state): State<AppState>, headers: axum::http::HeaderMap) -
     response
 }
 
+/// Insert a new user, returning `None` if the email is taken. The very first
+/// account becomes an approved admin so a fresh install can be set up without
+/// touching the database.
+async fn insert_user(
+    db: &sqlx::PgPool,
+    email: &str,
+    password_hash: &str,
+) -> Result<Option<(uuid::Uuid, Role)>, sqlx::Error> {
+    let mut tx = db.begin().await?;
+
+    // Two concurrent first registrations must not both become admin.
+    sqlx::query("LOCK TABLE users IN SHARE ROW EXCLUSIVE MODE")
+        .execute(&mut *tx)
+        .await?;
+
+    let row: Option<(uuid::Uuid, String)> = sqlx::query_as(
+        "INSERT INTO users (email, password_hash, role, is_approved) \
+         SELECT $1, $2, CASE WHEN first THEN 'admin' ELSE 'resident' END, first \
+         FROM (SELECT NOT EXISTS (SELECT 1 FROM users) AS first) bootstrap \
+         ON CONFLICT (email) DO NOTHING \
+         RETURNING id, role",
+    )
+    .bind(email)
+    .bind(password_hash)
+    .fetch_optional(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(row.map(|(id, role)| (id, Role::parse(&role).unwrap_or(Role::Viewer))))
+}
+
 // ── Me ──────────────────────────────────────────────────────────────────────
 
 async fn me(user: Result<AuthUser, Response>) -> Response {
@@ -235,6 +370,8 @@ async fn me(user: Result<AuthUser, Response>) -> Response {
             "email": user.email,
             "email_confirmed": user.email_confirmed,
             "is_approved": user.is_approved,
+            "role": user.role,
+            "permissions": user.role.permissions(),
         }))
         .into_response(),
         Err(e) => e,
@@ -337,10 +474,21 @@ struct ForgotPasswordRequest {
 
 async fn forgot_password(
     State(state): State<AppState>,
+    client: ClientInfo,
     Json(body): Json<ForgotPasswordRequest>,
 ) -> Response {
     let email = body.email.trim().to_lowercase();
 
+    // Every request counts, so the reservation is never given back.
+    let ip = client.ip.map(|ip| ip.to_string());
+    let mut throttle_keys = vec![(Bucket::ResetAccount, email.as_str())];
+    if let Some(ip) = ip.as_deref() {
+        throttle_keys.push((Bucket::ResetIp, ip));
+    }
+    if let Err(refused) = login_throttle::reserve(&state.db, &throttle_keys).await {
+        return refused.into_response();
+    }
+
     // Always return 200 to prevent email enumeration
     let row: Option<(uuid::Uuid,)> = sqlx::query_as("SELECT id FROM users WHERE email = $1")
         .bind(&email)
@@ -433,19 +581,284 @@ async fn reset_password(
     }
 
     // Invalidate all existing sessions for this user
-    let _ = sqlx::query("DELETE FROM sessions WHERE user_id = $1")
-        .bind(user_id)
-        .execute(&state.db)
-        .await;
+    if let Err(e) = delete_user_sessions(&state.db, user_id).await {
+        error!("Failed to invalidate sessions after password reset: {e}");
+    }
 
     info!(%user_id, "Password reset");
 
     Json(serde_json::json!({"message": "Password has been reset"})).into_response()
 }
 
+// ── Change Password ─────────────────────────────────────────────────────────
+
+#[derive(Deserialize)]
+struct ChangePasswordRequest {
+    current_password: String,
+    new_password: String,
+}
+
+/// Check a signed-in user's password, counting failures against the same
+/// throttle as login so a stolen session can't be used to guess it.
+pub(crate) async fn confirm_password(
+    state: &AppState,
+    client: &ClientInfo,
+    user: &AuthUser,
+    password: &str,
+) -> Result<(), Response> {
+    let attempt =
+        match login_throttle::reserve(&state.db, &[(Bucket::LoginAccount, &user.email)]).await {
+            Ok(attempts) => attempts[0],
+            Err(refused) => return Err(refused.into_response()),
+        };
+
+    let password_hash: String =
+        match sqlx::query_scalar("SELECT password_hash FROM users WHERE id = $1")
+            .bind(user.id)
+            .fetch_one(&state.db)
+            .await
+        {
+            Ok(hash) => hash,
+            Err(e) => {
+                error!("Failed to load password hash: {e}");
+                return Err(json_error(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "Database error",
+                ));
+            }
+        };
+
+    if !verify_password(password, &password_hash) {
+        login_throttle::login_failed(state, &user.email, attempt, client.ip).await;
+        return Err(json_error(StatusCode::UNAUTHORIZED, "Incorrect password"));
+    }
+
+    login_throttle::release(&state.db, Bucket::LoginAccount, &user.email).await;
+    Ok(())
+}
+
+async fn change_password(
+    State(state): State<AppState>,
+    user: SessionUser,
+    client: ClientInfo,
+    headers: axum::http::HeaderMap,
+    Json(body): Json<ChangePasswordRequest>,
+) -> Response {
+    if body.new_password.len() < 8 {
+        return json_error(
+            StatusCode::BAD_REQUEST,
+            "Password must be at least 8 characters",
+        );
+    }
+
+    if let Err(resp) = confirm_password(&state, &client, &user, &body.current_password).await {
+        return resp;
+    }
+
+    let password_hash = match hash_password(&body.new_password) {
+        Ok(h) => h,
+        Err(e) => {
+            error!("Failed to hash password: {e}");
+            return json_error(StatusCode::INTERNAL_SERVER_ERROR, "Password change failed");
+        }
+    };
+
+    if let Err(e) =
+        sqlx::query("UPDATE users SET password_hash = $1, updated_at = now() WHERE id = $2")
+            .bind(&password_hash)
+            .bind(user.id)
+            .execute(&state.db)
+            .await
+    {
+        error!("Failed to update password: {e}");
+        return json_error(StatusCode::INTERNAL_SERVER_ERROR, "Password change failed");
+    }
+
+    // Outstanding reset links would undo the change.
+    if let Err(e) = sqlx::query(
+        "UPDATE email_tokens SET used = TRUE \
+         WHERE user_id = $1 AND token_type = 'password_reset' AND used = FALSE",
+    )
+    .bind(user.id)
+    .execute(&state.db)
+    .await
+    {
+        error!("Failed to invalidate reset tokens: {e}");
+    }
+
+    // Keep this browser signed in; log out everywhere else.
+    if let Some(session_id) = current_session_id(&headers) {
+        if let Err(e) = delete_other_sessions(&state.db, user.id, session_id).await {
+            error!("Failed to invalidate sessions after password change: {e}");
+        }
+    }
+
+    info!(email = %user.email, "Password changed");
+
+    Json(serde_json::json!({"message": "Password changed"})).into_response()
+}
+
+// ── Change Email ────────────────────────────────────────────────────────────
+
+#[derive(Deserialize)]
+struct ChangeEmailRequest {
+    new_email: String,
+    password: String,
+}
+
+async fn change_email(
+    State(state): State<AppState>,
+    user: SessionUser,
+    client: ClientInfo,
+    Json(body): Json<ChangeEmailRequest>,
+) -> Response {
+    let new_email = body.new_email.trim().to_lowercase();
+    if new_email.is_empty() || !new_email.contains('@') {
+        return json_error(StatusCode::BAD_REQUEST, "Invalid email address");
+    }
+    if new_email == user.email {
+        return json_error(
+            StatusCode::BAD_REQUEST,
+            "That is already your email address",
+        );
+    }
+
+    if let Err(resp) = confirm_password(&state, &client, &user, &body.password).await {
+        return resp;
+    }
+
+    match sqlx::query_scalar::<_, bool>("SELECT EXISTS (SELECT 1 FROM users WHERE email = $1)")
+        .bind(&new_email)
+        .fetch_one(&state.db)
+        .await
+    {
+        Ok(false) => {}
+        Ok(true) => {
+            return json_error(
+                StatusCode::CONFLICT,
+                "An account with this email already exists",
+            )
+        }
+        Err(e) => {
+            error!("Failed to check email availability: {e}");
+            return json_error(StatusCode::INTERNAL_SERVER_ERROR, "Email change failed");
+        }
+    }
+
+    // Only the latest request can be confirmed.
+    if let Err(e) = sqlx::query(
+        "UPDATE email_tokens SET used = TRUE \
+         WHERE user_id = $1 AND token_type = 'email_change' AND used = FALSE",
+    )
+    .bind(user.id)
+    .execute(&state.db)
+    .await
+    {
+        error!("Failed to invalidate email change tokens: {e}");
+        return json_error(StatusCode::INTERNAL_SERVER_ERROR, "Email change failed");
+    }
+
+    let token = generate_token();
+    let expires_at = Utc::now() + Duration::hours(24);
+    if let Err(e) = sqlx::query(
+        "INSERT INTO email_tokens (id, user_id, token_type, expires_at, new_email) \
+         VALUES ($1, $2, 'email_change', $3, $4)",
+    )
+    .bind(&token)
+    .bind(user.id)
+    .bind(expires_at)
+    .bind(&new_email)
+    .execute(&state.db)
+    .await
+    {
+        error!("Failed to store email change token: {e}");
+        return json_error(StatusCode::INTERNAL_SERVER_ERROR, "Email change failed");
+    }
+
+    if let Err(e) = state
+        .mailer
+        .send_email_change_email(&new_email, &token)
+        .await
+    {
+        error!("Failed to send email change confirmation: {e}");
+        return json_error(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Failed to send confirmation email",
+        );
+    }
+
+    info!(email = %user.email, new_email = %new_email, "Email change requested");
+
+    Json(serde_json::json!({"message": "Confirmation sent to the new address"})).into_response()
+}
+
+async fn confirm_email_change(
+    State(state): State<AppState>,
+    Query(params): Query<ConfirmEmailParams>,
+) -> Response {
+    let row: Option<(uuid::Uuid, Option<String>)> = match sqlx::query_as(
+        "UPDATE email_tokens SET used = TRUE \
+         WHERE id = $1 AND token_type = 'email_change' AND expires_at > now() AND used = FALSE \
+         RETURNING user_id, new_email",
+    )
+    .bind(&params.token)
+    .fetch_optional(&state.db)
+    .await
+    {
+        Ok(row) => row,
+        Err(e) => {
+            error!("Failed to validate email change token: {e}");
+            return Redirect::temporary("/?error=email_change_failed").into_response();
+        }
+    };
+
+    let Some((user_id, Some(new_email))) = row else {
+        warn!(token = %params.token, "Invalid or expired email change token");
+        return Redirect::temporary("/?error=invalid_token").into_response();
+    };
+
+    // The old address comes from the row being replaced, so a concurrent
+    // change can't make us notify the wrong mailbox.
+    let old_email: Option<String> = match sqlx::query_scalar(
+        "UPDATE users u SET email = $2, email_confirmed = TRUE, updated_at = now() \
+         FROM (SELECT id, email FROM users WHERE id = $1 FOR UPDATE) old \
+         WHERE u.id = old.id RETURNING old.email",
+    )
+    .bind(user_id)
+    .bind(&new_email)
+    .fetch_optional(&state.db)
+    .await
+    {
+        Ok(email) => email,
+        Err(sqlx::Error::Database(e)) if e.is_unique_violation() => {
+            return Redirect::temporary("/?error=email_taken").into_response();
+        }
+        Err(e) => {
+            error!("Failed to change email: {e}");
+            return Redirect::temporary("/?error=email_change_failed").into_response();
+        }
+    };
+
+    let Some(old_email) = old_email else {
+        return Redirect::temporary("/?error=invalid_token").into_response();
+    };
+
+    info!(%user_id, old_email = %old_email, new_email = %new_email, "Email changed");
+
+    if let Err(e) = state
+        .mailer
+        .send_email_changed_email(&old_email, &new_email)
+        .await
+    {
+        error!("Failed to notify old email address: {e}");
+    }
+
+    Redirect::temporary("/?email_changed=true").into_response()
+}
+
 // ── Password Hashing ────────────────────────────────────────────────────────
 
-fn hash_password(password: &str) -> anyhow::Result<String> {
+pub(crate) fn hash_password(password: &str) -> anyhow::Result<String> {
     use argon2::{
         password_hash::{rand_core::OsRng, SaltString},
         Argon2, PasswordHasher,
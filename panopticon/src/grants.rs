@@ -0,0 +1,537 @@
+//! Per-user, per-device lock grants.
+//!
+//! Apart from admins, a user may only lock or unlock a device remotely if
+//! they hold a grant for it in `device_grants` allowing that action. A grant
+//! can carry a weekly schedule (days plus a time window in a named time
+//! zone); outside it the grant doesn't apply. Grants are checked for REST
+//! lock commands and for MQTT commands (acting as `MQTT_COMMAND_USER`).
+//!
+//! Every denied attempt is recorded in `access_denials`.
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    routing::{get, put},
+    Json, Router,
+};
+use chrono::{DateTime, Datelike, NaiveTime, Utc, Weekday};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+use crate::commands::LockAction;
+use crate::middleware::Authorized;
+use crate::permissions::{perm, Permission, Role};
+use crate::AppState;
+
+type ApiError = (StatusCode, &'static str);
+
+/// Weekly window during which a grant applies.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Schedule {
+    /// Days the window starts on. Empty means every day.
+    #[serde(default)]
+    pub days: Vec<Weekday>,
+    pub start: NaiveTime,
+    /// End of the window. Earlier than `start` means it runs past midnight.
+    pub end: NaiveTime,
+    /// IANA time zone name, e.g. `Europe/Berlin`.
+    pub timezone: String,
+}
+
+impl Schedule {
+    pub(crate) fn tz(&self) -> Option<Tz> {
+        self.timezone.parse().ok()
+    }
+
+    /// Whether `now` falls inside the window. An overnight window belongs to
+    /// the day it starts on, so a Friday 22:00–06:00 window covers Saturday
+    /// 03:00 but not Friday 03:00.
+    pub fn allows(&self, now: DateTime<Utc>) -> bool {
+        let Some(tz) = self.tz() else {
+            return false;
+        };
+        let local = now.with_timezone(&tz);
+        let time = local.time();
+        let day_allowed = |day: Weekday| self.days.is_empty() || self.days.contains(&day);
+
+        if self.start <= self.end {
+            day_allowed(local.weekday()) && time >= self.start && time < self.end
+        } else if time >= self.start {
+            day_allowed(local.weekday())
+        } else if time < self.end {
+            day_allowed(local.weekday().pred())
+        } else {
+            false
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Grant {
+    pub user_id: Uuid,
+    pub email: String,
+    pub device_id: String,
+    pub can_lock: bool,
+    pub can_unlock: bool,
+    pub schedule: Option<Schedule>,
+    pub updated_at: String,
+}
+
+/// Why a lock command was refused.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Denial {
+    /// The user's role doesn't allow operating locks at all.
+    Role,
+    /// No grant for this device.
+    NoGrant,
+    /// A grant exists, but not for this action.
+    ActionNotGranted,
+    /// The grant's schedule doesn't cover the current time.
+    OutsideSchedule,
+    /// The acting user doesn't exist (e.g. a misconfigured `MQTT_COMMAND_USER`).
+    UnknownUser,
+    /// Policy requires two-factor authentication for unlocking and the user
+    /// hasn't enabled it.
+    TwoFactorRequired,
+}
+
+impl Denial {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Role => "role",
+            Self::NoGrant => "no_grant",
+            Self::ActionNotGranted => "action_not_granted",
+            Self::OutsideSchedule => "outside_schedule",
+            Self::UnknownUser => "unknown_user",
+            Self::TwoFactorRequired => "two_factor_required",
+        }
+    }
+
+    pub fn message(self) -> &'static str {
+        match self {
+            Self::Role | Self::UnknownUser => "Not authorized",
+            Self::NoGrant => "No access to this device",
+            Self::ActionNotGranted => "Not permitted to perform this action on this device",
+            Self::OutsideSchedule => "Access to this device is not allowed at this time",
+            Self::TwoFactorRequired => "Enable two-factor authentication to unlock doors",
+        }
+    }
+}
+
+// ── Checks ──────────────────────────────────────────────────────────────────
+
+type GrantRow = (
+    bool,
+    bool,
+    Option<Vec<i16>>,
+    Option<NaiveTime>,
+    Option<NaiveTime>,
+    Option<String>,
+);
+
+pub(crate) fn schedule_from_columns(
+    days: Option<Vec<i16>>,
+    start: Option<NaiveTime>,
+    end: Option<NaiveTime>,
+    timezone: Option<String>,
+) -> Option<Schedule> {
+    let (start, end, timezone) = (start?, end?, timezone?);
+    let days = days
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|d| Weekday::try_from(u8::try_from(d - 1).ok()?).ok())
+        .collect();
+    Some(Schedule {
+        days,
+        start,
+        end,
+        timezone,
+    })
+}
+
+/// Check whether `user_id` may send `action` to `device_id` right now.
+/// Denials are recorded before being returned.
+pub async fn check(
+    db: &PgPool,
+    user_id: Uuid,
+    role: Role,
+    device_id: &str,
+    action: LockAction,
+    source: &str,
+) -> Result<(), Denial> {
+    let denial = match evaluate(db, user_id, role, device_id, action).await {
+        Ok(None) => return Ok(()),
+        Ok(Some(denial)) => denial,
+        Err(e) => {
+            // Fail closed.
+            error!(device_id, %user_id, "Failed to check device grant: {e:#}");
+            Denial::NoGrant
+        }
+    };
+
+    record_denial(db, Some(user_id), device_id, action, source, denial).await;
+    Err(denial)
+}
+
+/// Whether any device grants exist, i.e. remote lock access is restricted
+/// per device. Errors count as in use.
+pub async fn in_use(db: &PgPool) -> bool {
+    sqlx::query_scalar("SELECT EXISTS(SELECT 1 FROM device_grants)")
+        .fetch_one(db)
+        .await
+        .unwrap_or_else(|e| {
+            error!("Failed to check for device grants: {e:#}");
+            // Fail closed.
+            true
+        })
+}
+
+async fn evaluate(
+    db: &PgPool,
+    user_id: Uuid,
+    role: Role,
+    device_id: &str,
+    action: LockAction,
+) -> Result<Option<Denial>, sqlx::Error> {
+    if !role.has(Permission::OperateLocks) {
+        return Ok(Some(Denial::Role));
+    }
+    if role == Role::Admin {
+        return Ok(None);
+    }
+
+    let row: Option<GrantRow> = sqlx::query_as(
+        "SELECT can_lock, can_unlock, schedule_days, schedule_start, schedule_end, schedule_tz \
+         FROM device_grants WHERE user_id = $1 AND device_id = $2",
+    )
+    .bind(user_id)
+    .bind(device_id)
+    .fetch_optional(db)
+    .await?;
+
+    let Some((can_lock, can_unlock, days, start, end, tz)) = row else {
+        return Ok(Some(Denial::NoGrant));
+    };
+
+    let allowed = match action {
+        LockAction::Lock => can_lock,
+        LockAction::Unlock => can_unlock,
+    };
+    if !allowed {
+        return Ok(Some(Denial::ActionNotGranted));
+    }
+
+    if let Some(schedule) = schedule_from_columns(days, start, end, tz) {
+        if !schedule.allows(Utc::now()) {
+            return Ok(Some(Denial::OutsideSchedule));
+        }
+    }
+
+    Ok(None)
+}
+
+pub async fn record_denial(
+    db: &PgPool,
+    user_id: Option<Uuid>,
+    device_id: &str,
+    action: LockAction,
+    source: &str,
+    denial: Denial,
+) {
+    warn!(
+        device_id,
+        ?user_id,
+        command = action.as_str(),
+        source,
+        reason = denial.as_str(),
+        "Lock command denied"
+    );
+
+    if let Err(e) = sqlx::query(
+        "INSERT INTO access_denials (user_id, device_id, command, source, reason) \
+         VALUES ($1, $2, $3, $4, $5)",
+    )
+    .bind(user_id)
+    .bind(device_id)
+    .bind(action.as_str())
+    .bind(source)
+    .bind(denial.as_str())
+    .execute(db)
+    .await
+    {
+        error!(device_id, "Failed to record access denial: {e:#}");
+    }
+}
+
+// ── API ─────────────────────────────────────────────────────────────────────
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/devices/{id}/grants", get(list_grants))
+        .route(
+            "/devices/{id}/grants/{user_id}",
+            put(set_grant).delete(delete_grant),
+        )
+        .route("/admin/access-denials", get(list_denials))
+}
+
+type GrantListRow = (
+    Uuid,
+    String,
+    bool,
+    bool,
+    Option<Vec<i16>>,
+    Option<NaiveTime>,
+    Option<NaiveTime>,
+    Option<String>,
+    DateTime<Utc>,
+);
+
+async fn list_grants(
+    _user: Authorized<perm::ManageUsers>,
+    State(state): State<AppState>,
+    Path(device_id): Path<String>,
+) -> Result<Json<Vec<Grant>>, ApiError> {
+    let rows: Vec<GrantListRow> = sqlx::query_as(
+        "SELECT g.user_id, u.email, g.can_lock, g.can_unlock, g.schedule_days, \
+         g.schedule_start, g.schedule_end, g.schedule_tz, g.updated_at \
+         FROM device_grants g JOIN users u ON u.id = g.user_id \
+         WHERE g.device_id = $1 ORDER BY u.email",
+    )
+    .bind(&device_id)
+    .fetch_all(&state.db)
+    .await
+    .map_err(|e| {
+        error!("Failed to fetch device grants: {e:#}");
+        (StatusCode::INTERNAL_SERVER_ERROR, "Database error")
+    })?;
+
+    let grants = rows
+        .into_iter()
+        .map(
+            |(user_id, email, can_lock, can_unlock, days, start, end, tz, updated_at)| Grant {
+                user_id,
+                email,
+                device_id: device_id.clone(),
+                can_lock,
+                can_unlock,
+                schedule: schedule_from_columns(days, start, end, tz),
+                updated_at: updated_at.to_rfc3339(),
+            },
+        )
+        .collect();
+
+    Ok(Json(grants))
+}
+
+#[derive(Deserialize)]
+struct SetGrantRequest {
+    can_lock: bool,
+    can_unlock: bool,
+    schedule: Option<Schedule>,
+}
+
+async fn set_grant(
+    user: Authorized<perm::ManageUsers>,
+    State(state): State<AppState>,
+    Path((device_id, user_id)): Path<(String, Uuid)>,
+    Json(body): Json<SetGrantRequest>,
+) -> Result<StatusCode, ApiError> {
+    if let Some(ref schedule) = body.schedule {
+        if schedule.tz().is_none() {
+            return Err((StatusCode::BAD_REQUEST, "Unknown time zone"));
+        }
+        if schedule.start == schedule.end {
+            return Err((StatusCode::BAD_REQUEST, "Schedule window is empty"));
+        }
+    }
+
+    let schedule = body.schedule.as_ref();
+    let days: Option<Vec<i16>> = schedule.map(|s| {
+        s.days
+            .iter()
+            .map(|d| d.number_from_monday() as i16)
+            .collect()
+    });
+
+    let result = sqlx::query(
+        "INSERT INTO device_grants \
+         (user_id, device_id, can_lock, can_unlock, schedule_days, schedule_start, schedule_end, schedule_tz) \
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8) \
+         ON CONFLICT (user_id, device_id) DO UPDATE SET \
+         can_lock = $3, can_unlock = $4, schedule_days = $5, schedule_start = $6, \
+         schedule_end = $7, schedule_tz = $8, updated_at = now()",
+    )
+    .bind(user_id)
+    .bind(&device_id)
+    .bind(body.can_lock)
+    .bind(body.can_unlock)
+    .bind(days)
+    .bind(schedule.map(|s| s.start))
+    .bind(schedule.map(|s| s.end))
+    .bind(schedule.map(|s| s.timezone.as_str()))
+    .execute(&state.db)
+    .await;
+
+    match result {
+        Ok(_) => {}
+        Err(sqlx::Error::Database(e)) if e.is_foreign_key_violation() => {
+            return Err((StatusCode::NOT_FOUND, "User not found"));
+        }
+        Err(e) => {
+            error!("Failed to save device grant: {e:#}");
+            return Err((StatusCode::INTERNAL_SERVER_ERROR, "Database error"));
+        }
+    }
+
+    info!(
+        by = %user.email,
+        %user_id,
+        device_id,
+        can_lock = body.can_lock,
+        can_unlock = body.can_unlock,
+        scheduled = body.schedule.is_some(),
+        "Device grant updated"
+    );
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn delete_grant(
+    user: Authorized<perm::ManageUsers>,
+    State(state): State<AppState>,
+    Path((device_id, user_id)): Path<(String, Uuid)>,
+) -> Result<StatusCode, ApiError> {
+    let result = sqlx::query("DELETE FROM device_grants WHERE user_id = $1 AND device_id = $2")
+        .bind(user_id)
+        .bind(&device_id)
+        .execute(&state.db)
+        .await
+        .map_err(|e| {
+            error!("Failed to delete device grant: {e:#}");
+            (StatusCode::INTERNAL_SERVER_ERROR, "Database error")
+        })?;
+
+    if result.rows_affected() == 0 {
+        return Err((StatusCode::NOT_FOUND, "No grant for this user and device"));
+    }
+
+    info!(by = %user.email, %user_id, device_id, "Device grant removed");
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Serialize)]
+struct DenialEntry {
+    id: Uuid,
+    user_id: Option<Uuid>,
+    email: Option<String>,
+    device_id: String,
+    command: String,
+    source: String,
+    reason: String,
+    created_at: String,
+}
+
+type DenialRow = (
+    Uuid,
+    Option<Uuid>,
+    Option<String>,
+    String,
+    String,
+    String,
+    String,
+    DateTime<Utc>,
+);
+
+async fn list_denials(
+    _user: Authorized<perm::ManageUsers>,
+    State(state): State<AppState>,
+) -> Result<Json<Vec<DenialEntry>>, ApiError> {
+    let rows: Vec<DenialRow> = sqlx::query_as(
+        "SELECT d.id, d.user_id, u.email, d.device_id, d.command, d.source, d.reason, d.created_at \
+         FROM access_denials d LEFT JOIN users u ON u.id = d.user_id \
+         ORDER BY d.created_at DESC LIMIT 200",
+    )
+    .fetch_all(&state.db)
+    .await
+    .map_err(|e| {
+        error!("Failed to fetch access denials: {e:#}");
+        (StatusCode::INTERNAL_SERVER_ERROR, "Database error")
+    })?;
+
+    let entries = rows
+        .into_iter()
+        .map(
+            |(id, user_id, email, device_id, command, source, reason, created_at)| DenialEntry {
+                id,
+                user_id,
+                email,
+                device_id,
+                command,
+                source,
+                reason,
+                created_at: created_at.to_rfc3339(),
+            },
+        )
+        .collect();
+
+    Ok(Json(entries))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn schedule(days: &[Weekday], start: &str, end: &str, tz: &str) -> Schedule {
+        Schedule {
+            days: days.to_vec(),
+            start: start.parse().unwrap(),
+            end: end.parse().unwrap(),
+            timezone: tz.to_string(),
+        }
+    }
+
+    fn utc(y: i32, m: u32, d: u32, h: u32, min: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(y, m, d, h, min, 0).unwrap()
+    }
+
+    #[test]
+    fn daytime_window() {
+        // 2025-06-02 is a Monday.
+        let s = schedule(&[Weekday::Mon, Weekday::Tue], "08:00", "17:00", "UTC");
+        assert!(s.allows(utc(2025, 6, 2, 8, 0)));
+        assert!(s.allows(utc(2025, 6, 3, 16, 59)));
+        assert!(!s.allows(utc(2025, 6, 2, 17, 0)));
+        assert!(!s.allows(utc(2025, 6, 2, 7, 59)));
+        assert!(!s.allows(utc(2025, 6, 4, 12, 0)));
+    }
+
+    #[test]
+    fn overnight_window_belongs_to_start_day() {
+        let s = schedule(&[Weekday::Fri], "22:00", "06:00", "UTC");
+        // 2025-06-06 is a Friday.
+        assert!(s.allows(utc(2025, 6, 6, 23, 0)));
+        assert!(s.allows(utc(2025, 6, 7, 3, 0)));
+        assert!(!s.allows(utc(2025, 6, 6, 3, 0)));
+        assert!(!s.allows(utc(2025, 6, 7, 12, 0)));
+    }
+
+    #[test]
+    fn window_uses_time_zone() {
+        let s = schedule(&[], "09:00", "10:00", "America/New_York");
+        // 13:30 UTC is 09:30 EDT.
+        assert!(s.allows(utc(2025, 6, 2, 13, 30)));
+        assert!(!s.allows(utc(2025, 6, 2, 9, 30)));
+    }
+
+    #[test]
+    fn unknown_time_zone_denies() {
+        let s = schedule(&[], "00:00", "23:59", "Mars/Olympus");
+        assert!(!s.allows(utc(2025, 6, 2, 12, 0)));
+    }
+}
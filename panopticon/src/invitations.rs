@@ -0,0 +1,383 @@
+//! Admin invitations.
+//!
+//! An admin invites an email address with a role; the invitee gets a
+//! single-use link, chooses a password and is signed in with an account that
+//! is already confirmed and approved, skipping `/admin/pending-users`.
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::{delete, get, post},
+    Json, Router,
+};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+use crate::email_auth::{generate_token, hash_password, json_error, start_session, LoginUser};
+use crate::middleware::Authorized;
+use crate::permissions::{perm, Role};
+use crate::session::ClientInfo;
+use crate::AppState;
+
+type ApiError = (StatusCode, &'static str);
+
+const INVITATION_TTL_DAYS: i64 = 7;
+
+fn hash_token(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+/// Admin routes, nested under `/api`.
+pub fn admin_router() -> Router<AppState> {
+    Router::new()
+        .route(
+            "/admin/invitations",
+            get(list_invitations).post(create_invitation),
+        )
+        .route("/admin/invitations/{id}", delete(revoke_invitation))
+}
+
+/// Public routes for the invitee, nested under `/api/auth`.
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/invitations/{token}", get(get_invitation))
+        .route("/invitations/accept", post(accept_invitation))
+}
+
+#[derive(Serialize)]
+struct InvitationInfo {
+    id: Uuid,
+    email: String,
+    role: String,
+    invited_by: Option<String>,
+    created_at: String,
+    expires_at: String,
+}
+
+type InvitationRow = (
+    Uuid,
+    String,
+    String,
+    Option<String>,
+    DateTime<Utc>,
+    DateTime<Utc>,
+);
+
+async fn list_invitations(
+    _user: Authorized<perm::ManageUsers>,
+    State(state): State<AppState>,
+) -> Result<Json<Vec<InvitationInfo>>, ApiError> {
+    let rows: Vec<InvitationRow> = sqlx::query_as(
+        "SELECT i.id, i.email, i.role, u.email, i.created_at, i.expires_at \
+         FROM invitations i LEFT JOIN users u ON u.id = i.invited_by \
+         WHERE i.accepted_at IS NULL AND i.expires_at > now() \
+         ORDER BY i.created_at DESC",
+    )
+    .fetch_all(&state.db)
+    .await
+    .map_err(|e| {
+        error!("Failed to list invitations: {e}");
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Failed to list invitations",
+        )
+    })?;
+
+    let invitations = rows
+        .into_iter()
+        .map(
+            |(id, email, role, invited_by, created_at, expires_at)| InvitationInfo {
+                id,
+                email,
+                role,
+                invited_by,
+                created_at: created_at.to_rfc3339(),
+                expires_at: expires_at.to_rfc3339(),
+            },
+        )
+        .collect();
+
+    Ok(Json(invitations))
+}
+
+#[derive(Deserialize)]
+struct CreateInvitationRequest {
+    email: String,
+    role: Role,
+}
+
+async fn create_invitation(
+    user: Authorized<perm::ManageUsers>,
+    State(state): State<AppState>,
+    Json(body): Json<CreateInvitationRequest>,
+) -> Result<(StatusCode, Json<InvitationInfo>), ApiError> {
+    let email = body.email.trim().to_lowercase();
+    if email.is_empty() || !email.contains('@') {
+        return Err((StatusCode::BAD_REQUEST, "Invalid email address"));
+    }
+
+    let exists: bool = sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM users WHERE email = $1)")
+        .bind(&email)
+        .fetch_one(&state.db)
+        .await
+        .map_err(|e| {
+            error!("Failed to check for existing user: {e}");
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to create invitation",
+            )
+        })?;
+    if exists {
+        return Err((
+            StatusCode::CONFLICT,
+            "An account with this email already exists",
+        ));
+    }
+
+    let token = generate_token();
+    let expires_at = Utc::now() + Duration::days(INVITATION_TTL_DAYS);
+
+    let mut tx = state.db.begin().await.map_err(|e| {
+        error!("Failed to begin transaction: {e}");
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Failed to create invitation",
+        )
+    })?;
+
+    // A new invitation replaces any outstanding one for the same address.
+    let created: Result<(Uuid, DateTime<Utc>), sqlx::Error> = async {
+        sqlx::query("DELETE FROM invitations WHERE email = $1 AND accepted_at IS NULL")
+            .bind(&email)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query_as(
+            "INSERT INTO invitations (email, role, token_hash, invited_by, expires_at) \
+             VALUES ($1, $2, $3, $4, $5) RETURNING id, created_at",
+        )
+        .bind(&email)
+        .bind(body.role.as_str())
+        .bind(hash_token(&token))
+        .bind(user.id)
+        .bind(expires_at)
+        .fetch_one(&mut *tx)
+        .await
+    }
+    .await;
+
+    let (id, created_at) = created.map_err(|e| {
+        error!("Failed to store invitation: {e}");
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Failed to create invitation",
+        )
+    })?;
+
+    tx.commit().await.map_err(|e| {
+        error!("Failed to commit invitation: {e}");
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Failed to create invitation",
+        )
+    })?;
+
+    if let Err(e) = state
+        .mailer
+        .send_invitation_email(&email, &token, &user.email)
+        .await
+    {
+        error!("Failed to send invitation email: {e}");
+        let _ = sqlx::query("DELETE FROM invitations WHERE id = $1")
+            .bind(id)
+            .execute(&state.db)
+            .await;
+        return Err((StatusCode::BAD_GATEWAY, "Failed to send invitation email"));
+    }
+
+    info!(admin = %user.email, email = %email, role = body.role.as_str(), "Invitation sent");
+
+    Ok((
+        StatusCode::CREATED,
+        Json(InvitationInfo {
+            id,
+            email,
+            role: body.role.as_str().to_string(),
+            invited_by: Some(user.email.clone()),
+            created_at: created_at.to_rfc3339(),
+            expires_at: expires_at.to_rfc3339(),
+        }),
+    ))
+}
+
+async fn revoke_invitation(
+    user: Authorized<perm::ManageUsers>,
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Result<StatusCode, ApiError> {
+    let email: Option<String> = sqlx::query_scalar(
+        "DELETE FROM invitations WHERE id = $1 AND accepted_at IS NULL RETURNING email",
+    )
+    .bind(id)
+    .fetch_optional(&state.db)
+    .await
+    .map_err(|e| {
+        error!("Failed to revoke invitation: {e}");
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Failed to revoke invitation",
+        )
+    })?;
+
+    let email = email.ok_or((StatusCode::NOT_FOUND, "Invitation not found"))?;
+    info!(admin = %user.email, email = %email, "Invitation revoked");
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// What the accept page shows before the invitee picks a password.
+async fn get_invitation(State(state): State<AppState>, Path(token): Path<String>) -> Response {
+    let row: Option<(String, String)> = match sqlx::query_as(
+        "SELECT email, role FROM invitations \
+         WHERE token_hash = $1 AND accepted_at IS NULL AND expires_at > now()",
+    )
+    .bind(hash_token(&token))
+    .fetch_optional(&state.db)
+    .await
+    {
+        Ok(row) => row,
+        Err(e) => {
+            error!("Failed to load invitation: {e}");
+            return json_error(StatusCode::INTERNAL_SERVER_ERROR, "Database error");
+        }
+    };
+
+    match row {
+        Some((email, role)) => {
+            Json(serde_json::json!({ "email": email, "role": role })).into_response()
+        }
+        None => json_error(StatusCode::NOT_FOUND, "Invalid or expired invitation"),
+    }
+}
+
+#[derive(Deserialize)]
+struct AcceptInvitationRequest {
+    token: String,
+    password: String,
+}
+
+async fn accept_invitation(
+    State(state): State<AppState>,
+    client: ClientInfo,
+    Json(body): Json<AcceptInvitationRequest>,
+) -> Response {
+    if body.password.len() < 8 {
+        return json_error(
+            StatusCode::BAD_REQUEST,
+            "Password must be at least 8 characters",
+        );
+    }
+
+    let password_hash = match hash_password(&body.password) {
+        Ok(h) => h,
+        Err(e) => {
+            error!("Failed to hash password: {e}");
+            return json_error(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to accept invitation",
+            );
+        }
+    };
+
+    let mut tx = match state.db.begin().await {
+        Ok(tx) => tx,
+        Err(e) => {
+            error!("Failed to begin transaction: {e}");
+            return json_error(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to accept invitation",
+            );
+        }
+    };
+
+    let invitation: Option<(Uuid, String, String)> = match sqlx::query_as(
+        "UPDATE invitations SET accepted_at = now() \
+         WHERE token_hash = $1 AND accepted_at IS NULL AND expires_at > now() \
+         RETURNING id, email, role",
+    )
+    .bind(hash_token(&body.token))
+    .fetch_optional(&mut *tx)
+    .await
+    {
+        Ok(row) => row,
+        Err(e) => {
+            error!("Failed to claim invitation: {e}");
+            return json_error(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to accept invitation",
+            );
+        }
+    };
+
+    let Some((invitation_id, email, role)) = invitation else {
+        warn!("Invalid or expired invitation token");
+        return json_error(StatusCode::NOT_FOUND, "Invalid or expired invitation");
+    };
+
+    let user_id: Option<Uuid> = match sqlx::query_scalar(
+        "INSERT INTO users (email, password_hash, role, is_approved, email_confirmed) \
+         VALUES ($1, $2, $3, TRUE, TRUE) ON CONFLICT (email) DO NOTHING RETURNING id",
+    )
+    .bind(&email)
+    .bind(&password_hash)
+    .bind(&role)
+    .fetch_optional(&mut *tx)
+    .await
+    {
+        Ok(id) => id,
+        Err(e) => {
+            error!("Failed to create invited user: {e}");
+            return json_error(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to accept invitation",
+            );
+        }
+    };
+
+    let Some(user_id) = user_id else {
+        return json_error(
+            StatusCode::CONFLICT,
+            "An account with this email already exists",
+        );
+    };
+
+    let committed = async {
+        sqlx::query("UPDATE invitations SET user_id = $2 WHERE id = $1")
+            .bind(invitation_id)
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await
+    }
+    .await;
+    if let Err(e) = committed {
+        error!("Failed to accept invitation: {e}");
+        return json_error(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Failed to accept invitation",
+        );
+    }
+
+    info!(email = %email, role = %role, "Invitation accepted");
+
+    let user = LoginUser {
+        id: user_id,
+        email: &email,
+        email_confirmed: true,
+        is_approved: true,
+        role: &role,
+    };
+    start_session(&state, &client, &user, None).await
+}
@@ -48,6 +48,26 @@ pub fn load_whitelist() -> anyhow::Result<Arc<Vec<IpNet>>> {
     Ok(Arc::new(entries))
 }
 
+/// Parse a comma-separated list of CIDRs or bare IPs from environment
+/// variable `var` (`default` when unset), skipping invalid entries with a
+/// warning.
+pub fn networks_from_env(var: &str, default: &str) -> Vec<IpNet> {
+    let list = std::env::var(var).unwrap_or_else(|_| default.to_string());
+    list.split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .filter_map(|s| {
+            let net = s
+                .parse::<IpNet>()
+                .or_else(|_| s.parse::<IpAddr>().map(IpNet::from));
+            if net.is_err() {
+                warn!(var, entry = s, "Ignoring invalid network");
+            }
+            net.ok()
+        })
+        .collect()
+}
+
 /// Middleware that rejects requests from IPs not in the whitelist or geo radius.
 pub async fn check(
     whitelist: Arc<Vec<IpNet>>,
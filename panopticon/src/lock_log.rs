@@ -1,30 +1,40 @@
 use sqlx::PgPool;
 use uuid::Uuid;
 
-/// Record a lock state change to the database.
+/// Record a lock state change to the database and return the new row's ID.
+/// `command_id` is the lock command whose response reported this state, if
+/// any.
 pub async fn record(
     db: &PgPool,
     device_id: &str,
     lock_state: &str,
     source: &str,
     user_id: Option<Uuid>,
-) {
-    if let Err(e) = sqlx::query(
-        "INSERT INTO lock_state_log (device_id, lock_state, source, user_id) VALUES ($1, $2, $3, $4)",
+    command_id: Option<Uuid>,
+) -> Option<Uuid> {
+    match sqlx::query_scalar(
+        "INSERT INTO lock_state_log (device_id, lock_state, source, user_id, command_id) \
+         VALUES ($1, $2, $3, $4, $5) RETURNING id",
     )
     .bind(device_id)
     .bind(lock_state)
     .bind(source)
     .bind(user_id)
-    .execute(db)
+    .bind(command_id)
+    .fetch_one(db)
     .await
     {
-        tracing::error!(
-            device_id,
-            lock_state,
-            source,
-            ?user_id,
-            "Failed to log lock state change: {e:#}"
-        );
+        Ok(id) => Some(id),
+        Err(e) => {
+            tracing::error!(
+                device_id,
+                lock_state,
+                source,
+                ?user_id,
+                ?command_id,
+                "Failed to log lock state change: {e:#}"
+            );
+            None
+        }
     }
 }
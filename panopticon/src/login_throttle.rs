@@ -0,0 +1,414 @@
+//! Brute-force protection for password login and password reset.
+//!
+//! Attempts are counted per account (email) and per client IP in separate
+//! buckets. Each attempt is reserved before it is checked and given back if
+//! it succeeds, so what remains are failures. After a few free attempts each
+//! further failure doubles the lockout, up to a cap; a bucket forgets its
+//! count after a quiet day. The account owner is emailed once failures reach
+//! [`NOTIFY_AFTER`]. Admins can list and clear lockouts.
+
+use std::net::IpAddr;
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::{delete, get},
+    Json, Router,
+};
+use chrono::{DateTime, Duration, Utc};
+use serde::Serialize;
+use sqlx::PgPool;
+use tracing::{error, info, warn};
+
+use crate::email_auth::json_error;
+use crate::middleware::Authorized;
+use crate::permissions::perm;
+use crate::AppState;
+
+type ApiError = (StatusCode, &'static str);
+
+/// Account failures after which the owner is emailed.
+const NOTIFY_AFTER: i32 = 5;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Bucket {
+    LoginAccount,
+    LoginIp,
+    ResetAccount,
+    ResetIp,
+}
+
+/// How quickly a bucket locks up.
+struct Policy {
+    /// Failures allowed before any lockout.
+    free_attempts: i32,
+    /// Lockout after the first failure past the free ones; doubles after that.
+    base: Duration,
+    max: Duration,
+}
+
+impl Bucket {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::LoginAccount => "login_account",
+            Self::LoginIp => "login_ip",
+            Self::ResetAccount => "reset_account",
+            Self::ResetIp => "reset_ip",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "login_account" => Some(Self::LoginAccount),
+            "login_ip" => Some(Self::LoginIp),
+            "reset_account" => Some(Self::ResetAccount),
+            "reset_ip" => Some(Self::ResetIp),
+            _ => None,
+        }
+    }
+
+    fn policy(self) -> Policy {
+        match self {
+            Self::LoginAccount => Policy {
+                free_attempts: 3,
+                base: Duration::seconds(2),
+                max: Duration::minutes(15),
+            },
+            // Several people may share one address, so IPs get more slack.
+            Self::LoginIp => Policy {
+                free_attempts: 10,
+                base: Duration::seconds(1),
+                max: Duration::minutes(15),
+            },
+            // Every reset request counts, successful or not.
+            Self::ResetAccount => Policy {
+                free_attempts: 3,
+                base: Duration::minutes(1),
+                max: Duration::hours(1),
+            },
+            Self::ResetIp => Policy {
+                free_attempts: 10,
+                base: Duration::seconds(30),
+                max: Duration::hours(1),
+            },
+        }
+    }
+}
+
+/// Lockout after the `failures`-th consecutive failure.
+fn lockout(policy: &Policy, failures: i32) -> Duration {
+    let excess = failures - policy.free_attempts;
+    if excess <= 0 {
+        return Duration::zero();
+    }
+    // Past 2^20 the cap has long been reached; avoid overflow.
+    let factor = 1i32 << (excess - 1).min(20);
+    (policy.base * factor).min(policy.max)
+}
+
+/// Why [`reserve`] refused an attempt.
+#[derive(Debug)]
+pub enum Refused {
+    /// A bucket is locked for this long.
+    Locked(Duration),
+    /// The throttle couldn't be checked, so the attempt isn't allowed.
+    Unavailable,
+}
+
+impl IntoResponse for Refused {
+    fn into_response(self) -> Response {
+        match self {
+            Self::Locked(wait) => too_many_attempts(wait),
+            Self::Unavailable => json_error(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "Sign-in is temporarily unavailable, please try again later",
+            ),
+        }
+    }
+}
+
+/// Count an attempt against each bucket before it is verified. Every bucket
+/// is checked first, and the attempt is refused with the time left if any is
+/// locked; only then are they all charged, in one transaction, each with the
+/// lockout the attempt earns. So a locked IP can't run up an account's count,
+/// and concurrent attempts can't all get in before the first failure is
+/// recorded. Returns the attempt's number in each bucket; an attempt that
+/// turns out to be genuine gives its count back with [`release`] or
+/// [`clear`]. Database errors refuse the attempt.
+pub async fn reserve(db: &PgPool, keys: &[(Bucket, &str)]) -> Result<Vec<i32>, Refused> {
+    match try_reserve(db, keys).await {
+        Ok(Ok(attempts)) => Ok(attempts),
+        Ok(Err(wait)) => Err(Refused::Locked(wait.max(Duration::seconds(1)))),
+        Err(e) => {
+            error!("Failed to reserve login attempt: {e}");
+            Err(Refused::Unavailable)
+        }
+    }
+}
+
+async fn try_reserve(
+    db: &PgPool,
+    keys: &[(Bucket, &str)],
+) -> Result<Result<Vec<i32>, Duration>, sqlx::Error> {
+    let buckets: Vec<&str> = keys.iter().map(|(bucket, _)| bucket.as_str()).collect();
+    let subjects: Vec<&str> = keys.iter().map(|&(_, subject)| subject).collect();
+
+    let mut tx = db.begin().await?;
+    // Lock the existing rows so concurrent attempts on the same buckets queue
+    // up behind this one.
+    let locked_until: Option<DateTime<Utc>> = sqlx::query_scalar(
+        "SELECT max(locked_until) FROM ( \
+             SELECT locked_until FROM auth_throttle \
+             WHERE (bucket, subject) IN (SELECT * FROM unnest($1::text[], $2::text[])) \
+             ORDER BY bucket, subject FOR UPDATE \
+         ) t WHERE locked_until > now()",
+    )
+    .bind(&buckets)
+    .bind(&subjects)
+    .fetch_one(&mut *tx)
+    .await?;
+    if let Some(until) = locked_until {
+        return Ok(Err(until - Utc::now()));
+    }
+
+    let mut attempts = Vec::with_capacity(keys.len());
+    for &(bucket, subject) in keys {
+        let policy = bucket.policy();
+        // Lockout after each attempt number; the last entry is the cap.
+        let schedule: Vec<i64> = (1..=policy.free_attempts + 21)
+            .map(|n| lockout(&policy, n).num_seconds())
+            .collect();
+
+        let reserved: Option<i32> = sqlx::query_scalar(
+            "INSERT INTO auth_throttle (bucket, subject, failures, locked_until) \
+             VALUES ($1, $2, 1, now() + ($3::bigint[])[1] * interval '1 second') \
+             ON CONFLICT (bucket, subject) DO UPDATE SET \
+                failures = CASE WHEN auth_throttle.last_failure_at < now() - interval '1 day' \
+                                THEN 1 ELSE auth_throttle.failures + 1 END, \
+                last_failure_at = now(), \
+                locked_until = now() + ($3::bigint[])[LEAST( \
+                    CASE WHEN auth_throttle.last_failure_at < now() - interval '1 day' \
+                         THEN 1 ELSE auth_throttle.failures + 1 END, \
+                    cardinality($3::bigint[]))] * interval '1 second' \
+             WHERE auth_throttle.locked_until IS NULL OR auth_throttle.locked_until <= now() \
+             RETURNING failures",
+        )
+        .bind(bucket.as_str())
+        .bind(subject)
+        .bind(&schedule)
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        // Only a row inserted and locked by a concurrent attempt since the
+        // check above can refuse the update.
+        let Some(n) = reserved else {
+            return Ok(Err(Duration::seconds(1)));
+        };
+
+        let wait = lockout(&policy, n);
+        if wait > Duration::zero() {
+            warn!(
+                bucket = bucket.as_str(),
+                subject,
+                attempts = n,
+                seconds = wait.num_seconds(),
+                "Authentication attempts throttled"
+            );
+        }
+        attempts.push(n);
+    }
+
+    tx.commit().await?;
+    Ok(Ok(attempts))
+}
+
+/// Give back an attempt reserved with [`reserve`] that turned out to be
+/// genuine, lifting the lockout it set.
+pub async fn release(db: &PgPool, bucket: Bucket, subject: &str) {
+    if let Err(e) = sqlx::query(
+        "UPDATE auth_throttle SET failures = GREATEST(failures - 1, 0), locked_until = NULL \
+         WHERE bucket = $1 AND subject = $2",
+    )
+    .bind(bucket.as_str())
+    .bind(subject)
+    .execute(db)
+    .await
+    {
+        error!("Failed to release login attempt: {e}");
+    }
+}
+
+/// Report a failed sign-in for an email (which may not belong to any
+/// account), given the attempt number [`reserve`] returned for its
+/// `LoginAccount` bucket. The owner is emailed when failures pile up.
+pub async fn login_failed(state: &AppState, email: &str, attempt: i32, ip: Option<IpAddr>) {
+    if attempt != NOTIFY_AFTER {
+        return;
+    }
+
+    let exists: bool =
+        match sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM users WHERE email = $1)")
+            .bind(email)
+            .fetch_one(&state.db)
+            .await
+        {
+            Ok(exists) => exists,
+            Err(e) => {
+                error!("Failed to look up user for lockout notice: {e}");
+                return;
+            }
+        };
+    if !exists {
+        return;
+    }
+
+    // Sent in the background so response timing doesn't reveal whether the
+    // account exists.
+    let mailer = state.mailer.clone();
+    let email = email.to_string();
+    tokio::spawn(async move {
+        if let Err(e) = mailer.send_failed_login_email(&email, attempt, ip).await {
+            error!("Failed to send failed-login notice: {e}");
+        }
+    });
+}
+
+/// Forget failures for a bucket, e.g. after a successful login.
+pub async fn clear(db: &PgPool, bucket: Bucket, subject: &str) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query("DELETE FROM auth_throttle WHERE bucket = $1 AND subject = $2")
+        .bind(bucket.as_str())
+        .bind(subject)
+        .execute(db)
+        .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// 429 response telling the client how long to wait.
+fn too_many_attempts(wait: Duration) -> Response {
+    let seconds = (wait.num_milliseconds() + 999) / 1000;
+    let mut response = json_error(
+        StatusCode::TOO_MANY_REQUESTS,
+        "Too many attempts, please try again later",
+    );
+    response
+        .headers_mut()
+        .insert("retry-after", seconds.max(1).to_string().parse().unwrap());
+    response
+}
+
+// ── Admin ───────────────────────────────────────────────────────────────────
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/admin/lockouts", get(list_lockouts))
+        .route("/admin/lockouts/{bucket}/{subject}", delete(clear_lockout))
+}
+
+#[derive(Serialize)]
+struct LockoutInfo {
+    bucket: String,
+    subject: String,
+    failures: i32,
+    last_failure_at: String,
+    locked_until: Option<String>,
+}
+
+type LockoutRow = (String, String, i32, DateTime<Utc>, Option<DateTime<Utc>>);
+
+async fn list_lockouts(
+    _user: Authorized<perm::ManageUsers>,
+    State(state): State<AppState>,
+) -> Result<Json<Vec<LockoutInfo>>, ApiError> {
+    let rows: Vec<LockoutRow> = sqlx::query_as(
+        "SELECT bucket, subject, failures, last_failure_at, locked_until FROM auth_throttle \
+         WHERE locked_until > now() OR last_failure_at > now() - interval '1 day' \
+         ORDER BY last_failure_at DESC",
+    )
+    .fetch_all(&state.db)
+    .await
+    .map_err(|e| {
+        error!("Failed to list lockouts: {e}");
+        (StatusCode::INTERNAL_SERVER_ERROR, "Failed to list lockouts")
+    })?;
+
+    let lockouts = rows
+        .into_iter()
+        .map(
+            |(bucket, subject, failures, last_failure_at, locked_until)| LockoutInfo {
+                bucket,
+                subject,
+                failures,
+                last_failure_at: last_failure_at.to_rfc3339(),
+                locked_until: locked_until
+                    .filter(|t| *t > Utc::now())
+                    .map(|t| t.to_rfc3339()),
+            },
+        )
+        .collect();
+
+    Ok(Json(lockouts))
+}
+
+async fn clear_lockout(
+    user: Authorized<perm::ManageUsers>,
+    State(state): State<AppState>,
+    Path((bucket, subject)): Path<(String, String)>,
+) -> Result<StatusCode, ApiError> {
+    let bucket = Bucket::parse(&bucket).ok_or((StatusCode::BAD_REQUEST, "Unknown bucket"))?;
+
+    let cleared = clear(&state.db, bucket, &subject).await.map_err(|e| {
+        error!("Failed to clear lockout: {e}");
+        (StatusCode::INTERNAL_SERVER_ERROR, "Failed to clear lockout")
+    })?;
+    if !cleared {
+        return Err((StatusCode::NOT_FOUND, "No lockout for that subject"));
+    }
+
+    info!(admin = %user.email, bucket = bucket.as_str(), subject, "Lockout cleared");
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn free_attempts_do_not_lock() {
+        let policy = Bucket::LoginAccount.policy();
+        for failures in 0..=policy.free_attempts {
+            assert_eq!(lockout(&policy, failures), Duration::zero());
+        }
+    }
+
+    #[test]
+    fn lockout_doubles_then_caps() {
+        let policy = Bucket::LoginAccount.policy();
+        assert_eq!(lockout(&policy, 4), Duration::seconds(2));
+        assert_eq!(lockout(&policy, 5), Duration::seconds(4));
+        assert_eq!(lockout(&policy, 6), Duration::seconds(8));
+        assert_eq!(lockout(&policy, 20), Duration::minutes(15));
+        assert_eq!(lockout(&policy, i32::MAX), Duration::minutes(15));
+    }
+
+    #[test]
+    fn refusals_never_let_the_attempt_through() {
+        let locked = Refused::Locked(Duration::milliseconds(1500)).into_response();
+        assert_eq!(locked.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(locked.headers()["retry-after"], "2");
+        let unavailable = Refused::Unavailable.into_response();
+        assert_eq!(unavailable.status(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[test]
+    fn buckets_round_trip() {
+        for bucket in [
+            Bucket::LoginAccount,
+            Bucket::LoginIp,
+            Bucket::ResetAccount,
+            Bucket::ResetIp,
+        ] {
+            assert_eq!(Bucket::parse(bucket.as_str()), Some(bucket));
+        }
+    }
+}
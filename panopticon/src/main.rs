@@ -1,19 +1,38 @@
+mod alerts;
 mod api;
+mod api_tokens;
 mod auth_store;
+mod commands;
 mod db;
+mod device_status;
+mod digest;
 mod email;
 mod email_auth;
 mod geo_access;
+mod grants;
+mod invitations;
 mod ip_whitelist;
 pub mod lock_log;
+mod login_throttle;
 mod middleware;
 mod mqtt;
+mod notification_content;
+mod notification_prefs;
+mod notify_channels;
 mod oauth;
+mod oidc;
+mod outbox;
+mod outgoing_webhooks;
+mod permissions;
 mod push;
+mod push_actions;
+mod relock;
 mod sentinel;
 mod session;
 mod tcp;
+mod totp;
 pub mod utec;
+mod webauthn;
 mod webhook;
 mod ws;
 
@@ -25,7 +44,6 @@ use axum::{
 use include_dir::{include_dir, Dir};
 use mime_guess::from_path;
 use sqlx::PgPool;
-use tokio::sync::broadcast;
 use tower_http::trace::{DefaultOnResponse, TraceLayer};
 use tracing::{info, Level};
 
@@ -43,7 +61,11 @@ pub struct AppState {
     pub mailer: Mailer,
     pub push_config: Option<PushConfig>,
     pub sentinel_secret: String,
-    pub events: broadcast::Sender<ws::WsEvent>,
+    pub events: outbox::EventBus,
+    pub alert_config: alerts::AlertConfig,
+    pub command_queue: commands::CommandQueue,
+    pub oidc: Option<std::sync::Arc<oidc::OidcProvider>>,
+    pub device_names: notification_content::DeviceNames,
 }
 
 #[tokio::main]
@@ -61,6 +83,7 @@ async fn main() -> anyhow::Result<()> {
         .init();
 
     let db = db::init_pool().await?;
+    commands::expire_stale(&db).await;
     let auth_store = AuthStore::new()?;
     let mailer = Mailer::new()?;
     let push_config = PushConfig::new()?;
@@ -73,23 +96,11 @@ async fn main() -> anyhow::Result<()> {
     let sentinel_secret =
         std::env::var("SENTINEL_SECRET").unwrap_or_else(|_| "changeme".to_string());
 
-    let (events_tx, _) = broadcast::channel::<ws::WsEvent>(64);
-
-    // Spawn email notifier on access events
-    let email_rx = events_tx.subscribe();
-    tokio::spawn(email::spawn_email_notifier(
-        email_rx,
-        db.clone(),
-        mailer.clone(),
-    ));
-
-    // Spawn push notifier if VAPID keys are configured
-    if let Some(ref pc) = push_config {
-        let push_rx = events_tx.subscribe();
-        tokio::spawn(push::spawn_push_notifier(push_rx, db.clone(), pc.clone()));
-    }
+    let (events, outbox_rx) = outbox::EventBus::new(64);
 
     let mqtt_config = mqtt::MqttConfig::from_env();
+    let alert_config = alerts::AlertConfig::from_env();
+    let oidc = oidc::OidcProvider::from_env()?.map(std::sync::Arc::new);
 
     let state = AppState {
         db,
@@ -97,24 +108,63 @@ async fn main() -> anyhow::Result<()> {
         mailer,
         push_config,
         sentinel_secret,
-        events: events_tx,
+        events,
+        alert_config,
+        command_queue: commands::CommandQueue::default(),
+        oidc,
+        device_names: notification_content::DeviceNames::default(),
     };
 
+    // Spawn durable notification and webhook delivery
+    tokio::spawn(outbox::spawn_outbox_writer(outbox_rx, state.clone()));
+    tokio::spawn(outbox::spawn_delivery_worker(state.clone()));
+    tokio::spawn(outgoing_webhooks::spawn_delivery_worker(state.clone()));
+
     // Spawn MQTT bridge if configured
     if let Some(ref mc) = mqtt_config {
         let mqtt_rx = state.events.subscribe();
         tokio::spawn(mqtt::spawn_mqtt_bridge(mqtt_rx, state.clone(), mc.clone()));
     }
 
+    // Spawn low-battery / offline alert monitor
+    tokio::spawn(alerts::spawn_alert_monitor(
+        state.clone(),
+        state.alert_config.clone(),
+    ));
+
+    // Spawn auto-relock watchdog
+    let relock_rx = state.events.subscribe();
+    tokio::spawn(relock::spawn_relock_watchdog(relock_rx, state.clone()));
+
     // Spawn sentinel TCP listener on port 8008
     tokio::spawn(tcp::spawn_tcp_listener(state.clone()));
 
     // Routes behind the IP whitelist (all normal app routes)
     let protected = Router::new()
         .nest("/api/auth", email_auth::router())
+        .nest("/api/auth", totp::router())
+        .nest("/api/auth", webauthn::router())
+        .nest("/api/auth", session::router())
+        .nest("/api/auth", invitations::router())
+        .nest("/api/auth", oidc::router())
         .nest("/api/sentinel", sentinel::router())
         .nest("/api", push::router())
+        .nest("/api", push_actions::router())
         .nest("/api", api::router())
+        .nest("/api", api_tokens::router())
+        .nest("/api", notify_channels::router())
+        .nest("/api", notification_prefs::router())
+        .nest("/api", outbox::router())
+        .nest("/api", notification_content::router())
+        .nest("/api", outgoing_webhooks::router())
+        .nest("/api", alerts::router())
+        .nest("/api", relock::router())
+        .nest("/api", commands::router())
+        .nest("/api", grants::router())
+        .nest("/api", totp::policy_router())
+        .nest("/api", session::admin_router())
+        .nest("/api", login_throttle::router())
+        .nest("/api", invitations::admin_router())
         .nest("/api", ws::router())
         .nest("/auth", oauth::router())
         .fallback(handle_static_file)
@@ -154,7 +204,11 @@ async fn main() -> anyhow::Result<()> {
     let addr = "127.0.0.1:1337";
     info!("Panopticon listening on {addr}");
     let listener = tokio::net::TcpListener::bind(addr).await?;
-    axum::serve(listener, app).await?;
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<std::net::SocketAddr>(),
+    )
+    .await?;
 
     Ok(())
 }
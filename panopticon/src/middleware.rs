@@ -1,3 +1,6 @@
+use std::marker::PhantomData;
+use std::ops::Deref;
+
 use axum::{
     extract::FromRequestParts,
     http::{request::Parts, StatusCode},
@@ -7,7 +10,9 @@ use axum::{
 use serde::Serialize;
 use uuid::Uuid;
 
-use crate::session::{extract_session_id_from_cookies, get_user_by_session};
+use crate::api_tokens::get_user_by_token;
+use crate::permissions::{RequiredPermission, Role, Scope};
+use crate::session::{extract_session_id_from_cookies, get_user_by_session, SecondFactor};
 use crate::AppState;
 
 #[derive(Debug, Clone, Serialize)]
@@ -16,6 +21,22 @@ pub struct AuthUser {
     pub email: String,
     pub email_confirmed: bool,
     pub is_approved: bool,
+    pub role: Role,
+    /// Set when the request authenticated with an API token.
+    #[serde(skip)]
+    pub token_scopes: Option<Vec<Scope>>,
+    /// The second factor the browser session's login was completed with.
+    #[serde(skip)]
+    pub second_factor: Option<SecondFactor>,
+}
+
+impl AuthUser {
+    /// Browser sessions carry every scope; API tokens only their own.
+    pub fn has_scope(&self, scope: Scope) -> bool {
+        self.token_scopes
+            .as_ref()
+            .is_none_or(|scopes| scopes.contains(&scope))
+    }
 }
 
 impl<S> FromRequestParts<S> for AuthUser
@@ -29,6 +50,21 @@ where
         let app_state = AppState::from_ref(state);
         let pool = &app_state.db;
 
+        if let Some(token) = parts
+            .headers
+            .get("authorization")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "))
+        {
+            return get_user_by_token(pool, token.trim()).await.ok_or_else(|| {
+                (
+                    StatusCode::UNAUTHORIZED,
+                    Json(serde_json::json!({"error": "Invalid or expired token"})),
+                )
+                    .into_response()
+            });
+        }
+
         let cookie_header = parts
             .headers
             .get("cookie")
@@ -61,6 +97,87 @@ where
     }
 }
 
+/// A user signed in with a browser session rather than an API token. Used by
+/// account security endpoints (second factors, passkeys, API tokens) so a
+/// leaked token can't be used to entrench itself. Derefs to [`AuthUser`].
+pub struct SessionUser(pub AuthUser);
+
+impl Deref for SessionUser {
+    type Target = AuthUser;
+
+    fn deref(&self) -> &AuthUser {
+        &self.0
+    }
+}
+
+impl<S> FromRequestParts<S> for SessionUser
+where
+    S: Send + Sync,
+    AppState: FromRef<S>,
+{
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let user = AuthUser::from_request_parts(parts, state).await?;
+
+        if user.token_scopes.is_some() {
+            return Err((
+                StatusCode::FORBIDDEN,
+                Json(serde_json::json!({"error": "Sign in to do this"})),
+            )
+                .into_response());
+        }
+
+        Ok(Self(user))
+    }
+}
+
+/// An approved user whose role grants permission `P` (one of the marker types
+/// in `permissions::perm`). Derefs to the underlying [`AuthUser`].
+pub struct Authorized<P> {
+    pub user: AuthUser,
+    _permission: PhantomData<P>,
+}
+
+impl<P> Deref for Authorized<P> {
+    type Target = AuthUser;
+
+    fn deref(&self) -> &AuthUser {
+        &self.user
+    }
+}
+
+impl<S, P> FromRequestParts<S> for Authorized<P>
+where
+    S: Send + Sync,
+    AppState: FromRef<S>,
+    P: RequiredPermission,
+{
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let user = AuthUser::from_request_parts(parts, state).await?;
+
+        let scoped = user
+            .token_scopes
+            .as_ref()
+            .is_none_or(|scopes| P::SCOPES.iter().any(|s| scopes.contains(s)));
+
+        if !user.is_approved || !user.role.has(P::PERMISSION) || !scoped {
+            return Err((
+                StatusCode::FORBIDDEN,
+                Json(serde_json::json!({"error": "Not authorized"})),
+            )
+                .into_response());
+        }
+
+        Ok(Self {
+            user,
+            _permission: PhantomData,
+        })
+    }
+}
+
 /// Helper trait to extract AppState from state (mirrors axum's FromRef pattern).
 pub trait FromRef<T> {
     fn from_ref(input: &T) -> Self;
@@ -1,3 +1,4 @@
+use std::collections::HashMap;
 use std::time::Duration;
 
 use rumqttc::{AsyncClient, Event, Incoming, LastWill, MqttOptions, Publish, QoS};
@@ -5,7 +6,12 @@ use serde_json::json;
 use sqlx::PgPool;
 use tokio::sync::broadcast;
 use tracing::{error, info, warn};
+use uuid::Uuid;
 
+use crate::commands::{self, CommandStatus, LockAction};
+use crate::grants::{self, Denial};
+use crate::permissions::Role;
+use crate::totp;
 use crate::ws::WsEvent;
 use crate::AppState;
 
@@ -19,6 +25,11 @@ pub struct MqttConfig {
     pub password: Option<String>,
     pub client_id: String,
     pub discovery_prefix: String,
+    /// Email of the user MQTT lock commands act as. Commands are checked
+    /// against that user's role, device grants and the 2FA unlock policy.
+    /// Unset, no role check applies: locking is unrestricted and unlocking
+    /// is only allowed while no grants exist and the 2FA policy is off.
+    pub command_user: Option<String>,
 }
 
 impl MqttConfig {
@@ -42,6 +53,19 @@ impl MqttConfig {
         let discovery_prefix =
             std::env::var("MQTT_DISCOVERY_PREFIX").unwrap_or_else(|_| "homeassistant".into());
 
+        let command_user = std::env::var("MQTT_COMMAND_USER")
+            .ok()
+            .map(|e| e.trim().to_lowercase())
+            .filter(|e| !e.is_empty());
+        if command_user.is_none() {
+            error!(
+                "MQTT_COMMAND_USER not set: MQTT lock commands are not checked against any \
+                 user's role, so anyone who can publish to the broker can lock and unlock \
+                 every door until device grants or the 2FA unlock policy are in use. Set \
+                 MQTT_COMMAND_USER to a panopticon user to apply the same checks as the API"
+            );
+        }
+
         info!(host, port, client_id, "MQTT bridge enabled");
 
         Some(Self {
@@ -51,6 +75,7 @@ impl MqttConfig {
             password,
             client_id,
             discovery_prefix,
+            command_user,
         })
     }
 }
@@ -118,6 +143,9 @@ fn lock_discovery(config: &MqttConfig, device_id: &str, device_name: &str) -> se
         "payload_unlock": "UNLOCK",
         "state_locked": "LOCKED",
         "state_unlocked": "UNLOCKED",
+        "state_locking": "LOCKING",
+        "state_unlocking": "UNLOCKING",
+        "state_jammed": "JAMMED",
         "device": device_obj(config, device_id, device_name),
     })
 }
@@ -297,10 +325,42 @@ async fn publish_sentinel_discovery(client: &AsyncClient, config: &MqttConfig, d
     }
 }
 
+/// The Home Assistant lock state for a U-Tec lock state.
+fn mqtt_lock_state(lock_state: &str) -> &'static str {
+    match lock_state {
+        "locked" => "LOCKED",
+        "jammed" => "JAMMED",
+        _ => "UNLOCKED",
+    }
+}
+
+/// The lock state to publish for a command status update, if any. Confirmed
+/// commands are published via the LockState event that accompanies them.
+/// A failed or timed-out command is only a jam if the lock says so;
+/// otherwise the state it was in before is restored (empty, i.e. unknown, if
+/// there is none), replacing the LOCKING/UNLOCKING sent while it was pending.
+fn command_lock_state(
+    status: &str,
+    command: &str,
+    lock_state: Option<&str>,
+    last: Option<&'static str>,
+) -> Option<&'static str> {
+    match (status, command) {
+        ("pending", "lock") => Some("LOCKING"),
+        ("pending", _) => Some("UNLOCKING"),
+        ("failed" | "timed_out", _) => Some(match lock_state {
+            Some(ls) => mqtt_lock_state(ls),
+            None => last.unwrap_or(""),
+        }),
+        _ => None,
+    }
+}
+
 async fn publish_all_states(
     client: &AsyncClient,
     state: &AppState,
     known_lock_ids: &mut Vec<String>,
+    lock_states: &mut HashMap<String, &'static str>,
 ) {
     // Lock states
     if let Some(utec) = state.auth_store.client().await {
@@ -316,30 +376,12 @@ async fn publish_all_states(
                             let device_states = states.iter().find(|s| s.id == lock.id);
                             if let Some(ds) = device_states {
                                 if let Some(ls) = ds.lock_state() {
-                                    let mqtt_state =
-                                        if ls == "locked" { "LOCKED" } else { "UNLOCKED" };
+                                    let mqtt_state = mqtt_lock_state(&ls);
+                                    lock_states.insert(lock.id.clone(), mqtt_state);
                                     publish(client, &lock_state_topic(&lock.id), mqtt_state).await;
                                 }
                                 if let Some(battery) = ds.battery_level() {
-                                    let (min, max) = lock
-                                        .attributes
-                                        .as_ref()
-                                        .and_then(|a| a.get("batteryLevelRange"))
-                                        .map(|r| {
-                                            let min =
-                                                r.get("min").and_then(|v| v.as_u64()).unwrap_or(0);
-                                            let max = r
-                                                .get("max")
-                                                .and_then(|v| v.as_u64())
-                                                .unwrap_or(100);
-                                            (min, max)
-                                        })
-                                        .unwrap_or((0, 100));
-                                    let pct = if max > min {
-                                        ((battery.saturating_sub(min)) * 100) / (max - min)
-                                    } else {
-                                        battery
-                                    };
+                                    let pct = lock.battery_percent(battery);
                                     publish(client, &battery_topic(&lock.id), &pct.to_string())
                                         .await;
                                 }
@@ -357,6 +399,7 @@ async fn publish_all_states(
         // Auth unavailable — publish unknown state so HA doesn't show stale data.
         // Any value not matching "LOCKED" or "UNLOCKED" triggers HA's "unknown" state.
         warn!("MQTT: U-Tec auth unavailable, publishing unknown lock state");
+        lock_states.clear();
         for device_id in known_lock_ids.iter() {
             publish(client, &lock_state_topic(device_id), "").await;
         }
@@ -386,12 +429,45 @@ async fn publish_all_states(
 
 // ── Command handlers ────────────────────────────────────────────────────────
 
-async fn handle_lock_command(state: &AppState, device_id: &str, payload: &str) {
+async fn handle_lock_command(
+    state: &AppState,
+    config: &MqttConfig,
+    device_id: &str,
+    payload: &str,
+) {
     let command = payload.trim().to_uppercase();
-    if command != "LOCK" && command != "UNLOCK" {
-        warn!("MQTT: ignoring unknown lock command: {payload}");
-        return;
-    }
+    let action = match command.as_str() {
+        "LOCK" => LockAction::Lock,
+        "UNLOCK" => LockAction::Unlock,
+        _ => {
+            warn!("MQTT: ignoring unknown lock command: {payload}");
+            return;
+        }
+    };
+
+    let user_id = match config.command_user {
+        Some(ref email) => match authorize_command(state, email, device_id, action).await {
+            Some(id) => Some(id),
+            None => return,
+        },
+        None if action == LockAction::Unlock && unlock_restricted(state).await => {
+            error!(
+                device_id,
+                "MQTT: unlock refused, MQTT_COMMAND_USER is not set"
+            );
+            grants::record_denial(
+                &state.db,
+                None,
+                device_id,
+                action,
+                "mqtt",
+                Denial::UnknownUser,
+            )
+            .await;
+            return;
+        }
+        None => None,
+    };
 
     let Some(utec) = state.auth_store.client().await else {
         error!("MQTT: no U-Tec client available for lock command");
@@ -411,20 +487,87 @@ async fn handle_lock_command(state: &AppState, device_id: &str, payload: &str) {
         return;
     };
 
-    let result = if command == "LOCK" {
-        utec.lock(device).await
-    } else {
-        utec.unlock(device).await
-    };
+    match commands::execute(state, &utec, device, action, "mqtt", user_id, None).await {
+        Ok(record) if record.status == CommandStatus::Failed.as_str() => {
+            error!(
+                device_id,
+                command,
+                error = record.error.as_deref().unwrap_or(""),
+                "MQTT: lock command failed"
+            );
+        }
+        Ok(record) => {
+            info!(device_id, command, status = %record.status, "MQTT: lock command executed");
+        }
+        Err(e) => error!(device_id, command, "MQTT: lock command failed: {e}"),
+    }
+}
 
-    match result {
-        Ok(results) => {
-            crate::api::handle_lock_response(state, device_id, device, &results, "mqtt", None)
-                .await;
-            info!(device_id, command, "MQTT: lock command executed");
+/// Whether unlocks need an acting user: device grants exist or the 2FA unlock
+/// policy is on. Without `MQTT_COMMAND_USER` these can't be checked, so MQTT
+/// unlocks fail closed.
+async fn unlock_restricted(state: &AppState) -> bool {
+    grants::in_use(&state.db).await || totp::required_for_unlock(&state.db).await
+}
+
+/// Check an MQTT lock command against the configured command user's role,
+/// device grants and the 2FA unlock policy. Returns the user's id if the
+/// command may proceed.
+async fn authorize_command(
+    state: &AppState,
+    email: &str,
+    device_id: &str,
+    action: LockAction,
+) -> Option<Uuid> {
+    let row: Option<(Uuid, bool, String)> = match sqlx::query_as(
+        "SELECT id, is_approved AND suspended_at IS NULL, role FROM users WHERE email = $1",
+    )
+    .bind(email)
+    .fetch_optional(&state.db)
+    .await
+    {
+        Ok(row) => row,
+        Err(e) => {
+            error!("MQTT: failed to look up command user: {e:#}");
+            return None;
         }
-        Err(e) => error!(device_id, command, "MQTT: lock command failed: {e:#}"),
+    };
+
+    let Some((user_id, _, role)) = row.filter(|(_, approved, _)| *approved) else {
+        error!(
+            email,
+            "MQTT: command user not found, not approved or suspended"
+        );
+        grants::record_denial(
+            &state.db,
+            None,
+            device_id,
+            action,
+            "mqtt",
+            Denial::UnknownUser,
+        )
+        .await;
+        return None;
+    };
+
+    if action == LockAction::Unlock && !totp::account_unlock_allowed(&state.db, user_id).await {
+        grants::record_denial(
+            &state.db,
+            Some(user_id),
+            device_id,
+            action,
+            "mqtt",
+            Denial::TwoFactorRequired,
+        )
+        .await;
+        return None;
     }
+
+    let role = Role::parse(&role).unwrap_or(Role::Viewer);
+    grants::check(&state.db, user_id, role, device_id, action, "mqtt")
+        .await
+        .ok()
+        .map(|_| user_id)
 }
 
 async fn handle_mode_command(state: &AppState, payload: &str) {
@@ -493,6 +636,9 @@ pub async fn spawn_mqtt_bridge(
     // Cache of known lock device IDs — used to publish "unknown" state when
     // U-Tec auth is unavailable, so HA doesn't show stale lock state.
     let mut known_lock_ids: Vec<String> = Vec::new();
+    // Last lock state published per device, restored when a command fails
+    // without the lock reporting a new one.
+    let mut lock_states: HashMap<String, &'static str> = HashMap::new();
 
     info!("MQTT bridge started");
 
@@ -526,10 +672,10 @@ pub async fn spawn_mqtt_bridge(
 
                         // Publish discovery configs and current state
                         publish_all_discovery(&client, &config, &state).await;
-                        publish_all_states(&client, &state, &mut known_lock_ids).await;
+                        publish_all_states(&client, &state, &mut known_lock_ids, &mut lock_states).await;
                     }
                     Ok(Event::Incoming(Incoming::Publish(msg))) => {
-                        handle_incoming_publish(&state, &msg).await;
+                        handle_incoming_publish(&state, &config, &msg).await;
                     }
                     Ok(_) => {} // PingResp, SubAck, etc.
                     Err(e) => {
@@ -543,7 +689,7 @@ pub async fn spawn_mqtt_bridge(
             result = rx.recv() => {
                 match result {
                     Ok(event) => {
-                        handle_ws_event(&client, &config, &event).await;
+                        handle_ws_event(&client, &config, &mut lock_states, &event).await;
                     }
                     Err(broadcast::error::RecvError::Lagged(n)) => {
                         warn!("MQTT bridge lagged, skipped {n} events");
@@ -556,13 +702,13 @@ pub async fn spawn_mqtt_bridge(
             }
 
             _ = refresh_interval.tick() => {
-                publish_all_states(&client, &state, &mut known_lock_ids).await;
+                publish_all_states(&client, &state, &mut known_lock_ids, &mut lock_states).await;
             }
         }
     }
 }
 
-async fn handle_incoming_publish(state: &AppState, msg: &Publish) {
+async fn handle_incoming_publish(state: &AppState, config: &MqttConfig, msg: &Publish) {
     let topic = &msg.topic;
     let payload = match std::str::from_utf8(&msg.payload) {
         Ok(s) => s,
@@ -575,7 +721,7 @@ async fn handle_incoming_publish(state: &AppState, msg: &Publish) {
     // panopticon/lock/{device_id}/set
     if let Some(rest) = topic.strip_prefix(&format!("{BASE}/lock/")) {
         if let Some(device_id) = rest.strip_suffix("/set") {
-            handle_lock_command(state, device_id, payload).await;
+            handle_lock_command(state, config, device_id, payload).await;
             return;
         }
     }
@@ -586,16 +732,48 @@ async fn handle_incoming_publish(state: &AppState, msg: &Publish) {
     }
 }
 
-async fn handle_ws_event(client: &AsyncClient, config: &MqttConfig, event: &WsEvent) {
+async fn handle_ws_event(
+    client: &AsyncClient,
+    config: &MqttConfig,
+    lock_states: &mut HashMap<String, &'static str>,
+    event: &WsEvent,
+) {
     match event {
         WsEvent::LockState {
             device_id,
             lock_state,
+            ..
         } => {
-            let mqtt_state = if lock_state == "locked" {
-                "LOCKED"
-            } else {
-                "UNLOCKED"
+            let mqtt_state = mqtt_lock_state(lock_state);
+            lock_states.insert(device_id.clone(), mqtt_state);
+            publish(client, &lock_state_topic(device_id), mqtt_state).await;
+        }
+        WsEvent::BatteryLevel {
+            device_id,
+            battery_level,
+        } => {
+            publish(
+                client,
+                &battery_topic(device_id),
+                &battery_level.to_string(),
+            )
+            .await;
+        }
+        WsEvent::DeviceOnline { device_id, online } => {
+            let payload = if *online { "ON" } else { "OFF" };
+            publish(client, &lock_availability_topic(device_id), payload).await;
+        }
+        WsEvent::CommandStatus {
+            device_id,
+            command,
+            status,
+            lock_state,
+            ..
+        } => {
+            let last = lock_states.get(device_id).copied();
+            let Some(mqtt_state) = command_lock_state(status, command, lock_state.as_deref(), last)
+            else {
+                return;
             };
             publish(client, &lock_state_topic(device_id), mqtt_state).await;
         }
@@ -603,6 +781,7 @@ async fn handle_ws_event(client: &AsyncClient, config: &MqttConfig, event: &WsEv
             tag_id,
             action,
             created_at,
+            ..
         } => {
             let payload = json!({
                 "tag_id": tag_id,
@@ -635,3 +814,45 @@ async fn handle_ws_event(client: &AsyncClient, config: &MqttConfig, event: &WsEv
         _ => {} // CardAdded, CardRemoved, SentinelLog — no MQTT mapping
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_command_status_to_lock_state() {
+        let cases = [
+            ("pending", "lock", None, None, Some("LOCKING")),
+            ("pending", "unlock", None, None, Some("UNLOCKING")),
+            ("confirmed", "lock", Some("locked"), None, None),
+            ("confirmed", "unlock", Some("unlocked"), None, None),
+            (
+                "failed",
+                "lock",
+                Some("jammed"),
+                Some("UNLOCKED"),
+                Some("JAMMED"),
+            ),
+            ("failed", "lock", None, Some("UNLOCKED"), Some("UNLOCKED")),
+            ("failed", "unlock", None, Some("LOCKED"), Some("LOCKED")),
+            ("failed", "unlock", None, None, Some("")),
+            (
+                "timed_out",
+                "lock",
+                None,
+                Some("UNLOCKED"),
+                Some("UNLOCKED"),
+            ),
+            ("timed_out", "unlock", None, Some("LOCKED"), Some("LOCKED")),
+            ("timed_out", "unlock", Some("locked"), None, Some("LOCKED")),
+            ("timed_out", "lock", None, None, Some("")),
+        ];
+        for (status, command, lock_state, last, expected) in cases {
+            assert_eq!(
+                command_lock_state(status, command, lock_state, last),
+                expected,
+                "{status} {command}"
+            );
+        }
+    }
+}
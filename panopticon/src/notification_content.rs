@@ -0,0 +1,646 @@
+//! What notifications say.
+//!
+//! Events only carry IDs. [`message`] resolves them into names people know —
+//! the card's label, the lock's name in U-Tec, the sentinel's name — works
+//! out who caused a lock change, and fills in the subject and plain-text
+//! body template for the kind of event. Every channel sends the same text;
+//! email adds an HTML version around it. Admins can override the built-in
+//! templates per kind in `notification_templates`.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    routing::{get, put},
+    Json, Router,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use tokio::sync::RwLock;
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+use crate::auth_store::AuthStore;
+use crate::middleware::Authorized;
+use crate::notification_prefs::offline_sentinel_name;
+use crate::permissions::perm;
+use crate::ws::WsEvent;
+use crate::AppState;
+
+type ApiError = (StatusCode, &'static str);
+
+/// A kind of notification and its built-in text.
+pub struct Kind {
+    pub key: &'static str,
+    pub description: &'static str,
+    pub subject: &'static str,
+    pub body: &'static str,
+    /// Placeholders available to this kind's templates.
+    pub placeholders: &'static [&'static str],
+}
+
+const SCAN_PLACEHOLDERS: &[&str] = &["card", "tag_id", "reader"];
+const DEVICE_PLACEHOLDERS: &[&str] = &["door", "device_id"];
+
+pub const KINDS: &[Kind] = &[
+    Kind {
+        key: "scan_granted",
+        description: "A card was let in",
+        subject: "Access granted: {card}",
+        body: "{card} was granted access at {reader}.",
+        placeholders: SCAN_PLACEHOLDERS,
+    },
+    Kind {
+        key: "scan_denied",
+        description: "A card was refused",
+        subject: "Access denied: {card}",
+        body: "{card} was refused at {reader}.",
+        placeholders: SCAN_PLACEHOLDERS,
+    },
+    Kind {
+        key: "lock_change",
+        description: "A lock was locked or unlocked",
+        subject: "{door} {state}",
+        body: "{door} was {state} by {actor}.",
+        placeholders: &["door", "device_id", "state", "actor"],
+    },
+    Kind {
+        key: "battery_level",
+        description: "A lock reported its battery level",
+        subject: "{door} battery at {battery}%",
+        body: "The battery in {door} is at {battery}%.",
+        placeholders: &["door", "device_id", "battery"],
+    },
+    Kind {
+        key: "device_online",
+        description: "A lock came back online",
+        subject: "{door} is back online",
+        body: "{door} is reachable again.",
+        placeholders: DEVICE_PLACEHOLDERS,
+    },
+    Kind {
+        key: "device_offline",
+        description: "A lock went offline",
+        subject: "{door} is offline",
+        body: "{door} stopped responding.",
+        placeholders: DEVICE_PLACEHOLDERS,
+    },
+    Kind {
+        key: "device_alert",
+        description: "A lock alert was raised",
+        subject: "Alert: {door}",
+        body: "{message}",
+        placeholders: &["door", "device_id", "message"],
+    },
+    Kind {
+        key: "device_alert_resolved",
+        description: "A lock alert cleared",
+        subject: "Resolved: {door}",
+        body: "{message} (resolved)",
+        placeholders: &["door", "device_id", "message"],
+    },
+    Kind {
+        key: "relock_failed",
+        description: "Auto-relock could not lock a door",
+        subject: "Auto-relock failed: {door}",
+        body: "{door} could not be relocked: {error}",
+        placeholders: &["door", "device_id", "error"],
+    },
+    Kind {
+        key: "sentinel_offline",
+        description: "A sentinel lost its connection",
+        subject: "Sentinel offline: {sentinel}",
+        body: "{sentinel} has disconnected.",
+        placeholders: &["sentinel"],
+    },
+];
+
+pub fn kind(key: &str) -> Option<&'static Kind> {
+    KINDS.iter().find(|k| k.key == key)
+}
+
+/// A rendered notification.
+#[derive(Clone, Debug)]
+pub struct Message {
+    pub subject: String,
+    /// Plain text; may span several lines.
+    pub body: String,
+    /// Display name of the card involved, for grouping digests.
+    pub card: Option<String>,
+    /// Display name of the lock or sentinel involved.
+    pub door: Option<String>,
+}
+
+/// Replace `{name}` placeholders with values from `vars`. Unknown
+/// placeholders are left as they are.
+pub fn fill(template: &str, vars: &BTreeMap<&str, String>) -> String {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(open) = rest.find('{') {
+        out.push_str(&rest[..open]);
+        let after = &rest[open + 1..];
+        match after.find('}').and_then(|close| {
+            let name = &after[..close];
+            vars.get(name).map(|value| (value, close))
+        }) {
+            Some((value, close)) => {
+                out.push_str(value);
+                rest = &after[close + 1..];
+            }
+            None => {
+                out.push('{');
+                rest = after;
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Names of the `{placeholders}` used in a template.
+pub fn placeholders(template: &str) -> Vec<&str> {
+    let mut names = Vec::new();
+    let mut rest = template;
+    while let Some(open) = rest.find('{') {
+        rest = &rest[open + 1..];
+        if let Some(close) = rest.find('}') {
+            let name = &rest[..close];
+            if !name.is_empty() && name.chars().all(|c| c.is_ascii_lowercase() || c == '_') {
+                names.push(name);
+                rest = &rest[close + 1..];
+            }
+        }
+    }
+    names
+}
+
+/// An event's kind and the values for its placeholders.
+struct Described {
+    kind: &'static Kind,
+    vars: BTreeMap<&'static str, String>,
+    card: Option<String>,
+    door: Option<String>,
+}
+
+/// Render the notification for an event, or `None` if the event isn't
+/// notified.
+pub async fn message(state: &AppState, event: &WsEvent) -> Option<Message> {
+    let described = describe(state, event).await?;
+    let custom: Option<(String, String)> =
+        sqlx::query_as("SELECT subject, body FROM notification_templates WHERE kind = $1")
+            .bind(described.kind.key)
+            .fetch_optional(&state.db)
+            .await
+            .unwrap_or_else(|e| {
+                error!("Failed to load notification template: {e}");
+                None
+            });
+    let (subject, body) = match &custom {
+        Some((subject, body)) => (subject.as_str(), body.as_str()),
+        None => (described.kind.subject, described.kind.body),
+    };
+
+    Some(Message {
+        subject: fill(subject, &described.vars),
+        body: fill(body, &described.vars),
+        card: described.card,
+        door: described.door,
+    })
+}
+
+async fn describe(state: &AppState, event: &WsEvent) -> Option<Described> {
+    let db = &state.db;
+    let mut vars = BTreeMap::new();
+
+    // Device events all name the door; look it up once.
+    let device_id = match event {
+        WsEvent::LockState { device_id, .. }
+        | WsEvent::BatteryLevel { device_id, .. }
+        | WsEvent::DeviceOnline { device_id, .. }
+        | WsEvent::DeviceAlert { device_id, .. }
+        | WsEvent::RelockFailed { device_id, .. } => Some(device_id),
+        _ => None,
+    };
+    let door = match device_id {
+        Some(device_id) => {
+            let name = state
+                .device_names
+                .get(&state.auth_store, device_id)
+                .await
+                .unwrap_or_else(|| device_id.clone());
+            vars.insert("door", name.clone());
+            vars.insert("device_id", device_id.clone());
+            Some(name)
+        }
+        None => None,
+    };
+
+    let (key, card, door) = match event {
+        WsEvent::Scan {
+            tag_id,
+            action,
+            sentinel_id,
+            ..
+        } => {
+            let card = card_name(db, tag_id).await;
+            let reader = match sentinel_id {
+                Some(id) => sqlx::query_scalar("SELECT name FROM sentinels WHERE id = $1")
+                    .bind(id)
+                    .fetch_optional(db)
+                    .await
+                    .ok()
+                    .flatten(),
+                None => None,
+            };
+            vars.insert("card", card.clone());
+            vars.insert("tag_id", tag_id.clone());
+            vars.insert(
+                "reader",
+                reader.clone().unwrap_or_else(|| "the card reader".into()),
+            );
+            let key = if action == "granted" {
+                "scan_granted"
+            } else {
+                "scan_denied"
+            };
+            (key, Some(card), reader)
+        }
+        WsEvent::LockState {
+            lock_state, log_id, ..
+        } => {
+            vars.insert("state", lock_state.clone());
+            vars.insert("actor", lock_actor(db, *log_id).await);
+            ("lock_change", None, door)
+        }
+        WsEvent::BatteryLevel { battery_level, .. } => {
+            vars.insert("battery", battery_level.to_string());
+            ("battery_level", None, door)
+        }
+        WsEvent::DeviceOnline { online, .. } => {
+            let key = if *online {
+                "device_online"
+            } else {
+                "device_offline"
+            };
+            (key, None, door)
+        }
+        WsEvent::DeviceAlert {
+            active, message, ..
+        } => {
+            vars.insert("message", message.clone());
+            let key = if *active {
+                "device_alert"
+            } else {
+                "device_alert_resolved"
+            };
+            (key, None, door)
+        }
+        WsEvent::RelockFailed { error, .. } => {
+            vars.insert("error", error.clone());
+            ("relock_failed", None, door)
+        }
+        WsEvent::SentinelDisconnected { id } => {
+            let name = offline_sentinel_name(db, *id).await?;
+            vars.insert("sentinel", name.clone());
+            ("sentinel_offline", None, Some(name))
+        }
+        _ => return None,
+    };
+
+    Some(Described {
+        kind: kind(key)?,
+        vars,
+        card,
+        door,
+    })
+}
+
+/// "Label (tag)" for a labelled card, the tag ID otherwise.
+async fn card_name(db: &PgPool, tag_id: &str) -> String {
+    let label: Option<String> =
+        sqlx::query_scalar("SELECT label FROM access_cards WHERE tag_id = $1")
+            .bind(tag_id)
+            .fetch_optional(db)
+            .await
+            .ok()
+            .flatten()
+            .flatten();
+    match label.filter(|l| !l.trim().is_empty()) {
+        Some(label) => format!("{label} ({tag_id})"),
+        None => tag_id.to_string(),
+    }
+}
+
+/// Who caused a lock change, in words.
+pub(crate) fn actor_label(
+    log_source: &str,
+    user: Option<&str>,
+    command_source: Option<&str>,
+    card: Option<&str>,
+) -> String {
+    match (user, command_source, card) {
+        (Some(user), ..) => user.to_string(),
+        (None, Some("rfid"), Some(card)) => format!("card {card}"),
+        (None, Some("rfid"), None) => "a card".to_string(),
+        (None, Some("auto_relock"), _) => "auto-relock".to_string(),
+        (None, Some("mqtt"), _) => "Home Assistant".to_string(),
+        _ if log_source == "webhook" => "someone at the lock (keypad, key or U-Tec app)".into(),
+        _ => log_source.to_string(),
+    }
+}
+
+/// Log source, user email, command source and command card of a lock change.
+type ActorRow = (String, Option<String>, Option<String>, Option<String>);
+
+/// Look up who caused the lock change logged as `log_id`, naming the card
+/// recorded on the command for RFID unlocks.
+async fn lock_actor(db: &PgPool, log_id: Option<Uuid>) -> String {
+    let Some(log_id) = log_id else {
+        return "an unknown source".to_string();
+    };
+    let row: Option<ActorRow> = sqlx::query_as(
+        "SELECT l.source, u.email, c.source, c.tag_id FROM lock_state_log l \
+         LEFT JOIN users u ON u.id = l.user_id \
+         LEFT JOIN lock_commands c ON c.id = l.command_id \
+         WHERE l.id = $1",
+    )
+    .bind(log_id)
+    .fetch_optional(db)
+    .await
+    .unwrap_or_else(|e| {
+        error!("Failed to look up lock change source: {e}");
+        None
+    });
+    let Some((log_source, user, command_source, tag)) = row else {
+        return "an unknown source".to_string();
+    };
+
+    let card = match tag {
+        Some(tag) if command_source.as_deref() == Some("rfid") => Some(card_name(db, &tag).await),
+        _ => None,
+    };
+
+    actor_label(
+        &log_source,
+        user.as_deref(),
+        command_source.as_deref(),
+        card.as_deref(),
+    )
+}
+
+// ── Device names ────────────────────────────────────────────────────────────
+
+/// How long discovered lock names are trusted.
+const NAMES_TTL: Duration = Duration::from_secs(15 * 60);
+/// Minimum gap between discoveries triggered by an unknown device.
+const NAMES_MISS_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Lock names from U-Tec discovery, cached so notifications don't call the
+/// U-Tec API for every event.
+#[derive(Clone, Default)]
+pub struct DeviceNames {
+    inner: Arc<RwLock<DeviceNameCache>>,
+}
+
+#[derive(Default)]
+struct DeviceNameCache {
+    names: HashMap<String, String>,
+    fetched_at: Option<Instant>,
+}
+
+impl DeviceNames {
+    /// The lock's name, or `None` if U-Tec doesn't know it or isn't
+    /// connected.
+    pub async fn get(&self, auth_store: &AuthStore, device_id: &str) -> Option<String> {
+        {
+            let cache = self.inner.read().await;
+            let fresh = cache.fetched_at.is_some_and(|t| t.elapsed() < NAMES_TTL);
+            let recent = cache
+                .fetched_at
+                .is_some_and(|t| t.elapsed() < NAMES_MISS_INTERVAL);
+            match cache.names.get(device_id) {
+                Some(name) if fresh => return Some(name.clone()),
+                None if recent => return None,
+                _ => {}
+            }
+        }
+
+        let mut cache = self.inner.write().await;
+        cache.fetched_at = Some(Instant::now());
+        match auth_store.client().await {
+            Some(client) => match client.discover_locks().await {
+                Ok(locks) => {
+                    cache.names = locks.into_iter().map(|l| (l.id, l.name)).collect();
+                }
+                Err(e) => warn!("Failed to refresh lock names: {e:#}"),
+            },
+            None => warn!("U-Tec not connected — notifications use device IDs"),
+        }
+        cache.names.get(device_id).cloned()
+    }
+}
+
+// ── Admin ───────────────────────────────────────────────────────────────────
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/admin/notifications/templates", get(list_templates))
+        .route(
+            "/admin/notifications/templates/{kind}",
+            put(set_template).delete(reset_template),
+        )
+}
+
+#[derive(Serialize)]
+struct TemplateInfo {
+    kind: &'static str,
+    description: &'static str,
+    placeholders: &'static [&'static str],
+    subject: String,
+    body: String,
+    default_subject: &'static str,
+    default_body: &'static str,
+    customized: bool,
+}
+
+async fn list_templates(
+    _user: Authorized<perm::ManageUsers>,
+    State(state): State<AppState>,
+) -> Result<Json<Vec<TemplateInfo>>, ApiError> {
+    let rows: Vec<(String, String, String)> =
+        sqlx::query_as("SELECT kind, subject, body FROM notification_templates")
+            .fetch_all(&state.db)
+            .await
+            .map_err(|e| {
+                error!("Failed to list notification templates: {e}");
+                (StatusCode::INTERNAL_SERVER_ERROR, "Database error")
+            })?;
+    let mut custom: HashMap<String, (String, String)> = rows
+        .into_iter()
+        .map(|(kind, subject, body)| (kind, (subject, body)))
+        .collect();
+
+    Ok(Json(
+        KINDS
+            .iter()
+            .map(|k| {
+                let stored = custom.remove(k.key);
+                let customized = stored.is_some();
+                let (subject, body) =
+                    stored.unwrap_or_else(|| (k.subject.to_string(), k.body.to_string()));
+                TemplateInfo {
+                    kind: k.key,
+                    description: k.description,
+                    placeholders: k.placeholders,
+                    subject,
+                    body,
+                    default_subject: k.subject,
+                    default_body: k.body,
+                    customized,
+                }
+            })
+            .collect(),
+    ))
+}
+
+#[derive(Deserialize)]
+struct TemplateRequest {
+    subject: String,
+    body: String,
+}
+
+/// Check a template against the placeholders its kind provides.
+pub(crate) fn validate_template(
+    kind: &Kind,
+    subject: &str,
+    body: &str,
+) -> Result<(), &'static str> {
+    if subject.trim().is_empty() || body.trim().is_empty() {
+        return Err("Subject and body are required");
+    }
+    if subject.contains('\n') || subject.len() > 200 {
+        return Err("Subject must be a single line of at most 200 characters");
+    }
+    if body.len() > 2000 {
+        return Err("Body must be at most 2000 characters");
+    }
+    let unknown = placeholders(subject)
+        .into_iter()
+        .chain(placeholders(body))
+        .any(|p| !kind.placeholders.contains(&p));
+    if unknown {
+        return Err("Template uses a placeholder this kind doesn't provide");
+    }
+    Ok(())
+}
+
+async fn set_template(
+    user: Authorized<perm::ManageUsers>,
+    State(state): State<AppState>,
+    Path(key): Path<String>,
+    Json(req): Json<TemplateRequest>,
+) -> Result<StatusCode, ApiError> {
+    let kind = kind(&key).ok_or((StatusCode::NOT_FOUND, "Unknown notification kind"))?;
+    let subject = req.subject.trim();
+    let body = req.body.trim();
+    validate_template(kind, subject, body).map_err(|msg| (StatusCode::BAD_REQUEST, msg))?;
+
+    sqlx::query(
+        "INSERT INTO notification_templates (kind, subject, body, updated_by) \
+         VALUES ($1, $2, $3, $4) \
+         ON CONFLICT (kind) DO UPDATE SET \
+         subject = $2, body = $3, updated_by = $4, updated_at = now()",
+    )
+    .bind(kind.key)
+    .bind(subject)
+    .bind(body)
+    .bind(user.id)
+    .execute(&state.db)
+    .await
+    .map_err(|e| {
+        error!("Failed to save notification template: {e}");
+        (StatusCode::INTERNAL_SERVER_ERROR, "Database error")
+    })?;
+
+    info!(admin = %user.email, kind = kind.key, "Notification template updated");
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn reset_template(
+    user: Authorized<perm::ManageUsers>,
+    State(state): State<AppState>,
+    Path(key): Path<String>,
+) -> Result<StatusCode, ApiError> {
+    let kind = kind(&key).ok_or((StatusCode::NOT_FOUND, "Unknown notification kind"))?;
+    sqlx::query("DELETE FROM notification_templates WHERE kind = $1")
+        .bind(kind.key)
+        .execute(&state.db)
+        .await
+        .map_err(|e| {
+            error!("Failed to reset notification template: {e}");
+            (StatusCode::INTERNAL_SERVER_ERROR, "Database error")
+        })?;
+
+    info!(admin = %user.email, kind = kind.key, "Notification template reset");
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars(pairs: &[(&'static str, &str)]) -> BTreeMap<&'static str, String> {
+        pairs.iter().map(|(k, v)| (*k, v.to_string())).collect()
+    }
+
+    #[test]
+    fn fills_placeholders() {
+        let v = vars(&[("door", "Front door"), ("state", "unlocked")]);
+        assert_eq!(fill("{door} {state}", &v), "Front door unlocked");
+        assert_eq!(fill("{door} {nope} {", &v), "Front door {nope} {");
+        assert_eq!(fill("{{door}}", &v), "{Front door}");
+    }
+
+    #[test]
+    fn defaults_only_use_their_placeholders() {
+        for kind in KINDS {
+            assert!(
+                validate_template(kind, kind.subject, kind.body).is_ok(),
+                "{}",
+                kind.key
+            );
+        }
+        let lock = kind("lock_change").unwrap();
+        assert!(validate_template(lock, "{door}", "{card} did it").is_err());
+        assert!(validate_template(lock, "two\nlines", "{door}").is_err());
+        assert!(validate_template(lock, "{door}", " ").is_err());
+    }
+
+    #[test]
+    fn describes_lock_actors() {
+        assert_eq!(
+            actor_label("api", Some("ada@example.org"), Some("api"), None),
+            "ada@example.org"
+        );
+        assert_eq!(
+            actor_label(
+                "api",
+                None,
+                Some("rfid"),
+                Some("Ada's fob (80:00:48:23:4C)")
+            ),
+            "card Ada's fob (80:00:48:23:4C)"
+        );
+        assert_eq!(
+            actor_label("api", None, Some("auto_relock"), None),
+            "auto-relock"
+        );
+        assert_eq!(
+            actor_label("webhook", None, None, None),
+            "someone at the lock (keypad, key or U-Tec app)"
+        );
+    }
+}
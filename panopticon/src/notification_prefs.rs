@@ -0,0 +1,741 @@
+//! Per-user notification preferences.
+//!
+//! Each user has preferences per channel (email, web push): whether the
+//! channel is on, which event types it carries, optionally which cards and
+//! devices, and quiet hours in the user's time zone. Users without a stored
+//! row get [`Preferences::default_for`]. The email and push notifiers ask
+//! [`recipients`] who should hear about an event; scans only reach roles
+//! that can see cards, and device events only admins and users with a grant
+//! on the device. Email can also be batched into [`Digest`]s. Preferences
+//! can only be read or changed by approved users from a browser session
+//! (`perm::ManageNotifications`).
+
+use std::collections::BTreeMap;
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    routing::{get, put},
+    Json, Router,
+};
+use chrono::{DateTime, NaiveTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use tracing::{error, info};
+use uuid::Uuid;
+
+use crate::digest::Digest;
+use crate::grants::{schedule_from_columns, Schedule};
+use crate::middleware::Authorized;
+use crate::permissions::{perm, Permission, Role};
+use crate::ws::WsEvent;
+use crate::AppState;
+
+type ApiError = (StatusCode, &'static str);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Channel {
+    Email,
+    Push,
+    /// Destinations configured per user, see [`crate::notify_channels`].
+    Ntfy,
+    Gotify,
+    Matrix,
+}
+
+impl Channel {
+    pub const ALL: [Channel; 5] = [
+        Self::Email,
+        Self::Push,
+        Self::Ntfy,
+        Self::Gotify,
+        Self::Matrix,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Email => "email",
+            Self::Push => "push",
+            Self::Ntfy => "ntfy",
+            Self::Gotify => "gotify",
+            Self::Matrix => "matrix",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|c| c.as_str() == s)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventType {
+    /// A card was refused at a door.
+    ScanDenied,
+    /// A card was let in.
+    ScanGranted,
+    /// A lock was locked or unlocked.
+    LockChange,
+    /// Lock alerts, raised and cleared once per incident (low battery,
+    /// offline), and failed auto-relocks.
+    DeviceAlert,
+    /// A sentinel lost its last connection.
+    SentinelOffline,
+    /// Every battery reading and connectivity change. Off by default, since
+    /// `DeviceAlert` already covers the ones that matter.
+    DeviceStatus,
+}
+
+impl EventType {
+    pub const ALL: [EventType; 6] = [
+        Self::ScanDenied,
+        Self::ScanGranted,
+        Self::LockChange,
+        Self::DeviceAlert,
+        Self::SentinelOffline,
+        Self::DeviceStatus,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::ScanDenied => "scan_denied",
+            Self::ScanGranted => "scan_granted",
+            Self::LockChange => "lock_change",
+            Self::DeviceAlert => "device_alert",
+            Self::SentinelOffline => "sentinel_offline",
+            Self::DeviceStatus => "device_status",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|t| t.as_str() == s)
+    }
+}
+
+/// What an event is about, for matching against preferences.
+#[derive(Debug, PartialEq, Eq)]
+pub struct EventSubject<'a> {
+    pub event_type: EventType,
+    pub tag_id: Option<&'a str>,
+    pub device_id: Option<&'a str>,
+}
+
+impl<'a> EventSubject<'a> {
+    /// Classify an event, or `None` if it is never notified.
+    pub fn of(event: &'a WsEvent) -> Option<Self> {
+        let subject = |event_type, tag_id, device_id| {
+            Some(Self {
+                event_type,
+                tag_id,
+                device_id,
+            })
+        };
+        match event {
+            WsEvent::Scan { tag_id, action, .. } => {
+                let event_type = if action == "granted" {
+                    EventType::ScanGranted
+                } else {
+                    EventType::ScanDenied
+                };
+                subject(event_type, Some(tag_id.as_str()), None)
+            }
+            WsEvent::LockState { device_id, .. } => {
+                subject(EventType::LockChange, None, Some(device_id.as_str()))
+            }
+            WsEvent::DeviceAlert { device_id, .. } | WsEvent::RelockFailed { device_id, .. } => {
+                subject(EventType::DeviceAlert, None, Some(device_id.as_str()))
+            }
+            WsEvent::BatteryLevel { device_id, .. } | WsEvent::DeviceOnline { device_id, .. } => {
+                subject(EventType::DeviceStatus, None, Some(device_id.as_str()))
+            }
+            WsEvent::SentinelDisconnected { .. } => subject(EventType::SentinelOffline, None, None),
+            _ => None,
+        }
+    }
+
+    /// Whether a user may hear about this event at all, whatever their
+    /// preferences: scans name cards, so they need `ViewCards`, and device
+    /// events need a grant on the device unless the user is an admin.
+    pub fn visible_to(&self, role: Role, has_grant: bool) -> bool {
+        let scan = matches!(
+            self.event_type,
+            EventType::ScanGranted | EventType::ScanDenied
+        );
+        if scan && !role.has(Permission::ViewCards) {
+            return false;
+        }
+        self.device_id.is_none() || role == Role::Admin || has_grant
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Preferences {
+    pub enabled: bool,
+    pub event_types: Vec<EventType>,
+    /// Card tag IDs to notify about. `None` means all cards.
+    #[serde(default)]
+    pub tag_ids: Option<Vec<String>>,
+    /// Device IDs to notify about. `None` means all devices.
+    #[serde(default)]
+    pub device_ids: Option<Vec<String>>,
+    /// Nothing is sent while this window is active.
+    #[serde(default)]
+    pub quiet_hours: Option<Schedule>,
+    /// Email only: send each event or collect them into a digest.
+    #[serde(default)]
+    pub digest: Digest,
+}
+
+impl Preferences {
+    /// Preferences for users who never saved any: everything but raw device
+    /// status, except that email is off. ntfy, Gotify and Matrix only deliver
+    /// once the user has set up a destination.
+    pub fn default_for(channel: Channel) -> Self {
+        Self {
+            enabled: channel != Channel::Email,
+            event_types: EventType::ALL
+                .into_iter()
+                .filter(|&t| t != EventType::DeviceStatus)
+                .collect(),
+            tag_ids: None,
+            device_ids: None,
+            quiet_hours: None,
+            digest: Digest::Instant,
+        }
+    }
+
+    /// Whether an event about `subject` should be sent at `now`. Filters
+    /// only apply to events that carry the filtered attribute, so a card
+    /// filter doesn't silence lock changes.
+    pub fn wants(&self, subject: &EventSubject, now: DateTime<Utc>) -> bool {
+        let matches = |filter: &Option<Vec<String>>, value: Option<&str>| match (filter, value) {
+            (Some(allowed), Some(value)) => allowed.iter().any(|a| a == value),
+            _ => true,
+        };
+
+        self.enabled
+            && self.event_types.contains(&subject.event_type)
+            && matches(&self.tag_ids, subject.tag_id)
+            && matches(&self.device_ids, subject.device_id)
+            && !self.quiet_hours.as_ref().is_some_and(|q| q.allows(now))
+    }
+}
+
+type PreferencesRow = (
+    bool,
+    Vec<String>,
+    Option<Vec<String>>,
+    Option<Vec<String>>,
+    Option<Vec<i16>>,
+    Option<NaiveTime>,
+    Option<NaiveTime>,
+    Option<String>,
+    String,
+    Option<i16>,
+    Option<String>,
+);
+
+fn from_row(row: PreferencesRow) -> Preferences {
+    let (
+        enabled,
+        event_types,
+        tag_ids,
+        device_ids,
+        days,
+        start,
+        end,
+        tz,
+        digest,
+        digest_hour,
+        digest_tz,
+    ) = row;
+    Preferences {
+        enabled,
+        event_types: event_types
+            .iter()
+            .filter_map(|t| EventType::parse(t))
+            .collect(),
+        tag_ids,
+        device_ids,
+        quiet_hours: schedule_from_columns(days, start, end, tz),
+        digest: Digest::from_columns(&digest, digest_hour, digest_tz),
+    }
+}
+
+const PREFERENCE_COLUMNS: &str = "p.enabled, p.event_types, p.tag_ids, p.device_ids, \
+     p.quiet_days, p.quiet_start, p.quiet_end, p.quiet_tz, p.digest, p.digest_hour, p.digest_tz";
+
+/// Load a user's preferences for a channel, falling back to the defaults.
+pub async fn load(
+    db: &PgPool,
+    user_id: Uuid,
+    channel: Channel,
+) -> Result<Preferences, sqlx::Error> {
+    let row: Option<PreferencesRow> = sqlx::query_as(&format!(
+        "SELECT {PREFERENCE_COLUMNS} FROM notification_preferences p \
+         WHERE p.user_id = $1 AND p.channel = $2"
+    ))
+    .bind(user_id)
+    .bind(channel.as_str())
+    .fetch_optional(db)
+    .await?;
+
+    Ok(row
+        .map(from_row)
+        .unwrap_or_else(|| Preferences::default_for(channel)))
+}
+
+type RecipientRow = (
+    Uuid,
+    String,
+    String,
+    bool,
+    Option<bool>,
+    Option<Vec<String>>,
+    Option<Vec<String>>,
+    Option<Vec<String>>,
+    Option<Vec<i16>>,
+    Option<NaiveTime>,
+    Option<NaiveTime>,
+    Option<String>,
+    Option<String>,
+    Option<i16>,
+    Option<String>,
+);
+
+/// Someone who should hear about an event.
+#[derive(Debug)]
+pub struct Recipient {
+    pub user_id: Uuid,
+    pub email: String,
+    pub digest: Digest,
+}
+
+/// Approved, active users who may see `event` and want it on `channel`
+/// right now.
+pub async fn recipients(
+    db: &PgPool,
+    channel: Channel,
+    event: &WsEvent,
+) -> Result<Vec<Recipient>, sqlx::Error> {
+    let Some(subject) = EventSubject::of(event) else {
+        return Ok(Vec::new());
+    };
+
+    let rows: Vec<RecipientRow> = sqlx::query_as(&format!(
+        "SELECT u.id, u.email, u.role, \
+                EXISTS(SELECT 1 FROM device_grants g WHERE g.user_id = u.id AND g.device_id = $2), \
+                {PREFERENCE_COLUMNS} FROM users u \
+         LEFT JOIN notification_preferences p ON p.user_id = u.id AND p.channel = $1 \
+         WHERE u.is_approved = TRUE AND u.suspended_at IS NULL"
+    ))
+    .bind(channel.as_str())
+    .bind(subject.device_id)
+    .fetch_all(db)
+    .await?;
+
+    let now = Utc::now();
+    Ok(rows
+        .into_iter()
+        .filter_map(
+            |(
+                user_id,
+                email,
+                role,
+                has_grant,
+                enabled,
+                event_types,
+                tags,
+                devices,
+                days,
+                start,
+                end,
+                tz,
+                digest,
+                digest_hour,
+                digest_tz,
+            )| {
+                if !Role::parse(&role).is_some_and(|role| subject.visible_to(role, has_grant)) {
+                    return None;
+                }
+                // `enabled` is only NULL when the user has no stored row.
+                let prefs = match enabled {
+                    Some(enabled) => from_row((
+                        enabled,
+                        event_types.unwrap_or_default(),
+                        tags,
+                        devices,
+                        days,
+                        start,
+                        end,
+                        tz,
+                        digest.unwrap_or_default(),
+                        digest_hour,
+                        digest_tz,
+                    )),
+                    None => Preferences::default_for(channel),
+                };
+                prefs.wants(&subject, now).then_some(Recipient {
+                    user_id,
+                    email,
+                    digest: prefs.digest,
+                })
+            },
+        )
+        .collect())
+}
+
+/// Name of a sentinel that has just lost its last connection, or `None` if it
+/// is still connected through another session.
+pub async fn offline_sentinel_name(pool: &PgPool, id: Uuid) -> Option<String> {
+    match sqlx::query_scalar("SELECT name FROM sentinels WHERE id = $1 AND connected = FALSE")
+        .bind(id)
+        .fetch_optional(pool)
+        .await
+    {
+        Ok(name) => name,
+        Err(e) => {
+            error!("Failed to look up sentinel {id}: {e}");
+            None
+        }
+    }
+}
+
+// ── Routes ──────────────────────────────────────────────────────────────────
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route(
+            "/notifications",
+            get(get_notifications).put(update_notifications),
+        )
+        .route("/notifications/preferences", get(get_preferences))
+        .route(
+            "/notifications/preferences/{channel}",
+            put(set_preferences).delete(reset_preferences),
+        )
+}
+
+/// Summary for the dashboard's email toggle.
+#[derive(Serialize)]
+struct NotificationPrefs {
+    email: bool,
+}
+
+#[derive(Deserialize)]
+struct UpdateNotificationPrefs {
+    email: bool,
+}
+
+async fn get_notifications(
+    user: Authorized<perm::ManageNotifications>,
+    State(state): State<AppState>,
+) -> Result<Json<NotificationPrefs>, ApiError> {
+    let prefs = load(&state.db, user.id, Channel::Email)
+        .await
+        .map_err(|e| {
+            error!("Failed to fetch notification prefs: {e}");
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to fetch preferences",
+            )
+        })?;
+
+    Ok(Json(NotificationPrefs {
+        email: prefs.enabled,
+    }))
+}
+
+/// Turn email on or off, keeping the rest of the email preferences.
+async fn update_notifications(
+    user: Authorized<perm::ManageNotifications>,
+    State(state): State<AppState>,
+    Json(body): Json<UpdateNotificationPrefs>,
+) -> Result<Json<NotificationPrefs>, ApiError> {
+    let mut prefs = load(&state.db, user.id, Channel::Email)
+        .await
+        .map_err(|e| {
+            error!("Failed to fetch notification prefs: {e}");
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to update preferences",
+            )
+        })?;
+    prefs.enabled = body.email;
+
+    save(&state.db, user.id, Channel::Email, &prefs)
+        .await
+        .map_err(|e| {
+            error!("Failed to update notification prefs: {e}");
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to update preferences",
+            )
+        })?;
+
+    Ok(Json(NotificationPrefs { email: body.email }))
+}
+
+/// Preferences for every channel, keyed by channel name.
+async fn get_preferences(
+    user: Authorized<perm::ManageNotifications>,
+    State(state): State<AppState>,
+) -> Result<Json<BTreeMap<&'static str, Preferences>>, ApiError> {
+    let mut all = BTreeMap::new();
+    for channel in Channel::ALL {
+        let prefs = load(&state.db, user.id, channel).await.map_err(|e| {
+            error!("Failed to fetch notification prefs: {e}");
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to fetch preferences",
+            )
+        })?;
+        all.insert(channel.as_str(), prefs);
+    }
+    Ok(Json(all))
+}
+
+async fn set_preferences(
+    user: Authorized<perm::ManageNotifications>,
+    State(state): State<AppState>,
+    Path(channel): Path<String>,
+    Json(body): Json<Preferences>,
+) -> Result<Json<Preferences>, ApiError> {
+    let channel = Channel::parse(&channel).ok_or((StatusCode::NOT_FOUND, "Unknown channel"))?;
+
+    if let Some(ref quiet) = body.quiet_hours {
+        if quiet.tz().is_none() {
+            return Err((StatusCode::BAD_REQUEST, "Unknown time zone"));
+        }
+        if quiet.start == quiet.end {
+            return Err((StatusCode::BAD_REQUEST, "Quiet hours window is empty"));
+        }
+    }
+    if body.digest != Digest::Instant && channel != Channel::Email {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "Digests are only available for email",
+        ));
+    }
+    body.digest
+        .validate()
+        .map_err(|msg| (StatusCode::BAD_REQUEST, msg))?;
+
+    save(&state.db, user.id, channel, &body)
+        .await
+        .map_err(|e| {
+            error!("Failed to save notification prefs: {e}");
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to update preferences",
+            )
+        })?;
+
+    info!(
+        user = %user.email,
+        channel = channel.as_str(),
+        enabled = body.enabled,
+        "Notification preferences updated"
+    );
+
+    Ok(Json(body))
+}
+
+async fn reset_preferences(
+    user: Authorized<perm::ManageNotifications>,
+    State(state): State<AppState>,
+    Path(channel): Path<String>,
+) -> Result<Json<Preferences>, ApiError> {
+    let channel = Channel::parse(&channel).ok_or((StatusCode::NOT_FOUND, "Unknown channel"))?;
+
+    sqlx::query("DELETE FROM notification_preferences WHERE user_id = $1 AND channel = $2")
+        .bind(user.id)
+        .bind(channel.as_str())
+        .execute(&state.db)
+        .await
+        .map_err(|e| {
+            error!("Failed to reset notification prefs: {e}");
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to update preferences",
+            )
+        })?;
+
+    Ok(Json(Preferences::default_for(channel)))
+}
+
+async fn save(
+    db: &PgPool,
+    user_id: Uuid,
+    channel: Channel,
+    prefs: &Preferences,
+) -> Result<(), sqlx::Error> {
+    let event_types: Vec<&str> = prefs.event_types.iter().map(|t| t.as_str()).collect();
+    let quiet = prefs.quiet_hours.as_ref();
+    let days: Option<Vec<i16>> = quiet.map(|q| {
+        q.days
+            .iter()
+            .map(|d| d.number_from_monday() as i16)
+            .collect()
+    });
+    let (digest, digest_hour, digest_tz) = prefs.digest.columns();
+
+    sqlx::query(
+        "INSERT INTO notification_preferences \
+         (user_id, channel, enabled, event_types, tag_ids, device_ids, \
+          quiet_days, quiet_start, quiet_end, quiet_tz, digest, digest_hour, digest_tz) \
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13) \
+         ON CONFLICT (user_id, channel) DO UPDATE SET \
+         enabled = $3, event_types = $4, tag_ids = $5, device_ids = $6, quiet_days = $7, \
+         quiet_start = $8, quiet_end = $9, quiet_tz = $10, digest = $11, digest_hour = $12, \
+         digest_tz = $13, updated_at = now()",
+    )
+    .bind(user_id)
+    .bind(channel.as_str())
+    .bind(prefs.enabled)
+    .bind(event_types)
+    .bind(&prefs.tag_ids)
+    .bind(&prefs.device_ids)
+    .bind(days)
+    .bind(quiet.map(|q| q.start))
+    .bind(quiet.map(|q| q.end))
+    .bind(quiet.map(|q| q.timezone.as_str()))
+    .bind(digest)
+    .bind(digest_hour)
+    .bind(digest_tz)
+    .execute(db)
+    .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn scan(action: &str) -> WsEvent {
+        WsEvent::Scan {
+            tag_id: "0011223344".to_string(),
+            action: action.to_string(),
+            sentinel_id: None,
+            created_at: String::new(),
+        }
+    }
+
+    fn lock_change(device_id: &str) -> WsEvent {
+        WsEvent::LockState {
+            device_id: device_id.to_string(),
+            lock_state: "unlocked".to_string(),
+            log_id: None,
+        }
+    }
+
+    fn noon() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2026, 3, 2, 12, 0, 0).unwrap()
+    }
+
+    #[test]
+    fn defaults_per_channel() {
+        let event = scan("denied");
+        let subject = EventSubject::of(&event).unwrap();
+        assert!(Preferences::default_for(Channel::Push).wants(&subject, noon()));
+        assert!(!Preferences::default_for(Channel::Email).wants(&subject, noon()));
+    }
+
+    #[test]
+    fn scans_need_view_cards() {
+        let event = scan("granted");
+        let subject = EventSubject::of(&event).unwrap();
+        assert!(subject.visible_to(Role::Admin, false));
+        assert!(subject.visible_to(Role::Operator, false));
+        assert!(!subject.visible_to(Role::Resident, false));
+        assert!(!subject.visible_to(Role::Viewer, false));
+    }
+
+    #[test]
+    fn device_events_need_a_grant() {
+        let event = lock_change("front");
+        let subject = EventSubject::of(&event).unwrap();
+        assert!(subject.visible_to(Role::Admin, false));
+        assert!(subject.visible_to(Role::Resident, true));
+        assert!(!subject.visible_to(Role::Resident, false));
+        assert!(!subject.visible_to(Role::Operator, false));
+
+        let event = WsEvent::SentinelDisconnected { id: Uuid::nil() };
+        let subject = EventSubject::of(&event).unwrap();
+        assert!(subject.visible_to(Role::Viewer, false));
+    }
+
+    #[test]
+    fn raw_device_status_is_opt_in() {
+        let battery = WsEvent::BatteryLevel {
+            device_id: "front".to_string(),
+            battery_level: 41,
+        };
+        let subject = EventSubject::of(&battery).unwrap();
+        assert_eq!(subject.event_type, EventType::DeviceStatus);
+        assert!(!Preferences::default_for(Channel::Push).wants(&subject, noon()));
+
+        let relock = WsEvent::RelockFailed {
+            device_id: "front".to_string(),
+            error: "Lock reported unlocked".to_string(),
+        };
+        let subject = EventSubject::of(&relock).unwrap();
+        assert_eq!(subject.event_type, EventType::DeviceAlert);
+        assert!(Preferences::default_for(Channel::Push).wants(&subject, noon()));
+    }
+
+    #[test]
+    fn filters_by_event_type_card_and_device() {
+        let prefs = Preferences {
+            enabled: true,
+            event_types: vec![EventType::ScanDenied, EventType::LockChange],
+            tag_ids: Some(vec!["0011223344".to_string()]),
+            device_ids: Some(vec!["front".to_string()]),
+            quiet_hours: None,
+            digest: Digest::Instant,
+        };
+        let wants = |event: &WsEvent| prefs.wants(&EventSubject::of(event).unwrap(), noon());
+
+        assert!(wants(&scan("denied")));
+        assert!(!wants(&scan("granted")));
+        assert!(wants(&lock_change("front")));
+        assert!(!wants(&lock_change("back")));
+        assert!(!wants(&WsEvent::SentinelDisconnected { id: Uuid::nil() }));
+        assert!(EventSubject::of(&WsEvent::ModeChanged {
+            mode: "normal".to_string()
+        })
+        .is_none());
+    }
+
+    #[test]
+    fn quiet_hours_use_time_zone() {
+        let prefs = Preferences {
+            quiet_hours: Some(Schedule {
+                days: Vec::new(),
+                start: NaiveTime::from_hms_opt(22, 0, 0).unwrap(),
+                end: NaiveTime::from_hms_opt(7, 0, 0).unwrap(),
+                timezone: "America/New_York".to_string(),
+            }),
+            ..Preferences::default_for(Channel::Push)
+        };
+        let event = scan("denied");
+        let subject = EventSubject::of(&event).unwrap();
+
+        // 12:00 UTC is 07:00 in New York (EST): quiet hours just ended.
+        assert!(prefs.wants(&subject, noon()));
+        // 04:00 UTC is 23:00 the previous evening in New York.
+        let night = Utc.with_ymd_and_hms(2026, 3, 2, 4, 0, 0).unwrap();
+        assert!(!prefs.wants(&subject, night));
+    }
+
+    #[test]
+    fn event_types_round_trip() {
+        for t in EventType::ALL {
+            assert_eq!(EventType::parse(t.as_str()), Some(t));
+        }
+        assert_eq!(Channel::parse("push"), Some(Channel::Push));
+        assert_eq!(Channel::parse("sms"), None);
+    }
+}
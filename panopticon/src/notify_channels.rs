@@ -0,0 +1,745 @@
+//! ntfy, Gotify and Matrix notification channels.
+//!
+//! Unlike email and web push, these need a destination per user: an ntfy
+//! server and topic, a Gotify server and app token, or a Matrix homeserver,
+//! room and access token. Destinations live in `user_notification_channels`;
+//! which events they receive is set in the user's notification preferences
+//! for the same channel, and deliveries go through the outbox like any other
+//! notification.
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::{Arc, LazyLock};
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    routing::{get, post, put},
+    Json, Router,
+};
+use chrono::{DateTime, Utc};
+use ipnet::IpNet;
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+use crate::ip_whitelist::networks_from_env;
+use crate::middleware::Authorized;
+use crate::notification_prefs::Channel;
+use crate::permissions::perm;
+use crate::AppState;
+
+type ApiError = (StatusCode, &'static str);
+
+/// Internal networks an admin allows destinations on, from
+/// `NOTIFY_ALLOWED_NETWORKS` (comma-separated CIDRs), e.g. the LAN a
+/// self-hosted ntfy or Matrix server sits on.
+static ALLOWED_NETWORKS: LazyLock<Vec<IpNet>> =
+    LazyLock::new(|| networks_from_env("NOTIFY_ALLOWED_NETWORKS", ""));
+
+/// The IPv4 address embedded in an IPv4-mapped (`::ffff:0:0/96`), NAT64
+/// (`64:ff9b::/96`) or 6to4 (`2002::/16`) address, which reach that IPv4
+/// host.
+fn embedded_ipv4(ip: Ipv6Addr) -> Option<Ipv4Addr> {
+    let s = ip.segments();
+    let v4 = |hi: u16, lo: u16| Ipv4Addr::from((u32::from(hi) << 16) | u32::from(lo));
+    if let Some(v4) = ip.to_ipv4_mapped() {
+        Some(v4)
+    } else if s[..6] == [0x64, 0xff9b, 0, 0, 0, 0] {
+        Some(v4(s[6], s[7]))
+    } else if s[0] == 0x2002 {
+        Some(v4(s[1], s[2]))
+    } else {
+        None
+    }
+}
+
+/// Whether a destination may be at this address: it is public, or on one of
+/// the `allowed` networks.
+fn is_allowed(ip: IpAddr, allowed: &[IpNet]) -> bool {
+    let effective = match ip {
+        IpAddr::V6(v6) => embedded_ipv4(v6).map_or(ip, IpAddr::V4),
+        IpAddr::V4(_) => ip,
+    };
+    allowed
+        .iter()
+        .any(|net| net.contains(&ip) || net.contains(&effective))
+        || is_public(ip)
+}
+
+/// Whether an address is reachable from the internet. Destinations are set
+/// by users, so loopback, private, link-local and unique-local addresses are
+/// refused to keep them from reaching services on the server's network,
+/// unless an admin allows the network.
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            !(ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_multicast()
+                || ip.is_documentation()
+                || a == 0
+                // Carrier-grade NAT, 100.64.0.0/10
+                || (a == 100 && b & 0xc0 == 64))
+        }
+        IpAddr::V6(ip) => match embedded_ipv4(ip) {
+            Some(v4) => is_public(IpAddr::V4(v4)),
+            None => {
+                !(ip.is_loopback()
+                    || ip.is_unspecified()
+                    || ip.is_multicast()
+                    || ip.is_unique_local()
+                    || ip.is_unicast_link_local())
+            }
+        },
+    }
+}
+
+/// Resolves with the system resolver but drops addresses that are neither
+/// public nor on an allowed network, so a host that resolves differently
+/// after it was checked still can't reach the internal network.
+pub(crate) struct PublicResolver;
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            let addrs: Vec<SocketAddr> = tokio::net::lookup_host((name.as_str(), 0))
+                .await?
+                .filter(|addr| is_allowed(addr.ip(), &ALLOWED_NETWORKS))
+                .collect();
+            if addrs.is_empty() {
+                return Err(format!("{} has no allowed address", name.as_str()).into());
+            }
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+/// The client for all channel requests: public addresses only, and no
+/// redirects, which could point anywhere.
+static HTTP: LazyLock<reqwest::Client> = LazyLock::new(|| {
+    reqwest::Client::builder()
+        .timeout(std::time::Duration::from_secs(15))
+        .redirect(reqwest::redirect::Policy::none())
+        .dns_resolver(Arc::new(PublicResolver))
+        .build()
+        .expect("Failed to build HTTP client")
+});
+
+#[derive(Debug, PartialEq)]
+pub(crate) enum HostError {
+    Invalid,
+    Unresolvable,
+    NotPublic,
+}
+
+impl HostError {
+    pub(crate) fn message(&self) -> &'static str {
+        match self {
+            Self::Invalid => "Invalid server URL",
+            Self::Unresolvable => "Server host could not be resolved",
+            Self::NotPublic => "Server must have a public address or be on an allowed network",
+        }
+    }
+}
+
+/// Resolve a server URL's host and check that every address is public or on
+/// an allowed network.
+pub(crate) async fn check_host(server_url: &str) -> Result<(), HostError> {
+    let url = Url::parse(server_url).map_err(|_| HostError::Invalid)?;
+    let port = url.port_or_known_default().unwrap_or(443);
+    let host = url.host_str().ok_or(HostError::Invalid)?;
+    // IPv6 literals come bracketed.
+    let literal = host.trim_start_matches('[').trim_end_matches(']');
+    let addrs: Vec<IpAddr> = match literal.parse::<IpAddr>() {
+        Ok(ip) => vec![ip],
+        Err(_) => tokio::net::lookup_host((host, port))
+            .await
+            .map_err(|_| HostError::Unresolvable)?
+            .map(|addr| addr.ip())
+            .collect(),
+    };
+    if addrs.is_empty() {
+        return Err(HostError::Unresolvable);
+    }
+    if !addrs
+        .into_iter()
+        .all(|ip| is_allowed(ip, &ALLOWED_NETWORKS))
+    {
+        return Err(HostError::NotPublic);
+    }
+    Ok(())
+}
+
+/// Where to send a user's notifications for one channel.
+#[derive(Clone, Debug)]
+pub struct Destination {
+    pub channel: Channel,
+    pub server_url: String,
+    /// ntfy topic or Matrix room ID.
+    pub target: Option<String>,
+    /// ntfy access token (optional), Gotify app token or Matrix access token.
+    pub token: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct SendError {
+    pub message: String,
+    /// Retrying can't help, e.g. the token was rejected.
+    pub permanent: bool,
+}
+
+impl SendError {
+    fn retry(message: impl Into<String>) -> Self {
+        Self {
+            message: message.into(),
+            permanent: false,
+        }
+    }
+
+    fn permanent(message: impl Into<String>) -> Self {
+        Self {
+            message: message.into(),
+            permanent: true,
+        }
+    }
+
+    /// Rate limits and server errors are worth retrying; other client errors
+    /// mean the destination is misconfigured.
+    fn from_status(status: reqwest::StatusCode) -> Self {
+        let message = format!("HTTP {status}");
+        if status == reqwest::StatusCode::TOO_MANY_REQUESTS || status.is_server_error() {
+            Self::retry(message)
+        } else {
+            Self::permanent(message)
+        }
+    }
+}
+
+impl Destination {
+    /// Check that the fields this channel needs are present and sensible.
+    pub fn validate(&self) -> Result<(), &'static str> {
+        let url = reqwest::Url::parse(&self.server_url).map_err(|_| "Invalid server URL")?;
+        if !matches!(url.scheme(), "http" | "https") || url.host_str().is_none() {
+            return Err("Server URL must be http or https");
+        }
+        let target = self.target.as_deref().unwrap_or("");
+        let token = self.token.as_deref().unwrap_or("");
+        match self.channel {
+            Channel::Ntfy if target.is_empty() => Err("An ntfy topic is required"),
+            Channel::Ntfy
+                if !target
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') =>
+            {
+                Err("ntfy topics may only contain letters, digits, - and _")
+            }
+            Channel::Gotify if token.is_empty() => Err("A Gotify app token is required"),
+            Channel::Matrix if !target.starts_with('!') || !target.contains(':') => {
+                Err("Matrix room must be a room ID like !abc:example.org")
+            }
+            Channel::Matrix if token.is_empty() => Err("A Matrix access token is required"),
+            Channel::Email | Channel::Push => Err("Channel has no per-user destination"),
+            _ => Ok(()),
+        }
+    }
+
+    /// Send one notification to the destination, provided its server has a
+    /// public address. `txn_id` identifies the delivery; Matrix uses it to
+    /// de-duplicate retries.
+    pub async fn deliver(&self, txn_id: Uuid, title: &str, body: &str) -> Result<(), SendError> {
+        match check_host(&self.server_url).await {
+            Ok(()) => self.send(&HTTP, txn_id, title, body).await,
+            Err(HostError::Unresolvable) => {
+                Err(SendError::retry(HostError::Unresolvable.message()))
+            }
+            Err(e) => {
+                warn!(channel = self.channel.as_str(), server = %self.server_url, "Refused notification destination: {}", e.message());
+                Err(SendError::permanent(e.message()))
+            }
+        }
+    }
+
+    async fn send(
+        &self,
+        http: &reqwest::Client,
+        txn_id: Uuid,
+        title: &str,
+        body: &str,
+    ) -> Result<(), SendError> {
+        let base = self.server_url.trim_end_matches('/');
+        let target = self.target.as_deref().unwrap_or("");
+        let token = self.token.as_deref().filter(|t| !t.is_empty());
+
+        let request = match self.channel {
+            // JSON publishing avoids header encoding limits on the title.
+            Channel::Ntfy => {
+                let req = http.post(base).json(&serde_json::json!({
+                    "topic": target,
+                    "title": title,
+                    "message": body,
+                    "tags": ["door"],
+                }));
+                match token {
+                    Some(token) => req.bearer_auth(token),
+                    None => req,
+                }
+            }
+            Channel::Gotify => http
+                .post(format!("{base}/message"))
+                .header("X-Gotify-Key", token.unwrap_or(""))
+                .json(&serde_json::json!({
+                    "title": title,
+                    "message": body,
+                    "priority": 5,
+                })),
+            Channel::Matrix => http
+                .put(format!(
+                    "{base}/_matrix/client/v3/rooms/{}/send/m.room.message/{txn_id}",
+                    urlencoding::encode(target)
+                ))
+                .bearer_auth(token.unwrap_or(""))
+                .json(&serde_json::json!({
+                    "msgtype": "m.text",
+                    "body": format!("{title}\n{body}"),
+                    "format": "org.matrix.custom.html",
+                    "formatted_body": format!(
+                        "<strong>{}</strong><br>{}",
+                        html_escape(title),
+                        html_escape(body).replace('\n', "<br>")
+                    ),
+                })),
+            Channel::Email | Channel::Push => {
+                return Err(SendError::permanent("Channel has no per-user destination"))
+            }
+        };
+
+        let resp = request
+            .send()
+            .await
+            .map_err(|e| SendError::retry(format!("Request failed: {e}")))?;
+        if resp.status().is_success() {
+            Ok(())
+        } else {
+            Err(SendError::from_status(resp.status()))
+        }
+    }
+}
+
+fn html_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+// ── Routes ──────────────────────────────────────────────────────────────────
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/notifications/channels", get(list_destinations))
+        .route(
+            "/notifications/channels/{channel}",
+            put(set_destination).delete(delete_destination),
+        )
+        .route(
+            "/notifications/channels/{channel}/test",
+            post(test_destination),
+        )
+}
+
+fn parse_channel(channel: &str) -> Result<Channel, ApiError> {
+    match Channel::parse(channel) {
+        Some(c @ (Channel::Ntfy | Channel::Gotify | Channel::Matrix)) => Ok(c),
+        _ => Err((StatusCode::NOT_FOUND, "Unknown channel")),
+    }
+}
+
+/// A destination as shown to its owner. Tokens are never returned.
+#[derive(Serialize)]
+struct DestinationInfo {
+    channel: String,
+    server_url: String,
+    target: Option<String>,
+    has_token: bool,
+    updated_at: String,
+}
+
+type DestinationRow = (String, String, Option<String>, bool, DateTime<Utc>);
+
+async fn list_destinations(
+    user: Authorized<perm::ManageNotifications>,
+    State(state): State<AppState>,
+) -> Result<Json<Vec<DestinationInfo>>, ApiError> {
+    let rows: Vec<DestinationRow> = sqlx::query_as(
+        "SELECT channel, server_url, target, COALESCE(token, '') <> '', updated_at \
+         FROM user_notification_channels WHERE user_id = $1 ORDER BY channel",
+    )
+    .bind(user.id)
+    .fetch_all(&state.db)
+    .await
+    .map_err(|e| {
+        error!("Failed to list notification channels: {e}");
+        (StatusCode::INTERNAL_SERVER_ERROR, "Database error")
+    })?;
+
+    Ok(Json(
+        rows.into_iter()
+            .map(
+                |(channel, server_url, target, has_token, updated_at)| DestinationInfo {
+                    channel,
+                    server_url,
+                    target,
+                    has_token,
+                    updated_at: updated_at.to_rfc3339(),
+                },
+            )
+            .collect(),
+    ))
+}
+
+#[derive(Deserialize)]
+struct SetDestinationRequest {
+    server_url: String,
+    target: Option<String>,
+    token: Option<String>,
+}
+
+async fn set_destination(
+    user: Authorized<perm::ManageNotifications>,
+    State(state): State<AppState>,
+    Path(channel): Path<String>,
+    Json(body): Json<SetDestinationRequest>,
+) -> Result<StatusCode, ApiError> {
+    let channel = parse_channel(&channel)?;
+    let destination = Destination {
+        channel,
+        server_url: body.server_url.trim().to_string(),
+        target: body.target.map(|t| t.trim().to_string()),
+        token: body.token.map(|t| t.trim().to_string()),
+    };
+    destination
+        .validate()
+        .map_err(|msg| (StatusCode::BAD_REQUEST, msg))?;
+    check_host(&destination.server_url)
+        .await
+        .map_err(|e| (StatusCode::BAD_REQUEST, e.message()))?;
+
+    sqlx::query(
+        "INSERT INTO user_notification_channels (user_id, channel, server_url, target, token) \
+         VALUES ($1, $2, $3, $4, $5) \
+         ON CONFLICT (user_id, channel) DO UPDATE SET \
+         server_url = $3, target = $4, token = $5, updated_at = now()",
+    )
+    .bind(user.id)
+    .bind(channel.as_str())
+    .bind(&destination.server_url)
+    .bind(&destination.target)
+    .bind(&destination.token)
+    .execute(&state.db)
+    .await
+    .map_err(|e| {
+        error!("Failed to save notification channel: {e}");
+        (StatusCode::INTERNAL_SERVER_ERROR, "Database error")
+    })?;
+
+    info!(user = %user.email, channel = channel.as_str(), "Notification channel configured");
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn delete_destination(
+    user: Authorized<perm::ManageNotifications>,
+    State(state): State<AppState>,
+    Path(channel): Path<String>,
+) -> Result<StatusCode, ApiError> {
+    let channel = parse_channel(&channel)?;
+    let result =
+        sqlx::query("DELETE FROM user_notification_channels WHERE user_id = $1 AND channel = $2")
+            .bind(user.id)
+            .bind(channel.as_str())
+            .execute(&state.db)
+            .await
+            .map_err(|e| {
+                error!("Failed to remove notification channel: {e}");
+                (StatusCode::INTERNAL_SERVER_ERROR, "Database error")
+            })?;
+    if result.rows_affected() == 0 {
+        return Err((StatusCode::NOT_FOUND, "Channel not configured"));
+    }
+
+    info!(user = %user.email, channel = channel.as_str(), "Notification channel removed");
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Load a user's destination for a channel.
+pub async fn load(
+    db: &sqlx::PgPool,
+    user_id: Uuid,
+    channel: Channel,
+) -> Result<Option<Destination>, sqlx::Error> {
+    let row: Option<(String, Option<String>, Option<String>)> = sqlx::query_as(
+        "SELECT server_url, target, token FROM user_notification_channels \
+         WHERE user_id = $1 AND channel = $2",
+    )
+    .bind(user_id)
+    .bind(channel.as_str())
+    .fetch_optional(db)
+    .await?;
+
+    Ok(row.map(|(server_url, target, token)| Destination {
+        channel,
+        server_url,
+        target,
+        token,
+    }))
+}
+
+/// Send a test message straight away, bypassing preferences and the outbox.
+async fn test_destination(
+    user: Authorized<perm::ManageNotifications>,
+    State(state): State<AppState>,
+    Path(channel): Path<String>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let channel = parse_channel(&channel).map_err(|(s, m)| (s, m.to_string()))?;
+    let destination = load(&state.db, user.id, channel)
+        .await
+        .map_err(|e| {
+            error!("Failed to load notification channel: {e}");
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Database error".to_string(),
+            )
+        })?
+        .ok_or((StatusCode::NOT_FOUND, "Channel not configured".to_string()))?;
+
+    destination
+        .deliver(
+            Uuid::new_v4(),
+            "Panopticon test",
+            "Notifications from Panopticon will arrive here.",
+        )
+        .await
+        .map_err(|e| (StatusCode::BAD_GATEWAY, e.message))?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    use axum::{body::Body, extract::Request};
+
+    #[derive(Debug)]
+    struct Captured {
+        method: String,
+        path: String,
+        headers: axum::http::HeaderMap,
+        body: serde_json::Value,
+    }
+
+    /// A local stand-in for an ntfy, Gotify or Matrix server that records
+    /// requests and answers with `status`.
+    async fn stand_in(status: StatusCode) -> (String, Arc<Mutex<Vec<Captured>>>) {
+        let captured = Arc::new(Mutex::new(Vec::new()));
+        let log = captured.clone();
+        let app = Router::new().fallback(move |req: Request<Body>| {
+            let log = log.clone();
+            async move {
+                let (parts, body) = req.into_parts();
+                let bytes = axum::body::to_bytes(body, usize::MAX).await.unwrap();
+                log.lock().unwrap().push(Captured {
+                    method: parts.method.to_string(),
+                    path: parts.uri.path().to_string(),
+                    headers: parts.headers,
+                    body: serde_json::from_slice(&bytes).unwrap_or_default(),
+                });
+                status
+            }
+        });
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        (format!("http://{addr}"), captured)
+    }
+
+    fn destination(channel: Channel, server_url: &str, target: &str, token: &str) -> Destination {
+        Destination {
+            channel,
+            server_url: server_url.to_string(),
+            target: Some(target.to_string()).filter(|t| !t.is_empty()),
+            token: Some(token.to_string()).filter(|t| !t.is_empty()),
+        }
+    }
+
+    #[tokio::test]
+    async fn ntfy_publishes_json_to_topic() {
+        let (url, captured) = stand_in(StatusCode::OK).await;
+        let dest = destination(Channel::Ntfy, &url, "front-door", "tk_secret");
+        dest.send(
+            &reqwest::Client::new(),
+            Uuid::nil(),
+            "Access Denied",
+            "Card 0011",
+        )
+        .await
+        .unwrap();
+
+        let requests = captured.lock().unwrap();
+        assert_eq!(requests[0].method, "POST");
+        assert_eq!(requests[0].path, "/");
+        assert_eq!(requests[0].headers["authorization"], "Bearer tk_secret");
+        assert_eq!(requests[0].body["topic"], "front-door");
+        assert_eq!(requests[0].body["title"], "Access Denied");
+        assert_eq!(requests[0].body["message"], "Card 0011");
+    }
+
+    #[tokio::test]
+    async fn gotify_posts_message_with_app_token() {
+        let (url, captured) = stand_in(StatusCode::OK).await;
+        let dest = destination(Channel::Gotify, &format!("{url}/"), "", "AbCdEf");
+        dest.send(
+            &reqwest::Client::new(),
+            Uuid::nil(),
+            "Lock unlocked",
+            "front is now unlocked",
+        )
+        .await
+        .unwrap();
+
+        let requests = captured.lock().unwrap();
+        assert_eq!(requests[0].path, "/message");
+        assert_eq!(requests[0].headers["x-gotify-key"], "AbCdEf");
+        assert_eq!(requests[0].body["message"], "front is now unlocked");
+    }
+
+    #[tokio::test]
+    async fn matrix_puts_event_with_transaction_id() {
+        let (url, captured) = stand_in(StatusCode::OK).await;
+        let dest = destination(Channel::Matrix, &url, "!room:example.org", "syt_token");
+        let txn = Uuid::new_v4();
+        dest.send(&reqwest::Client::new(), txn, "Sentinel offline", "hall <1>")
+            .await
+            .unwrap();
+
+        let requests = captured.lock().unwrap();
+        assert_eq!(requests[0].method, "PUT");
+        assert_eq!(
+            requests[0].path,
+            format!("/_matrix/client/v3/rooms/%21room%3Aexample.org/send/m.room.message/{txn}")
+        );
+        assert_eq!(requests[0].headers["authorization"], "Bearer syt_token");
+        assert_eq!(requests[0].body["body"], "Sentinel offline\nhall <1>");
+        assert_eq!(
+            requests[0].body["formatted_body"],
+            "<strong>Sentinel offline</strong><br>hall &lt;1&gt;"
+        );
+    }
+
+    #[tokio::test]
+    async fn classifies_failures() {
+        let http = reqwest::Client::new();
+
+        let (url, _) = stand_in(StatusCode::UNAUTHORIZED).await;
+        let err = destination(Channel::Gotify, &url, "", "bad")
+            .send(&http, Uuid::nil(), "t", "b")
+            .await
+            .unwrap_err();
+        assert!(err.permanent);
+
+        let (url, _) = stand_in(StatusCode::SERVICE_UNAVAILABLE).await;
+        let err = destination(Channel::Gotify, &url, "", "ok")
+            .send(&http, Uuid::nil(), "t", "b")
+            .await
+            .unwrap_err();
+        assert!(!err.permanent);
+    }
+
+    #[test]
+    fn refuses_internal_addresses() {
+        for ip in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.20.0.1",
+            "192.168.1.10",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "::1",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:192.168.1.10",
+            // NAT64 and 6to4 reach the embedded IPv4 address.
+            "64:ff9b::a00:1",
+            "64:ff9b::7f00:1",
+            "2002:c0a8:10a::1",
+            "2002:7f00:1::",
+        ] {
+            assert!(!is_public(ip.parse().unwrap()), "{ip}");
+        }
+        for ip in [
+            "93.184.215.14",
+            "2606:4700::1111",
+            "64:ff9b::5db8:d70e",
+            "2002:5db8:d70e::1",
+        ] {
+            assert!(is_public(ip.parse().unwrap()), "{ip}");
+        }
+    }
+
+    #[test]
+    fn allows_admin_listed_networks() {
+        let allowed: Vec<IpNet> = ["192.168.1.0/24", "fd00::/8"]
+            .iter()
+            .map(|n| n.parse().unwrap())
+            .collect();
+        for ip in [
+            "192.168.1.20",
+            "::ffff:192.168.1.20",
+            "64:ff9b::c0a8:114",
+            "2002:c0a8:114::1",
+            "fd12::1",
+            "93.184.215.14",
+        ] {
+            assert!(is_allowed(ip.parse().unwrap(), &allowed), "{ip}");
+        }
+        for ip in ["192.168.2.20", "10.0.0.1", "127.0.0.1", "fe80::1"] {
+            assert!(!is_allowed(ip.parse().unwrap(), &allowed), "{ip}");
+        }
+        assert!(!is_allowed("192.168.1.20".parse().unwrap(), &[]));
+    }
+
+    #[tokio::test]
+    async fn delivery_refuses_internal_servers() {
+        let (url, captured) = stand_in(StatusCode::OK).await;
+        for server in [url.as_str(), "http://localhost:9", "http://[::1]:9"] {
+            let err = destination(Channel::Gotify, server, "", "k")
+                .deliver(Uuid::nil(), "t", "b")
+                .await
+                .unwrap_err();
+            assert!(err.permanent, "{server}");
+        }
+        assert!(captured.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn validates_destinations() {
+        let ok = |c, t, k| destination(c, "https://ntfy.example.org", t, k).validate();
+        assert!(ok(Channel::Ntfy, "door_alerts", "").is_ok());
+        assert!(ok(Channel::Ntfy, "", "").is_err());
+        assert!(ok(Channel::Ntfy, "a/b", "").is_err());
+        assert!(ok(Channel::Gotify, "", "").is_err());
+        assert!(ok(Channel::Matrix, "!abc:example.org", "tok").is_ok());
+        assert!(ok(Channel::Matrix, "#alias:example.org", "tok").is_err());
+        assert!(ok(Channel::Email, "", "").is_err());
+        assert!(destination(Channel::Gotify, "file:///etc", "", "k")
+            .validate()
+            .is_err());
+    }
+}
@@ -19,7 +19,8 @@ use serde::{Deserialize, Serialize};
 use tracing::{error, info};
 
 use crate::auth_store::AuthData;
-use crate::middleware::AuthUser;
+use crate::middleware::Authorized;
+use crate::permissions::perm;
 use crate::utec::UTec;
 use crate::AppState;
 
@@ -51,7 +52,7 @@ pub fn router() -> Router<AppState> {
 }
 
 /// Redirect the user to U-Tec's OAuth2 authorization page.
-async fn login(_user: AuthUser) -> Response {
+async fn login(_user: Authorized<perm::ManageIntegrations>) -> Response {
     let state = generate_state();
     let redirect_uri = format!("{}/auth/callback", *BASE_URL);
 
@@ -183,7 +184,10 @@ struct AuthStatus {
 }
 
 /// Check whether we have a valid cached token.
-async fn status(_user: AuthUser, State(state): State<AppState>) -> Json<AuthStatus> {
+async fn status(
+    _user: Authorized<perm::ViewDevices>,
+    State(state): State<AppState>,
+) -> Json<AuthStatus> {
     match state.auth_store.get().await {
         Some(data) => {
             let expired = data
@@ -205,7 +209,10 @@ async fn status(_user: AuthUser, State(state): State<AppState>) -> Json<AuthStat
 }
 
 /// Clear cached credentials.
-async fn logout(_user: AuthUser, State(state): State<AppState>) -> Response {
+async fn logout(
+    _user: Authorized<perm::ManageIntegrations>,
+    State(state): State<AppState>,
+) -> Response {
     match state.auth_store.clear().await {
         Ok(_) => axum::http::StatusCode::NO_CONTENT.into_response(),
         Err(e) => {
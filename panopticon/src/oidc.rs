@@ -0,0 +1,938 @@
+//! Single sign-on through a generic OpenID Connect provider.
+//!
+//! Flow:
+//! 1. `/api/auth/oidc/login` redirects to the provider's authorization
+//!    endpoint (authorization code flow with PKCE, state and nonce)
+//! 2. The provider redirects back to `/api/auth/oidc/callback`, which only
+//!    proceeds in the browser holding the state cookie set by step 1
+//! 3. We exchange the code, validate the ID token against the provider's
+//!    JWKS, map claims to a role and find, link or create the account
+//! 4. The user gets a normal panopticon session
+//!
+//! Configured with `OIDC_*` environment variables (see `.env.example`).
+//! The provider is trusted to handle second factors, so local TOTP is not
+//! asked for. Any provider serving a discovery document works, including a
+//! local stand-in over plain HTTP.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use anyhow::{bail, Context};
+use axum::{
+    extract::{Query, State},
+    http::HeaderMap,
+    response::{IntoResponse, Redirect, Response},
+    routing::get,
+    Json, Router,
+};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::{Duration, Utc};
+use jsonwebtoken::jwk::JwkSet;
+use jsonwebtoken::{decode, decode_header, Algorithm, DecodingKey, Validation};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use subtle::ConstantTimeEq;
+use tokio::sync::RwLock;
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+use crate::email_auth::{generate_token, hash_password, is_secure, start_session, LoginUser};
+use crate::permissions::Role;
+use crate::session::ClientInfo;
+use crate::AppState;
+
+const LOGIN_STATE_TTL_MINUTES: i64 = 10;
+/// Ties a login to the browser that started it, so a callback URL from
+/// someone else's login can't sign this browser into their account.
+const STATE_COOKIE: &str = "panopticon_oidc_state";
+
+pub struct OidcProvider {
+    issuer: String,
+    client_id: String,
+    client_secret: Option<String>,
+    scopes: String,
+    redirect_uri: String,
+    display_name: String,
+    role_claim: String,
+    role_map: Vec<(String, Role)>,
+    default_role: Option<Role>,
+    http: reqwest::Client,
+    metadata: RwLock<Option<Arc<Metadata>>>,
+}
+
+struct Metadata {
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks_uri: String,
+    jwks: JwkSet,
+}
+
+#[derive(Deserialize)]
+struct Discovery {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks_uri: String,
+}
+
+impl OidcProvider {
+    /// Load from environment. Returns `None` unless `OIDC_ISSUER` is set.
+    pub fn from_env() -> anyhow::Result<Option<Self>> {
+        let Ok(issuer) = std::env::var("OIDC_ISSUER") else {
+            return Ok(None);
+        };
+        let client_id = std::env::var("OIDC_CLIENT_ID")
+            .context("OIDC_CLIENT_ID must be set with OIDC_ISSUER")?;
+        let base_url =
+            std::env::var("BASE_URL").unwrap_or_else(|_| "http://localhost:5173".to_string());
+
+        let role_map = match std::env::var("OIDC_ROLE_MAP") {
+            Ok(map) => parse_role_map(&map)?,
+            Err(_) => Vec::new(),
+        };
+        let default_role = match std::env::var("OIDC_DEFAULT_ROLE") {
+            Ok(role) => Some(
+                Role::parse(role.trim())
+                    .with_context(|| format!("Invalid OIDC_DEFAULT_ROLE '{role}'"))?,
+            ),
+            Err(_) => None,
+        };
+
+        let provider = Self {
+            issuer: issuer.trim_end_matches('/').to_string(),
+            client_id,
+            client_secret: std::env::var("OIDC_CLIENT_SECRET").ok(),
+            scopes: std::env::var("OIDC_SCOPES")
+                .unwrap_or_else(|_| "openid email profile".to_string()),
+            redirect_uri: std::env::var("OIDC_REDIRECT_URI").unwrap_or_else(|_| {
+                format!("{}/api/auth/oidc/callback", base_url.trim_end_matches('/'))
+            }),
+            display_name: std::env::var("OIDC_DISPLAY_NAME")
+                .unwrap_or_else(|_| "single sign-on".to_string()),
+            role_claim: std::env::var("OIDC_ROLE_CLAIM").unwrap_or_else(|_| "groups".to_string()),
+            role_map,
+            default_role,
+            http: reqwest::Client::new(),
+            metadata: RwLock::new(None),
+        };
+
+        info!(issuer = %provider.issuer, "OpenID Connect login enabled");
+        Ok(Some(provider))
+    }
+
+    /// Discovery document and signing keys, fetched on first use. `refresh`
+    /// refetches them, e.g. when the provider rotated its keys.
+    async fn metadata(&self, refresh: bool) -> anyhow::Result<Arc<Metadata>> {
+        if !refresh {
+            if let Some(metadata) = self.metadata.read().await.as_ref() {
+                return Ok(metadata.clone());
+            }
+        }
+
+        let url = format!("{}/.well-known/openid-configuration", self.issuer);
+        let discovery: Discovery = self
+            .http
+            .get(&url)
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .with_context(|| format!("Failed to fetch {url}"))?
+            .json()
+            .await
+            .context("Malformed discovery document")?;
+        if discovery.issuer.trim_end_matches('/') != self.issuer {
+            bail!("Discovery document is for issuer {}", discovery.issuer);
+        }
+
+        let jwks: JwkSet = self
+            .http
+            .get(&discovery.jwks_uri)
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .context("Failed to fetch JWKS")?
+            .json()
+            .await
+            .context("Malformed JWKS")?;
+
+        let metadata = Arc::new(Metadata {
+            authorization_endpoint: discovery.authorization_endpoint,
+            token_endpoint: discovery.token_endpoint,
+            jwks_uri: discovery.jwks_uri,
+            jwks,
+        });
+        *self.metadata.write().await = Some(metadata.clone());
+        Ok(metadata)
+    }
+}
+
+/// Parse `value=role` pairs separated by commas, e.g.
+/// `door-admins=admin,housemates=resident`.
+fn parse_role_map(map: &str) -> anyhow::Result<Vec<(String, Role)>> {
+    map.split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            let (value, role) = entry
+                .split_once('=')
+                .with_context(|| format!("Invalid OIDC_ROLE_MAP entry '{entry}'"))?;
+            let role = Role::parse(role.trim())
+                .with_context(|| format!("Invalid role in OIDC_ROLE_MAP entry '{entry}'"))?;
+            Ok((value.trim().to_string(), role))
+        })
+        .collect()
+}
+
+/// Most privileged role whose claim value the user has. The claim may be a
+/// single string or an array of strings.
+fn map_role(claim: Option<&serde_json::Value>, role_map: &[(String, Role)]) -> Option<Role> {
+    let values: Vec<&str> = match claim {
+        Some(serde_json::Value::String(s)) => vec![s.as_str()],
+        Some(serde_json::Value::Array(items)) => items.iter().filter_map(|v| v.as_str()).collect(),
+        _ => Vec::new(),
+    };
+
+    const BY_PRIVILEGE: [Role; 4] = [Role::Admin, Role::Operator, Role::Resident, Role::Viewer];
+    BY_PRIVILEGE.into_iter().find(|role| {
+        role_map
+            .iter()
+            .any(|(value, mapped)| mapped == role && values.contains(&value.as_str()))
+    })
+}
+
+#[derive(Debug, Deserialize)]
+struct IdClaims {
+    sub: String,
+    email: Option<String>,
+    /// Some providers send this as a string.
+    email_verified: Option<serde_json::Value>,
+    nonce: Option<String>,
+    #[serde(flatten)]
+    extra: HashMap<String, serde_json::Value>,
+}
+
+impl IdClaims {
+    fn email_verified(&self) -> bool {
+        match &self.email_verified {
+            Some(serde_json::Value::Bool(b)) => *b,
+            Some(serde_json::Value::String(s)) => s == "true",
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum TokenError {
+    /// No key in the JWKS matches; the provider may have rotated keys.
+    UnknownKey,
+    Invalid(&'static str),
+}
+
+/// Validate an ID token's signature, issuer, audience, expiry and nonce.
+fn validate_id_token(
+    token: &str,
+    jwks: &JwkSet,
+    issuer: &str,
+    client_id: &str,
+    nonce: &str,
+) -> Result<IdClaims, TokenError> {
+    let header = decode_header(token).map_err(|_| TokenError::Invalid("Malformed ID token"))?;
+
+    // Only asymmetric algorithms: the client secret must not be a signing key.
+    if matches!(
+        header.alg,
+        Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512
+    ) {
+        return Err(TokenError::Invalid("Unsupported ID token algorithm"));
+    }
+
+    let jwk = match header.kid.as_deref() {
+        Some(kid) => jwks.find(kid),
+        None if jwks.keys.len() == 1 => jwks.keys.first(),
+        None => None,
+    }
+    .ok_or(TokenError::UnknownKey)?;
+    let key =
+        DecodingKey::from_jwk(jwk).map_err(|_| TokenError::Invalid("Unusable signing key"))?;
+
+    let mut validation = Validation::new(header.alg);
+    validation.set_issuer(&[issuer]);
+    validation.set_audience(&[client_id]);
+    validation.set_required_spec_claims(&["exp", "iss", "aud", "sub"]);
+
+    let claims = decode::<IdClaims>(token, &key, &validation)
+        .map_err(|_| TokenError::Invalid("ID token failed validation"))?
+        .claims;
+
+    if claims.nonce.as_deref() != Some(nonce) {
+        return Err(TokenError::Invalid("Nonce mismatch"));
+    }
+
+    Ok(claims)
+}
+
+// ── Routes ──────────────────────────────────────────────────────────────────
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/oidc", get(info))
+        .route("/oidc/login", get(login))
+        .route("/oidc/callback", get(callback))
+}
+
+/// Whether SSO is available, for the login page.
+async fn info(State(state): State<AppState>) -> Response {
+    Json(serde_json::json!({
+        "enabled": state.oidc.is_some(),
+        "name": state.oidc.as_ref().map(|p| p.display_name.as_str()),
+    }))
+    .into_response()
+}
+
+fn login_error(code: &str) -> Response {
+    Redirect::to(&format!("/login?error={code}")).into_response()
+}
+
+fn state_cookie(login_state: &str, secure: bool) -> String {
+    let max_age = LOGIN_STATE_TTL_MINUTES * 60;
+    let secure_flag = if secure { "; Secure" } else { "" };
+    format!(
+        "{STATE_COOKIE}={login_state}; HttpOnly; SameSite=Lax; Path=/api/auth/oidc; \
+         Max-Age={max_age}{secure_flag}"
+    )
+}
+
+fn clear_state_cookie(secure: bool) -> String {
+    let secure_flag = if secure { "; Secure" } else { "" };
+    format!("{STATE_COOKIE}=; HttpOnly; SameSite=Lax; Path=/api/auth/oidc; Max-Age=0{secure_flag}")
+}
+
+/// Whether the request carries the state cookie for `login_state`.
+fn state_cookie_matches(headers: &HeaderMap, login_state: &str) -> bool {
+    headers
+        .get_all("cookie")
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|h| h.split(';'))
+        .filter_map(|c| c.trim().strip_prefix(STATE_COOKIE)?.strip_prefix('='))
+        .any(|value| bool::from(value.as_bytes().ct_eq(login_state.as_bytes())))
+}
+
+async fn login(State(state): State<AppState>) -> Response {
+    let Some(provider) = state.oidc.as_deref() else {
+        return login_error("sso_disabled");
+    };
+
+    let metadata = match provider.metadata(false).await {
+        Ok(m) => m,
+        Err(e) => {
+            error!("OIDC discovery failed: {e:#}");
+            return login_error("sso_unavailable");
+        }
+    };
+
+    let login_state = generate_token();
+    let nonce = generate_token();
+    let code_verifier = generate_token();
+    let code_challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(code_verifier.as_bytes()));
+
+    if let Err(e) = store_login_state(&state.db, &login_state, &nonce, &code_verifier).await {
+        error!("Failed to store OIDC login state: {e}");
+        return login_error("sso_unavailable");
+    }
+
+    let url = format!(
+        "{}{}response_type=code&client_id={}&redirect_uri={}&scope={}&state={}&nonce={}\
+         &code_challenge={}&code_challenge_method=S256",
+        metadata.authorization_endpoint,
+        if metadata.authorization_endpoint.contains('?') {
+            '&'
+        } else {
+            '?'
+        },
+        urlencoding::encode(&provider.client_id),
+        urlencoding::encode(&provider.redirect_uri),
+        urlencoding::encode(&provider.scopes),
+        login_state,
+        nonce,
+        code_challenge,
+    );
+
+    let mut response = Redirect::to(&url).into_response();
+    if let Ok(cookie) = state_cookie(&login_state, is_secure()).parse() {
+        response.headers_mut().insert("set-cookie", cookie);
+    }
+    response
+}
+
+async fn store_login_state(
+    db: &PgPool,
+    state: &str,
+    nonce: &str,
+    code_verifier: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM oidc_login_states WHERE expires_at < now()")
+        .execute(db)
+        .await?;
+    sqlx::query(
+        "INSERT INTO oidc_login_states (state, nonce, code_verifier, expires_at) \
+         VALUES ($1, $2, $3, $4)",
+    )
+    .bind(state)
+    .bind(nonce)
+    .bind(code_verifier)
+    .bind(Utc::now() + Duration::minutes(LOGIN_STATE_TTL_MINUTES))
+    .execute(db)
+    .await?;
+    Ok(())
+}
+
+#[derive(Deserialize)]
+struct CallbackParams {
+    code: Option<String>,
+    state: Option<String>,
+    error: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+async fn callback(
+    State(state): State<AppState>,
+    client: ClientInfo,
+    headers: HeaderMap,
+    Query(params): Query<CallbackParams>,
+) -> Response {
+    let mut response = finish_login(&state, &client, &headers, params).await;
+    // The state is single-use whatever the outcome.
+    if let Ok(cookie) = clear_state_cookie(is_secure()).parse() {
+        response.headers_mut().append("set-cookie", cookie);
+    }
+    response
+}
+
+async fn finish_login(
+    state: &AppState,
+    client: &ClientInfo,
+    headers: &HeaderMap,
+    params: CallbackParams,
+) -> Response {
+    let Some(provider) = state.oidc.as_deref() else {
+        return login_error("sso_disabled");
+    };
+
+    if let Some(error) = params.error {
+        warn!(error, "OIDC provider returned an error");
+        return login_error("sso_denied");
+    }
+    let (Some(code), Some(login_state)) = (params.code, params.state) else {
+        return login_error("sso_failed");
+    };
+
+    let claims = match verify_callback(&state.db, provider, headers, &code, &login_state).await {
+        Ok(claims) => claims,
+        Err(code) => return login_error(code),
+    };
+
+    let (user_id, email, email_confirmed, role) =
+        match resolve_user(&state.db, provider, &claims).await {
+            Ok(user) => user,
+            Err(ResolveError::Refused(reason)) => {
+                warn!(sub = %claims.sub, reason, "OIDC login refused");
+                return login_error("sso_refused");
+            }
+            Err(ResolveError::Database(e)) => {
+                error!("Failed to resolve OIDC user: {e}");
+                return login_error("sso_failed");
+            }
+        };
+
+    info!(email = %email, issuer = %provider.issuer, "User logged in with OIDC");
+
+    // Reuse the normal login response for the session cookie, but send the
+    // browser back to the app.
+    // The provider's own second factors can't be verified, so the session
+    // doesn't count as one.
+    let user = LoginUser {
+        id: user_id,
+        email: &email,
+        email_confirmed,
+        is_approved: true,
+        role: role.as_str(),
+    };
+    let session = start_session(state, client, &user, None).await;
+    if !session.status().is_success() {
+        return login_error("sso_refused");
+    }
+    let mut response = Redirect::to("/").into_response();
+    if let Some(cookie) = session.headers().get("set-cookie") {
+        response.headers_mut().insert("set-cookie", cookie.clone());
+    }
+    response
+}
+
+/// Check the callback belongs to a login this browser started, then redeem
+/// the code. Errors are `/login?error=` codes.
+async fn verify_callback(
+    db: &PgPool,
+    provider: &OidcProvider,
+    headers: &HeaderMap,
+    code: &str,
+    login_state: &str,
+) -> Result<IdClaims, &'static str> {
+    if !state_cookie_matches(headers, login_state) {
+        warn!("OIDC callback without a matching state cookie");
+        return Err("sso_failed");
+    }
+
+    let row: Option<(String, String)> = sqlx::query_as(
+        "DELETE FROM oidc_login_states WHERE state = $1 AND expires_at > now() \
+         RETURNING nonce, code_verifier",
+    )
+    .bind(login_state)
+    .fetch_optional(db)
+    .await
+    .map_err(|e| {
+        error!("Failed to load OIDC login state: {e}");
+        "sso_failed"
+    })?;
+    let Some((nonce, code_verifier)) = row else {
+        warn!("Unknown or expired OIDC state");
+        return Err("sso_expired");
+    };
+
+    exchange_code(provider, code, &code_verifier, &nonce)
+        .await
+        .map_err(|e| {
+            error!("OIDC login failed: {e:#}");
+            "sso_failed"
+        })
+}
+
+async fn exchange_code(
+    provider: &OidcProvider,
+    code: &str,
+    code_verifier: &str,
+    nonce: &str,
+) -> anyhow::Result<IdClaims> {
+    let metadata = provider.metadata(false).await?;
+
+    let mut form = vec![
+        ("grant_type", "authorization_code"),
+        ("code", code),
+        ("redirect_uri", provider.redirect_uri.as_str()),
+        ("client_id", provider.client_id.as_str()),
+        ("code_verifier", code_verifier),
+    ];
+    if let Some(secret) = provider.client_secret.as_deref() {
+        form.push(("client_secret", secret));
+    }
+
+    let tokens: TokenResponse = provider
+        .http
+        .post(&metadata.token_endpoint)
+        .form(&form)
+        .send()
+        .await
+        .and_then(|r| r.error_for_status())
+        .context("Token request failed")?
+        .json()
+        .await
+        .context("Malformed token response")?;
+
+    let validate = |jwks: &JwkSet| {
+        validate_id_token(
+            &tokens.id_token,
+            jwks,
+            &provider.issuer,
+            &provider.client_id,
+            nonce,
+        )
+    };
+
+    match validate(&metadata.jwks) {
+        Ok(claims) => Ok(claims),
+        Err(TokenError::UnknownKey) => {
+            info!(jwks_uri = %metadata.jwks_uri, "Unknown ID token key, refreshing JWKS");
+            let metadata = provider.metadata(true).await?;
+            validate(&metadata.jwks).map_err(|e| anyhow::anyhow!("{e:?}"))
+        }
+        Err(e) => bail!("{e:?}"),
+    }
+}
+
+enum ResolveError {
+    Refused(&'static str),
+    Database(sqlx::Error),
+}
+
+impl From<sqlx::Error> for ResolveError {
+    fn from(e: sqlx::Error) -> Self {
+        Self::Database(e)
+    }
+}
+
+/// Find the account linked to these claims, link an existing account with
+/// the same verified email, or create one. Returns id, email, confirmation
+/// and role. Roles from the claim mapping are applied on every login.
+async fn resolve_user(
+    db: &PgPool,
+    provider: &OidcProvider,
+    claims: &IdClaims,
+) -> Result<(Uuid, String, bool, Role), ResolveError> {
+    let mapped = map_role(claims.extra.get(&provider.role_claim), &provider.role_map);
+
+    let mut tx = db.begin().await?;
+
+    // Serializes with role changes and deletions (see `api::lock_users`).
+    sqlx::query("LOCK TABLE users IN SHARE ROW EXCLUSIVE MODE")
+        .execute(&mut *tx)
+        .await?;
+
+    let linked: Option<Uuid> = sqlx::query_scalar(
+        "SELECT user_id FROM user_identities WHERE issuer = $1 AND subject = $2",
+    )
+    .bind(&provider.issuer)
+    .bind(&claims.sub)
+    .fetch_optional(&mut *tx)
+    .await?;
+
+    let user_id = match linked {
+        Some(id) => id,
+        None => {
+            let email = claims
+                .email
+                .as_deref()
+                .map(|e| e.trim().to_lowercase())
+                .filter(|e| e.contains('@'))
+                .ok_or(ResolveError::Refused("no email claim"))?;
+
+            let existing: Option<Uuid> =
+                sqlx::query_scalar("SELECT id FROM users WHERE email = $1")
+                    .bind(&email)
+                    .fetch_optional(&mut *tx)
+                    .await?;
+
+            let id = match existing {
+                // Linking by an unverified address would let anyone who
+                // can set that address at the provider take the account.
+                Some(_) if !claims.email_verified() => {
+                    return Err(ResolveError::Refused("email not verified"));
+                }
+                Some(id) => id,
+                None => {
+                    let role = mapped
+                        .or(provider.default_role)
+                        .ok_or(ResolveError::Refused("no role mapped"))?;
+                    // Password login stays unusable until the user resets it.
+                    let unusable = hash_password(&generate_token())
+                        .map_err(|_| ResolveError::Refused("password hashing failed"))?;
+                    sqlx::query_scalar(
+                        "INSERT INTO users (email, password_hash, role, is_approved, email_confirmed) \
+                         VALUES ($1, $2, $3, TRUE, $4) RETURNING id",
+                    )
+                    .bind(&email)
+                    .bind(&unusable)
+                    .bind(role.as_str())
+                    .bind(claims.email_verified())
+                    .fetch_one(&mut *tx)
+                    .await?
+                }
+            };
+
+            sqlx::query(
+                "INSERT INTO user_identities (issuer, subject, user_id) VALUES ($1, $2, $3)",
+            )
+            .bind(&provider.issuer)
+            .bind(&claims.sub)
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+            info!(issuer = %provider.issuer, sub = %claims.sub, %email, "Linked OIDC identity");
+            id
+        }
+    };
+
+    let (email, email_confirmed, current): (String, bool, String) =
+        sqlx::query_as("SELECT email, email_confirmed, role FROM users WHERE id = $1")
+            .bind(user_id)
+            .fetch_one(&mut *tx)
+            .await?;
+    let mut role = Role::parse(&current).unwrap_or(Role::Viewer);
+
+    if let Some(new_role) = mapped.filter(|r| *r != role) {
+        let last_admin = role == Role::Admin && {
+            let others: i64 = sqlx::query_scalar(
+                "SELECT COUNT(*) FROM users \
+                 WHERE role = 'admin' AND is_approved = TRUE AND suspended_at IS NULL AND id <> $1",
+            )
+            .bind(user_id)
+            .fetch_one(&mut *tx)
+            .await?;
+            others == 0
+        };
+        if last_admin {
+            warn!(%email, "Not demoting the last admin from OIDC claims");
+        } else {
+            sqlx::query(
+                "UPDATE users SET role = $1, is_approved = TRUE, updated_at = now() WHERE id = $2",
+            )
+            .bind(new_role.as_str())
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await?;
+            info!(%email, from = role.as_str(), to = new_role.as_str(), "Role updated from OIDC claims");
+            role = new_role;
+        }
+    }
+
+    sqlx::query(
+        "UPDATE user_identities SET last_login_at = now() WHERE issuer = $1 AND subject = $2",
+    )
+    .bind(&provider.issuer)
+    .bind(&claims.sub)
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok((user_id, email, email_confirmed, role))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jsonwebtoken::{encode, EncodingKey, Header};
+    use openssl::rsa::Rsa;
+
+    const ISSUER: &str = "http://localhost:9000/default";
+    const CLIENT_ID: &str = "panopticon";
+
+    /// A stand-in provider: an RSA key and its JWKS.
+    fn provider_key() -> (EncodingKey, JwkSet) {
+        let rsa = Rsa::generate(2048).unwrap();
+        let encoding = EncodingKey::from_rsa_pem(&rsa.private_key_to_pem().unwrap()).unwrap();
+        let jwks = serde_json::from_value(serde_json::json!({
+            "keys": [{
+                "kty": "RSA",
+                "kid": "test-key",
+                "alg": "RS256",
+                "use": "sig",
+                "n": URL_SAFE_NO_PAD.encode(rsa.n().to_vec()),
+                "e": URL_SAFE_NO_PAD.encode(rsa.e().to_vec()),
+            }]
+        }))
+        .unwrap();
+        (encoding, jwks)
+    }
+
+    fn id_token(key: &EncodingKey, claims: serde_json::Value) -> String {
+        let mut header = Header::new(Algorithm::RS256);
+        header.kid = Some("test-key".to_string());
+        encode(&header, &claims, key).unwrap()
+    }
+
+    fn claims(overrides: serde_json::Value) -> serde_json::Value {
+        let mut claims = serde_json::json!({
+            "iss": ISSUER,
+            "aud": CLIENT_ID,
+            "sub": "user-1",
+            "exp": Utc::now().timestamp() + 300,
+            "nonce": "n-1",
+            "email": "alice@example.com",
+            "email_verified": true,
+            "groups": ["housemates"],
+        });
+        for (k, v) in overrides.as_object().unwrap() {
+            claims[k] = v.clone();
+        }
+        claims
+    }
+
+    #[test]
+    fn accepts_valid_id_token() {
+        let (key, jwks) = provider_key();
+        let token = id_token(&key, claims(serde_json::json!({})));
+        let claims = validate_id_token(&token, &jwks, ISSUER, CLIENT_ID, "n-1").unwrap();
+        assert_eq!(claims.sub, "user-1");
+        assert!(claims.email_verified());
+        assert_eq!(
+            map_role(
+                claims.extra.get("groups"),
+                &[("housemates".to_string(), Role::Resident)]
+            ),
+            Some(Role::Resident)
+        );
+    }
+
+    #[test]
+    fn rejects_bad_id_tokens() {
+        let (key, jwks) = provider_key();
+        let check = |overrides| {
+            let token = id_token(&key, claims(overrides));
+            validate_id_token(&token, &jwks, ISSUER, CLIENT_ID, "n-1")
+        };
+
+        assert!(check(serde_json::json!({"iss": "http://evil"})).is_err());
+        assert!(check(serde_json::json!({"aud": "other-client"})).is_err());
+        assert!(check(serde_json::json!({"exp": Utc::now().timestamp() - 3600})).is_err());
+        assert_eq!(
+            check(serde_json::json!({"nonce": "replayed"})).unwrap_err(),
+            TokenError::Invalid("Nonce mismatch")
+        );
+
+        let (other_key, _) = provider_key();
+        let forged = id_token(&other_key, claims(serde_json::json!({})));
+        assert!(validate_id_token(&forged, &jwks, ISSUER, CLIENT_ID, "n-1").is_err());
+    }
+
+    #[test]
+    fn rejects_unknown_key_and_symmetric_algorithms() {
+        let (key, jwks) = provider_key();
+        let mut header = Header::new(Algorithm::RS256);
+        header.kid = Some("rotated".to_string());
+        let token = encode(&header, &claims(serde_json::json!({})), &key).unwrap();
+        assert_eq!(
+            validate_id_token(&token, &jwks, ISSUER, CLIENT_ID, "n-1").unwrap_err(),
+            TokenError::UnknownKey
+        );
+
+        let hs = encode(
+            &Header::new(Algorithm::HS256),
+            &claims(serde_json::json!({})),
+            &EncodingKey::from_secret(b"client-secret"),
+        )
+        .unwrap();
+        assert!(validate_id_token(&hs, &jwks, ISSUER, CLIENT_ID, "n-1").is_err());
+    }
+
+    type TokenRequests = Arc<std::sync::Mutex<Vec<HashMap<String, String>>>>;
+
+    /// A local stand-in provider serving discovery, JWKS and a token
+    /// endpoint that answers every code with an ID token for `nonce`.
+    async fn stand_in_provider(nonce: &str) -> (OidcProvider, TokenRequests) {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let issuer = format!("http://{}", listener.local_addr().unwrap());
+        let (key, jwks) = provider_key();
+        let token = id_token(
+            &key,
+            claims(serde_json::json!({"iss": issuer, "nonce": nonce})),
+        );
+
+        let requests = TokenRequests::default();
+        let log = requests.clone();
+        let discovery = serde_json::json!({
+            "issuer": issuer,
+            "authorization_endpoint": format!("{issuer}/authorize"),
+            "token_endpoint": format!("{issuer}/token"),
+            "jwks_uri": format!("{issuer}/jwks"),
+        });
+        let app = Router::new()
+            .route(
+                "/.well-known/openid-configuration",
+                get(move || async move { Json(discovery) }),
+            )
+            .route("/jwks", get(move || async move { Json(jwks) }))
+            .route(
+                "/token",
+                axum::routing::post(
+                    move |axum::Form(form): axum::Form<HashMap<String, String>>| async move {
+                        log.lock().unwrap().push(form);
+                        Json(serde_json::json!({ "id_token": token, "token_type": "Bearer" }))
+                    },
+                ),
+            );
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let provider = OidcProvider {
+            issuer,
+            client_id: CLIENT_ID.to_string(),
+            client_secret: None,
+            scopes: "openid email".to_string(),
+            redirect_uri: "http://localhost:5173/api/auth/oidc/callback".to_string(),
+            display_name: "Stand-in".to_string(),
+            role_claim: "groups".to_string(),
+            role_map: Vec::new(),
+            default_role: None,
+            http: reqwest::Client::new(),
+            metadata: RwLock::new(None),
+        };
+        (provider, requests)
+    }
+
+    #[tokio::test]
+    async fn exchanges_code_with_stand_in_provider() {
+        let (provider, requests) = stand_in_provider("n-1").await;
+
+        let claims = exchange_code(&provider, "code-1", "verifier-1", "n-1")
+            .await
+            .unwrap();
+        assert_eq!(claims.sub, "user-1");
+        assert_eq!(claims.email.as_deref(), Some("alice@example.com"));
+
+        {
+            let requests = requests.lock().unwrap();
+            assert_eq!(requests[0]["grant_type"], "authorization_code");
+            assert_eq!(requests[0]["code"], "code-1");
+            assert_eq!(requests[0]["code_verifier"], "verifier-1");
+            assert_eq!(requests[0]["client_id"], CLIENT_ID);
+        }
+
+        // A token for another login's nonce is refused.
+        assert!(exchange_code(&provider, "code-2", "verifier-2", "n-2")
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn callback_requires_matching_state_cookie() {
+        let (provider, requests) = stand_in_provider("n-1").await;
+        // Never connected: the cookie check must fail before the database.
+        let db = PgPool::connect_lazy("postgres://localhost/unused").unwrap();
+
+        let cookie = |value: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert("cookie", value.parse().unwrap());
+            headers
+        };
+        for headers in [
+            HeaderMap::new(),
+            cookie("panopticon_session=abc"),
+            cookie("panopticon_oidc_state=someone-elses-state"),
+        ] {
+            assert_eq!(
+                verify_callback(&db, &provider, &headers, "code-1", "state-1")
+                    .await
+                    .unwrap_err(),
+                "sso_failed"
+            );
+        }
+        assert!(requests.lock().unwrap().is_empty());
+
+        assert!(state_cookie_matches(
+            &cookie("panopticon_session=abc; panopticon_oidc_state=state-1"),
+            "state-1"
+        ));
+        assert!(state_cookie(&"s".repeat(64), true).contains("HttpOnly; SameSite=Lax"));
+        assert!(state_cookie("state-1", true).ends_with("; Secure"));
+    }
+
+    #[test]
+    fn maps_most_privileged_role() {
+        let map = parse_role_map("residents=resident, door-admins=admin,viewers=viewer").unwrap();
+        let groups = serde_json::json!(["viewers", "door-admins"]);
+        assert_eq!(map_role(Some(&groups), &map), Some(Role::Admin));
+        assert_eq!(
+            map_role(Some(&serde_json::json!("residents")), &map),
+            Some(Role::Resident)
+        );
+        assert_eq!(map_role(Some(&serde_json::json!(["guests"])), &map), None);
+        assert_eq!(map_role(None, &map), None);
+        assert!(parse_role_map("admins=root").is_err());
+        assert!(parse_role_map("admins").is_err());
+    }
+}
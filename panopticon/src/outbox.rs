@@ -0,0 +1,708 @@
+//! Durable notification and webhook delivery.
+//!
+//! [`EventBus`] wraps the live broadcast channel. Besides broadcasting, it
+//! hands every event to an unbounded queue, so slow consumers can no longer
+//! make notifications lag out. The outbox writer stores each notifiable
+//! event in `notification_outbox` and fans it out into one
+//! `notification_deliveries` row per recipient and channel, rendered up
+//! front, or parks it for the recipient's next [`digest`]; it also queues
+//! [`outgoing_webhooks`] deliveries, which have their own worker. The
+//! delivery worker sends due rows and retries failures with exponential backoff; after
+//! [`MAX_ATTEMPTS`] a delivery is dead-lettered. Admins can list deliveries
+//! and re-send dead ones.
+
+use std::sync::Arc;
+use std::time::Instant;
+
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    routing::{get, post},
+    Json, Router,
+};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use tokio::sync::{broadcast, mpsc, Notify};
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+use crate::digest::Digest;
+use crate::middleware::Authorized;
+use crate::notification_prefs::{self, Channel, EventSubject};
+use crate::notify_channels::Destination;
+use crate::permissions::perm;
+use crate::push::{Notification, PushError};
+use crate::ws::WsEvent;
+use crate::{digest, notification_content, outgoing_webhooks, push, push_actions, AppState};
+
+type ApiError = (StatusCode, &'static str);
+
+/// Attempts before a delivery is dead-lettered.
+const MAX_ATTEMPTS: i32 = 10;
+/// How often the worker looks for due retries when nothing wakes it.
+const POLL_INTERVAL: std::time::Duration = std::time::Duration::from_secs(15);
+const BATCH_SIZE: i64 = 100;
+const RETENTION_DAYS: i32 = 30;
+
+// ── Event bus ───────────────────────────────────────────────────────────────
+
+/// The application's event channel: a `broadcast` channel for live
+/// consumers plus durable hand-off to the outbox writer.
+#[derive(Clone)]
+pub struct EventBus {
+    live: broadcast::Sender<WsEvent>,
+    outbox: mpsc::UnboundedSender<WsEvent>,
+    deliveries_due: Arc<Notify>,
+    webhooks_due: Arc<Notify>,
+}
+
+impl EventBus {
+    /// Create the bus and the receiving end for [`spawn_outbox_writer`].
+    pub fn new(capacity: usize) -> (Self, mpsc::UnboundedReceiver<WsEvent>) {
+        let (live, _) = broadcast::channel(capacity);
+        let (outbox, outbox_rx) = mpsc::unbounded_channel();
+        let bus = Self {
+            live,
+            outbox,
+            deliveries_due: Arc::new(Notify::new()),
+            webhooks_due: Arc::new(Notify::new()),
+        };
+        (bus, outbox_rx)
+    }
+
+    /// Publish an event. Returns the number of live subscribers reached.
+    pub fn send(&self, event: WsEvent) -> usize {
+        if self.outbox.send(event.clone()).is_err() {
+            error!("Outbox writer is gone, notifications and webhooks dropped");
+        }
+        self.live.send(event).unwrap_or(0)
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<WsEvent> {
+        self.live.subscribe()
+    }
+
+    /// Tell the delivery worker there is work now rather than at its next poll.
+    pub fn wake_deliveries(&self) {
+        self.deliveries_due.notify_one();
+    }
+
+    /// Tell the webhook worker there is work now rather than at its next poll.
+    pub fn wake_webhooks(&self) {
+        self.webhooks_due.notify_one();
+    }
+
+    /// Wait until [`wake_webhooks`](Self::wake_webhooks) is called.
+    pub(crate) async fn webhooks_woken(&self) {
+        self.webhooks_due.notified().await;
+    }
+}
+
+// ── Outbox writer ───────────────────────────────────────────────────────────
+
+pub async fn spawn_outbox_writer(mut rx: mpsc::UnboundedReceiver<WsEvent>, state: AppState) {
+    info!("Notification outbox writer started");
+    while let Some(event) = rx.recv().await {
+        let notifications = enqueue(&state, &event).await.unwrap_or_else(|e| {
+            error!(?event, "Failed to store notification: {e}");
+            0
+        });
+        let webhooks = outgoing_webhooks::enqueue(&state.db, &event)
+            .await
+            .unwrap_or_else(|e| {
+                error!(?event, "Failed to queue webhooks: {e}");
+                0
+            });
+        if notifications > 0 {
+            state.events.wake_deliveries();
+        }
+        if webhooks > 0 {
+            state.events.wake_webhooks();
+        }
+    }
+    info!("Notification outbox writer shutting down (channel closed)");
+}
+
+/// Store an event and its deliveries. Returns the number of deliveries.
+async fn enqueue(state: &AppState, event: &WsEvent) -> Result<u64, sqlx::Error> {
+    let Some(subject) = EventSubject::of(event) else {
+        return Ok(0);
+    };
+
+    let email_to = notification_prefs::recipients(&state.db, Channel::Email, event).await?;
+    let mut others: Vec<(Channel, Vec<Uuid>)> = Vec::new();
+    for channel in [
+        Channel::Push,
+        Channel::Ntfy,
+        Channel::Gotify,
+        Channel::Matrix,
+    ] {
+        if channel == Channel::Push && state.push_config.is_none() {
+            continue;
+        }
+        let users: Vec<Uuid> = notification_prefs::recipients(&state.db, channel, event)
+            .await?
+            .into_iter()
+            .map(|r| r.user_id)
+            .collect();
+        if !users.is_empty() {
+            others.push((channel, users));
+        }
+    }
+    if email_to.is_empty() && others.is_empty() {
+        return Ok(0);
+    }
+    // Every channel carries the same text.
+    let Some(message) = notification_content::message(state, event).await else {
+        return Ok(0);
+    };
+
+    let mut tx = state.db.begin().await?;
+    let outbox_id: Uuid = sqlx::query_scalar(
+        "INSERT INTO notification_outbox (event_type, event) VALUES ($1, $2) RETURNING id",
+    )
+    .bind(subject.event_type.as_str())
+    .bind(serde_json::to_value(event).unwrap_or_default())
+    .fetch_one(&mut *tx)
+    .await?;
+
+    let mut count = 0;
+    for recipient in &email_to {
+        if recipient.digest != Digest::Instant {
+            sqlx::query(
+                "INSERT INTO notification_digest_items \
+                 (user_id, outbox_id, event_type, tag_id, device_id, summary, card, door) \
+                 VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
+            )
+            .bind(recipient.user_id)
+            .bind(outbox_id)
+            .bind(subject.event_type.as_str())
+            .bind(subject.tag_id)
+            .bind(subject.device_id)
+            .bind(&message.subject)
+            .bind(&message.card)
+            .bind(&message.door)
+            .execute(&mut *tx)
+            .await?;
+            continue;
+        }
+        sqlx::query(
+            "INSERT INTO notification_deliveries \
+             (outbox_id, user_id, channel, email, subject, body) \
+             VALUES ($1, $2, 'email', $3, $4, $5)",
+        )
+        .bind(outbox_id)
+        .bind(recipient.user_id)
+        .bind(&recipient.email)
+        .bind(&message.subject)
+        .bind(&message.body)
+        .execute(&mut *tx)
+        .await?;
+        count += 1;
+    }
+    for (channel, users) in &others {
+        // One delivery per push subscription; the other channels need a
+        // configured destination.
+        let query = if *channel == Channel::Push {
+            "INSERT INTO notification_deliveries \
+             (outbox_id, user_id, channel, push_subscription_id, subject, body) \
+             SELECT $1, user_id, $2, id, $3, $4 FROM push_subscriptions \
+             WHERE user_id = ANY($5)"
+        } else {
+            "INSERT INTO notification_deliveries \
+             (outbox_id, user_id, channel, subject, body) \
+             SELECT $1, user_id, $2, $3, $4 FROM user_notification_channels \
+             WHERE channel = $2 AND user_id = ANY($5)"
+        };
+        count += sqlx::query(query)
+            .bind(outbox_id)
+            .bind(channel.as_str())
+            .bind(&message.subject)
+            .bind(&message.body)
+            .bind(users)
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+    }
+
+    tx.commit().await?;
+    Ok(count)
+}
+
+// ── Delivery worker ─────────────────────────────────────────────────────────
+
+/// Delay before retrying after the `attempts`-th failure, or `None` once the
+/// delivery should be dead-lettered. 1, 2, 4, … minutes, capped at 2 hours.
+pub(crate) fn retry_delay(attempts: i32) -> Option<Duration> {
+    if attempts >= MAX_ATTEMPTS {
+        return None;
+    }
+    let factor = 1i32 << (attempts - 1).clamp(0, 20);
+    Some((Duration::minutes(1) * factor).min(Duration::hours(2)))
+}
+
+/// Why a send failed.
+struct DeliveryError {
+    message: String,
+    /// Retrying can't help, e.g. the push subscription was removed.
+    permanent: bool,
+}
+
+impl DeliveryError {
+    fn retry(message: impl Into<String>) -> Self {
+        Self {
+            message: message.into(),
+            permanent: false,
+        }
+    }
+
+    fn permanent(message: impl Into<String>) -> Self {
+        Self {
+            message: message.into(),
+            permanent: true,
+        }
+    }
+}
+
+type DueRow = (
+    Uuid,
+    String,
+    Option<String>,
+    Option<Uuid>,
+    String,
+    String,
+    i32,
+    Option<String>,
+    Option<String>,
+    Option<String>,
+    Option<String>,
+    Option<String>,
+    Option<String>,
+    Option<String>,
+);
+
+pub async fn spawn_delivery_worker(state: AppState) {
+    let http = reqwest::Client::builder()
+        .timeout(std::time::Duration::from_secs(30))
+        .build()
+        .expect("Failed to build HTTP client");
+    let mut last_prune: Option<Instant> = None;
+
+    info!("Notification delivery worker started");
+    loop {
+        digest::compile_due(&state).await;
+        while deliver_due(&state, &http).await == BATCH_SIZE as usize {}
+
+        if last_prune.is_none_or(|t| t.elapsed() > std::time::Duration::from_secs(3600)) {
+            prune(&state.db).await;
+            last_prune = Some(Instant::now());
+        }
+
+        tokio::select! {
+            _ = state.events.deliveries_due.notified() => {}
+            _ = tokio::time::sleep(POLL_INTERVAL) => {}
+        }
+    }
+}
+
+/// Attempt one batch of due deliveries. Returns how many were attempted.
+async fn deliver_due(state: &AppState, http: &reqwest::Client) -> usize {
+    let rows: Vec<DueRow> = match sqlx::query_as(
+        "SELECT d.id, d.channel, d.email, d.push_subscription_id, d.subject, d.body, d.attempts, \
+                s.endpoint, s.p256dh, s.auth, s.vapid_key, t.server_url, t.target, t.token \
+         FROM notification_deliveries d \
+         LEFT JOIN push_subscriptions s ON s.id = d.push_subscription_id \
+         LEFT JOIN user_notification_channels t \
+                ON t.user_id = d.user_id AND t.channel = d.channel \
+         WHERE d.status = 'pending' AND d.next_attempt_at <= now() \
+         ORDER BY d.next_attempt_at LIMIT $1",
+    )
+    .bind(BATCH_SIZE)
+    .fetch_all(&state.db)
+    .await
+    {
+        Ok(rows) => rows,
+        Err(e) => {
+            error!("Failed to load due notifications: {e}");
+            return 0;
+        }
+    };
+
+    let attempted = rows.len();
+    for (
+        id,
+        channel,
+        email,
+        subscription_id,
+        subject,
+        body,
+        attempts,
+        endpoint,
+        p256dh,
+        auth,
+        vapid_key,
+        server_url,
+        target,
+        token,
+    ) in rows
+    {
+        let result = match Channel::parse(&channel) {
+            Some(Channel::Email) => match email {
+                Some(to) => state
+                    .mailer
+                    .send_access_event_email(&to, &subject, &body)
+                    .await
+                    .map_err(|e| DeliveryError::retry(format!("{e:#}"))),
+                None => Err(DeliveryError::permanent("No recipient address")),
+            },
+            Some(Channel::Push) => {
+                match (&state.push_config, subscription_id, endpoint, p256dh, auth) {
+                    (None, ..) => Err(DeliveryError::permanent(
+                        "Push notifications not configured",
+                    )),
+                    (
+                        Some(config),
+                        Some(subscription_id),
+                        Some(endpoint),
+                        Some(p256dh),
+                        Some(auth),
+                    ) => {
+                        let notification = Notification {
+                            title: &subject,
+                            body: &body,
+                            actions: push_actions::for_delivery(&state.db, id).await,
+                        };
+                        match config
+                            .send(
+                                http,
+                                &endpoint,
+                                &p256dh,
+                                &auth,
+                                vapid_key.as_deref(),
+                                &notification,
+                            )
+                            .await
+                        {
+                            Ok(()) => {
+                                push::record_success(&state.db, subscription_id).await;
+                                Ok(())
+                            }
+                            Err(PushError::Gone) => {
+                                if let Err(e) =
+                                    sqlx::query("DELETE FROM push_subscriptions WHERE id = $1")
+                                        .bind(subscription_id)
+                                        .execute(&state.db)
+                                        .await
+                                {
+                                    error!("Failed to remove stale push subscription: {e}");
+                                }
+                                Err(DeliveryError::permanent("Push subscription expired"))
+                            }
+                            Err(PushError::Failed(e)) => {
+                                if push::record_failure(&state.db, subscription_id, &e).await {
+                                    Err(DeliveryError::permanent(
+                                        "Push subscription removed after repeated failures",
+                                    ))
+                                } else {
+                                    Err(DeliveryError::retry(e))
+                                }
+                            }
+                        }
+                    }
+                    _ => Err(DeliveryError::permanent("Push subscription removed")),
+                }
+            }
+            Some(channel) => match server_url {
+                Some(server_url) => Destination {
+                    channel,
+                    server_url,
+                    target,
+                    token,
+                }
+                .deliver(id, &subject, &body)
+                .await
+                .map_err(|e| DeliveryError {
+                    message: e.message,
+                    permanent: e.permanent,
+                }),
+                None => Err(DeliveryError::permanent("Channel no longer configured")),
+            },
+            None => Err(DeliveryError::permanent("Unknown channel")),
+        };
+
+        record_attempt(&state.db, id, attempts + 1, result).await;
+    }
+
+    attempted
+}
+
+async fn record_attempt(db: &PgPool, id: Uuid, attempts: i32, result: Result<(), DeliveryError>) {
+    let update = match result {
+        Ok(()) => sqlx::query(
+            "UPDATE notification_deliveries \
+             SET status = 'sent', attempts = $2, sent_at = now(), last_error = NULL WHERE id = $1",
+        )
+        .bind(id)
+        .bind(attempts),
+        Err(e) => {
+            let delay = if e.permanent {
+                None
+            } else {
+                retry_delay(attempts)
+            };
+            match delay {
+                Some(delay) => {
+                    warn!(delivery = %id, attempts, error = %e.message, "Notification failed, will retry");
+                    sqlx::query(
+                        "UPDATE notification_deliveries \
+                         SET attempts = $2, last_error = $3, next_attempt_at = now() + $4 \
+                         WHERE id = $1",
+                    )
+                    .bind(id)
+                    .bind(attempts)
+                    .bind(e.message)
+                    .bind(delay)
+                }
+                None => {
+                    error!(delivery = %id, attempts, error = %e.message, "Notification dead-lettered");
+                    sqlx::query(
+                        "UPDATE notification_deliveries \
+                         SET status = 'dead', attempts = $2, last_error = $3 WHERE id = $1",
+                    )
+                    .bind(id)
+                    .bind(attempts)
+                    .bind(e.message)
+                }
+            }
+        }
+    };
+
+    if let Err(e) = update.execute(db).await {
+        error!(delivery = %id, "Failed to record notification attempt: {e}");
+    }
+}
+
+async fn prune(db: &PgPool) {
+    for (table, what) in [
+        ("notification_outbox", "notifications"),
+        // Digests have no outbox row to cascade from.
+        ("notification_deliveries", "digests"),
+        ("webhook_deliveries", "webhook deliveries"),
+    ] {
+        match sqlx::query(&format!(
+            "DELETE FROM {table} WHERE created_at < now() - make_interval(days => $1)"
+        ))
+        .bind(RETENTION_DAYS)
+        .execute(db)
+        .await
+        {
+            Ok(r) if r.rows_affected() > 0 => {
+                info!(rows = r.rows_affected(), "Pruned old {what}")
+            }
+            Ok(_) => {}
+            Err(e) => error!("Failed to prune {table}: {e}"),
+        }
+    }
+}
+
+// ── Admin ───────────────────────────────────────────────────────────────────
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/admin/notifications/deliveries", get(list_deliveries))
+        .route(
+            "/admin/notifications/deliveries/resend",
+            post(resend_dead_deliveries),
+        )
+        .route(
+            "/admin/notifications/deliveries/{id}/resend",
+            post(resend_delivery),
+        )
+}
+
+#[derive(Deserialize)]
+struct DeliveryQuery {
+    /// `pending`, `sent` or `dead`. All when absent.
+    status: Option<String>,
+}
+
+#[derive(Serialize)]
+struct DeliveryEntry {
+    id: Uuid,
+    event_type: String,
+    channel: String,
+    recipient: Option<String>,
+    subject: String,
+    status: String,
+    attempts: i32,
+    last_error: Option<String>,
+    next_attempt_at: String,
+    sent_at: Option<String>,
+    created_at: String,
+}
+
+type DeliveryRow = (
+    Uuid,
+    String,
+    String,
+    Option<String>,
+    String,
+    String,
+    i32,
+    Option<String>,
+    DateTime<Utc>,
+    Option<DateTime<Utc>>,
+    DateTime<Utc>,
+);
+
+async fn list_deliveries(
+    _user: Authorized<perm::ManageUsers>,
+    State(state): State<AppState>,
+    Query(query): Query<DeliveryQuery>,
+) -> Result<Json<Vec<DeliveryEntry>>, ApiError> {
+    if let Some(ref status) = query.status {
+        if !["pending", "sent", "dead"].contains(&status.as_str()) {
+            return Err((StatusCode::BAD_REQUEST, "Unknown status"));
+        }
+    }
+
+    let rows: Vec<DeliveryRow> = sqlx::query_as(
+        "SELECT d.id, COALESCE(o.event_type, 'digest'), d.channel, COALESCE(d.email, u.email), d.subject, d.status, \
+                d.attempts, d.last_error, d.next_attempt_at, d.sent_at, d.created_at \
+         FROM notification_deliveries d \
+         LEFT JOIN notification_outbox o ON o.id = d.outbox_id \
+         LEFT JOIN users u ON u.id = d.user_id \
+         WHERE $1::text IS NULL OR d.status = $1 \
+         ORDER BY d.created_at DESC LIMIT 200",
+    )
+    .bind(&query.status)
+    .fetch_all(&state.db)
+    .await
+    .map_err(|e| {
+        error!("Failed to list notification deliveries: {e}");
+        (StatusCode::INTERNAL_SERVER_ERROR, "Database error")
+    })?;
+
+    let entries = rows
+        .into_iter()
+        .map(
+            |(
+                id,
+                event_type,
+                channel,
+                recipient,
+                subject,
+                status,
+                attempts,
+                last_error,
+                next_attempt_at,
+                sent_at,
+                created_at,
+            )| DeliveryEntry {
+                id,
+                event_type,
+                channel,
+                recipient,
+                subject,
+                status,
+                attempts,
+                last_error,
+                next_attempt_at: next_attempt_at.to_rfc3339(),
+                sent_at: sent_at.map(|t| t.to_rfc3339()),
+                created_at: created_at.to_rfc3339(),
+            },
+        )
+        .collect();
+
+    Ok(Json(entries))
+}
+
+const RESET_FOR_RESEND: &str = "UPDATE notification_deliveries \
+     SET status = 'pending', attempts = 0, next_attempt_at = now() \
+     WHERE status = 'dead'";
+
+async fn resend_delivery(
+    user: Authorized<perm::ManageUsers>,
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Result<StatusCode, ApiError> {
+    let result = sqlx::query(&format!("{RESET_FOR_RESEND} AND id = $1"))
+        .bind(id)
+        .execute(&state.db)
+        .await
+        .map_err(|e| {
+            error!("Failed to requeue notification: {e}");
+            (StatusCode::INTERNAL_SERVER_ERROR, "Database error")
+        })?;
+    if result.rows_affected() == 0 {
+        return Err((StatusCode::NOT_FOUND, "No dead delivery with that ID"));
+    }
+
+    state.events.wake_deliveries();
+    info!(admin = %user.email, delivery = %id, "Notification requeued");
+    Ok(StatusCode::ACCEPTED)
+}
+
+#[derive(Serialize)]
+struct ResendResponse {
+    requeued: u64,
+}
+
+async fn resend_dead_deliveries(
+    user: Authorized<perm::ManageUsers>,
+    State(state): State<AppState>,
+) -> Result<Json<ResendResponse>, ApiError> {
+    let result = sqlx::query(RESET_FOR_RESEND)
+        .execute(&state.db)
+        .await
+        .map_err(|e| {
+            error!("Failed to requeue notifications: {e}");
+            (StatusCode::INTERNAL_SERVER_ERROR, "Database error")
+        })?;
+
+    state.events.wake_deliveries();
+    info!(admin = %user.email, count = result.rows_affected(), "Dead notifications requeued");
+    Ok(Json(ResendResponse {
+        requeued: result.rows_affected(),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn retry_delay_doubles_then_caps() {
+        assert_eq!(retry_delay(1), Some(Duration::minutes(1)));
+        assert_eq!(retry_delay(2), Some(Duration::minutes(2)));
+        assert_eq!(retry_delay(5), Some(Duration::minutes(16)));
+        assert_eq!(retry_delay(8), Some(Duration::hours(2)));
+        assert_eq!(retry_delay(MAX_ATTEMPTS - 1), Some(Duration::hours(2)));
+        assert_eq!(retry_delay(MAX_ATTEMPTS), None);
+    }
+
+    #[tokio::test]
+    async fn bus_hands_every_event_to_outbox() {
+        let (bus, mut outbox) = EventBus::new(4);
+        let mut live = bus.subscribe();
+
+        let _ = bus.send(WsEvent::ModeChanged {
+            mode: "normal".to_string(),
+        });
+        let _ = bus.send(WsEvent::Scan {
+            tag_id: "0011223344".to_string(),
+            action: "denied".to_string(),
+            sentinel_id: None,
+            created_at: String::new(),
+        });
+
+        assert!(matches!(live.recv().await, Ok(WsEvent::ModeChanged { .. })));
+        assert!(matches!(live.recv().await, Ok(WsEvent::Scan { .. })));
+        assert!(matches!(
+            outbox.recv().await,
+            Some(WsEvent::ModeChanged { .. })
+        ));
+        assert!(matches!(outbox.recv().await, Some(WsEvent::Scan { .. })));
+    }
+}
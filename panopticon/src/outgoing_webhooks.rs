@@ -0,0 +1,798 @@
+//! Outgoing webhooks.
+//!
+//! Admins register endpoints that subscribe to `WsEvent` types. The outbox
+//! writer (see [`crate::outbox`]) turns each matching event into a
+//! `webhook_deliveries` row, and the webhook worker POSTs it as JSON:
+//!
+//! ```text
+//! {"id": "<delivery id>", "type": "lock_state", "created_at": "…", "data": {…}}
+//! ```
+//!
+//! Every attempt is signed with the endpoint's secret:
+//!
+//! - `X-Panopticon-Timestamp`: Unix seconds at send time
+//! - `X-Panopticon-Signature`: `sha256=` + hex HMAC-SHA256 of `"{timestamp}.{body}"`
+//!
+//! Receivers should recompute the signature and reject stale timestamps
+//! (e.g. older than five minutes) to prevent replays. Failed deliveries are
+//! retried with the same backoff as notifications, then dead-lettered.
+//!
+//! Webhooks have their own delivery worker, which sends to endpoints
+//! concurrently; an endpoint that times out is skipped until the next pass.
+//! Endpoint URLs must be public or on `NOTIFY_ALLOWED_NETWORKS`, as for
+//! notification channels, and redirects are not followed.
+
+use std::collections::HashSet;
+use std::sync::{Arc, LazyLock};
+use std::time::Instant;
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    routing::{get, post},
+    Json, Router,
+};
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use sqlx::PgPool;
+use tokio::task::JoinSet;
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+use crate::email_auth::generate_token;
+use crate::middleware::Authorized;
+use crate::notify_channels::{check_host, PublicResolver};
+use crate::outbox::retry_delay;
+use crate::permissions::perm;
+use crate::ws::WsEvent;
+use crate::AppState;
+
+type ApiError = (StatusCode, &'static str);
+
+const BATCH_SIZE: i64 = 100;
+const REQUEST_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+/// How often the worker looks for due retries when nothing wakes it.
+const POLL_INTERVAL: std::time::Duration = std::time::Duration::from_secs(15);
+
+/// The client for all webhook requests. Endpoints get the same address
+/// policy as notification channels, and no redirects, which could point
+/// anywhere.
+static HTTP: LazyLock<reqwest::Client> = LazyLock::new(|| {
+    reqwest::Client::builder()
+        .timeout(REQUEST_TIMEOUT)
+        .redirect(reqwest::redirect::Policy::none())
+        .dns_resolver(Arc::new(PublicResolver))
+        .build()
+        .expect("Failed to build HTTP client")
+});
+
+/// `sha256=<hex>` signature over `"{timestamp}.{body}"`.
+fn sign(secret: &str, timestamp: i64, body: &str) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body.as_bytes());
+    format!("sha256={:x}", mac.finalize().into_bytes())
+}
+
+fn generate_secret() -> String {
+    format!("whsec_{}", generate_token())
+}
+
+// ── Enqueue and deliver ─────────────────────────────────────────────────────
+
+/// Queue `event` for every enabled endpoint subscribed to its type. Returns
+/// the number of deliveries.
+pub async fn enqueue(db: &PgPool, event: &WsEvent) -> Result<u64, sqlx::Error> {
+    let event_type = event.event_type();
+    let data = serde_json::to_value(event)
+        .ok()
+        .and_then(|mut v| v.get_mut("data").map(serde_json::Value::take))
+        .unwrap_or(serde_json::Value::Null);
+    let created_at = Utc::now().to_rfc3339();
+
+    // The delivery ID is part of the payload, so build it per row.
+    let result = sqlx::query(
+        "INSERT INTO webhook_deliveries (id, webhook_id, event_type, payload) \
+         SELECT d.id, w.id, $1, \
+                jsonb_build_object('id', d.id, 'type', $1::text, 'created_at', $2::text, 'data', $3::jsonb)::text \
+         FROM outgoing_webhooks w CROSS JOIN LATERAL (SELECT gen_random_uuid() AS id) d \
+         WHERE w.enabled = TRUE AND $1 = ANY(w.event_types)",
+    )
+    .bind(event_type)
+    .bind(created_at)
+    .bind(data)
+    .execute(db)
+    .await?;
+
+    Ok(result.rows_affected())
+}
+
+/// A due delivery, as loaded by [`deliver_due`].
+struct Due {
+    id: Uuid,
+    event_type: String,
+    attempts: i32,
+    payload: String,
+}
+
+/// An endpoint and its due deliveries, oldest first.
+struct Endpoint {
+    id: Uuid,
+    url: String,
+    secret: String,
+    deliveries: Vec<Due>,
+}
+
+/// The outcome of one POST.
+struct Attempt {
+    response_status: Option<u16>,
+    duration_ms: i32,
+    error: Option<String>,
+    timed_out: bool,
+}
+
+type DueRow = (Uuid, Uuid, String, String, String, i32, String);
+
+/// Deliver due webhooks until the process exits. Runs apart from the
+/// notification worker so a slow endpoint can't hold up alerts.
+pub async fn spawn_delivery_worker(state: AppState) {
+    info!("Webhook delivery worker started");
+    loop {
+        // Endpoints that time out sit out the rest of the pass.
+        let mut timed_out = HashSet::new();
+        while deliver_due(&state.db, &HTTP, &mut timed_out).await == BATCH_SIZE as usize {}
+
+        tokio::select! {
+            _ = state.events.webhooks_woken() => {}
+            _ = tokio::time::sleep(POLL_INTERVAL) => {}
+        }
+    }
+}
+
+/// Attempt one batch of due deliveries. Disabled endpoints keep theirs
+/// pending until re-enabled; endpoints in `timed_out` are skipped, and those
+/// that time out now are added. Endpoints are sent to concurrently, each in
+/// order. Returns how many deliveries were loaded.
+async fn deliver_due(db: &PgPool, http: &reqwest::Client, timed_out: &mut HashSet<Uuid>) -> usize {
+    let skipped: Vec<Uuid> = timed_out.iter().copied().collect();
+    let rows: Vec<DueRow> = match sqlx::query_as(
+        "SELECT d.id, w.id, w.url, w.secret, d.event_type, d.attempts, d.payload \
+         FROM webhook_deliveries d JOIN outgoing_webhooks w ON w.id = d.webhook_id \
+         WHERE d.status = 'pending' AND d.next_attempt_at <= now() \
+           AND w.enabled = TRUE AND w.id <> ALL($2) \
+         ORDER BY d.next_attempt_at LIMIT $1",
+    )
+    .bind(BATCH_SIZE)
+    .bind(&skipped)
+    .fetch_all(db)
+    .await
+    {
+        Ok(rows) => rows,
+        Err(e) => {
+            error!("Failed to load due webhook deliveries: {e}");
+            return 0;
+        }
+    };
+
+    let loaded = rows.len();
+    let mut endpoints: Vec<Endpoint> = Vec::new();
+    for (id, webhook_id, url, secret, event_type, attempts, payload) in rows {
+        let due = Due {
+            id,
+            event_type,
+            attempts,
+            payload,
+        };
+        match endpoints.iter_mut().find(|e| e.id == webhook_id) {
+            Some(endpoint) => endpoint.deliveries.push(due),
+            None => endpoints.push(Endpoint {
+                id: webhook_id,
+                url,
+                secret,
+                deliveries: vec![due],
+            }),
+        }
+    }
+
+    let mut tasks = JoinSet::new();
+    for endpoint in endpoints {
+        let db = db.clone();
+        let http = http.clone();
+        tasks.spawn(async move {
+            let webhook_id = endpoint.id;
+            let timed_out = match check_host(&endpoint.url).await {
+                Ok(()) => {
+                    endpoint
+                        .deliver(&http, |id, attempts, attempt| {
+                            record_attempt(&db, webhook_id, id, attempts, attempt)
+                        })
+                        .await
+                }
+                Err(e) => {
+                    warn!(%webhook_id, url = %endpoint.url, "Refused webhook endpoint: {}", e.message());
+                    for due in &endpoint.deliveries {
+                        let attempt = Attempt {
+                            response_status: None,
+                            duration_ms: 0,
+                            error: Some(e.message().to_string()),
+                            timed_out: false,
+                        };
+                        record_attempt(&db, webhook_id, due.id, due.attempts, attempt).await;
+                    }
+                    false
+                }
+            };
+            timed_out.then_some(webhook_id)
+        });
+    }
+    while let Some(result) = tasks.join_next().await {
+        match result {
+            Ok(Some(webhook_id)) => {
+                warn!(%webhook_id, "Webhook endpoint timed out, skipping it until the next pass");
+                timed_out.insert(webhook_id);
+            }
+            Ok(None) => {}
+            Err(e) => error!("Webhook delivery task failed: {e}"),
+        }
+    }
+
+    loaded
+}
+
+impl Endpoint {
+    /// POST the deliveries in order, passing each attempt to `record`. Stops
+    /// at the first timeout, leaving the rest due, and returns whether it
+    /// timed out.
+    async fn deliver<F, Fut>(&self, http: &reqwest::Client, mut record: F) -> bool
+    where
+        F: FnMut(Uuid, i32, Attempt) -> Fut,
+        Fut: std::future::Future<Output = ()>,
+    {
+        for due in &self.deliveries {
+            let attempt = self.send(http, due).await;
+            let timed_out = attempt.timed_out;
+            record(due.id, due.attempts, attempt).await;
+            if timed_out {
+                return true;
+            }
+        }
+        false
+    }
+
+    async fn send(&self, http: &reqwest::Client, due: &Due) -> Attempt {
+        let timestamp = Utc::now().timestamp();
+        let started = Instant::now();
+        let result = http
+            .post(&self.url)
+            .header("Content-Type", "application/json")
+            .header("User-Agent", "panopticon-webhooks")
+            .header("X-Panopticon-Event", &due.event_type)
+            .header("X-Panopticon-Delivery", due.id.to_string())
+            .header("X-Panopticon-Timestamp", timestamp.to_string())
+            .header(
+                "X-Panopticon-Signature",
+                sign(&self.secret, timestamp, &due.payload),
+            )
+            .body(due.payload.clone())
+            .send()
+            .await;
+        let duration_ms = started.elapsed().as_millis().min(i32::MAX as u128) as i32;
+
+        let (response_status, error, timed_out) = match result {
+            Ok(resp) if resp.status().is_success() => (Some(resp.status().as_u16()), None, false),
+            Ok(resp) => (
+                Some(resp.status().as_u16()),
+                Some(format!("HTTP {}", resp.status())),
+                false,
+            ),
+            Err(e) => (None, Some(format!("{e}")), e.is_timeout()),
+        };
+        Attempt {
+            response_status,
+            duration_ms,
+            error,
+            timed_out,
+        }
+    }
+}
+
+/// Store the outcome of an attempt at delivery `id`, which had `attempts`
+/// before it.
+async fn record_attempt(db: &PgPool, webhook_id: Uuid, id: Uuid, attempts: i32, attempt: Attempt) {
+    let attempts = attempts + 1;
+    let response_status = attempt.response_status.map(i32::from);
+    let duration_ms = attempt.duration_ms;
+    let query = match attempt.error {
+        None => sqlx::query(
+            "UPDATE webhook_deliveries SET status = 'sent', attempts = $2, \
+             response_status = $3, duration_ms = $4, last_error = NULL, sent_at = now() \
+             WHERE id = $1",
+        )
+        .bind(id)
+        .bind(attempts)
+        .bind(response_status)
+        .bind(duration_ms),
+        Some(e) => match retry_delay(attempts) {
+            Some(delay) => {
+                warn!(%webhook_id, delivery = %id, attempts, error = %e, "Webhook delivery failed, will retry");
+                sqlx::query(
+                    "UPDATE webhook_deliveries SET attempts = $2, response_status = $3, \
+                     duration_ms = $4, last_error = $5, next_attempt_at = now() + $6 \
+                     WHERE id = $1",
+                )
+                .bind(id)
+                .bind(attempts)
+                .bind(response_status)
+                .bind(duration_ms)
+                .bind(e)
+                .bind(delay)
+            }
+            None => {
+                error!(%webhook_id, delivery = %id, attempts, error = %e, "Webhook delivery dead-lettered");
+                sqlx::query(
+                    "UPDATE webhook_deliveries SET status = 'dead', attempts = $2, \
+                     response_status = $3, duration_ms = $4, last_error = $5 WHERE id = $1",
+                )
+                .bind(id)
+                .bind(attempts)
+                .bind(response_status)
+                .bind(duration_ms)
+                .bind(e)
+            }
+        },
+    };
+    if let Err(e) = query.execute(db).await {
+        error!(delivery = %id, "Failed to record webhook attempt: {e}");
+    }
+}
+
+// ── Admin ───────────────────────────────────────────────────────────────────
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/admin/webhooks", get(list_webhooks).post(create_webhook))
+        .route(
+            "/admin/webhooks/{id}",
+            axum::routing::patch(update_webhook).delete(delete_webhook),
+        )
+        .route("/admin/webhooks/{id}/rotate-secret", post(rotate_secret))
+        .route("/admin/webhooks/{id}/deliveries", get(list_deliveries))
+        .route(
+            "/admin/webhooks/{id}/deliveries/{delivery_id}/resend",
+            post(resend_delivery),
+        )
+}
+
+#[derive(Serialize)]
+struct WebhookInfo {
+    id: Uuid,
+    url: String,
+    description: Option<String>,
+    event_types: Vec<String>,
+    enabled: bool,
+    created_by: Option<String>,
+    created_at: String,
+    /// Only returned when the webhook is created or its secret rotated.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    secret: Option<String>,
+}
+
+type WebhookRow = (
+    Uuid,
+    String,
+    Option<String>,
+    Vec<String>,
+    bool,
+    Option<String>,
+    DateTime<Utc>,
+);
+
+impl WebhookInfo {
+    fn from_row(row: WebhookRow) -> Self {
+        let (id, url, description, event_types, enabled, created_by, created_at) = row;
+        Self {
+            id,
+            url,
+            description,
+            event_types,
+            enabled,
+            created_by,
+            created_at: created_at.to_rfc3339(),
+            secret: None,
+        }
+    }
+}
+
+const WEBHOOK_COLUMNS: &str = "w.id, w.url, w.description, w.event_types, w.enabled, u.email, \
+     w.created_at FROM outgoing_webhooks w LEFT JOIN users u ON u.id = w.created_by";
+
+/// Check the URL's scheme, and that its host is public or on an allowed
+/// network, as for notification channels.
+async fn validate_url(url: &str) -> Result<(), ApiError> {
+    let parsed = reqwest::Url::parse(url).map_err(|_| (StatusCode::BAD_REQUEST, "Invalid URL"))?;
+    if !matches!(parsed.scheme(), "http" | "https") || parsed.host_str().is_none() {
+        return Err((StatusCode::BAD_REQUEST, "URL must be http or https"));
+    }
+    check_host(url)
+        .await
+        .map_err(|e| (StatusCode::BAD_REQUEST, e.message()))
+}
+
+fn validate_event_types(event_types: &[String]) -> Result<(), ApiError> {
+    if event_types.is_empty() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "Subscribe to at least one event type",
+        ));
+    }
+    if !event_types
+        .iter()
+        .all(|t| WsEvent::TYPES.contains(&t.as_str()))
+    {
+        return Err((StatusCode::BAD_REQUEST, "Unknown event type"));
+    }
+    Ok(())
+}
+
+fn db_error(e: sqlx::Error) -> ApiError {
+    error!("Webhook query failed: {e}");
+    (StatusCode::INTERNAL_SERVER_ERROR, "Database error")
+}
+
+async fn fetch_webhook(db: &PgPool, id: Uuid) -> Result<WebhookInfo, ApiError> {
+    let row: Option<WebhookRow> =
+        sqlx::query_as(&format!("SELECT {WEBHOOK_COLUMNS} WHERE w.id = $1"))
+            .bind(id)
+            .fetch_optional(db)
+            .await
+            .map_err(db_error)?;
+    row.map(WebhookInfo::from_row)
+        .ok_or((StatusCode::NOT_FOUND, "Webhook not found"))
+}
+
+async fn list_webhooks(
+    _user: Authorized<perm::ManageIntegrations>,
+    State(state): State<AppState>,
+) -> Result<Json<Vec<WebhookInfo>>, ApiError> {
+    let rows: Vec<WebhookRow> =
+        sqlx::query_as(&format!("SELECT {WEBHOOK_COLUMNS} ORDER BY w.created_at"))
+            .fetch_all(&state.db)
+            .await
+            .map_err(db_error)?;
+
+    Ok(Json(rows.into_iter().map(WebhookInfo::from_row).collect()))
+}
+
+#[derive(Deserialize)]
+struct CreateWebhookRequest {
+    url: String,
+    description: Option<String>,
+    event_types: Vec<String>,
+}
+
+async fn create_webhook(
+    user: Authorized<perm::ManageIntegrations>,
+    State(state): State<AppState>,
+    Json(body): Json<CreateWebhookRequest>,
+) -> Result<(StatusCode, Json<WebhookInfo>), ApiError> {
+    validate_url(&body.url).await?;
+    validate_event_types(&body.event_types)?;
+
+    let secret = generate_secret();
+    let id: Uuid = sqlx::query_scalar(
+        "INSERT INTO outgoing_webhooks (url, description, secret, event_types, created_by) \
+         VALUES ($1, $2, $3, $4, $5) RETURNING id",
+    )
+    .bind(&body.url)
+    .bind(&body.description)
+    .bind(&secret)
+    .bind(&body.event_types)
+    .bind(user.id)
+    .fetch_one(&state.db)
+    .await
+    .map_err(db_error)?;
+
+    info!(admin = %user.email, %id, url = %body.url, "Webhook created");
+
+    let mut webhook = fetch_webhook(&state.db, id).await?;
+    webhook.secret = Some(secret);
+    Ok((StatusCode::CREATED, Json(webhook)))
+}
+
+#[derive(Deserialize)]
+struct UpdateWebhookRequest {
+    url: Option<String>,
+    description: Option<String>,
+    event_types: Option<Vec<String>>,
+    enabled: Option<bool>,
+}
+
+async fn update_webhook(
+    user: Authorized<perm::ManageIntegrations>,
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    Json(body): Json<UpdateWebhookRequest>,
+) -> Result<Json<WebhookInfo>, ApiError> {
+    if let Some(ref url) = body.url {
+        validate_url(url).await?;
+    }
+    if let Some(ref event_types) = body.event_types {
+        validate_event_types(event_types)?;
+    }
+
+    let result = sqlx::query(
+        "UPDATE outgoing_webhooks SET url = COALESCE($2, url), \
+         description = COALESCE($3, description), event_types = COALESCE($4, event_types), \
+         enabled = COALESCE($5, enabled), updated_at = now() WHERE id = $1",
+    )
+    .bind(id)
+    .bind(&body.url)
+    .bind(&body.description)
+    .bind(&body.event_types)
+    .bind(body.enabled)
+    .execute(&state.db)
+    .await
+    .map_err(db_error)?;
+    if result.rows_affected() == 0 {
+        return Err((StatusCode::NOT_FOUND, "Webhook not found"));
+    }
+
+    info!(admin = %user.email, %id, "Webhook updated");
+    Ok(Json(fetch_webhook(&state.db, id).await?))
+}
+
+async fn delete_webhook(
+    user: Authorized<perm::ManageIntegrations>,
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Result<StatusCode, ApiError> {
+    let result = sqlx::query("DELETE FROM outgoing_webhooks WHERE id = $1")
+        .bind(id)
+        .execute(&state.db)
+        .await
+        .map_err(db_error)?;
+    if result.rows_affected() == 0 {
+        return Err((StatusCode::NOT_FOUND, "Webhook not found"));
+    }
+
+    info!(admin = %user.email, %id, "Webhook deleted");
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn rotate_secret(
+    user: Authorized<perm::ManageIntegrations>,
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<WebhookInfo>, ApiError> {
+    let secret = generate_secret();
+    let result =
+        sqlx::query("UPDATE outgoing_webhooks SET secret = $2, updated_at = now() WHERE id = $1")
+            .bind(id)
+            .bind(&secret)
+            .execute(&state.db)
+            .await
+            .map_err(db_error)?;
+    if result.rows_affected() == 0 {
+        return Err((StatusCode::NOT_FOUND, "Webhook not found"));
+    }
+
+    info!(admin = %user.email, %id, "Webhook secret rotated");
+    let mut webhook = fetch_webhook(&state.db, id).await?;
+    webhook.secret = Some(secret);
+    Ok(Json(webhook))
+}
+
+#[derive(Serialize)]
+struct DeliveryEntry {
+    id: Uuid,
+    event_type: String,
+    status: String,
+    attempts: i32,
+    response_status: Option<i32>,
+    last_error: Option<String>,
+    duration_ms: Option<i32>,
+    next_attempt_at: String,
+    sent_at: Option<String>,
+    created_at: String,
+}
+
+type DeliveryRow = (
+    Uuid,
+    String,
+    String,
+    i32,
+    Option<i32>,
+    Option<String>,
+    Option<i32>,
+    DateTime<Utc>,
+    Option<DateTime<Utc>>,
+    DateTime<Utc>,
+);
+
+async fn list_deliveries(
+    _user: Authorized<perm::ManageIntegrations>,
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<Vec<DeliveryEntry>>, ApiError> {
+    let rows: Vec<DeliveryRow> = sqlx::query_as(
+        "SELECT id, event_type, status, attempts, response_status, last_error, duration_ms, \
+                next_attempt_at, sent_at, created_at \
+         FROM webhook_deliveries WHERE webhook_id = $1 ORDER BY created_at DESC LIMIT 200",
+    )
+    .bind(id)
+    .fetch_all(&state.db)
+    .await
+    .map_err(db_error)?;
+
+    let entries = rows
+        .into_iter()
+        .map(
+            |(
+                id,
+                event_type,
+                status,
+                attempts,
+                response_status,
+                last_error,
+                duration_ms,
+                next_attempt_at,
+                sent_at,
+                created_at,
+            )| DeliveryEntry {
+                id,
+                event_type,
+                status,
+                attempts,
+                response_status,
+                last_error,
+                duration_ms,
+                next_attempt_at: next_attempt_at.to_rfc3339(),
+                sent_at: sent_at.map(|t| t.to_rfc3339()),
+                created_at: created_at.to_rfc3339(),
+            },
+        )
+        .collect();
+
+    Ok(Json(entries))
+}
+
+/// Queue a delivery again, whatever its state. Receivers see the same
+/// delivery ID and can de-duplicate on it.
+async fn resend_delivery(
+    user: Authorized<perm::ManageIntegrations>,
+    State(state): State<AppState>,
+    Path((id, delivery_id)): Path<(Uuid, Uuid)>,
+) -> Result<StatusCode, ApiError> {
+    let result = sqlx::query(
+        "UPDATE webhook_deliveries SET status = 'pending', attempts = 0, next_attempt_at = now() \
+         WHERE id = $1 AND webhook_id = $2",
+    )
+    .bind(delivery_id)
+    .bind(id)
+    .execute(&state.db)
+    .await
+    .map_err(db_error)?;
+    if result.rows_affected() == 0 {
+        return Err((StatusCode::NOT_FOUND, "Delivery not found"));
+    }
+
+    state.events.wake_webhooks();
+    info!(admin = %user.email, webhook = %id, delivery = %delivery_id, "Webhook delivery requeued");
+    Ok(StatusCode::ACCEPTED)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signs_timestamp_and_body() {
+        // Same as Python: hmac.new(b"whsec_test", b'1700000000.{"type":"scan"}', sha256)
+        assert_eq!(
+            sign("whsec_test", 1_700_000_000, r#"{"type":"scan"}"#),
+            "sha256=720de47f767d64204b14f79c207999775c8b7c33da6cfa890fa2b681b983ed56"
+        );
+        assert_ne!(
+            sign("whsec_test", 1_700_000_001, r#"{"type":"scan"}"#),
+            sign("whsec_test", 1_700_000_000, r#"{"type":"scan"}"#)
+        );
+    }
+
+    #[test]
+    fn event_type_matches_serialized_tag() {
+        let events = [
+            WsEvent::ModeChanged {
+                mode: "normal".to_string(),
+            },
+            WsEvent::CardRemoved { id: Uuid::nil() },
+            WsEvent::SentinelDisconnected { id: Uuid::nil() },
+            WsEvent::LockState {
+                device_id: "front".to_string(),
+                lock_state: "locked".to_string(),
+                log_id: None,
+            },
+        ];
+        for event in events {
+            let json = serde_json::to_value(&event).unwrap();
+            assert_eq!(json["type"], event.event_type());
+            assert!(WsEvent::TYPES.contains(&event.event_type()));
+        }
+    }
+
+    #[test]
+    fn validates_subscriptions() {
+        assert!(validate_event_types(&["scan".to_string(), "lock_state".to_string()]).is_ok());
+        assert!(validate_event_types(&[]).is_err());
+        assert!(validate_event_types(&["lock_changed".to_string()]).is_err());
+    }
+
+    #[tokio::test]
+    async fn refuses_internal_urls() {
+        assert!(validate_url("https://93.184.216.34/panopticon")
+            .await
+            .is_ok());
+        assert!(validate_url("ftp://93.184.216.34").await.is_err());
+        for url in [
+            "http://10.0.0.5:8080/events",
+            "http://127.0.0.1/hook",
+            "http://[::1]/hook",
+            "http://[64:ff9b::a00:5]/hook",
+        ] {
+            assert_eq!(
+                validate_url(url).await.unwrap_err().1,
+                "Server must have a public address or be on an allowed network",
+                "{url}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn skips_the_rest_of_an_endpoint_after_a_timeout() {
+        let received = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let count = received.clone();
+        let app = Router::new().fallback(move || {
+            let count = count.clone();
+            async move {
+                count.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                tokio::time::sleep(std::time::Duration::from_secs(5)).await;
+                StatusCode::OK
+            }
+        });
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let endpoint = Endpoint {
+            id: Uuid::new_v4(),
+            url,
+            secret: "whsec_test".to_string(),
+            deliveries: (0..3)
+                .map(|_| Due {
+                    id: Uuid::new_v4(),
+                    event_type: "scan".to_string(),
+                    attempts: 0,
+                    payload: "{}".to_string(),
+                })
+                .collect(),
+        };
+        let http = reqwest::Client::builder()
+            .timeout(std::time::Duration::from_millis(100))
+            .build()
+            .unwrap();
+        let mut recorded = Vec::new();
+        let timed_out = endpoint
+            .deliver(&http, |id, _, attempt| {
+                recorded.push((id, attempt.timed_out, attempt.error.is_some()));
+                std::future::ready(())
+            })
+            .await;
+
+        assert!(timed_out);
+        assert_eq!(recorded, [(endpoint.deliveries[0].id, true, true)]);
+        assert_eq!(received.load(std::sync::atomic::Ordering::SeqCst), 1);
+    }
+}
@@ -0,0 +1,225 @@
+//! Roles and the permissions they grant.
+//!
+//! Every user has a role in `users.role`. Handlers declare what they need by
+//! taking a `middleware::Authorized<perm::X>` extractor, which rejects
+//! unapproved users and users whose role lacks permission `X`.
+//!
+//! Requests made with an API token are further limited to the token's
+//! [`Scope`]s: each marker lists the scopes that satisfy it.
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    /// Everything, including user management and the U-Tec connection.
+    Admin,
+    /// Day-to-day running of the system: locks, cards and sentinels.
+    Operator,
+    /// Can lock and unlock doors.
+    Resident,
+    /// Read-only access.
+    Viewer,
+}
+
+impl Role {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Admin => "admin",
+            Self::Operator => "operator",
+            Self::Resident => "resident",
+            Self::Viewer => "viewer",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "admin" => Some(Self::Admin),
+            "operator" => Some(Self::Operator),
+            "resident" => Some(Self::Resident),
+            "viewer" => Some(Self::Viewer),
+            _ => None,
+        }
+    }
+
+    pub fn permissions(self) -> &'static [Permission] {
+        use Permission::*;
+        match self {
+            Self::Admin => &[
+                ViewDevices,
+                ViewCards,
+                OperateLocks,
+                ManageLocks,
+                ManageAccess,
+                ManageUsers,
+                ManageIntegrations,
+            ],
+            Self::Operator => &[
+                ViewDevices,
+                ViewCards,
+                OperateLocks,
+                ManageLocks,
+                ManageAccess,
+            ],
+            Self::Resident => &[ViewDevices, OperateLocks],
+            Self::Viewer => &[ViewDevices],
+        }
+    }
+
+    pub fn has(self, permission: Permission) -> bool {
+        self.permissions().contains(&permission)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Permission {
+    /// See devices, lock state, alerts and sentinels.
+    ViewDevices,
+    /// See RFID cards, their labels and scan history.
+    ViewCards,
+    /// Lock and unlock doors.
+    OperateLocks,
+    /// Configure lock behaviour (auto-relock) and see the users stored on locks.
+    ManageLocks,
+    /// Remove RFID cards and change sentinel mode.
+    ManageAccess,
+    /// Approve, delete and assign roles to users.
+    ManageUsers,
+    /// Connect and disconnect the U-Tec account.
+    ManageIntegrations,
+}
+
+/// What an API token may be used for. A token never grants more than its
+/// owner's role; scopes only narrow it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Scope {
+    /// Devices, lock state, alerts and command status.
+    #[serde(rename = "devices:read")]
+    DevicesRead,
+    /// Cards, sentinels and scan history.
+    #[serde(rename = "cards:read")]
+    CardsRead,
+    /// Remove cards and change sentinel mode.
+    #[serde(rename = "cards:write")]
+    CardsWrite,
+    #[serde(rename = "locks:lock")]
+    LocksLock,
+    #[serde(rename = "locks:unlock")]
+    LocksUnlock,
+    /// Auto-relock policies and the users stored on locks.
+    #[serde(rename = "locks:manage")]
+    LocksManage,
+    /// User administration, device grants and access denials.
+    #[serde(rename = "users:manage")]
+    UsersManage,
+}
+
+impl Scope {
+    pub const ALL: [Scope; 7] = [
+        Self::DevicesRead,
+        Self::CardsRead,
+        Self::CardsWrite,
+        Self::LocksLock,
+        Self::LocksUnlock,
+        Self::LocksManage,
+        Self::UsersManage,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::DevicesRead => "devices:read",
+            Self::CardsRead => "cards:read",
+            Self::CardsWrite => "cards:write",
+            Self::LocksLock => "locks:lock",
+            Self::LocksUnlock => "locks:unlock",
+            Self::LocksManage => "locks:manage",
+            Self::UsersManage => "users:manage",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|scope| scope.as_str() == s)
+    }
+}
+
+/// Ties a marker type to the permission it stands for, so handlers can name
+/// the permission they need in their extractor type.
+pub trait RequiredPermission {
+    const PERMISSION: Permission;
+    /// Token scopes that satisfy this requirement. Empty means API tokens
+    /// are never accepted.
+    const SCOPES: &'static [Scope];
+}
+
+/// Marker types for `middleware::Authorized`.
+pub mod perm {
+    use super::{Permission, RequiredPermission, Scope};
+
+    macro_rules! markers {
+        ($($name:ident => $permission:ident [$($scope:ident),*]),* $(,)?) => {
+            $(
+                pub struct $name;
+
+                impl RequiredPermission for $name {
+                    const PERMISSION: Permission = Permission::$permission;
+                    const SCOPES: &'static [Scope] = &[$(Scope::$scope),*];
+                }
+            )*
+        };
+    }
+
+    markers!(
+        ViewDevices => ViewDevices [DevicesRead],
+        ViewCards => ViewCards [CardsRead],
+        OperateLocks => OperateLocks [LocksLock, LocksUnlock],
+        ManageLocks => ManageLocks [LocksManage],
+        ManageAccess => ManageAccess [CardsWrite],
+        ManageUsers => ManageUsers [UsersManage],
+        ManageIntegrations => ManageIntegrations [],
+        // A user's own notification settings and push subscriptions: any
+        // approved user, from a browser session only, so a leaked token
+        // can't silence alerts or redirect them.
+        ManageNotifications => ViewDevices [],
+        // Security policy such as 2FA for unlocking: admins, from a browser
+        // session only, so a leaked `users:manage` token can't weaken it.
+        ManageSecurity => ManageUsers [],
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roles_round_trip() {
+        for role in [Role::Admin, Role::Operator, Role::Resident, Role::Viewer] {
+            assert_eq!(Role::parse(role.as_str()), Some(role));
+        }
+        assert_eq!(Role::parse("root"), None);
+    }
+
+    #[test]
+    fn scopes_round_trip() {
+        for scope in Scope::ALL {
+            assert_eq!(Scope::parse(scope.as_str()), Some(scope));
+            let json = serde_json::to_value(scope).unwrap();
+            assert_eq!(json, scope.as_str());
+        }
+        assert_eq!(Scope::parse("locks:*"), None);
+    }
+
+    #[test]
+    fn roles_are_nested() {
+        let roles = [Role::Viewer, Role::Resident, Role::Operator, Role::Admin];
+        for pair in roles.windows(2) {
+            for p in pair[0].permissions() {
+                assert!(pair[1].has(*p), "{:?} lacks {p:?}", pair[1]);
+            }
+        }
+        assert!(!Role::Resident.has(Permission::ManageAccess));
+        assert!(!Role::Operator.has(Permission::ManageUsers));
+        assert!(!Role::Viewer.has(Permission::OperateLocks));
+        assert!(!Role::Resident.has(Permission::ViewCards));
+    }
+}
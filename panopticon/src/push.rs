@@ -1,24 +1,25 @@
-use std::time::Duration;
-
 use anyhow::{Context, Result};
 use axum::{
-    extract::State,
-    http::StatusCode,
-    routing::{get, post},
+    extract::{Path, State},
+    http::{header, HeaderMap, StatusCode},
+    routing::{delete, get, post},
     Json, Router,
 };
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{DateTime, Utc};
 use reqwest::Url;
 use serde::{Deserialize, Serialize};
 use sqlx::PgPool;
-use tokio::sync::broadcast;
 use tracing::{error, info, warn};
+use uuid::Uuid;
 use web_push::{
     ContentEncoding, PartialVapidSignatureBuilder, SubscriptionInfo, VapidSignatureBuilder,
     WebPushMessageBuilder,
 };
 
-use crate::middleware::AuthUser;
-use crate::ws::WsEvent;
+use crate::middleware::Authorized;
+use crate::permissions::perm;
+use crate::push_actions::PushAction;
 use crate::AppState;
 
 type ApiError = (StatusCode, &'static str);
@@ -29,6 +30,28 @@ type ApiError = (StatusCode, &'static str);
 pub struct PushConfig {
     vapid_builder: PartialVapidSignatureBuilder,
     vapid_public_key: String,
+    /// The current public key in a fixed encoding, recorded with each
+    /// subscription.
+    key_id: String,
+    /// The key being rotated out, if any. Subscriptions made with it are
+    /// still signed with it and asked to subscribe again.
+    previous: Option<PreviousKey>,
+}
+
+#[derive(Clone)]
+struct PreviousKey {
+    vapid_builder: PartialVapidSignatureBuilder,
+    public_key: String,
+}
+
+fn key_id(builder: &PartialVapidSignatureBuilder) -> String {
+    URL_SAFE_NO_PAD.encode(builder.get_public_key())
+}
+
+fn load_vapid_key(path: &str) -> Result<PartialVapidSignatureBuilder> {
+    let pem_file = std::fs::File::open(path).with_context(|| format!("open {path}"))?;
+    VapidSignatureBuilder::from_pem_no_sub(pem_file)
+        .map_err(|e| anyhow::anyhow!("Failed to load VAPID key {path}: {e}"))
 }
 
 impl PushConfig {
@@ -42,19 +65,50 @@ impl PushConfig {
         };
         let public_key = std::env::var("VAPID_PUBLIC_KEY")
             .context("VAPID_PUBLIC_KEY must be set when VAPID_PRIVATE_KEY_PATH is set")?;
-
-        let pem_file =
-            std::fs::File::open(&key_path).with_context(|| format!("open {key_path}"))?;
-        let vapid_builder = VapidSignatureBuilder::from_pem_no_sub(pem_file)
-            .map_err(|e| anyhow::anyhow!("Failed to load VAPID key: {e}"))?;
+        let vapid_builder = load_vapid_key(&key_path)?;
+
+        let previous = match std::env::var("VAPID_PREVIOUS_PRIVATE_KEY_PATH") {
+            Ok(path) => {
+                let vapid_builder = load_vapid_key(&path)?;
+                let public_key = key_id(&vapid_builder);
+                info!("Previous VAPID key loaded, clients will be moved to the new key");
+                Some(PreviousKey {
+                    vapid_builder,
+                    public_key,
+                })
+            }
+            Err(_) => None,
+        };
 
         info!("Push notifications enabled (VAPID key loaded)");
 
         Ok(Some(Self {
+            key_id: key_id(&vapid_builder),
             vapid_builder,
             vapid_public_key: public_key,
+            previous,
         }))
     }
+
+    /// Whether a subscription made with `vapid_key` should subscribe again
+    /// with the current key. Subscriptions from before keys were recorded
+    /// count as made with the previous key while one is configured.
+    fn is_stale(&self, vapid_key: Option<&str>) -> bool {
+        match vapid_key {
+            Some(key) => key != self.key_id,
+            None => self.previous.is_some(),
+        }
+    }
+
+    /// The key to sign with for a subscription made with `vapid_key`.
+    fn signing_key(&self, vapid_key: Option<&str>) -> &PartialVapidSignatureBuilder {
+        match &self.previous {
+            Some(previous) if vapid_key.is_none_or(|key| key == previous.public_key) => {
+                &previous.vapid_builder
+            }
+            _ => &self.vapid_builder,
+        }
+    }
 }
 
 // ── API endpoints ───────────────────────────────────────────────────────────
@@ -64,6 +118,8 @@ pub fn router() -> Router<AppState> {
         .route("/push/vapid-key", get(vapid_key))
         .route("/push/subscribe", post(subscribe))
         .route("/push/unsubscribe", post(unsubscribe))
+        .route("/push/subscriptions", get(list_subscriptions))
+        .route("/push/subscriptions/{id}", delete(delete_subscription))
 }
 
 #[derive(Serialize)]
@@ -72,7 +128,7 @@ struct VapidKeyResponse {
 }
 
 async fn vapid_key(
-    _user: AuthUser,
+    _user: Authorized<perm::ViewDevices>,
     State(state): State<AppState>,
 ) -> Result<Json<VapidKeyResponse>, ApiError> {
     let config = state
@@ -90,6 +146,49 @@ struct SubscribeRequest {
     endpoint: String,
     p256dh: String,
     auth: String,
+    /// Shown in the device list. Derived from the user agent when absent.
+    name: Option<String>,
+    /// The endpoint this subscription takes over from, when a client
+    /// subscribes again after a key rotation. Its name is kept.
+    replaces: Option<String>,
+}
+
+const MAX_NAME_LEN: usize = 100;
+
+/// A readable name for a browser, e.g. "Firefox on Android".
+fn device_name(user_agent: &str) -> String {
+    let browser = if user_agent.contains("Edg/") {
+        "Edge"
+    } else if user_agent.contains("OPR/") {
+        "Opera"
+    } else if user_agent.contains("Firefox/") || user_agent.contains("FxiOS/") {
+        "Firefox"
+    } else if user_agent.contains("Chrome/") || user_agent.contains("CriOS/") {
+        "Chrome"
+    } else if user_agent.contains("Safari/") {
+        "Safari"
+    } else {
+        return "Unknown device".into();
+    };
+    // Android and iOS user agents also name Linux and Mac OS X.
+    let os = if user_agent.contains("Android") {
+        "Android"
+    } else if user_agent.contains("iPhone") {
+        "iPhone"
+    } else if user_agent.contains("iPad") {
+        "iPad"
+    } else if user_agent.contains("CrOS") {
+        "ChromeOS"
+    } else if user_agent.contains("Windows") {
+        "Windows"
+    } else if user_agent.contains("Mac OS X") {
+        "macOS"
+    } else if user_agent.contains("Linux") {
+        "Linux"
+    } else {
+        return browser.into();
+    };
+    format!("{browser} on {os}")
 }
 
 fn validate_push_endpoint(endpoint: &str) -> Result<(), ApiError> {
@@ -119,37 +218,72 @@ fn validate_push_endpoint(endpoint: &str) -> Result<(), ApiError> {
 }
 
 async fn subscribe(
-    user: AuthUser,
+    user: Authorized<perm::ManageNotifications>,
     State(state): State<AppState>,
+    headers: HeaderMap,
     Json(body): Json<SubscribeRequest>,
 ) -> Result<StatusCode, ApiError> {
-    state
+    let config = state
         .push_config
         .as_ref()
         .ok_or((StatusCode::NOT_FOUND, "Push notifications not configured"))?;
 
     validate_push_endpoint(&body.endpoint)?;
+    let name = body
+        .name
+        .as_deref()
+        .map(str::trim)
+        .filter(|n| !n.is_empty());
+    if name.is_some_and(|n| n.chars().count() > MAX_NAME_LEN) {
+        return Err((StatusCode::BAD_REQUEST, "Device name is too long"));
+    }
+    let user_agent = headers
+        .get(header::USER_AGENT)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default();
+
+    let db_err = |e: sqlx::Error| {
+        error!("Failed to save push subscription: {e}");
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Failed to save subscription",
+        )
+    };
+
+    let replaced_name: Option<Option<String>> = match body.replaces.as_deref() {
+        Some(old) if old != body.endpoint => sqlx::query_scalar(
+            "DELETE FROM push_subscriptions WHERE endpoint = $1 AND user_id = $2 RETURNING name",
+        )
+        .bind(old)
+        .bind(user.id)
+        .fetch_optional(&state.db)
+        .await
+        .map_err(db_err)?,
+        _ => None,
+    };
+    let name = name
+        .map(str::to_string)
+        .or(replaced_name.flatten())
+        .unwrap_or_else(|| device_name(user_agent));
 
     sqlx::query(
-        "INSERT INTO push_subscriptions (user_id, endpoint, p256dh, auth)
-         VALUES ($1, $2, $3, $4)
-         ON CONFLICT (endpoint) DO UPDATE SET user_id = $1, p256dh = $3, auth = $4",
+        "INSERT INTO push_subscriptions (user_id, endpoint, p256dh, auth, name, user_agent, vapid_key)
+         VALUES ($1, $2, $3, $4, $5, $6, $7)
+         ON CONFLICT (endpoint) DO UPDATE SET user_id = $1, p256dh = $3, auth = $4, name = $5,
+             user_agent = $6, vapid_key = $7, failure_count = 0, last_error = NULL",
     )
     .bind(user.id)
     .bind(&body.endpoint)
     .bind(&body.p256dh)
     .bind(&body.auth)
+    .bind(&name)
+    .bind(user_agent)
+    .bind(&config.key_id)
     .execute(&state.db)
     .await
-    .map_err(|e| {
-        error!("Failed to save push subscription: {e}");
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            "Failed to save subscription",
-        )
-    })?;
+    .map_err(db_err)?;
 
-    info!(user_id = %user.id, "Push subscription saved");
+    info!(user_id = %user.id, device = %name, "Push subscription saved");
     Ok(StatusCode::NO_CONTENT)
 }
 
@@ -159,7 +293,7 @@ struct UnsubscribeRequest {
 }
 
 async fn unsubscribe(
-    user: AuthUser,
+    user: Authorized<perm::ManageNotifications>,
     State(state): State<AppState>,
     Json(body): Json<UnsubscribeRequest>,
 ) -> Result<StatusCode, ApiError> {
@@ -179,173 +313,323 @@ async fn unsubscribe(
     Ok(StatusCode::NO_CONTENT)
 }
 
-// ── Background notifier ─────────────────────────────────────────────────────
+#[derive(Serialize)]
+struct SubscriptionEntry {
+    id: Uuid,
+    name: Option<String>,
+    user_agent: Option<String>,
+    created_at: String,
+    last_success_at: Option<String>,
+    last_failure_at: Option<String>,
+    last_error: Option<String>,
+    failure_count: i32,
+    /// Made with a VAPID key that has since been rotated out.
+    stale: bool,
+}
 
-struct PushSubscriptionRow {
-    id: uuid::Uuid,
-    endpoint: String,
-    p256dh: String,
-    auth: String,
+type SubscriptionRow = (
+    Uuid,
+    Option<String>,
+    Option<String>,
+    Option<String>,
+    DateTime<Utc>,
+    Option<DateTime<Utc>>,
+    Option<DateTime<Utc>>,
+    Option<String>,
+    i32,
+);
+
+/// The caller's browsers that receive push notifications.
+async fn list_subscriptions(
+    user: Authorized<perm::ManageNotifications>,
+    State(state): State<AppState>,
+) -> Result<Json<Vec<SubscriptionEntry>>, ApiError> {
+    let rows: Vec<SubscriptionRow> = sqlx::query_as(
+        "SELECT id, name, user_agent, vapid_key, created_at, last_success_at, last_failure_at, \
+         last_error, failure_count \
+         FROM push_subscriptions WHERE user_id = $1 ORDER BY created_at",
+    )
+    .bind(user.id)
+    .fetch_all(&state.db)
+    .await
+    .map_err(|e| {
+        error!("Failed to list push subscriptions: {e}");
+        (StatusCode::INTERNAL_SERVER_ERROR, "Database error")
+    })?;
+
+    let entries = rows
+        .into_iter()
+        .map(
+            |(
+                id,
+                name,
+                user_agent,
+                vapid_key,
+                created_at,
+                last_success_at,
+                last_failure_at,
+                last_error,
+                failure_count,
+            )| SubscriptionEntry {
+                id,
+                name,
+                user_agent,
+                created_at: created_at.to_rfc3339(),
+                last_success_at: last_success_at.map(|t| t.to_rfc3339()),
+                last_failure_at: last_failure_at.map(|t| t.to_rfc3339()),
+                last_error,
+                failure_count,
+                stale: state
+                    .push_config
+                    .as_ref()
+                    .is_some_and(|c| c.is_stale(vapid_key.as_deref())),
+            },
+        )
+        .collect();
+    Ok(Json(entries))
 }
 
-impl PushSubscriptionRow {
-    fn from_row(row: &sqlx::postgres::PgRow) -> Result<Self, sqlx::Error> {
-        use sqlx::Row;
-        Ok(Self {
-            id: row.try_get("id")?,
-            endpoint: row.try_get("endpoint")?,
-            p256dh: row.try_get("p256dh")?,
-            auth: row.try_get("auth")?,
-        })
+/// Stop push notifications to one of the caller's browsers.
+async fn delete_subscription(
+    user: Authorized<perm::ManageNotifications>,
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Result<StatusCode, ApiError> {
+    let result = sqlx::query("DELETE FROM push_subscriptions WHERE id = $1 AND user_id = $2")
+        .bind(id)
+        .bind(user.id)
+        .execute(&state.db)
+        .await
+        .map_err(|e| {
+            error!("Failed to delete push subscription: {e}");
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to remove subscription",
+            )
+        })?;
+    if result.rows_affected() == 0 {
+        return Err((StatusCode::NOT_FOUND, "Subscription not found"));
     }
+
+    info!(user_id = %user.id, subscription = %id, "Push subscription removed");
+    Ok(StatusCode::NO_CONTENT)
 }
 
-pub async fn spawn_push_notifier(
-    mut rx: broadcast::Receiver<WsEvent>,
-    pool: PgPool,
-    config: PushConfig,
-) {
-    let http_client = reqwest::Client::builder()
-        .timeout(Duration::from_secs(30))
-        .build()
-        .expect("Failed to build HTTP client");
-
-    info!("Push notifier started");
-    loop {
-        let event = match rx.recv().await {
-            Ok(e) => e,
-            Err(broadcast::error::RecvError::Lagged(n)) => {
-                warn!("Push notifier lagged, skipped {n} events");
-                continue;
-            }
-            Err(broadcast::error::RecvError::Closed) => {
-                info!("Push notifier shutting down (channel closed)");
-                return;
-            }
-        };
+// ── Delivery ────────────────────────────────────────────────────────────────
 
-        let (title, body) = match &event {
-            WsEvent::Scan { tag_id, action, .. } => {
-                let title = format!(
-                    "Access {}",
-                    if action == "granted" {
-                        "Granted"
-                    } else {
-                        "Denied"
-                    }
-                );
-                let body = format!("Card {} — {}", tag_id, action);
-                (title, body)
-            }
-            WsEvent::LockState {
-                device_id,
-                lock_state,
-            } => {
-                let title = format!("Lock {}", lock_state);
-                let body = format!("{} is now {}", device_id, lock_state);
-                (title, body)
-            }
-            _ => continue,
-        };
+/// The payload the service worker shows.
+#[derive(Serialize)]
+pub struct Notification<'a> {
+    pub title: &'a str,
+    pub body: &'a str,
+    /// Buttons shown under the notification, if any.
+    pub actions: Vec<PushAction>,
+}
 
-        let payload = serde_json::json!({ "title": title, "body": body }).to_string();
+#[derive(Serialize)]
+struct Payload<'a> {
+    #[serde(flatten)]
+    notification: &'a Notification<'a>,
+    /// Ask the service worker to subscribe again with the current key.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    resubscribe: bool,
+}
 
-        let rows: Vec<PushSubscriptionRow> = match sqlx::query(
-            "SELECT ps.id, ps.endpoint, ps.p256dh, ps.auth
-             FROM push_subscriptions ps
-             JOIN users u ON u.id = ps.user_id
-             WHERE u.is_approved = TRUE",
-        )
-        .fetch_all(&pool)
-        .await
-        {
-            Ok(raw) => {
-                let mut out = Vec::with_capacity(raw.len());
-                for row in &raw {
-                    match PushSubscriptionRow::from_row(row) {
-                        Ok(r) => out.push(r),
-                        Err(e) => {
-                            error!("Failed to parse push subscription row: {e}");
-                        }
-                    }
-                }
-                out
-            }
-            Err(e) => {
-                error!("Failed to query push subscriptions: {e}");
-                continue;
-            }
-        };
+#[derive(Debug)]
+pub enum PushError {
+    /// The push service no longer knows the subscription; it should be
+    /// removed.
+    Gone,
+    Failed(String),
+}
 
-        for row in rows {
-            let sub_info = SubscriptionInfo::new(&row.endpoint, &row.p256dh, &row.auth);
-
-            let sig = match config.vapid_builder.clone().add_sub_info(&sub_info).build() {
-                Ok(s) => s,
-                Err(e) => {
-                    error!(endpoint = %row.endpoint, "VAPID signing failed: {e}");
-                    continue;
-                }
-            };
-
-            let mut builder = WebPushMessageBuilder::new(&sub_info);
-            builder.set_payload(ContentEncoding::Aes128Gcm, payload.as_bytes());
-            builder.set_vapid_signature(sig);
-
-            let message = match builder.build() {
-                Ok(m) => m,
-                Err(e) => {
-                    error!(endpoint = %row.endpoint, "Failed to build push message: {e}");
-                    continue;
-                }
-            };
-
-            // Build the HTTP request from the WebPushMessage
-            let endpoint_url = message.endpoint.to_string();
-            let mut req = http_client.post(&endpoint_url).header("TTL", message.ttl);
-
-            if let Some(payload) = message.payload {
-                req = req
-                    .header("Content-Encoding", payload.content_encoding.to_str())
-                    .header("Content-Type", "application/octet-stream");
-
-                for (k, v) in &payload.crypto_headers {
-                    req = req.header(*k, v);
-                }
-                req = req.body(payload.content);
+impl PushConfig {
+    /// Send one notification to a subscription made with `vapid_key`.
+    pub async fn send(
+        &self,
+        http: &reqwest::Client,
+        endpoint: &str,
+        p256dh: &str,
+        auth: &str,
+        vapid_key: Option<&str>,
+        notification: &Notification<'_>,
+    ) -> Result<(), PushError> {
+        let payload = serde_json::to_string(&Payload {
+            notification,
+            resubscribe: self.is_stale(vapid_key),
+        })
+        .unwrap_or_default();
+        let sub_info = SubscriptionInfo::new(endpoint, p256dh, auth);
+
+        let sig = self
+            .signing_key(vapid_key)
+            .clone()
+            .add_sub_info(&sub_info)
+            .build()
+            .map_err(|e| PushError::Failed(format!("VAPID signing failed: {e}")))?;
+
+        let mut builder = WebPushMessageBuilder::new(&sub_info);
+        builder.set_payload(ContentEncoding::Aes128Gcm, payload.as_bytes());
+        builder.set_vapid_signature(sig);
+        let message = builder
+            .build()
+            .map_err(|e| PushError::Failed(format!("Failed to build push message: {e}")))?;
+
+        // Build the HTTP request from the WebPushMessage
+        let mut req = http
+            .post(message.endpoint.to_string())
+            .header("TTL", message.ttl);
+        if let Some(payload) = message.payload {
+            req = req
+                .header("Content-Encoding", payload.content_encoding.to_str())
+                .header("Content-Type", "application/octet-stream");
+
+            for (k, v) in &payload.crypto_headers {
+                req = req.header(*k, v);
             }
+            req = req.body(payload.content);
+        }
 
-            let pool = pool.clone();
-            let endpoint = row.endpoint.clone();
-            tokio::spawn(async move {
-                match req.send().await {
-                    Ok(resp) => {
-                        let status = resp.status();
-                        if status.is_success() {
-                            // ok
-                        } else if status == reqwest::StatusCode::GONE
-                            || status == reqwest::StatusCode::NOT_FOUND
-                        {
-                            warn!(endpoint = %endpoint, "Push endpoint stale ({status}), removing");
-                            let _ = sqlx::query("DELETE FROM push_subscriptions WHERE id = $1")
-                                .bind(row.id)
-                                .execute(&pool)
-                                .await;
-                        } else {
-                            let body_text = resp.text().await.unwrap_or_default();
-                            error!(
-                                endpoint = %endpoint,
-                                status = %status,
-                                body = %body_text,
-                                "Push delivery failed"
-                            );
-                        }
-                    }
-                    Err(e) => {
-                        error!(endpoint = %endpoint, "Push HTTP request failed: {e}");
-                    }
-                }
-            });
+        let resp = req
+            .send()
+            .await
+            .map_err(|e| PushError::Failed(format!("Push HTTP request failed: {e}")))?;
+        let status = resp.status();
+        if status.is_success() {
+            Ok(())
+        } else if status == reqwest::StatusCode::GONE || status == reqwest::StatusCode::NOT_FOUND {
+            warn!(endpoint, "Push endpoint stale ({status})");
+            Err(PushError::Gone)
+        } else {
+            let body_text = resp.text().await.unwrap_or_default();
+            Err(PushError::Failed(format!("{status}: {body_text}")))
         }
     }
 }
+
+// ── Subscription health ─────────────────────────────────────────────────────
+
+/// Consecutive failed sends, together with [`PRUNE_AFTER_DAYS`] without a
+/// successful one, after which a subscription is removed.
+const PRUNE_AFTER_FAILURES: i32 = 20;
+const PRUNE_AFTER_DAYS: i32 = 3;
+
+pub async fn record_success(db: &PgPool, id: Uuid) {
+    if let Err(e) = sqlx::query(
+        "UPDATE push_subscriptions SET last_success_at = now(), failure_count = 0 WHERE id = $1",
+    )
+    .bind(id)
+    .execute(db)
+    .await
+    {
+        error!(subscription = %id, "Failed to record push success: {e}");
+    }
+}
+
+/// Count a failed send. Returns whether the subscription was removed.
+pub async fn record_failure(db: &PgPool, id: Uuid, error: &str) -> bool {
+    if let Err(e) = sqlx::query(
+        "UPDATE push_subscriptions SET failure_count = failure_count + 1, \
+         last_failure_at = now(), last_error = $2 WHERE id = $1",
+    )
+    .bind(id)
+    .bind(error)
+    .execute(db)
+    .await
+    {
+        error!(subscription = %id, "Failed to record push failure: {e}");
+        return false;
+    }
+
+    match sqlx::query(
+        "DELETE FROM push_subscriptions WHERE id = $1 AND failure_count >= $2 \
+         AND COALESCE(last_success_at, created_at) < now() - make_interval(days => $3)",
+    )
+    .bind(id)
+    .bind(PRUNE_AFTER_FAILURES)
+    .bind(PRUNE_AFTER_DAYS)
+    .execute(db)
+    .await
+    {
+        Ok(result) if result.rows_affected() > 0 => {
+            warn!(subscription = %id, "Removed push subscription after repeated failures");
+            true
+        }
+        Ok(_) => false,
+        Err(e) => {
+            error!(subscription = %id, "Failed to prune push subscription: {e}");
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_devices_from_user_agents() {
+        assert_eq!(
+            device_name(
+                "Mozilla/5.0 (Linux; Android 14; Pixel 8) AppleWebKit/537.36 \
+                 (KHTML, like Gecko) Chrome/126.0.0.0 Mobile Safari/537.36"
+            ),
+            "Chrome on Android"
+        );
+        assert_eq!(
+            device_name(
+                "Mozilla/5.0 (iPhone; CPU iPhone OS 17_5 like Mac OS X) AppleWebKit/605.1.15 \
+                 (KHTML, like Gecko) Version/17.5 Mobile/15E148 Safari/604.1"
+            ),
+            "Safari on iPhone"
+        );
+        assert_eq!(
+            device_name("Mozilla/5.0 (X11; Linux x86_64; rv:128.0) Gecko/20100101 Firefox/128.0"),
+            "Firefox on Linux"
+        );
+        assert_eq!(
+            device_name(
+                "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 \
+                 (KHTML, like Gecko) Chrome/126.0.0.0 Safari/537.36 Edg/126.0.0.0"
+            ),
+            "Edge on Windows"
+        );
+        assert_eq!(device_name(""), "Unknown device");
+    }
+
+    fn vapid_key() -> PartialVapidSignatureBuilder {
+        let group =
+            openssl::ec::EcGroup::from_curve_name(openssl::nid::Nid::X9_62_PRIME256V1).unwrap();
+        let pem = openssl::ec::EcKey::generate(&group)
+            .unwrap()
+            .private_key_to_pem()
+            .unwrap();
+        VapidSignatureBuilder::from_pem_no_sub(pem.as_slice()).unwrap()
+    }
+
+    #[test]
+    fn signs_with_the_key_a_subscription_was_made_with() {
+        let (current, old) = (vapid_key(), vapid_key());
+        let (current_id, old_id) = (key_id(&current), key_id(&old));
+        let mut config = PushConfig {
+            key_id: current_id.clone(),
+            vapid_public_key: current_id.clone(),
+            vapid_builder: current,
+            previous: None,
+        };
+        assert!(!config.is_stale(None));
+        assert!(!config.is_stale(Some(&current_id)));
+
+        config.previous = Some(PreviousKey {
+            vapid_builder: old,
+            public_key: old_id.clone(),
+        });
+        let signer = |key: Option<&str>| key_id(config.signing_key(key));
+        assert!(config.is_stale(None));
+        assert!(config.is_stale(Some(&old_id)));
+        assert!(!config.is_stale(Some(&current_id)));
+        assert_eq!(signer(None), old_id);
+        assert_eq!(signer(Some(&old_id)), old_id);
+        assert_eq!(signer(Some(&current_id)), current_id);
+    }
+}
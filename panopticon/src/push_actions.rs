@@ -0,0 +1,392 @@
+//! Action buttons on push notifications.
+//!
+//! A push about an unlocked door can offer "Lock door", and one about a
+//! denied scan "Grant this card". Each button carries a token signed with a
+//! server key that names the user, the notified event, the action and its
+//! target, and expires after [`TOKEN_TTL_MINUTES`]. The service worker posts
+//! the token back to `/api/push/actions`; the action then runs with the
+//! user's current permissions through the same code as the dashboard. Each
+//! action on an event can be performed once.
+
+use axum::{extract::State, http::StatusCode, routing::post, Json, Router};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use sqlx::PgPool;
+use tracing::{error, info};
+use uuid::Uuid;
+
+use crate::commands::LockAction;
+use crate::email_auth::generate_token;
+use crate::middleware::AuthUser;
+use crate::permissions::{Permission, Role};
+use crate::ws::WsEvent;
+use crate::{api, sentinel, AppState};
+
+type ApiError = (StatusCode, &'static str);
+
+const TOKEN_TTL_MINUTES: i64 = 15;
+const KEY_CONFIG: &str = "push_action_key";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    /// Lock the door named by the target device ID.
+    Lock,
+    /// Add the target tag ID to the access list.
+    GrantCard,
+}
+
+impl Action {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Lock => "lock",
+            Self::GrantCard => "grant_card",
+        }
+    }
+
+    fn title(self) -> &'static str {
+        match self {
+            Self::Lock => "Lock door",
+            Self::GrantCard => "Grant this card",
+        }
+    }
+
+    fn permission(self) -> Permission {
+        match self {
+            Self::Lock => Permission::OperateLocks,
+            Self::GrantCard => Permission::ManageAccess,
+        }
+    }
+}
+
+/// What a token allows.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct Claims {
+    user_id: Uuid,
+    /// The notified event (`notification_outbox.id`).
+    outbox_id: Uuid,
+    action: Action,
+    target: String,
+    /// Unix time.
+    expires_at: i64,
+}
+
+/// A button as sent in the push payload.
+#[derive(Debug, Serialize)]
+pub struct PushAction {
+    pub action: Action,
+    pub title: &'static str,
+    pub token: String,
+}
+
+fn signature(key: &str, payload: &str) -> Vec<u8> {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(key.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(payload.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn sign(key: &str, claims: &Claims) -> String {
+    let payload = URL_SAFE_NO_PAD.encode(serde_json::to_vec(claims).unwrap_or_default());
+    let sig = URL_SAFE_NO_PAD.encode(signature(key, &payload));
+    format!("{payload}.{sig}")
+}
+
+/// Check the signature and expiry of a token.
+fn verify(key: &str, token: &str, now: i64) -> Option<Claims> {
+    let (payload, sig) = token.split_once('.')?;
+    let sig = URL_SAFE_NO_PAD.decode(sig).ok()?;
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(key.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(payload.as_bytes());
+    mac.verify_slice(&sig).ok()?;
+
+    let claims: Claims = serde_json::from_slice(&URL_SAFE_NO_PAD.decode(payload).ok()?).ok()?;
+    (claims.expires_at > now).then_some(claims)
+}
+
+/// The signing key, created on first use.
+async fn signing_key(db: &PgPool) -> Result<String, sqlx::Error> {
+    sqlx::query("INSERT INTO system_config (key, value) VALUES ($1, $2) ON CONFLICT DO NOTHING")
+        .bind(KEY_CONFIG)
+        .bind(generate_token())
+        .execute(db)
+        .await?;
+    sqlx::query_scalar("SELECT value FROM system_config WHERE key = $1")
+        .bind(KEY_CONFIG)
+        .fetch_one(db)
+        .await
+}
+
+/// The action an event offers, and its target.
+fn offered(event: &WsEvent) -> Option<(Action, &str)> {
+    match event {
+        WsEvent::LockState {
+            device_id,
+            lock_state,
+            ..
+        } if lock_state == "unlocked" => Some((Action::Lock, device_id)),
+        WsEvent::Scan { tag_id, action, .. } if action == "denied" => {
+            Some((Action::GrantCard, tag_id))
+        }
+        _ => None,
+    }
+}
+
+/// Buttons for a push delivery, signed for its recipient. Actions the
+/// recipient's role can't perform aren't offered.
+pub async fn for_delivery(db: &PgPool, delivery_id: Uuid) -> Vec<PushAction> {
+    let row: Option<(Uuid, Uuid, serde_json::Value, String)> = match sqlx::query_as(
+        "SELECT d.user_id, o.id, o.event, u.role FROM notification_deliveries d \
+         JOIN notification_outbox o ON o.id = d.outbox_id \
+         JOIN users u ON u.id = d.user_id WHERE d.id = $1",
+    )
+    .bind(delivery_id)
+    .fetch_optional(db)
+    .await
+    {
+        Ok(row) => row,
+        Err(e) => {
+            error!(delivery = %delivery_id, "Failed to load push delivery: {e}");
+            return Vec::new();
+        }
+    };
+    let Some((user_id, outbox_id, event, role)) = row else {
+        return Vec::new();
+    };
+    let Ok(event) = serde_json::from_value::<WsEvent>(event) else {
+        return Vec::new();
+    };
+    let Some((action, target)) = offered(&event) else {
+        return Vec::new();
+    };
+    if !Role::parse(&role).is_some_and(|r| r.has(action.permission())) {
+        return Vec::new();
+    }
+
+    let key = match signing_key(db).await {
+        Ok(key) => key,
+        Err(e) => {
+            error!("Failed to load push action key: {e}");
+            return Vec::new();
+        }
+    };
+    let claims = Claims {
+        user_id,
+        outbox_id,
+        action,
+        target: target.to_string(),
+        expires_at: Utc::now().timestamp() + TOKEN_TTL_MINUTES * 60,
+    };
+    vec![PushAction {
+        action,
+        title: action.title(),
+        token: sign(&key, &claims),
+    }]
+}
+
+/// Mark a token's action on its event as used. Returns `false` if it
+/// already was, by this or another recipient.
+async fn claim(db: &PgPool, claims: &Claims) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query(
+        "INSERT INTO push_action_uses (outbox_id, action, user_id) VALUES ($1, $2, $3) \
+         ON CONFLICT DO NOTHING",
+    )
+    .bind(claims.outbox_id)
+    .bind(claims.action.as_str())
+    .bind(claims.user_id)
+    .execute(db)
+    .await?;
+    Ok(result.rows_affected() == 1)
+}
+
+async fn release(db: &PgPool, claims: &Claims) {
+    if let Err(e) = sqlx::query("DELETE FROM push_action_uses WHERE outbox_id = $1 AND action = $2")
+        .bind(claims.outbox_id)
+        .bind(claims.action.as_str())
+        .execute(db)
+        .await
+    {
+        error!("Failed to release push action: {e}");
+    }
+}
+
+// ── Routes ──────────────────────────────────────────────────────────────────
+
+pub fn router() -> Router<AppState> {
+    Router::new().route("/push/actions", post(perform))
+}
+
+#[derive(Deserialize)]
+struct ActionRequest {
+    token: String,
+}
+
+#[derive(Serialize)]
+struct ActionResponse {
+    message: &'static str,
+}
+
+/// Perform a notification action. The token stands in for the session, as
+/// the service worker may act from a locked phone.
+async fn perform(
+    State(state): State<AppState>,
+    Json(req): Json<ActionRequest>,
+) -> Result<Json<ActionResponse>, ApiError> {
+    let key = signing_key(&state.db).await.map_err(|e| {
+        error!("Failed to load push action key: {e}");
+        (StatusCode::INTERNAL_SERVER_ERROR, "Database error")
+    })?;
+    let claims = verify(&key, &req.token, Utc::now().timestamp()).ok_or((
+        StatusCode::UNAUTHORIZED,
+        "Action link is invalid or has expired",
+    ))?;
+
+    let user: Option<(String, bool, String)> = sqlx::query_as(
+        "SELECT email, email_confirmed, role FROM users \
+         WHERE id = $1 AND is_approved = TRUE AND suspended_at IS NULL",
+    )
+    .bind(claims.user_id)
+    .fetch_optional(&state.db)
+    .await
+    .map_err(|e| {
+        error!("Failed to load user for push action: {e}");
+        (StatusCode::INTERNAL_SERVER_ERROR, "Database error")
+    })?;
+    let (email, email_confirmed, role) =
+        user.ok_or((StatusCode::FORBIDDEN, "Account is not active"))?;
+    let user = AuthUser {
+        id: claims.user_id,
+        email,
+        email_confirmed,
+        is_approved: true,
+        role: Role::parse(&role).unwrap_or(Role::Viewer),
+        token_scopes: None,
+        second_factor: None,
+    };
+    if !user.role.has(claims.action.permission()) {
+        return Err((StatusCode::FORBIDDEN, "Insufficient permissions"));
+    }
+
+    if !claim(&state.db, &claims).await.map_err(|e| {
+        error!("Failed to record push action: {e}");
+        (StatusCode::INTERNAL_SERVER_ERROR, "Database error")
+    })? {
+        return Err((StatusCode::CONFLICT, "This action has already been used"));
+    }
+
+    let result = match claims.action {
+        Action::Lock => api::send_lock_command(
+            &state,
+            &user,
+            &claims.target,
+            LockAction::Lock,
+            "push",
+            None,
+        )
+        .await
+        .map(|_| "Locking door"),
+        Action::GrantCard => sentinel::grant_card(&state, &claims.target, user.id)
+            .await
+            .map(|added| {
+                if added {
+                    "Card granted"
+                } else {
+                    "Card already has access"
+                }
+            })
+            .map_err(|e| {
+                error!("Failed to grant card: {e}");
+                (StatusCode::INTERNAL_SERVER_ERROR, "Database error")
+            }),
+    };
+    // A failed action can be tried again.
+    let message = match result {
+        Ok(message) => message,
+        Err(e) => {
+            release(&state.db, &claims).await;
+            return Err(e);
+        }
+    };
+
+    info!(
+        user = %user.email,
+        action = ?claims.action,
+        target = %claims.target,
+        "Push notification action performed"
+    );
+    Ok(Json(ActionResponse { message }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn claims(expires_at: i64) -> Claims {
+        Claims {
+            user_id: Uuid::new_v4(),
+            outbox_id: Uuid::new_v4(),
+            action: Action::Lock,
+            target: "front-door".to_string(),
+            expires_at,
+        }
+    }
+
+    #[test]
+    fn tokens_round_trip_until_expiry() {
+        let c = claims(1_000);
+        let token = sign("key", &c);
+        assert_eq!(verify("key", &token, 999), Some(c));
+        assert_eq!(verify("key", &token, 1_000), None);
+        assert_eq!(verify("other", &token, 999), None);
+    }
+
+    #[test]
+    fn tampered_tokens_are_rejected() {
+        let token = sign("key", &claims(1_000));
+        let (_, sig) = token.split_once('.').unwrap();
+        let mut forged = claims(1_000);
+        forged.target = "back-door".to_string();
+        let forged_payload = URL_SAFE_NO_PAD.encode(serde_json::to_vec(&forged).unwrap());
+        assert_eq!(verify("key", &format!("{forged_payload}.{sig}"), 999), None);
+        assert_eq!(verify("key", "garbage", 999), None);
+    }
+
+    #[test]
+    fn action_names_match_serialized_form() {
+        for action in [Action::Lock, Action::GrantCard] {
+            assert_eq!(serde_json::to_value(action).unwrap(), action.as_str());
+        }
+    }
+
+    #[test]
+    fn offers_actions_for_unlocks_and_denials() {
+        let unlocked = WsEvent::LockState {
+            device_id: "front-door".to_string(),
+            lock_state: "unlocked".to_string(),
+            log_id: None,
+        };
+        assert_eq!(offered(&unlocked), Some((Action::Lock, "front-door")));
+
+        let locked = WsEvent::LockState {
+            device_id: "front-door".to_string(),
+            lock_state: "locked".to_string(),
+            log_id: None,
+        };
+        assert_eq!(offered(&locked), None);
+
+        let denied = WsEvent::Scan {
+            tag_id: "80:00:48:23:4C".to_string(),
+            action: "denied".to_string(),
+            sentinel_id: None,
+            created_at: String::new(),
+        };
+        assert_eq!(
+            offered(&denied),
+            Some((Action::GrantCard, "80:00:48:23:4C"))
+        );
+    }
+}
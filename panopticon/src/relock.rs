@@ -0,0 +1,495 @@
+//! Auto-relock watchdog.
+//!
+//! Each lock can have a relock policy: after `delay_secs` in the `unlocked`
+//! state, panopticon sends `st.lock/lock` itself. The watchdog listens on the
+//! event bus, so unlocks reported by webhooks, the API, MQTT and deferred
+//! polling all arm the timer, and any `locked` report disarms it. Timers
+//! live in memory, so at startup and whenever the watchdog falls behind the
+//! bus it re-reads every lock's state from U-Tec and arms timers for doors
+//! that are unlocked.
+//!
+//! Relocks go through `commands::execute` with source `auto_relock`, so they
+//! are tracked and land in `lock_state_log` like any other command. The
+//! timer retries a lock command that fails or times out, waiting the delay
+//! again each time; if the door still isn't confirmed locked after
+//! `MAX_RELOCK_ATTEMPTS` tries, a `WsEvent::RelockFailed` notifies users.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    routing::get,
+    Json, Router,
+};
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+use tokio::task::JoinHandle;
+use tracing::{error, info, warn};
+
+use crate::commands::{self, CommandStatus, LockAction};
+use crate::middleware::Authorized;
+use crate::permissions::perm;
+use crate::ws::WsEvent;
+use crate::AppState;
+
+type ApiError = (StatusCode, &'static str);
+
+/// Give up (and notify) after this many relock attempts without the lock
+/// reporting `locked`.
+const MAX_RELOCK_ATTEMPTS: u32 = 3;
+
+const MIN_DELAY_SECS: i32 = 5;
+const MAX_DELAY_SECS: i32 = 24 * 60 * 60;
+
+// ── API ─────────────────────────────────────────────────────────────────────
+
+#[derive(Serialize, Deserialize)]
+struct RelockPolicy {
+    enabled: bool,
+    delay_secs: i32,
+}
+
+pub fn router() -> Router<AppState> {
+    Router::new().route(
+        "/devices/{id}/relock",
+        get(get_policy).put(set_policy).delete(delete_policy),
+    )
+}
+
+async fn get_policy(
+    _user: Authorized<perm::ViewDevices>,
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<Json<RelockPolicy>, ApiError> {
+    let policy = load_policy(&state, &id)
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Database error"))?
+        .ok_or((StatusCode::NOT_FOUND, "No relock policy for this device"))?;
+
+    Ok(Json(policy))
+}
+
+async fn set_policy(
+    _user: Authorized<perm::ManageLocks>,
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Json(body): Json<RelockPolicy>,
+) -> Result<Json<RelockPolicy>, ApiError> {
+    if !(MIN_DELAY_SECS..=MAX_DELAY_SECS).contains(&body.delay_secs) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "delay_secs must be between 5 and 86400",
+        ));
+    }
+
+    sqlx::query(
+        "INSERT INTO relock_policies (device_id, enabled, delay_secs) VALUES ($1, $2, $3) \
+         ON CONFLICT (device_id) DO UPDATE SET enabled = $2, delay_secs = $3, updated_at = now()",
+    )
+    .bind(&id)
+    .bind(body.enabled)
+    .bind(body.delay_secs)
+    .execute(&state.db)
+    .await
+    .map_err(|e| {
+        error!("Failed to save relock policy: {e:#}");
+        (StatusCode::INTERNAL_SERVER_ERROR, "Database error")
+    })?;
+
+    info!(device_id = %id, enabled = body.enabled, delay_secs = body.delay_secs, "Relock policy updated");
+
+    Ok(Json(body))
+}
+
+async fn delete_policy(
+    _user: Authorized<perm::ManageLocks>,
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<StatusCode, ApiError> {
+    sqlx::query("DELETE FROM relock_policies WHERE device_id = $1")
+        .bind(&id)
+        .execute(&state.db)
+        .await
+        .map_err(|e| {
+            error!("Failed to delete relock policy: {e:#}");
+            (StatusCode::INTERNAL_SERVER_ERROR, "Database error")
+        })?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn load_policy(state: &AppState, device_id: &str) -> Result<Option<RelockPolicy>, ()> {
+    let row: Option<(bool, i32)> =
+        sqlx::query_as("SELECT enabled, delay_secs FROM relock_policies WHERE device_id = $1")
+            .bind(device_id)
+            .fetch_optional(&state.db)
+            .await
+            .map_err(|e| error!(device_id, "Failed to load relock policy: {e:#}"))?;
+
+    Ok(row.map(|(enabled, delay_secs)| RelockPolicy {
+        enabled,
+        delay_secs,
+    }))
+}
+
+// ── Watchdog ────────────────────────────────────────────────────────────────
+
+/// A relock timer for one device. The timer task makes every attempt
+/// itself, since the `unlocked` report caused by a failed relock arrives
+/// while it is still running.
+struct Pending {
+    timer: JoinHandle<()>,
+    /// Set when the timer ran out of attempts. The entry then stays until the
+    /// lock reports something other than `unlocked`, so further `unlocked`
+    /// reports don't start a fresh round of attempts.
+    gave_up: Arc<AtomicBool>,
+}
+
+/// What a lock state report means for a device's timer.
+#[derive(Debug, PartialEq)]
+enum Step {
+    /// Not unlocked any more: forget the device.
+    Disarm,
+    /// A timer is still running, or it gave up.
+    Wait,
+    Arm,
+}
+
+fn next_step(pending: Option<&Pending>, lock_state: &str) -> Step {
+    if lock_state != "unlocked" {
+        return Step::Disarm;
+    }
+
+    // Repeated `unlocked` reports (webhook + deferred poll) while a timer
+    // is armed don't restart it. A timer that finished without giving up
+    // locked the door, so this is a new unlock.
+    match pending {
+        None => Step::Arm,
+        Some(p) if !p.timer.is_finished() || p.gave_up.load(Ordering::Relaxed) => Step::Wait,
+        Some(_) => Step::Arm,
+    }
+}
+
+fn notify_failure(state: &AppState, device_id: &str, error: String) {
+    let _ = state.events.send(WsEvent::RelockFailed {
+        device_id: device_id.to_string(),
+        error,
+    });
+}
+
+/// Send the lock command for a device and record the result as `auto_relock`.
+/// Succeeds only if the lock confirmed it is locked.
+async fn relock(state: &AppState, device_id: &str) -> Result<(), String> {
+    let Some(client) = state.auth_store.client().await else {
+        error!(device_id, "Auto-relock: no U-Tec client available");
+        return Err("U-Tec not connected".to_string());
+    };
+
+    let locks = match client.discover_locks().await {
+        Ok(l) => l,
+        Err(e) => {
+            error!(device_id, "Auto-relock: failed to discover locks: {e:#}");
+            return Err(format!("Failed to discover locks: {e}"));
+        }
+    };
+
+    let Some(device) = locks.iter().find(|d| d.id == device_id) else {
+        error!(device_id, "Auto-relock: device not found");
+        return Err("Device not found".to_string());
+    };
+
+    match commands::execute(
+        state,
+        &client,
+        device,
+        LockAction::Lock,
+        "auto_relock",
+        None,
+        None,
+    )
+    .await
+    {
+        Ok(record) if record.status == CommandStatus::Confirmed.as_str() => {
+            info!(device_id, command_id = %record.id, "Auto-relock: door locked");
+            Ok(())
+        }
+        // A timed-out command never saw the lock's state, so the door may
+        // still be unlocked.
+        Ok(record) => {
+            let error = record.error.unwrap_or_default();
+            error!(device_id, status = %record.status, "Auto-relock: lock command failed: {error}");
+            Err(format!("Lock command failed: {error}"))
+        }
+        Err(e) => {
+            error!(device_id, "Auto-relock: failed to record lock command: {e}");
+            Err(format!("Lock command failed: {e}"))
+        }
+    }
+}
+
+/// Start a timer that waits `delay` before each of up to
+/// `MAX_RELOCK_ATTEMPTS` calls to `relock`, stopping once one succeeds.
+/// If none does, `give_up` gets the last error.
+fn spawn_timer<R, Fut, G>(delay: Duration, mut relock: R, give_up: G) -> Pending
+where
+    R: FnMut() -> Fut + Send + 'static,
+    Fut: Future<Output = Result<(), String>> + Send,
+    G: FnOnce(String) + Send + 'static,
+{
+    let gave_up = Arc::new(AtomicBool::new(false));
+    let flag = gave_up.clone();
+    let timer = tokio::spawn(async move {
+        let mut error = String::new();
+        for attempt in 1..=MAX_RELOCK_ATTEMPTS {
+            tokio::time::sleep(delay).await;
+            match relock().await {
+                Ok(()) => return,
+                Err(e) => {
+                    warn!(attempt, "Auto-relock attempt failed: {e}");
+                    error = e;
+                }
+            }
+        }
+        flag.store(true, Ordering::Relaxed);
+        give_up(error);
+    });
+    Pending { timer, gave_up }
+}
+
+/// Arm or disarm a device's timer for a reported lock state.
+async fn handle_state(
+    state: &AppState,
+    pending: &mut HashMap<String, Pending>,
+    device_id: String,
+    lock_state: &str,
+) {
+    match next_step(pending.get(&device_id), lock_state) {
+        Step::Disarm => {
+            if let Some(p) = pending.remove(&device_id) {
+                p.timer.abort();
+            }
+            return;
+        }
+        Step::Wait => return,
+        Step::Arm => {}
+    }
+
+    let policy = match load_policy(state, &device_id).await {
+        Ok(Some(p)) if p.enabled => p,
+        _ => return,
+    };
+
+    let delay = Duration::from_secs(policy.delay_secs as u64);
+    info!(
+        device_id,
+        delay_secs = policy.delay_secs,
+        "Auto-relock armed"
+    );
+
+    let relock = {
+        let state = state.clone();
+        let device_id = device_id.clone();
+        move || {
+            let state = state.clone();
+            let device_id = device_id.clone();
+            async move { relock(&state, &device_id).await }
+        }
+    };
+    let give_up = {
+        let state = state.clone();
+        let device_id = device_id.clone();
+        move |error: String| {
+            let attempts = MAX_RELOCK_ATTEMPTS;
+            warn!(device_id, attempts, "Auto-relock: giving up");
+            notify_failure(
+                &state,
+                &device_id,
+                format!("Door still unlocked after {attempts} relock attempts: {error}"),
+            );
+        }
+    };
+
+    pending.insert(device_id, spawn_timer(delay, relock, give_up));
+}
+
+/// Read every lock's current state and arm or disarm timers to match. Timers
+/// only live in memory, so this covers unlocks missed while the watchdog
+/// lagged behind the event bus or the server was down.
+async fn reconcile(state: &AppState, pending: &mut HashMap<String, Pending>) {
+    let Some(client) = state.auth_store.client().await else {
+        warn!("Auto-relock: U-Tec not connected, can't check for unlocked doors");
+        return;
+    };
+
+    let locks = match client.discover_locks().await {
+        Ok(l) => l,
+        Err(e) => {
+            error!("Auto-relock: failed to discover locks: {e:#}");
+            return;
+        }
+    };
+    let lock_refs: Vec<&_> = locks.iter().collect();
+    let states = match client.query_devices(&lock_refs).await {
+        Ok(s) => s,
+        Err(e) => {
+            error!("Auto-relock: failed to query lock states: {e:#}");
+            return;
+        }
+    };
+
+    for ds in states {
+        if let Some(lock_state) = ds.lock_state() {
+            handle_state(state, pending, ds.id.clone(), &lock_state).await;
+        }
+    }
+}
+
+pub async fn spawn_relock_watchdog(mut rx: broadcast::Receiver<WsEvent>, state: AppState) {
+    let mut pending: HashMap<String, Pending> = HashMap::new();
+
+    info!("Relock watchdog started");
+    reconcile(&state, &mut pending).await;
+    loop {
+        match rx.recv().await {
+            Ok(WsEvent::LockState {
+                device_id,
+                lock_state,
+                ..
+            }) => handle_state(&state, &mut pending, device_id, &lock_state).await,
+            Ok(_) => {}
+            Err(broadcast::error::RecvError::Lagged(n)) => {
+                warn!("Relock watchdog lagged, skipped {n} events; re-reading lock states");
+                reconcile(&state, &mut pending).await;
+            }
+            Err(broadcast::error::RecvError::Closed) => {
+                info!("Relock watchdog shutting down (channel closed)");
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicU32;
+    use std::sync::Mutex;
+
+    fn pending(running: bool, gave_up: bool) -> Pending {
+        let timer = if running {
+            tokio::spawn(std::future::pending())
+        } else {
+            tokio::spawn(async {})
+        };
+        Pending {
+            timer,
+            gave_up: Arc::new(AtomicBool::new(gave_up)),
+        }
+    }
+
+    async fn finish(p: &Pending) {
+        while !p.timer.is_finished() {
+            tokio::task::yield_now().await;
+        }
+    }
+
+    #[tokio::test]
+    async fn arms_on_unlock() {
+        assert_eq!(next_step(None, "unlocked"), Step::Arm);
+        // Another report while the timer runs leaves it alone.
+        assert_eq!(
+            next_step(Some(&pending(true, false)), "unlocked"),
+            Step::Wait
+        );
+        // The last timer locked the door, so this is a new unlock.
+        let done = pending(false, false);
+        finish(&done).await;
+        assert_eq!(next_step(Some(&done), "unlocked"), Step::Arm);
+    }
+
+    #[tokio::test]
+    async fn disarms_on_other_states() {
+        for lock_state in ["locked", "jammed", "unknown"] {
+            assert_eq!(next_step(None, lock_state), Step::Disarm);
+            assert_eq!(
+                next_step(Some(&pending(true, false)), lock_state),
+                Step::Disarm
+            );
+        }
+    }
+
+    /// A relock that never locks the door: each attempt reports `unlocked`
+    /// back to the watchdog while the timer is still running, as
+    /// `commands::record_lock_state` does, and the timer retries on its own
+    /// until it gives up.
+    #[tokio::test]
+    async fn retries_a_failed_relock_then_gives_up_once() {
+        let calls = Arc::new(AtomicU32::new(0));
+        let failures = Arc::new(Mutex::new(Vec::new()));
+        let (reports, mut reported) = tokio::sync::mpsc::unbounded_channel();
+
+        let relock = {
+            let calls = calls.clone();
+            move || {
+                let n = calls.fetch_add(1, Ordering::SeqCst) + 1;
+                let reports = reports.clone();
+                async move {
+                    reports.send("unlocked").unwrap();
+                    Err(format!("Lock reported unlocked ({n})"))
+                }
+            }
+        };
+        let give_up = {
+            let failures = failures.clone();
+            move |error| failures.lock().unwrap().push(error)
+        };
+        let p = spawn_timer(Duration::ZERO, relock, give_up);
+
+        let mut seen = 0;
+        while let Some(lock_state) = reported.recv().await {
+            seen += 1;
+            if seen < MAX_RELOCK_ATTEMPTS {
+                assert_eq!(next_step(Some(&p), lock_state), Step::Wait);
+            }
+        }
+        finish(&p).await;
+
+        assert_eq!(calls.load(Ordering::SeqCst), MAX_RELOCK_ATTEMPTS);
+        assert_eq!(
+            *failures.lock().unwrap(),
+            [format!("Lock reported unlocked ({MAX_RELOCK_ATTEMPTS})")]
+        );
+        // Gave up: more `unlocked` reports don't start another round, but a
+        // locked door clears the entry.
+        assert_eq!(next_step(Some(&p), "unlocked"), Step::Wait);
+        assert_eq!(next_step(Some(&p), "locked"), Step::Disarm);
+    }
+
+    #[tokio::test]
+    async fn stops_retrying_once_locked() {
+        let calls = Arc::new(AtomicU32::new(0));
+        let relock = {
+            let calls = calls.clone();
+            move || {
+                let n = calls.fetch_add(1, Ordering::SeqCst) + 1;
+                async move {
+                    if n < 2 {
+                        Err("Lock command failed: timed out".to_string())
+                    } else {
+                        Ok(())
+                    }
+                }
+            }
+        };
+        let p = spawn_timer(Duration::ZERO, relock, |error| panic!("gave up: {error}"));
+        finish(&p).await;
+
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+        assert!(!p.gave_up.load(Ordering::Relaxed));
+    }
+}
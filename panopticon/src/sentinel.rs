@@ -8,7 +8,9 @@ use serde::{Deserialize, Serialize};
 use tracing::{error, info, warn};
 use uuid::Uuid;
 
-use crate::middleware::AuthUser;
+use crate::commands::{self, CommandStatus};
+use crate::middleware::Authorized;
+use crate::permissions::perm;
 use crate::ws::WsEvent;
 use crate::AppState;
 
@@ -109,7 +111,12 @@ pub fn is_valid_tag_id(tag_id: &str) -> bool {
 
 /// Core scan processing logic shared by both the HTTP handler and TCP handler.
 /// Returns the action string ("enrolled", "granted", or "denied").
-pub async fn process_scan(state: &AppState, tag_id: &str) -> Result<String, String> {
+/// `sentinel_id` is the reader, if the transport identifies it.
+pub async fn process_scan(
+    state: &AppState,
+    tag_id: &str,
+    sentinel_id: Option<Uuid>,
+) -> Result<String, String> {
     // Read current mode
     let mode: String =
         sqlx::query_scalar("SELECT value FROM system_config WHERE key = 'sentinel_mode'")
@@ -151,12 +158,22 @@ pub async fn process_scan(state: &AppState, tag_id: &str) -> Result<String, Stri
                     match client.discover_locks().await {
                         Ok(locks) => {
                             if let Some(lock) = locks.first() {
-                                match client.unlock(lock).await {
+                                match commands::execute_for_card(state, &client, lock, tag_id).await
+                                {
+                                    Ok(record)
+                                        if record.status == CommandStatus::Failed.as_str() =>
+                                    {
+                                        error!(
+                                            tag_id = %tag_id,
+                                            error = record.error.as_deref().unwrap_or(""),
+                                            "Failed to unlock"
+                                        )
+                                    }
                                     Ok(_) => {
                                         info!(tag_id = %tag_id, lock = %lock.name, "Door unlocked")
                                     }
                                     Err(e) => {
-                                        error!(tag_id = %tag_id, "Failed to unlock: {e:#}")
+                                        error!(tag_id = %tag_id, "Failed to unlock: {e}")
                                     }
                                 }
                             } else {
@@ -194,32 +211,58 @@ pub async fn process_scan(state: &AppState, tag_id: &str) -> Result<String, Stri
     let _ = state.events.send(WsEvent::Scan {
         tag_id: tag_id.to_string(),
         action: action.to_string(),
+        sentinel_id,
         created_at: scan_row.1.to_rfc3339(),
     });
 
     // If enrolled, also broadcast the new card
     if action == "enrolled" {
-        let card: Option<(Uuid, String, Option<String>, chrono::DateTime<chrono::Utc>)> =
-            sqlx::query_as(
-                "SELECT id, tag_id, label, created_at FROM access_cards WHERE tag_id = $1",
-            )
+        broadcast_card_added(state, tag_id).await;
+    }
+
+    Ok(action.to_string())
+}
+
+/// Add a card to the access list, as enrolling it would. Returns `false` if
+/// it was already there.
+pub async fn grant_card(
+    state: &AppState,
+    tag_id: &str,
+    granted_by: Uuid,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query(
+        "INSERT INTO access_cards (tag_id, created_by) VALUES ($1, $2) ON CONFLICT DO NOTHING",
+    )
+    .bind(tag_id)
+    .bind(granted_by)
+    .execute(&state.db)
+    .await?;
+    if result.rows_affected() == 0 {
+        return Ok(false);
+    }
+
+    info!(tag_id = %tag_id, by = %granted_by, "Card granted");
+    broadcast_card_added(state, tag_id).await;
+    Ok(true)
+}
+
+async fn broadcast_card_added(state: &AppState, tag_id: &str) {
+    let card: Option<(Uuid, String, Option<String>, chrono::DateTime<chrono::Utc>)> =
+        sqlx::query_as("SELECT id, tag_id, label, created_at FROM access_cards WHERE tag_id = $1")
             .bind(tag_id)
             .fetch_optional(&state.db)
             .await
             .ok()
             .flatten();
 
-        if let Some((id, tag_id, label, created_at)) = card {
-            let _ = state.events.send(WsEvent::CardAdded {
-                id,
-                tag_id,
-                label,
-                created_at: created_at.to_rfc3339(),
-            });
-        }
+    if let Some((id, tag_id, label, created_at)) = card {
+        let _ = state.events.send(WsEvent::CardAdded {
+            id,
+            tag_id,
+            label,
+            created_at: created_at.to_rfc3339(),
+        });
     }
-
-    Ok(action.to_string())
 }
 
 // ── Handlers ────────────────────────────────────────────────────────────────
@@ -236,7 +279,7 @@ async fn handle_scan(
         return Err((StatusCode::BAD_REQUEST, "Invalid tag_id format"));
     }
 
-    let action = process_scan(&state, &req.tag_id)
+    let action = process_scan(&state, &req.tag_id, None)
         .await
         .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Database error"))?;
 
@@ -244,7 +287,7 @@ async fn handle_scan(
 }
 
 async fn get_mode(
-    _user: AuthUser,
+    _user: Authorized<perm::ViewCards>,
     State(state): State<AppState>,
 ) -> Result<Json<ModeResponse>, ApiError> {
     let mode: String =
@@ -260,7 +303,7 @@ async fn get_mode(
 }
 
 async fn set_mode(
-    _user: AuthUser,
+    _user: Authorized<perm::ManageAccess>,
     State(state): State<AppState>,
     Json(req): Json<SetModeRequest>,
 ) -> Result<Json<ModeResponse>, ApiError> {
@@ -287,7 +330,7 @@ async fn set_mode(
 }
 
 async fn list_cards(
-    _user: AuthUser,
+    _user: Authorized<perm::ViewCards>,
     State(state): State<AppState>,
 ) -> Result<Json<Vec<CardResponse>>, ApiError> {
     let rows: Vec<(Uuid, String, Option<String>, chrono::DateTime<chrono::Utc>)> = sqlx::query_as(
@@ -314,7 +357,7 @@ async fn list_cards(
 }
 
 async fn remove_card(
-    _user: AuthUser,
+    _user: Authorized<perm::ManageAccess>,
     State(state): State<AppState>,
     Path(id): Path<Uuid>,
 ) -> Result<StatusCode, ApiError> {
@@ -339,7 +382,7 @@ async fn remove_card(
 }
 
 async fn scan_log(
-    _user: AuthUser,
+    _user: Authorized<perm::ViewCards>,
     State(state): State<AppState>,
 ) -> Result<Json<Vec<ScanLogEntry>>, ApiError> {
     let rows: Vec<(Uuid, String, String, chrono::DateTime<chrono::Utc>)> = sqlx::query_as(
@@ -376,7 +419,7 @@ type SentinelRow = (
 );
 
 async fn list_sentinels(
-    _user: AuthUser,
+    _user: Authorized<perm::ViewCards>,
     State(state): State<AppState>,
 ) -> Result<Json<Vec<SentinelResponse>>, ApiError> {
     let rows: Vec<SentinelRow> = sqlx::query_as(
@@ -406,7 +449,7 @@ async fn list_sentinels(
 }
 
 async fn sentinel_logs(
-    _user: AuthUser,
+    _user: Authorized<perm::ViewCards>,
     State(state): State<AppState>,
     Path(id): Path<Uuid>,
     Query(query): Query<LogsQuery>,
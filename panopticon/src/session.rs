@@ -1,10 +1,29 @@
+use std::net::{IpAddr, SocketAddr};
+use std::sync::LazyLock;
+
 use anyhow::Result;
-use chrono::{Duration, Utc};
+use axum::{
+    extract::{ConnectInfo, FromRequestParts, Path, State},
+    http::{request::Parts, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{delete, get},
+    Json, Router,
+};
+use chrono::{DateTime, Duration, Utc};
+use ipnet::IpNet;
 use rand::Rng;
+use serde::Serialize;
 use sqlx::PgPool;
+use tracing::{error, info};
 use uuid::Uuid;
 
-use crate::middleware::AuthUser;
+use crate::email_auth::is_secure;
+use crate::ip_whitelist::networks_from_env;
+use crate::middleware::{AuthUser, Authorized, SessionUser};
+use crate::permissions::{perm, Role};
+use crate::AppState;
+
+type ApiError = (StatusCode, &'static str);
 
 const SESSION_COOKIE: &str = "panopticon_session";
 const SESSION_MAX_AGE_DAYS: i64 = 30;
@@ -14,16 +33,114 @@ pub fn generate_session_id() -> String {
     hex::encode(&bytes)
 }
 
-pub async fn create_session(pool: &PgPool, user_id: uuid::Uuid) -> Result<String> {
+/// How a login proved a second factor, recorded on the session it creates.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SecondFactor {
+    /// A TOTP or recovery code.
+    Totp,
+    Passkey,
+}
+
+impl SecondFactor {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Totp => "totp",
+            Self::Passkey => "passkey",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "totp" => Some(Self::Totp),
+            "passkey" => Some(Self::Passkey),
+            _ => None,
+        }
+    }
+}
+
+/// Reverse proxies whose `X-Forwarded-For` is believed, from
+/// `TRUSTED_PROXIES` (comma-separated addresses or CIDRs). Panopticon only
+/// listens on loopback, so the default trusts a proxy on the same host.
+static TRUSTED_PROXIES: LazyLock<Vec<IpNet>> =
+    LazyLock::new(|| networks_from_env("TRUSTED_PROXIES", "127.0.0.0/8,::1/128"));
+
+/// Where a request came from, recorded on the sessions it creates.
+pub struct ClientInfo {
+    /// `None` when the address can't be determined or a forwarded address
+    /// doesn't parse.
+    pub ip: Option<IpAddr>,
+    pub user_agent: Option<String>,
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for ClientInfo {
+    type Rejection = std::convert::Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let header = |name: &str| {
+            parts
+                .headers
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(str::trim)
+                .filter(|v| !v.is_empty())
+        };
+
+        let peer = parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip());
+        let forwarded_for: Vec<&str> = parts
+            .headers
+            .get_all("x-forwarded-for")
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .collect();
+
+        Ok(Self {
+            ip: client_ip(peer, &forwarded_for.join(","), &TRUSTED_PROXIES),
+            user_agent: header("user-agent").map(|ua| ua.chars().take(512).collect()),
+        })
+    }
+}
+
+/// The client address of a request from `peer`. Only a trusted proxy's
+/// `X-Forwarded-For` is believed, and then only up to the first address
+/// that no trusted proxy added: anything further left is the client's own
+/// claim.
+fn client_ip(peer: Option<IpAddr>, forwarded_for: &str, trusted: &[IpNet]) -> Option<IpAddr> {
+    let is_trusted = |ip: &IpAddr| trusted.iter().any(|net| net.contains(ip));
+
+    let mut ip = peer?;
+    for entry in forwarded_for.rsplit(',').map(str::trim) {
+        if !is_trusted(&ip) || entry.is_empty() {
+            break;
+        }
+        ip = entry.parse().ok()?;
+    }
+    Some(ip)
+}
+
+pub async fn create_session(
+    pool: &PgPool,
+    user_id: uuid::Uuid,
+    client: &ClientInfo,
+    second_factor: Option<SecondFactor>,
+) -> Result<String> {
     let session_id = generate_session_id();
     let expires_at = Utc::now() + Duration::days(SESSION_MAX_AGE_DAYS);
 
-    sqlx::query("INSERT INTO sessions (id, user_id, expires_at) VALUES ($1, $2, $3)")
-        .bind(&session_id)
-        .bind(user_id)
-        .bind(expires_at)
-        .execute(pool)
-        .await?;
+    sqlx::query(
+        "INSERT INTO sessions (id, user_id, expires_at, ip, user_agent, second_factor, \
+         last_seen_at) VALUES ($1, $2, $3, $4, $5, $6, now())",
+    )
+    .bind(&session_id)
+    .bind(user_id)
+    .bind(expires_at)
+    .bind(client.ip.map(|ip| ip.to_string()))
+    .bind(&client.user_agent)
+    .bind(second_factor.map(SecondFactor::as_str))
+    .execute(pool)
+    .await?;
 
     Ok(session_id)
 }
@@ -58,22 +175,237 @@ pub fn extract_session_id_from_cookies(cookie_header: &str) -> Option<&str> {
 }
 
 pub(crate) async fn get_user_by_session(pool: &PgPool, session_id: &str) -> Option<AuthUser> {
-    let row: Option<(Uuid, String, bool, bool)> = sqlx::query_as(
-        "SELECT u.id, u.email, u.email_confirmed, u.is_approved \
+    let row: Option<(Uuid, String, bool, bool, String, Option<String>)> = sqlx::query_as(
+        "SELECT u.id, u.email, u.email_confirmed, u.is_approved, u.role, s.second_factor \
          FROM users u JOIN sessions s ON u.id = s.user_id \
-         WHERE s.id = $1 AND s.expires_at > now()",
+         WHERE s.id = $1 AND s.expires_at > now() AND u.suspended_at IS NULL",
     )
     .bind(session_id)
     .fetch_optional(pool)
     .await
     .ok()?;
 
-    row.map(|(id, email, email_confirmed, is_approved)| AuthUser {
-        id,
-        email,
-        email_confirmed,
-        is_approved,
-    })
+    if row.is_some() {
+        // Coarse granularity keeps this from writing on every request.
+        if let Err(e) = sqlx::query(
+            "UPDATE sessions SET last_seen_at = now() WHERE id = $1 \
+             AND (last_seen_at IS NULL OR last_seen_at < now() - interval '1 minute')",
+        )
+        .bind(session_id)
+        .execute(pool)
+        .await
+        {
+            error!("Failed to update session last activity: {e}");
+        }
+    }
+
+    row.map(
+        |(id, email, email_confirmed, is_approved, role, second_factor)| AuthUser {
+            id,
+            email,
+            email_confirmed,
+            is_approved,
+            role: Role::parse(&role).unwrap_or(Role::Viewer),
+            token_scopes: None,
+            second_factor: second_factor.as_deref().and_then(SecondFactor::parse),
+        },
+    )
+}
+
+// ── Session management ──────────────────────────────────────────────────────
+
+/// Routes for a user's own sessions, nested under `/api/auth`.
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/sessions", get(list_own).delete(revoke_all_own))
+        .route("/sessions/{id}", delete(revoke_own))
+}
+
+/// Admin routes for other users' sessions, nested under `/api`.
+pub fn admin_router() -> Router<AppState> {
+    Router::new()
+        .route(
+            "/admin/users/{id}/sessions",
+            get(list_for_user).delete(revoke_all_for_user),
+        )
+        .route(
+            "/admin/users/{id}/sessions/{session_id}",
+            delete(revoke_for_user),
+        )
+}
+
+#[derive(Serialize)]
+struct SessionInfo {
+    id: Uuid,
+    created_at: String,
+    last_seen_at: Option<String>,
+    ip: Option<String>,
+    user_agent: Option<String>,
+    /// Whether this is the session making the request.
+    current: bool,
+}
+
+type SessionRow = (
+    String,
+    Uuid,
+    DateTime<Utc>,
+    Option<DateTime<Utc>>,
+    Option<String>,
+    Option<String>,
+);
+
+pub(crate) fn current_session_id(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get("cookie")
+        .and_then(|v| v.to_str().ok())
+        .and_then(extract_session_id_from_cookies)
+}
+
+async fn load_sessions(
+    pool: &PgPool,
+    user_id: Uuid,
+    current: Option<&str>,
+) -> Result<Json<Vec<SessionInfo>>, ApiError> {
+    let rows: Vec<SessionRow> = sqlx::query_as(
+        "SELECT id, public_id, created_at, last_seen_at, ip, user_agent FROM sessions \
+         WHERE user_id = $1 AND expires_at > now() \
+         ORDER BY COALESCE(last_seen_at, created_at) DESC",
+    )
+    .bind(user_id)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| {
+        error!("Failed to list sessions: {e}");
+        (StatusCode::INTERNAL_SERVER_ERROR, "Failed to list sessions")
+    })?;
+
+    let sessions = rows
+        .into_iter()
+        .map(
+            |(id, public_id, created_at, last_seen_at, ip, user_agent)| SessionInfo {
+                id: public_id,
+                created_at: created_at.to_rfc3339(),
+                last_seen_at: last_seen_at.map(|t| t.to_rfc3339()),
+                ip,
+                user_agent,
+                current: current == Some(id.as_str()),
+            },
+        )
+        .collect();
+
+    Ok(Json(sessions))
+}
+
+async fn revoke_one(pool: &PgPool, user_id: Uuid, public_id: Uuid) -> Result<StatusCode, ApiError> {
+    let result = sqlx::query("DELETE FROM sessions WHERE public_id = $1 AND user_id = $2")
+        .bind(public_id)
+        .bind(user_id)
+        .execute(pool)
+        .await
+        .map_err(|e| {
+            error!("Failed to revoke session: {e}");
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to revoke session",
+            )
+        })?;
+
+    if result.rows_affected() == 0 {
+        return Err((StatusCode::NOT_FOUND, "Session not found"));
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Delete every session belonging to a user.
+pub async fn delete_user_sessions(pool: &PgPool, user_id: Uuid) -> Result<u64> {
+    let result = sqlx::query("DELETE FROM sessions WHERE user_id = $1")
+        .bind(user_id)
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected())
+}
+
+/// Delete every session of a user except `keep`.
+pub async fn delete_other_sessions(pool: &PgPool, user_id: Uuid, keep: &str) -> Result<u64> {
+    let result = sqlx::query("DELETE FROM sessions WHERE user_id = $1 AND id <> $2")
+        .bind(user_id)
+        .bind(keep)
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected())
+}
+
+async fn list_own(
+    user: SessionUser,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<Vec<SessionInfo>>, ApiError> {
+    load_sessions(&state.db, user.id, current_session_id(&headers)).await
+}
+
+async fn revoke_own(
+    user: SessionUser,
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Result<StatusCode, ApiError> {
+    revoke_one(&state.db, user.id, id).await
+}
+
+/// Log out everywhere, including this browser.
+async fn revoke_all_own(user: SessionUser, State(state): State<AppState>) -> Response {
+    match delete_user_sessions(&state.db, user.id).await {
+        Ok(count) => {
+            info!(email = %user.email, count, "Logged out everywhere");
+            let mut response = StatusCode::NO_CONTENT.into_response();
+            response.headers_mut().insert(
+                "set-cookie",
+                clear_session_cookie(is_secure()).parse().unwrap(),
+            );
+            response
+        }
+        Err(e) => {
+            error!("Failed to revoke sessions: {e}");
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({"error": "Failed to revoke sessions"})),
+            )
+                .into_response()
+        }
+    }
+}
+
+async fn list_for_user(
+    _user: Authorized<perm::ManageUsers>,
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<Vec<SessionInfo>>, ApiError> {
+    load_sessions(&state.db, id, None).await
+}
+
+async fn revoke_for_user(
+    user: Authorized<perm::ManageUsers>,
+    State(state): State<AppState>,
+    Path((id, session_id)): Path<(Uuid, Uuid)>,
+) -> Result<StatusCode, ApiError> {
+    let status = revoke_one(&state.db, id, session_id).await?;
+    info!(admin = %user.email, user_id = %id, session = %session_id, "Session revoked by admin");
+    Ok(status)
+}
+
+async fn revoke_all_for_user(
+    user: Authorized<perm::ManageUsers>,
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Result<StatusCode, ApiError> {
+    let count = delete_user_sessions(&state.db, id).await.map_err(|e| {
+        error!("Failed to revoke sessions: {e}");
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Failed to revoke sessions",
+        )
+    })?;
+    info!(admin = %user.email, user_id = %id, count, "All sessions revoked by admin");
+    Ok(StatusCode::NO_CONTENT)
 }
 
 /// Encode bytes as hex (avoids adding a hex crate dependency).
@@ -82,3 +414,43 @@ mod hex {
         bytes.iter().map(|b| format!("{b:02x}")).collect()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn believes_forwarded_for_only_from_trusted_proxies() {
+        let trusted: Vec<IpNet> = vec![
+            "127.0.0.0/8".parse().unwrap(),
+            "10.0.0.0/24".parse().unwrap(),
+        ];
+        let ip = |s: &str| Some(s.parse::<IpAddr>().unwrap());
+        let loopback = ip("127.0.0.1");
+
+        // Direct connections can't claim another address.
+        assert_eq!(
+            client_ip(ip("203.0.113.9"), "198.51.100.1", &trusted),
+            ip("203.0.113.9")
+        );
+        // The proxy's own entry wins over anything the client sent before it.
+        assert_eq!(
+            client_ip(loopback, "198.51.100.1", &trusted),
+            ip("198.51.100.1")
+        );
+        assert_eq!(
+            client_ip(loopback, "6.6.6.6, 198.51.100.1", &trusted),
+            ip("198.51.100.1")
+        );
+        // Chains of trusted proxies are followed.
+        assert_eq!(
+            client_ip(loopback, "6.6.6.6, 198.51.100.1, 10.0.0.5", &trusted),
+            ip("198.51.100.1")
+        );
+        // No header: the proxy is all we know.
+        assert_eq!(client_ip(loopback, "", &trusted), loopback);
+        // Markup or garbage where an address should be is dropped.
+        assert_eq!(client_ip(loopback, "<a href=x>hi</a>", &trusted), None);
+        assert_eq!(client_ip(None, "198.51.100.1", &trusted), None);
+    }
+}
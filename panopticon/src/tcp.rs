@@ -245,7 +245,7 @@ async fn handle_connection(
                     continue;
                 }
 
-                match process_scan(&state, tag_id).await {
+                match process_scan(&state, tag_id, Some(sentinel_id)).await {
                     Ok(action) => {
                         info!(%addr, tag_id, action, "Scan processed via TCP");
                         let response = format!("RESULT: {action}\n");
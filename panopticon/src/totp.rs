@@ -0,0 +1,766 @@
+//! TOTP two-factor authentication (RFC 6238).
+//!
+//! Users enrol by requesting a secret (returned with an `otpauth://` URI for
+//! authenticator apps) and confirming it with a first code, which also
+//! issues single-use recovery codes. Once enabled, `email_auth::login` stops
+//! after the password check and returns a short-lived challenge; the session
+//! is only created by `POST /api/auth/login/totp` with a valid code.
+//!
+//! Admins can set `require_2fa_for_unlock` in `system_config`, after which
+//! remote unlocks are refused unless the session was signed in with a second
+//! factor (a TOTP code or a passkey).
+
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use chrono::{Duration, Utc};
+use hmac::{Hmac, Mac};
+use rand::Rng;
+use serde::Deserialize;
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+use crate::email_auth::{generate_token, json_error, start_session, LoginUser};
+use crate::login_throttle::{self, Bucket};
+use crate::middleware::{AuthUser, Authorized, SessionUser};
+use crate::permissions::{perm, Permission};
+use crate::session::{ClientInfo, SecondFactor};
+use crate::AppState;
+
+const ISSUER: &str = "Panopticon";
+const STEP_SECS: u64 = 30;
+const DIGITS: u32 = 6;
+const SECRET_LEN: usize = 20;
+const RECOVERY_CODE_COUNT: usize = 10;
+const CHALLENGE_TTL_MINUTES: i64 = 5;
+const MAX_CHALLENGE_ATTEMPTS: i32 = 5;
+const POLICY_KEY: &str = "require_2fa_for_unlock";
+
+// ── Algorithm ───────────────────────────────────────────────────────────────
+
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+/// RFC 4648 base32 without padding, as used in `otpauth://` URIs.
+fn base32_encode(data: &[u8]) -> String {
+    let mut out = String::with_capacity(data.len().div_ceil(5) * 8);
+    let mut buffer: u64 = 0;
+    let mut bits = 0;
+    for &byte in data {
+        buffer = (buffer << 8) | u64::from(byte);
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            out.push(BASE32_ALPHABET[((buffer >> bits) & 0x1f) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        out.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 0x1f) as usize] as char);
+    }
+    out
+}
+
+fn base32_decode(s: &str) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(s.len() * 5 / 8);
+    let mut buffer: u64 = 0;
+    let mut bits = 0;
+    for c in s.bytes().filter(|&c| c != b'=') {
+        let value = BASE32_ALPHABET
+            .iter()
+            .position(|&a| a == c.to_ascii_uppercase())? as u64;
+        buffer = (buffer << 5) | value;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((buffer >> bits) as u8);
+        }
+    }
+    Some(out)
+}
+
+/// HOTP (RFC 4226) with HMAC-SHA1.
+fn hotp(secret: &[u8], counter: u64, digits: u32) -> u32 {
+    let mut mac = Hmac::<Sha1>::new_from_slice(secret).expect("HMAC accepts any key length");
+    mac.update(&counter.to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+
+    let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+    let binary = u32::from_be_bytes([
+        hash[offset] & 0x7f,
+        hash[offset + 1],
+        hash[offset + 2],
+        hash[offset + 3],
+    ]);
+    binary % 10u32.pow(digits)
+}
+
+/// Check a 6-digit code against the current time step and one step either
+/// side. Steps at or before `last_step` are rejected so a code can't be
+/// replayed. Returns the matching step.
+fn verify_code(secret: &[u8], code: &str, unix_secs: u64, last_step: Option<i64>) -> Option<u64> {
+    let code = code.trim();
+    if code.len() != DIGITS as usize || !code.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let code: u32 = code.parse().ok()?;
+
+    let current = unix_secs / STEP_SECS;
+    [current.saturating_sub(1), current, current + 1]
+        .into_iter()
+        .filter(|&step| last_step.is_none_or(|last| step as i64 > last))
+        .find(|&step| hotp(secret, step, DIGITS) == code)
+}
+
+fn otpauth_uri(email: &str, secret: &str) -> String {
+    let label = urlencoding::encode(&format!("{ISSUER}:{email}")).into_owned();
+    format!(
+        "otpauth://totp/{label}?secret={secret}&issuer={ISSUER}&algorithm=SHA1&digits={DIGITS}&period={STEP_SECS}"
+    )
+}
+
+fn unix_now() -> u64 {
+    Utc::now().timestamp().max(0) as u64
+}
+
+// ── Recovery codes ──────────────────────────────────────────────────────────
+
+fn generate_recovery_code() -> String {
+    let mut rng = rand::thread_rng();
+    let chars: String = (0..10)
+        .map(|_| BASE32_ALPHABET[rng.gen_range(0..32)].to_ascii_lowercase() as char)
+        .collect();
+    format!("{}-{}", &chars[..5], &chars[5..])
+}
+
+fn hash_recovery_code(code: &str) -> String {
+    let normalized: String = code
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+    format!("{:x}", Sha256::digest(normalized.as_bytes()))
+}
+
+/// Replace a user's recovery codes, returning the new plaintext codes.
+async fn replace_recovery_codes(db: &PgPool, user_id: Uuid) -> Result<Vec<String>, sqlx::Error> {
+    let codes: Vec<String> = (0..RECOVERY_CODE_COUNT)
+        .map(|_| generate_recovery_code())
+        .collect();
+    let hashes: Vec<String> = codes.iter().map(|c| hash_recovery_code(c)).collect();
+
+    let mut tx = db.begin().await?;
+    sqlx::query("DELETE FROM totp_recovery_codes WHERE user_id = $1")
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query(
+        "INSERT INTO totp_recovery_codes (user_id, code_hash) SELECT $1, UNNEST($2::TEXT[])",
+    )
+    .bind(user_id)
+    .bind(&hashes)
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+
+    Ok(codes)
+}
+
+// ── Persistence ─────────────────────────────────────────────────────────────
+
+pub async fn is_enabled(db: &PgPool, user_id: Uuid) -> Result<bool, sqlx::Error> {
+    let enabled: Option<bool> =
+        sqlx::query_scalar("SELECT enabled FROM user_totp WHERE user_id = $1")
+            .bind(user_id)
+            .fetch_optional(db)
+            .await?;
+    Ok(enabled.unwrap_or(false))
+}
+
+/// Verify a TOTP code (or, failing that, an unused recovery code) for a user
+/// with 2FA enabled, consuming it.
+async fn verify_for_user(db: &PgPool, user_id: Uuid, code: &str) -> Result<bool, sqlx::Error> {
+    let row: Option<(String, Option<i64>)> = sqlx::query_as(
+        "SELECT secret, last_used_step FROM user_totp WHERE user_id = $1 AND enabled = TRUE",
+    )
+    .bind(user_id)
+    .fetch_optional(db)
+    .await?;
+
+    let Some((secret, last_step)) = row else {
+        return Ok(false);
+    };
+    let Some(secret) = base32_decode(&secret) else {
+        error!(%user_id, "Stored TOTP secret is not valid base32");
+        return Ok(false);
+    };
+
+    if let Some(step) = verify_code(&secret, code, unix_now(), last_step) {
+        // Guard against two concurrent logins using the same code.
+        let updated = sqlx::query(
+            "UPDATE user_totp SET last_used_step = $2 \
+             WHERE user_id = $1 AND (last_used_step IS NULL OR last_used_step < $2)",
+        )
+        .bind(user_id)
+        .bind(step as i64)
+        .execute(db)
+        .await?;
+        return Ok(updated.rows_affected() == 1);
+    }
+
+    let used: Option<Uuid> = sqlx::query_scalar(
+        "UPDATE totp_recovery_codes SET used_at = now() \
+         WHERE user_id = $1 AND code_hash = $2 AND used_at IS NULL RETURNING id",
+    )
+    .bind(user_id)
+    .bind(hash_recovery_code(code))
+    .fetch_optional(db)
+    .await?;
+
+    if used.is_some() {
+        info!(%user_id, "Recovery code used");
+    }
+    Ok(used.is_some())
+}
+
+// ── Policy ──────────────────────────────────────────────────────────────────
+
+pub async fn required_for_unlock(db: &PgPool) -> bool {
+    let value: Option<String> =
+        sqlx::query_scalar("SELECT value FROM system_config WHERE key = $1")
+            .bind(POLICY_KEY)
+            .fetch_optional(db)
+            .await
+            .unwrap_or_else(|e| {
+                error!("Failed to read 2FA policy: {e}");
+                // Fail closed.
+                Some("true".into())
+            });
+    value.as_deref() == Some("true")
+}
+
+/// Whether the 2FA policy lets this user unlock remotely. A browser session
+/// must itself have been completed with a second factor, so one from before
+/// enrolment or from single sign-on doesn't count. API tokens, which have no
+/// login of their own, need 2FA enabled on the account.
+pub async fn unlock_allowed(db: &PgPool, user: &AuthUser) -> bool {
+    if user.token_scopes.is_none() {
+        return user.second_factor.is_some() || !required_for_unlock(db).await;
+    }
+    account_unlock_allowed(db, user.id).await
+}
+
+/// Whether the 2FA policy lets an account act without a session (an API
+/// token or the MQTT command user) unlock remotely: it must have 2FA enabled.
+pub async fn account_unlock_allowed(db: &PgPool, user_id: Uuid) -> bool {
+    if !required_for_unlock(db).await {
+        return true;
+    }
+    is_enabled(db, user_id).await.unwrap_or(false)
+}
+
+// ── Login step ──────────────────────────────────────────────────────────────
+
+/// Called by `email_auth::login` after the password check for users with 2FA
+/// enabled: returns a challenge to complete with `POST /login/totp` instead
+/// of a session.
+pub async fn start_challenge(state: &AppState, user_id: Uuid) -> Response {
+    let challenge = generate_token();
+    let expires_at = Utc::now() + Duration::minutes(CHALLENGE_TTL_MINUTES);
+
+    if let Err(e) =
+        sqlx::query("INSERT INTO login_challenges (id, user_id, expires_at) VALUES ($1, $2, $3)")
+            .bind(&challenge)
+            .bind(user_id)
+            .bind(expires_at)
+            .execute(&state.db)
+            .await
+    {
+        error!("Failed to store login challenge: {e}");
+        return json_error(StatusCode::INTERNAL_SERVER_ERROR, "Login failed");
+    }
+
+    Json(serde_json::json!({
+        "two_factor_required": true,
+        "challenge": challenge,
+    }))
+    .into_response()
+}
+
+#[derive(Deserialize)]
+struct LoginTotpRequest {
+    challenge: String,
+    code: String,
+}
+
+async fn login_totp(
+    State(state): State<AppState>,
+    client: ClientInfo,
+    Json(body): Json<LoginTotpRequest>,
+) -> Response {
+    let row: Option<(Uuid, i32)> = match sqlx::query_as(
+        "UPDATE login_challenges SET attempts = attempts + 1 \
+         WHERE id = $1 AND expires_at > now() RETURNING user_id, attempts",
+    )
+    .bind(&body.challenge)
+    .fetch_optional(&state.db)
+    .await
+    {
+        Ok(row) => row,
+        Err(e) => {
+            error!("Failed to load login challenge: {e}");
+            return json_error(StatusCode::INTERNAL_SERVER_ERROR, "Login failed");
+        }
+    };
+
+    let Some((user_id, attempts)) = row else {
+        return json_error(
+            StatusCode::UNAUTHORIZED,
+            "Login expired, please sign in again",
+        );
+    };
+
+    let user: Option<(String, bool, bool, String)> = match sqlx::query_as(
+        "SELECT email, email_confirmed, is_approved, role FROM users WHERE id = $1",
+    )
+    .bind(user_id)
+    .fetch_optional(&state.db)
+    .await
+    {
+        Ok(row) => row,
+        Err(e) => {
+            error!("Database error during login: {e}");
+            return json_error(StatusCode::INTERNAL_SERVER_ERROR, "Login failed");
+        }
+    };
+
+    let Some((email, email_confirmed, is_approved, role)) = user else {
+        return json_error(
+            StatusCode::UNAUTHORIZED,
+            "Login expired, please sign in again",
+        );
+    };
+
+    // Wrong codes count as failed logins, so the password step's lockout
+    // also covers guessing codes across challenges.
+    let ip = client.ip.map(|ip| ip.to_string());
+    let mut throttle_keys = vec![(Bucket::LoginAccount, email.as_str())];
+    if let Some(ip) = ip.as_deref() {
+        throttle_keys.push((Bucket::LoginIp, ip));
+    }
+    let attempt = match login_throttle::reserve(&state.db, &throttle_keys).await {
+        Ok(attempts) => attempts[0],
+        Err(refused) => {
+            let _ = delete_challenge(&state.db, &body.challenge).await;
+            return refused.into_response();
+        }
+    };
+
+    if attempts > MAX_CHALLENGE_ATTEMPTS {
+        let _ = delete_challenge(&state.db, &body.challenge).await;
+        warn!(%user_id, "Too many two-factor attempts");
+        return json_error(
+            StatusCode::UNAUTHORIZED,
+            "Too many attempts, please sign in again",
+        );
+    }
+
+    match verify_for_user(&state.db, user_id, &body.code).await {
+        Ok(true) => {}
+        Ok(false) => {
+            login_throttle::login_failed(&state, &email, attempt, client.ip).await;
+            return json_error(StatusCode::UNAUTHORIZED, "Invalid code");
+        }
+        Err(e) => {
+            error!("Failed to verify two-factor code: {e}");
+            return json_error(StatusCode::INTERNAL_SERVER_ERROR, "Login failed");
+        }
+    }
+
+    let _ = delete_challenge(&state.db, &body.challenge).await;
+    if let Err(e) = login_throttle::clear(&state.db, Bucket::LoginAccount, &email).await {
+        error!("Failed to reset login throttle: {e}");
+    }
+    if let Some(ip) = ip.as_deref() {
+        login_throttle::release(&state.db, Bucket::LoginIp, ip).await;
+    }
+
+    info!(email = %email, "User logged in with two-factor code");
+
+    let user = LoginUser {
+        id: user_id,
+        email: &email,
+        email_confirmed,
+        is_approved,
+        role: &role,
+    };
+    start_session(&state, &client, &user, Some(SecondFactor::Totp)).await
+}
+
+async fn delete_challenge(db: &PgPool, challenge: &str) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM login_challenges WHERE id = $1 OR expires_at < now()")
+        .bind(challenge)
+        .execute(db)
+        .await?;
+    Ok(())
+}
+
+// ── Enrolment API ───────────────────────────────────────────────────────────
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/totp", get(status))
+        .route("/totp/enroll", post(enroll))
+        .route("/totp/confirm", post(confirm))
+        .route("/totp/disable", post(disable))
+        .route("/totp/recovery-codes", post(regenerate_recovery_codes))
+        .route("/login/totp", post(login_totp))
+}
+
+async fn status(user: SessionUser, State(state): State<AppState>) -> Response {
+    let enabled = match is_enabled(&state.db, user.id).await {
+        Ok(e) => e,
+        Err(e) => {
+            error!("Failed to load two-factor status: {e}");
+            return json_error(StatusCode::INTERNAL_SERVER_ERROR, "Database error");
+        }
+    };
+
+    let remaining: i64 = sqlx::query_scalar(
+        "SELECT COUNT(*) FROM totp_recovery_codes WHERE user_id = $1 AND used_at IS NULL",
+    )
+    .bind(user.id)
+    .fetch_one(&state.db)
+    .await
+    .unwrap_or(0);
+
+    let required = user.role.has(Permission::OperateLocks) && required_for_unlock(&state.db).await;
+
+    Json(serde_json::json!({
+        "enabled": enabled,
+        "recovery_codes_remaining": remaining,
+        "required_for_unlock": required,
+    }))
+    .into_response()
+}
+
+async fn enroll(user: SessionUser, State(state): State<AppState>) -> Response {
+    match is_enabled(&state.db, user.id).await {
+        Ok(false) => {}
+        Ok(true) => {
+            return json_error(
+                StatusCode::CONFLICT,
+                "Two-factor authentication is already enabled",
+            )
+        }
+        Err(e) => {
+            error!("Failed to load two-factor status: {e}");
+            return json_error(StatusCode::INTERNAL_SERVER_ERROR, "Database error");
+        }
+    }
+
+    let secret_bytes: [u8; SECRET_LEN] = rand::thread_rng().r#gen();
+    let secret = base32_encode(&secret_bytes);
+
+    if let Err(e) = sqlx::query(
+        "INSERT INTO user_totp (user_id, secret) VALUES ($1, $2) \
+         ON CONFLICT (user_id) DO UPDATE SET secret = $2, last_used_step = NULL, created_at = now()",
+    )
+    .bind(user.id)
+    .bind(&secret)
+    .execute(&state.db)
+    .await
+    {
+        error!("Failed to store TOTP secret: {e}");
+        return json_error(StatusCode::INTERNAL_SERVER_ERROR, "Database error");
+    }
+
+    Json(serde_json::json!({
+        "secret": secret,
+        "otpauth_uri": otpauth_uri(&user.email, &secret),
+    }))
+    .into_response()
+}
+
+#[derive(Deserialize)]
+struct CodeRequest {
+    code: String,
+}
+
+async fn confirm(
+    user: SessionUser,
+    State(state): State<AppState>,
+    Json(body): Json<CodeRequest>,
+) -> Response {
+    let secret: Option<String> = match sqlx::query_scalar(
+        "SELECT secret FROM user_totp WHERE user_id = $1 AND enabled = FALSE",
+    )
+    .bind(user.id)
+    .fetch_optional(&state.db)
+    .await
+    {
+        Ok(s) => s,
+        Err(e) => {
+            error!("Failed to load TOTP secret: {e}");
+            return json_error(StatusCode::INTERNAL_SERVER_ERROR, "Database error");
+        }
+    };
+
+    let Some(secret) = secret.as_deref().and_then(base32_decode) else {
+        return json_error(
+            StatusCode::BAD_REQUEST,
+            "No two-factor enrolment in progress",
+        );
+    };
+
+    let Some(step) = verify_code(&secret, &body.code, unix_now(), None) else {
+        return json_error(StatusCode::BAD_REQUEST, "Invalid code");
+    };
+
+    if let Err(e) = sqlx::query(
+        "UPDATE user_totp SET enabled = TRUE, enabled_at = now(), last_used_step = $2 \
+         WHERE user_id = $1",
+    )
+    .bind(user.id)
+    .bind(step as i64)
+    .execute(&state.db)
+    .await
+    {
+        error!("Failed to enable TOTP: {e}");
+        return json_error(StatusCode::INTERNAL_SERVER_ERROR, "Database error");
+    }
+
+    let codes = match replace_recovery_codes(&state.db, user.id).await {
+        Ok(c) => c,
+        Err(e) => {
+            error!("Failed to create recovery codes: {e}");
+            return json_error(StatusCode::INTERNAL_SERVER_ERROR, "Database error");
+        }
+    };
+
+    info!(email = %user.email, "Two-factor authentication enabled");
+
+    Json(serde_json::json!({ "recovery_codes": codes })).into_response()
+}
+
+/// Check the code a signed-in user gives to change their two-factor
+/// settings. Wrong codes count against the account's login lockout, so a
+/// stolen session can't be used to guess a code and turn 2FA off.
+pub(crate) async fn verify_settings_code(
+    state: &AppState,
+    client: &ClientInfo,
+    user: &SessionUser,
+    code: &str,
+) -> Result<(), Response> {
+    let key = [(Bucket::LoginAccount, user.email.as_str())];
+    let attempt = match login_throttle::reserve(&state.db, &key).await {
+        Ok(attempts) => attempts[0],
+        Err(refused) => return Err(refused.into_response()),
+    };
+
+    match verify_for_user(&state.db, user.id, code).await {
+        Ok(true) => {
+            login_throttle::release(&state.db, Bucket::LoginAccount, &user.email).await;
+            Ok(())
+        }
+        Ok(false) => {
+            login_throttle::login_failed(state, &user.email, attempt, client.ip).await;
+            Err(json_error(StatusCode::BAD_REQUEST, "Invalid code"))
+        }
+        Err(e) => {
+            error!("Failed to verify two-factor code: {e}");
+            Err(json_error(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Database error",
+            ))
+        }
+    }
+}
+
+async fn disable(
+    user: SessionUser,
+    State(state): State<AppState>,
+    client: ClientInfo,
+    Json(body): Json<CodeRequest>,
+) -> Response {
+    if let Err(response) = verify_settings_code(&state, &client, &user, &body.code).await {
+        return response;
+    }
+
+    let result = async {
+        sqlx::query("DELETE FROM totp_recovery_codes WHERE user_id = $1")
+            .bind(user.id)
+            .execute(&state.db)
+            .await?;
+        sqlx::query("DELETE FROM user_totp WHERE user_id = $1")
+            .bind(user.id)
+            .execute(&state.db)
+            .await
+    }
+    .await;
+
+    if let Err(e) = result {
+        error!("Failed to disable TOTP: {e}");
+        return json_error(StatusCode::INTERNAL_SERVER_ERROR, "Database error");
+    }
+
+    info!(email = %user.email, "Two-factor authentication disabled");
+
+    StatusCode::NO_CONTENT.into_response()
+}
+
+async fn regenerate_recovery_codes(
+    user: SessionUser,
+    State(state): State<AppState>,
+    client: ClientInfo,
+    Json(body): Json<CodeRequest>,
+) -> Response {
+    if let Err(response) = verify_settings_code(&state, &client, &user, &body.code).await {
+        return response;
+    }
+
+    match replace_recovery_codes(&state.db, user.id).await {
+        Ok(codes) => Json(serde_json::json!({ "recovery_codes": codes })).into_response(),
+        Err(e) => {
+            error!("Failed to create recovery codes: {e}");
+            json_error(StatusCode::INTERNAL_SERVER_ERROR, "Database error")
+        }
+    }
+}
+
+// ── Admin policy ────────────────────────────────────────────────────────────
+
+pub fn policy_router() -> Router<AppState> {
+    Router::new().route("/admin/security-policy", get(get_policy).put(set_policy))
+}
+
+async fn get_policy(
+    _user: Authorized<perm::ManageUsers>,
+    State(state): State<AppState>,
+) -> Response {
+    Json(serde_json::json!({
+        "require_2fa_for_unlock": required_for_unlock(&state.db).await,
+    }))
+    .into_response()
+}
+
+#[derive(Deserialize)]
+struct PolicyRequest {
+    require_2fa_for_unlock: bool,
+}
+
+/// Turning the policy off needs a session that was completed with a second
+/// factor, so a stolen admin session can't weaken it either.
+async fn set_policy(
+    user: Authorized<perm::ManageSecurity>,
+    State(state): State<AppState>,
+    Json(body): Json<PolicyRequest>,
+) -> Response {
+    if !body.require_2fa_for_unlock && user.second_factor.is_none() {
+        return json_error(
+            StatusCode::FORBIDDEN,
+            "Sign in with a second factor to turn this policy off",
+        );
+    }
+
+    if let Err(e) = sqlx::query(
+        "INSERT INTO system_config (key, value) VALUES ($1, $2) \
+         ON CONFLICT (key) DO UPDATE SET value = $2",
+    )
+    .bind(POLICY_KEY)
+    .bind(body.require_2fa_for_unlock.to_string())
+    .execute(&state.db)
+    .await
+    {
+        error!("Failed to update 2FA policy: {e}");
+        return json_error(StatusCode::INTERNAL_SERVER_ERROR, "Database error");
+    }
+
+    info!(
+        by = %user.email,
+        require_2fa_for_unlock = body.require_2fa_for_unlock,
+        "Security policy updated"
+    );
+
+    Json(serde_json::json!({
+        "require_2fa_for_unlock": body.require_2fa_for_unlock,
+    }))
+    .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RFC_SECRET: &[u8] = b"12345678901234567890";
+
+    #[test]
+    fn hotp_rfc4226_vectors() {
+        let expected = [
+            755224, 287082, 359152, 969429, 338314, 254676, 287922, 162583, 399871, 520489,
+        ];
+        for (counter, code) in expected.into_iter().enumerate() {
+            assert_eq!(hotp(RFC_SECRET, counter as u64, 6), code);
+        }
+    }
+
+    #[test]
+    fn totp_rfc6238_sha1_vectors() {
+        let vectors = [
+            (59, 94287082),
+            (1111111109, 7081804),
+            (1111111111, 14050471),
+            (1234567890, 89005924),
+            (2000000000, 69279037),
+            (20000000000, 65353130),
+        ];
+        for (time, code) in vectors {
+            assert_eq!(hotp(RFC_SECRET, time / STEP_SECS, 8), code, "t={time}");
+        }
+    }
+
+    #[test]
+    fn base32_round_trip() {
+        // RFC 4648 test vector, without padding.
+        assert_eq!(base32_encode(b"foobar"), "MZXW6YTBOI");
+        assert_eq!(base32_decode("MZXW6YTBOI").unwrap(), b"foobar");
+        assert_eq!(base32_decode("mzxw6ytboi======").unwrap(), b"foobar");
+        assert!(base32_decode("not base32!").is_none());
+        let secret: [u8; SECRET_LEN] = rand::thread_rng().r#gen();
+        assert_eq!(base32_decode(&base32_encode(&secret)).unwrap(), secret);
+    }
+
+    #[test]
+    fn verify_accepts_adjacent_steps_and_rejects_replay() {
+        let now = 1_111_111_111;
+        let step = now / STEP_SECS;
+        let code = |s: u64| format!("{:06}", hotp(RFC_SECRET, s, DIGITS));
+
+        assert_eq!(verify_code(RFC_SECRET, &code(step), now, None), Some(step));
+        assert_eq!(
+            verify_code(RFC_SECRET, &code(step - 1), now, None),
+            Some(step - 1)
+        );
+        assert_eq!(verify_code(RFC_SECRET, &code(step - 2), now, None), None);
+        assert_eq!(
+            verify_code(RFC_SECRET, &code(step), now, Some(step as i64)),
+            None
+        );
+        assert_eq!(verify_code(RFC_SECRET, "12345", now, None), None);
+    }
+
+    #[test]
+    fn recovery_code_hash_ignores_formatting() {
+        let code = generate_recovery_code();
+        assert_eq!(code.len(), 11);
+        assert_eq!(
+            hash_recovery_code(&code),
+            hash_recovery_code(&code.replace('-', "").to_uppercase())
+        );
+    }
+}
@@ -123,6 +123,25 @@ impl Device {
     pub fn is_lock(&self) -> bool {
         matches!(self.category.as_deref(), Some("LOCK" | "SmartLock"))
     }
+
+    /// Normalize a raw battery level to 0-100% using the device's
+    /// `batteryLevelRange` from discovery (e.g. min=1, max=5).
+    pub fn battery_percent(&self, raw: u64) -> u64 {
+        let (min, max) = self
+            .attributes
+            .as_ref()
+            .and_then(|a| a.get("batteryLevelRange"))
+            .map(|r| {
+                let min = r.get("min").and_then(|v| v.as_u64()).unwrap_or(0);
+                let max = r.get("max").and_then(|v| v.as_u64()).unwrap_or(100);
+                (min, max)
+            })
+            .unwrap_or((0, 100));
+        if max <= min {
+            return raw;
+        }
+        ((raw.saturating_sub(min)) * 100) / (max - min)
+    }
 }
 
 /// Basic device info from discovery.
@@ -201,10 +220,15 @@ impl DeviceWithStates {
 
     /// Check if the device is online (has `st.healthCheck/status == "Online"`).
     pub fn is_online(&self) -> bool {
+        self.online().unwrap_or(false)
+    }
+
+    /// Get the reported connectivity, or `None` if the payload carries no
+    /// `st.healthCheck` state (e.g. a webhook that only reports a lock change).
+    pub fn online(&self) -> Option<bool> {
         self.get_state("st.healthCheck", "status")
             .and_then(|s| s.value.as_str())
             .map(|s| s.eq_ignore_ascii_case("online"))
-            .unwrap_or(false)
     }
 
     /// Get the lock state if this is a lock device.
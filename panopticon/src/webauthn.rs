@@ -0,0 +1,954 @@
+//! WebAuthn / passkey sign-in.
+//!
+//! A minimal relying party for platform passkeys: ES256 and RS256
+//! credentials, `none` attestation (the attestation statement is not
+//! checked), and user verification required. Registration needs an
+//! existing session that proved a second factor, or a step-up, and the
+//! owner is emailed when a passkey is added; authentication uses discoverable credentials, so the
+//! user doesn't type an email, and ends in `email_auth::start_session` like
+//! a password login. A passkey with user verification counts as two factors,
+//! so TOTP is not asked for.
+//!
+//! The relying party ID and origin come from `BASE_URL` (override the ID
+//! with `WEBAUTHN_RP_ID`, e.g. to share passkeys across subdomains).
+
+use std::sync::LazyLock;
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::{patch, post},
+    Json, Router,
+};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::{DateTime, Duration, Utc};
+use ciborium::value::Value;
+use openssl::bn::BigNum;
+use openssl::ec::{EcGroup, EcKey};
+use openssl::hash::MessageDigest;
+use openssl::nid::Nid;
+use openssl::pkey::PKey;
+use openssl::rsa::Rsa;
+use openssl::sign::Verifier;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+use crate::email_auth::{confirm_password, generate_token, json_error, start_session, LoginUser};
+use crate::middleware::SessionUser;
+use crate::session::{ClientInfo, SecondFactor};
+use crate::{totp, AppState};
+
+const RP_NAME: &str = "Panopticon";
+const CHALLENGE_TTL_MINUTES: i64 = 5;
+const TIMEOUT_MS: i64 = 60_000;
+
+/// COSE algorithm identifiers.
+const ES256: i64 = -7;
+const RS256: i64 = -257;
+
+/// Authenticator data flags.
+const FLAG_USER_PRESENT: u8 = 0x01;
+const FLAG_USER_VERIFIED: u8 = 0x04;
+const FLAG_ATTESTED_DATA: u8 = 0x40;
+
+struct RelyingParty {
+    id: String,
+    origin: String,
+}
+
+static RP: LazyLock<RelyingParty> = LazyLock::new(|| {
+    let base_url =
+        std::env::var("BASE_URL").unwrap_or_else(|_| "http://localhost:5173".to_string());
+    let origin = base_url.trim_end_matches('/').to_string();
+    let host = origin
+        .split("://")
+        .nth(1)
+        .unwrap_or(&origin)
+        .split(['/', ':'])
+        .next()
+        .unwrap_or_default()
+        .to_string();
+    let id = std::env::var("WEBAUTHN_RP_ID").unwrap_or(host);
+    RelyingParty { id, origin }
+});
+
+// ── Ceremony checks ─────────────────────────────────────────────────────────
+
+#[derive(Deserialize)]
+struct ClientData {
+    #[serde(rename = "type")]
+    kind: String,
+    challenge: String,
+    origin: String,
+}
+
+/// Parse `clientDataJSON` and check its type, challenge and origin.
+fn check_client_data(
+    client_data_json: &[u8],
+    kind: &str,
+    challenge: &[u8],
+    origin: &str,
+) -> Result<(), &'static str> {
+    let data: ClientData =
+        serde_json::from_slice(client_data_json).map_err(|_| "Malformed client data")?;
+    if data.kind != kind {
+        return Err("Wrong ceremony type");
+    }
+    let received = URL_SAFE_NO_PAD
+        .decode(data.challenge.trim_end_matches('='))
+        .map_err(|_| "Malformed challenge")?;
+    if received != challenge {
+        return Err("Challenge mismatch");
+    }
+    if data.origin != origin {
+        return Err("Origin mismatch");
+    }
+    Ok(())
+}
+
+struct AuthData {
+    rp_id_hash: [u8; 32],
+    flags: u8,
+    sign_count: u32,
+    /// Present in registration responses.
+    credential: Option<(Vec<u8>, Value)>,
+}
+
+fn parse_auth_data(data: &[u8]) -> Result<AuthData, &'static str> {
+    if data.len() < 37 {
+        return Err("Authenticator data too short");
+    }
+    let mut rp_id_hash = [0u8; 32];
+    rp_id_hash.copy_from_slice(&data[..32]);
+    let flags = data[32];
+    let sign_count = u32::from_be_bytes([data[33], data[34], data[35], data[36]]);
+
+    let credential = if flags & FLAG_ATTESTED_DATA != 0 {
+        // 16-byte AAGUID, 2-byte length, credential ID, COSE public key.
+        let rest = data.get(37 + 16..).ok_or("Attested data truncated")?;
+        let len = u16::from_be_bytes([
+            *rest.first().ok_or("Attested data truncated")?,
+            *rest.get(1).ok_or("Attested data truncated")?,
+        ]) as usize;
+        let id = rest
+            .get(2..2 + len)
+            .ok_or("Credential ID truncated")?
+            .to_vec();
+        let key: Value = ciborium::de::from_reader(&rest[2 + len..])
+            .map_err(|_| "Malformed credential public key")?;
+        Some((id, key))
+    } else {
+        None
+    };
+
+    Ok(AuthData {
+        rp_id_hash,
+        flags,
+        sign_count,
+        credential,
+    })
+}
+
+fn check_auth_data(auth: &AuthData, rp_id: &str) -> Result<(), &'static str> {
+    if auth.rp_id_hash[..] != Sha256::digest(rp_id.as_bytes())[..] {
+        return Err("Relying party mismatch");
+    }
+    if auth.flags & FLAG_USER_PRESENT == 0 {
+        return Err("User not present");
+    }
+    if auth.flags & FLAG_USER_VERIFIED == 0 {
+        return Err("User not verified");
+    }
+    Ok(())
+}
+
+fn cbor_map_get(map: &[(Value, Value)], key: i64) -> Option<&Value> {
+    map.iter()
+        .find(|(k, _)| k.as_integer().is_some_and(|i| i128::from(i) == key as i128))
+        .map(|(_, v)| v)
+}
+
+fn cbor_int(value: Option<&Value>) -> Option<i64> {
+    value
+        .and_then(Value::as_integer)
+        .and_then(|i| i64::try_from(i128::from(i)).ok())
+}
+
+fn cbor_bytes(value: Option<&Value>) -> Option<&[u8]> {
+    value.and_then(Value::as_bytes).map(Vec::as_slice)
+}
+
+/// Convert a COSE public key to its algorithm and a DER SubjectPublicKeyInfo.
+fn cose_key_to_spki(key: &Value) -> Result<(i64, Vec<u8>), &'static str> {
+    let map = key.as_map().ok_or("Public key is not a map")?;
+    let kty = cbor_int(cbor_map_get(map, 1)).ok_or("Missing key type")?;
+    let alg = cbor_int(cbor_map_get(map, 3)).ok_or("Missing key algorithm")?;
+
+    let pkey = match (kty, alg) {
+        (2, ES256) => {
+            if cbor_int(cbor_map_get(map, -1)) != Some(1) {
+                return Err("Unsupported curve");
+            }
+            let x = cbor_bytes(cbor_map_get(map, -2)).ok_or("Missing x coordinate")?;
+            let y = cbor_bytes(cbor_map_get(map, -3)).ok_or("Missing y coordinate")?;
+            let group =
+                EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).map_err(|_| "Bad EC key")?;
+            let x = BigNum::from_slice(x).map_err(|_| "Bad EC key")?;
+            let y = BigNum::from_slice(y).map_err(|_| "Bad EC key")?;
+            let ec = EcKey::from_public_key_affine_coordinates(&group, &x, &y)
+                .map_err(|_| "Bad EC key")?;
+            PKey::from_ec_key(ec).map_err(|_| "Bad EC key")?
+        }
+        (3, RS256) => {
+            let n = cbor_bytes(cbor_map_get(map, -1)).ok_or("Missing RSA modulus")?;
+            let e = cbor_bytes(cbor_map_get(map, -2)).ok_or("Missing RSA exponent")?;
+            let rsa = Rsa::from_public_components(
+                BigNum::from_slice(n).map_err(|_| "Bad RSA key")?,
+                BigNum::from_slice(e).map_err(|_| "Bad RSA key")?,
+            )
+            .map_err(|_| "Bad RSA key")?;
+            PKey::from_rsa(rsa).map_err(|_| "Bad RSA key")?
+        }
+        _ => return Err("Unsupported key algorithm"),
+    };
+
+    let spki = pkey.public_key_to_der().map_err(|_| "Bad public key")?;
+    Ok((alg, spki))
+}
+
+/// Verify an assertion signature over `authenticatorData || SHA-256(clientDataJSON)`.
+fn verify_assertion(
+    alg: i64,
+    spki: &[u8],
+    auth_data: &[u8],
+    client_data_json: &[u8],
+    signature: &[u8],
+) -> bool {
+    if alg != ES256 && alg != RS256 {
+        return false;
+    }
+    let Ok(pkey) = PKey::public_key_from_der(spki) else {
+        return false;
+    };
+    let Ok(mut verifier) = Verifier::new(MessageDigest::sha256(), &pkey) else {
+        return false;
+    };
+    let client_data_hash = Sha256::digest(client_data_json);
+    verifier.update(auth_data).is_ok()
+        && verifier.update(&client_data_hash).is_ok()
+        && verifier.verify(signature).unwrap_or(false)
+}
+
+fn b64(data: &[u8]) -> String {
+    URL_SAFE_NO_PAD.encode(data)
+}
+
+fn unb64(s: &str) -> Result<Vec<u8>, &'static str> {
+    URL_SAFE_NO_PAD
+        .decode(s.trim_end_matches('='))
+        .map_err(|_| "Malformed base64url")
+}
+
+// ── Challenges ──────────────────────────────────────────────────────────────
+
+async fn create_challenge(
+    db: &PgPool,
+    user_id: Option<Uuid>,
+    kind: &str,
+) -> Result<(String, [u8; 32]), sqlx::Error> {
+    let id = generate_token();
+    let challenge: [u8; 32] = rand::thread_rng().r#gen();
+    let expires_at = Utc::now() + Duration::minutes(CHALLENGE_TTL_MINUTES);
+
+    // Opportunistically drop abandoned ceremonies.
+    sqlx::query("DELETE FROM webauthn_challenges WHERE expires_at < now()")
+        .execute(db)
+        .await?;
+    sqlx::query(
+        "INSERT INTO webauthn_challenges (id, user_id, kind, challenge, expires_at) \
+         VALUES ($1, $2, $3, $4, $5)",
+    )
+    .bind(&id)
+    .bind(user_id)
+    .bind(kind)
+    .bind(&challenge[..])
+    .bind(expires_at)
+    .execute(db)
+    .await?;
+
+    Ok((id, challenge))
+}
+
+/// Consume a challenge, returning its bytes and owning user.
+async fn take_challenge(
+    db: &PgPool,
+    id: &str,
+    kind: &str,
+) -> Result<Option<(Vec<u8>, Option<Uuid>)>, sqlx::Error> {
+    sqlx::query_as(
+        "DELETE FROM webauthn_challenges WHERE id = $1 AND kind = $2 AND expires_at > now() \
+         RETURNING challenge, user_id",
+    )
+    .bind(id)
+    .bind(kind)
+    .fetch_optional(db)
+    .await
+}
+
+// ── API ─────────────────────────────────────────────────────────────────────
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/webauthn/register/start", post(register_start))
+        .route("/webauthn/register/finish", post(register_finish))
+        .route("/webauthn/login/start", post(login_start))
+        .route("/webauthn/login/finish", post(login_finish))
+        .route(
+            "/webauthn/credentials",
+            axum::routing::get(list_credentials),
+        )
+        .route(
+            "/webauthn/credentials/{id}",
+            patch(rename_credential).delete(delete_credential),
+        )
+}
+
+#[derive(Default, Deserialize)]
+struct RegisterStartRequest {
+    /// TOTP or recovery code, when the account has two-factor enabled.
+    code: Option<String>,
+    /// Current password, when the 2FA-for-unlock policy is on.
+    password: Option<String>,
+}
+
+/// A passkey sign-in counts as a second factor, so adding one needs a
+/// session that already proved one, or a fresh step-up: a TOTP code if the
+/// account has 2FA, otherwise the password while the unlock policy is on.
+/// Without this an OIDC or stolen session could mint its own second factor.
+async fn step_up(
+    state: &AppState,
+    client: &ClientInfo,
+    user: &SessionUser,
+    body: &RegisterStartRequest,
+) -> Result<(), Response> {
+    if user.second_factor.is_some() {
+        return Ok(());
+    }
+    let totp_enabled = match totp::is_enabled(&state.db, user.id).await {
+        Ok(enabled) => enabled,
+        Err(e) => {
+            error!("Failed to load two-factor status: {e}");
+            return Err(json_error(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Database error",
+            ));
+        }
+    };
+
+    if totp_enabled {
+        let Some(code) = body.code.as_deref() else {
+            return Err(json_error(
+                StatusCode::FORBIDDEN,
+                "Enter a two-factor code to add a passkey",
+            ));
+        };
+        totp::verify_settings_code(state, client, user, code).await
+    } else if totp::required_for_unlock(&state.db).await {
+        let Some(password) = body.password.as_deref() else {
+            return Err(json_error(
+                StatusCode::FORBIDDEN,
+                "Enter your password to add a passkey",
+            ));
+        };
+        confirm_password(state, client, user, password).await
+    } else {
+        Ok(())
+    }
+}
+
+async fn register_start(
+    user: SessionUser,
+    State(state): State<AppState>,
+    client: ClientInfo,
+    body: Option<Json<RegisterStartRequest>>,
+) -> Response {
+    let body = body.map(|Json(b)| b).unwrap_or_default();
+    if let Err(response) = step_up(&state, &client, &user, &body).await {
+        return response;
+    }
+
+    let existing: Vec<(Vec<u8>,)> =
+        match sqlx::query_as("SELECT credential_id FROM webauthn_credentials WHERE user_id = $1")
+            .bind(user.id)
+            .fetch_all(&state.db)
+            .await
+        {
+            Ok(rows) => rows,
+            Err(e) => {
+                error!("Failed to load passkeys: {e}");
+                return json_error(StatusCode::INTERNAL_SERVER_ERROR, "Database error");
+            }
+        };
+
+    let (challenge_id, challenge) =
+        match create_challenge(&state.db, Some(user.id), "register").await {
+            Ok(c) => c,
+            Err(e) => {
+                error!("Failed to store WebAuthn challenge: {e}");
+                return json_error(StatusCode::INTERNAL_SERVER_ERROR, "Database error");
+            }
+        };
+
+    let exclude: Vec<_> = existing
+        .iter()
+        .map(|(id,)| serde_json::json!({"type": "public-key", "id": b64(id)}))
+        .collect();
+
+    Json(serde_json::json!({
+        "challenge_id": challenge_id,
+        "publicKey": {
+            "rp": { "id": RP.id, "name": RP_NAME },
+            "user": {
+                "id": b64(user.id.as_bytes()),
+                "name": user.email,
+                "displayName": user.email,
+            },
+            "challenge": b64(&challenge),
+            "pubKeyCredParams": [
+                { "type": "public-key", "alg": ES256 },
+                { "type": "public-key", "alg": RS256 },
+            ],
+            "timeout": TIMEOUT_MS,
+            "attestation": "none",
+            "authenticatorSelection": {
+                "residentKey": "required",
+                "userVerification": "required",
+            },
+            "excludeCredentials": exclude,
+        },
+    }))
+    .into_response()
+}
+
+#[derive(Deserialize)]
+struct AttestationResponse {
+    #[serde(rename = "clientDataJSON")]
+    client_data_json: String,
+    #[serde(rename = "attestationObject")]
+    attestation_object: String,
+}
+
+#[derive(Deserialize)]
+struct RegistrationCredential {
+    response: AttestationResponse,
+}
+
+#[derive(Deserialize)]
+struct RegisterFinishRequest {
+    challenge_id: String,
+    name: Option<String>,
+    credential: RegistrationCredential,
+}
+
+struct NewCredential {
+    credential_id: Vec<u8>,
+    alg: i64,
+    spki: Vec<u8>,
+    sign_count: u32,
+}
+
+/// Validate a registration response and extract the new credential.
+fn verify_registration(
+    challenge: &[u8],
+    response: &AttestationResponse,
+) -> Result<NewCredential, &'static str> {
+    let client_data = unb64(&response.client_data_json)?;
+    check_client_data(&client_data, "webauthn.create", challenge, &RP.origin)?;
+
+    let attestation = unb64(&response.attestation_object)?;
+    let attestation: Value =
+        ciborium::de::from_reader(&attestation[..]).map_err(|_| "Malformed attestation")?;
+    let auth_data = attestation
+        .as_map()
+        .and_then(|m| m.iter().find(|(k, _)| k.as_text() == Some("authData")))
+        .and_then(|(_, v)| v.as_bytes())
+        .ok_or("Missing authenticator data")?;
+
+    let auth = parse_auth_data(auth_data)?;
+    check_auth_data(&auth, &RP.id)?;
+    let (credential_id, key) = auth.credential.ok_or("Missing credential data")?;
+    let (alg, spki) = cose_key_to_spki(&key)?;
+
+    Ok(NewCredential {
+        credential_id,
+        alg,
+        spki,
+        sign_count: auth.sign_count,
+    })
+}
+
+async fn register_finish(
+    user: SessionUser,
+    State(state): State<AppState>,
+    Json(body): Json<RegisterFinishRequest>,
+) -> Response {
+    let challenge = match take_challenge(&state.db, &body.challenge_id, "register").await {
+        Ok(Some((challenge, Some(owner)))) if owner == user.id => challenge,
+        Ok(_) => return json_error(StatusCode::BAD_REQUEST, "Registration expired, try again"),
+        Err(e) => {
+            error!("Failed to load WebAuthn challenge: {e}");
+            return json_error(StatusCode::INTERNAL_SERVER_ERROR, "Database error");
+        }
+    };
+
+    let credential = match verify_registration(&challenge, &body.credential.response) {
+        Ok(v) => v,
+        Err(reason) => {
+            warn!(email = %user.email, reason, "Passkey registration rejected");
+            return json_error(StatusCode::BAD_REQUEST, reason);
+        }
+    };
+
+    let name = body
+        .name
+        .as_deref()
+        .map(str::trim)
+        .filter(|n| !n.is_empty())
+        .unwrap_or("Passkey")
+        .chars()
+        .take(64)
+        .collect::<String>();
+
+    let id: Result<Option<Uuid>, _> = sqlx::query_scalar(
+        "INSERT INTO webauthn_credentials (user_id, credential_id, public_key, alg, sign_count, name) \
+         VALUES ($1, $2, $3, $4, $5, $6) ON CONFLICT (credential_id) DO NOTHING RETURNING id",
+    )
+    .bind(user.id)
+    .bind(&credential.credential_id)
+    .bind(&credential.spki)
+    .bind(credential.alg as i32)
+    .bind(i64::from(credential.sign_count))
+    .bind(&name)
+    .fetch_optional(&state.db)
+    .await;
+
+    match id {
+        Ok(Some(id)) => {
+            info!(email = %user.email, name, "Passkey registered");
+            if let Err(e) = state
+                .mailer
+                .send_passkey_added_email(&user.email, &name)
+                .await
+            {
+                error!("Failed to send passkey notice: {e}");
+            }
+            Json(serde_json::json!({ "id": id, "name": name })).into_response()
+        }
+        Ok(None) => json_error(StatusCode::CONFLICT, "Passkey already registered"),
+        Err(e) => {
+            error!("Failed to store passkey: {e}");
+            json_error(StatusCode::INTERNAL_SERVER_ERROR, "Database error")
+        }
+    }
+}
+
+async fn login_start(State(state): State<AppState>) -> Response {
+    let (challenge_id, challenge) = match create_challenge(&state.db, None, "login").await {
+        Ok(c) => c,
+        Err(e) => {
+            error!("Failed to store WebAuthn challenge: {e}");
+            return json_error(StatusCode::INTERNAL_SERVER_ERROR, "Login failed");
+        }
+    };
+
+    Json(serde_json::json!({
+        "challenge_id": challenge_id,
+        "publicKey": {
+            "challenge": b64(&challenge),
+            "rpId": RP.id,
+            "timeout": TIMEOUT_MS,
+            "userVerification": "required",
+            "allowCredentials": [],
+        },
+    }))
+    .into_response()
+}
+
+#[derive(Deserialize)]
+struct AssertionResponse {
+    #[serde(rename = "clientDataJSON")]
+    client_data_json: String,
+    #[serde(rename = "authenticatorData")]
+    authenticator_data: String,
+    signature: String,
+    #[serde(rename = "userHandle")]
+    user_handle: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct AssertionCredential {
+    #[serde(rename = "rawId")]
+    raw_id: String,
+    response: AssertionResponse,
+}
+
+#[derive(Deserialize)]
+struct LoginFinishRequest {
+    challenge_id: String,
+    credential: AssertionCredential,
+}
+
+type StoredCredential = (Uuid, Uuid, Vec<u8>, i32, i64);
+
+async fn login_finish(
+    State(state): State<AppState>,
+    client: ClientInfo,
+    Json(body): Json<LoginFinishRequest>,
+) -> Response {
+    let challenge = match take_challenge(&state.db, &body.challenge_id, "login").await {
+        Ok(Some((challenge, _))) => challenge,
+        Ok(None) => return json_error(StatusCode::UNAUTHORIZED, "Sign-in expired, try again"),
+        Err(e) => {
+            error!("Failed to load WebAuthn challenge: {e}");
+            return json_error(StatusCode::INTERNAL_SERVER_ERROR, "Login failed");
+        }
+    };
+
+    let Ok(credential_id) = unb64(&body.credential.raw_id) else {
+        return json_error(StatusCode::BAD_REQUEST, "Malformed credential");
+    };
+
+    let stored: Option<StoredCredential> = match sqlx::query_as(
+        "SELECT id, user_id, public_key, alg, sign_count FROM webauthn_credentials \
+         WHERE credential_id = $1",
+    )
+    .bind(&credential_id)
+    .fetch_optional(&state.db)
+    .await
+    {
+        Ok(row) => row,
+        Err(e) => {
+            error!("Failed to load passkey: {e}");
+            return json_error(StatusCode::INTERNAL_SERVER_ERROR, "Login failed");
+        }
+    };
+
+    let Some((id, user_id, spki, alg, stored_count)) = stored else {
+        return json_error(StatusCode::UNAUTHORIZED, "Unknown passkey");
+    };
+
+    let sign_count = match verify_login(
+        &challenge,
+        user_id,
+        alg.into(),
+        &spki,
+        stored_count,
+        &body.credential.response,
+    ) {
+        Ok(count) => count,
+        Err(reason) => {
+            warn!(%user_id, credential = %id, reason, "Passkey sign-in rejected");
+            return json_error(StatusCode::UNAUTHORIZED, "Passkey sign-in failed");
+        }
+    };
+
+    if let Err(e) = sqlx::query(
+        "UPDATE webauthn_credentials SET sign_count = $2, last_used_at = now() WHERE id = $1",
+    )
+    .bind(id)
+    .bind(i64::from(sign_count))
+    .execute(&state.db)
+    .await
+    {
+        error!("Failed to update passkey: {e}");
+    }
+
+    let user: Option<(String, bool, bool, String)> = match sqlx::query_as(
+        "SELECT email, email_confirmed, is_approved, role FROM users WHERE id = $1",
+    )
+    .bind(user_id)
+    .fetch_optional(&state.db)
+    .await
+    {
+        Ok(row) => row,
+        Err(e) => {
+            error!("Database error during login: {e}");
+            return json_error(StatusCode::INTERNAL_SERVER_ERROR, "Login failed");
+        }
+    };
+
+    let Some((email, email_confirmed, is_approved, role)) = user else {
+        return json_error(StatusCode::UNAUTHORIZED, "Unknown passkey");
+    };
+
+    info!(email = %email, "User logged in with passkey");
+
+    let user = LoginUser {
+        id: user_id,
+        email: &email,
+        email_confirmed,
+        is_approved,
+        role: &role,
+    };
+    start_session(&state, &client, &user, Some(SecondFactor::Passkey)).await
+}
+
+/// Validate an assertion against a stored credential, returning the new
+/// sign count.
+fn verify_login(
+    challenge: &[u8],
+    user_id: Uuid,
+    alg: i64,
+    spki: &[u8],
+    stored_count: i64,
+    response: &AssertionResponse,
+) -> Result<u32, &'static str> {
+    let client_data = unb64(&response.client_data_json)?;
+    check_client_data(&client_data, "webauthn.get", challenge, &RP.origin)?;
+
+    if let Some(ref handle) = response.user_handle {
+        if unb64(handle)? != user_id.as_bytes() {
+            return Err("User handle mismatch");
+        }
+    }
+
+    let auth_data = unb64(&response.authenticator_data)?;
+    let auth = parse_auth_data(&auth_data)?;
+    check_auth_data(&auth, &RP.id)?;
+
+    let signature = unb64(&response.signature)?;
+    if !verify_assertion(alg, spki, &auth_data, &client_data, &signature) {
+        return Err("Bad signature");
+    }
+
+    // Authenticators that keep a counter must increase it; a stale value
+    // suggests a cloned credential.
+    if (auth.sign_count != 0 || stored_count != 0) && i64::from(auth.sign_count) <= stored_count {
+        return Err("Sign count did not increase");
+    }
+
+    Ok(auth.sign_count)
+}
+
+// ── Credential management ───────────────────────────────────────────────────
+
+#[derive(Serialize)]
+struct CredentialInfo {
+    id: Uuid,
+    name: String,
+    created_at: String,
+    last_used_at: Option<String>,
+}
+
+type CredentialRow = (Uuid, String, DateTime<Utc>, Option<DateTime<Utc>>);
+
+async fn list_credentials(user: SessionUser, State(state): State<AppState>) -> Response {
+    let rows: Vec<CredentialRow> = match sqlx::query_as(
+        "SELECT id, name, created_at, last_used_at FROM webauthn_credentials \
+         WHERE user_id = $1 ORDER BY created_at",
+    )
+    .bind(user.id)
+    .fetch_all(&state.db)
+    .await
+    {
+        Ok(rows) => rows,
+        Err(e) => {
+            error!("Failed to load passkeys: {e}");
+            return json_error(StatusCode::INTERNAL_SERVER_ERROR, "Database error");
+        }
+    };
+
+    let credentials: Vec<CredentialInfo> = rows
+        .into_iter()
+        .map(|(id, name, created_at, last_used_at)| CredentialInfo {
+            id,
+            name,
+            created_at: created_at.to_rfc3339(),
+            last_used_at: last_used_at.map(|t| t.to_rfc3339()),
+        })
+        .collect();
+
+    Json(credentials).into_response()
+}
+
+#[derive(Deserialize)]
+struct RenameRequest {
+    name: String,
+}
+
+async fn rename_credential(
+    user: SessionUser,
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    Json(body): Json<RenameRequest>,
+) -> Response {
+    let name: String = body.name.trim().chars().take(64).collect();
+    if name.is_empty() {
+        return json_error(StatusCode::BAD_REQUEST, "Name must not be empty");
+    }
+
+    match sqlx::query("UPDATE webauthn_credentials SET name = $3 WHERE id = $1 AND user_id = $2")
+        .bind(id)
+        .bind(user.id)
+        .bind(&name)
+        .execute(&state.db)
+        .await
+    {
+        Ok(r) if r.rows_affected() == 0 => json_error(StatusCode::NOT_FOUND, "Passkey not found"),
+        Ok(_) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) => {
+            error!("Failed to rename passkey: {e}");
+            json_error(StatusCode::INTERNAL_SERVER_ERROR, "Database error")
+        }
+    }
+}
+
+async fn delete_credential(
+    user: SessionUser,
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Response {
+    match sqlx::query("DELETE FROM webauthn_credentials WHERE id = $1 AND user_id = $2")
+        .bind(id)
+        .bind(user.id)
+        .execute(&state.db)
+        .await
+    {
+        Ok(r) if r.rows_affected() == 0 => json_error(StatusCode::NOT_FOUND, "Passkey not found"),
+        Ok(_) => {
+            info!(email = %user.email, credential = %id, "Passkey removed");
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Err(e) => {
+            error!("Failed to delete passkey: {e}");
+            json_error(StatusCode::INTERNAL_SERVER_ERROR, "Database error")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use openssl::bn::BigNumContext;
+    use openssl::sign::Signer;
+
+    const RP_ID: &str = "door.example.com";
+
+    fn es256_key() -> (PKey<openssl::pkey::Private>, Value) {
+        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+        let ec = EcKey::generate(&group).unwrap();
+        let mut x = BigNum::new().unwrap();
+        let mut y = BigNum::new().unwrap();
+        let mut ctx = BigNumContext::new().unwrap();
+        ec.public_key()
+            .affine_coordinates(&group, &mut x, &mut y, &mut ctx)
+            .unwrap();
+        let cose = Value::Map(vec![
+            (Value::Integer(1.into()), Value::Integer(2.into())),
+            (Value::Integer(3.into()), Value::Integer(ES256.into())),
+            (Value::Integer((-1).into()), Value::Integer(1.into())),
+            (
+                Value::Integer((-2).into()),
+                Value::Bytes(x.to_vec_padded(32).unwrap()),
+            ),
+            (
+                Value::Integer((-3).into()),
+                Value::Bytes(y.to_vec_padded(32).unwrap()),
+            ),
+        ]);
+        (PKey::from_ec_key(ec).unwrap(), cose)
+    }
+
+    fn auth_data(flags: u8, count: u32, credential: Option<(&[u8], &Value)>) -> Vec<u8> {
+        let mut data = Sha256::digest(RP_ID.as_bytes()).to_vec();
+        data.push(flags);
+        data.extend_from_slice(&count.to_be_bytes());
+        if let Some((id, key)) = credential {
+            data.extend_from_slice(&[0u8; 16]);
+            data.extend_from_slice(&(id.len() as u16).to_be_bytes());
+            data.extend_from_slice(id);
+            ciborium::ser::into_writer(key, &mut data).unwrap();
+        }
+        data
+    }
+
+    #[test]
+    fn registration_and_assertion_round_trip() {
+        let (private, cose) = es256_key();
+        let flags = FLAG_USER_PRESENT | FLAG_USER_VERIFIED;
+
+        let reg = auth_data(flags | FLAG_ATTESTED_DATA, 0, Some((b"cred-1", &cose)));
+        let parsed = parse_auth_data(&reg).unwrap();
+        check_auth_data(&parsed, RP_ID).unwrap();
+        let (id, key) = parsed.credential.unwrap();
+        assert_eq!(id, b"cred-1");
+        let (alg, spki) = cose_key_to_spki(&key).unwrap();
+        assert_eq!(alg, ES256);
+
+        let assertion = auth_data(flags, 1, None);
+        let client_data =
+            br#"{"type":"webauthn.get","challenge":"AAEC","origin":"https://door.example.com"}"#;
+        let mut signer = Signer::new(MessageDigest::sha256(), &private).unwrap();
+        signer.update(&assertion).unwrap();
+        signer.update(&Sha256::digest(client_data)).unwrap();
+        let signature = signer.sign_to_vec().unwrap();
+
+        assert!(verify_assertion(
+            alg,
+            &spki,
+            &assertion,
+            client_data,
+            &signature
+        ));
+        assert!(!verify_assertion(alg, &spki, &reg, client_data, &signature));
+        check_client_data(
+            client_data,
+            "webauthn.get",
+            &[0, 1, 2],
+            "https://door.example.com",
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn rejects_wrong_rp_and_missing_verification() {
+        let auth = parse_auth_data(&auth_data(FLAG_USER_PRESENT, 0, None)).unwrap();
+        assert_eq!(check_auth_data(&auth, RP_ID), Err("User not verified"));
+        let auth =
+            parse_auth_data(&auth_data(FLAG_USER_PRESENT | FLAG_USER_VERIFIED, 0, None)).unwrap();
+        assert_eq!(
+            check_auth_data(&auth, "evil.example.com"),
+            Err("Relying party mismatch")
+        );
+        assert!(parse_auth_data(&[0u8; 10]).is_err());
+    }
+
+    #[test]
+    fn rejects_mismatched_client_data() {
+        let data =
+            br#"{"type":"webauthn.get","challenge":"AAEC","origin":"https://evil.example.com"}"#;
+        assert_eq!(
+            check_client_data(data, "webauthn.get", &[0, 1, 2], "https://door.example.com"),
+            Err("Origin mismatch")
+        );
+        assert_eq!(
+            check_client_data(
+                data,
+                "webauthn.create",
+                &[0, 1, 2],
+                "https://evil.example.com"
+            ),
+            Err("Wrong ceremony type")
+        );
+        assert_eq!(
+            check_client_data(data, "webauthn.get", &[9], "https://evil.example.com"),
+            Err("Challenge mismatch")
+        );
+    }
+}
@@ -18,8 +18,9 @@ use axum::{
 use serde::Deserialize;
 use subtle::ConstantTimeEq;
 use tower_http::limit::RequestBodyLimitLayer;
-use tracing::info;
+use tracing::{info, warn};
 
+use crate::device_status;
 use crate::lock_log;
 use crate::utec::DeviceWithStates;
 use crate::ws::WsEvent;
@@ -69,6 +70,25 @@ async fn handle_utec_notification(
         return StatusCode::UNAUTHORIZED;
     }
 
+    // Battery levels arrive raw (e.g. 1-5) and must be normalized using the
+    // device's batteryLevelRange from discovery, so only discover when needed.
+    let locks = if body
+        .payload
+        .devices
+        .iter()
+        .any(|d| d.battery_level().is_some())
+    {
+        match state.auth_store.client().await {
+            Some(client) => client.discover_locks().await.unwrap_or_else(|e| {
+                warn!("Webhook: failed to discover locks for battery range: {e:#}");
+                Vec::new()
+            }),
+            None => Vec::new(),
+        }
+    } else {
+        Vec::new()
+    };
+
     // Process each device's state changes
     for device in &body.payload.devices {
         if let Some(lock_state) = device.lock_state() {
@@ -77,12 +97,41 @@ async fn handle_utec_notification(
                 lock_state = %lock_state,
                 "Webhook: lock state change"
             );
-            lock_log::record(&state.db, &device.id, &lock_state, "webhook", None).await;
+            let log_id =
+                lock_log::record(&state.db, &device.id, &lock_state, "webhook", None, None).await;
             let _ = state.events.send(WsEvent::LockState {
                 device_id: device.id.clone(),
                 lock_state,
+                log_id,
             });
         }
+
+        // Without the device's range a raw reading can't be turned into a
+        // percentage, so skip it rather than store a bogus level.
+        let battery_level = device.battery_level().and_then(|raw| {
+            let percent = locks
+                .iter()
+                .find(|l| l.id == device.id)
+                .map(|l| l.battery_percent(raw));
+            if percent.is_none() {
+                warn!(
+                    device_id = %device.id,
+                    raw,
+                    "Webhook: battery range unknown, ignoring battery level"
+                );
+            }
+            percent
+        });
+        let online = device.online();
+        if battery_level.is_some() || online.is_some() {
+            info!(
+                device_id = %device.id,
+                ?battery_level,
+                ?online,
+                "Webhook: device status"
+            );
+            device_status::observe(&state, &device.id, battery_level, online, "webhook").await;
+        }
     }
 
     StatusCode::OK
@@ -8,21 +8,24 @@ use axum::{
     routing::get,
     Router,
 };
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use tokio::sync::broadcast;
 use uuid::Uuid;
 
+use crate::permissions::Permission;
 use crate::session::{extract_session_id_from_cookies, get_user_by_session};
 use crate::AppState;
 
 // ── Event types ─────────────────────────────────────────────────────────────
 
-#[derive(Clone, Debug, Serialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(tag = "type", content = "data", rename_all = "snake_case")]
 pub enum WsEvent {
     Scan {
         tag_id: String,
         action: String,
+        /// The sentinel that read the card, when it is known.
+        sentinel_id: Option<Uuid>,
         created_at: String,
     },
     ModeChanged {
@@ -40,6 +43,34 @@ pub enum WsEvent {
     LockState {
         device_id: String,
         lock_state: String,
+        /// The `lock_state_log` row for this change, when it was recorded.
+        log_id: Option<Uuid>,
+    },
+    BatteryLevel {
+        device_id: String,
+        battery_level: u64,
+    },
+    DeviceOnline {
+        device_id: String,
+        online: bool,
+    },
+    DeviceAlert {
+        device_id: String,
+        kind: String,
+        active: bool,
+        message: String,
+    },
+    RelockFailed {
+        device_id: String,
+        error: String,
+    },
+    CommandStatus {
+        id: Uuid,
+        device_id: String,
+        command: String,
+        status: String,
+        lock_state: Option<String>,
+        error: Option<String>,
     },
     SentinelConnected {
         id: Uuid,
@@ -56,6 +87,42 @@ pub enum WsEvent {
 }
 
 impl WsEvent {
+    /// Every value of the serialized `type` tag.
+    pub const TYPES: [&'static str; 13] = [
+        "scan",
+        "mode_changed",
+        "card_added",
+        "card_removed",
+        "lock_state",
+        "battery_level",
+        "device_online",
+        "device_alert",
+        "relock_failed",
+        "command_status",
+        "sentinel_connected",
+        "sentinel_disconnected",
+        "sentinel_log",
+    ];
+
+    /// The serialized `type` tag, e.g. `lock_state`.
+    pub fn event_type(&self) -> &'static str {
+        match self {
+            Self::Scan { .. } => "scan",
+            Self::ModeChanged { .. } => "mode_changed",
+            Self::CardAdded { .. } => "card_added",
+            Self::CardRemoved { .. } => "card_removed",
+            Self::LockState { .. } => "lock_state",
+            Self::BatteryLevel { .. } => "battery_level",
+            Self::DeviceOnline { .. } => "device_online",
+            Self::DeviceAlert { .. } => "device_alert",
+            Self::RelockFailed { .. } => "relock_failed",
+            Self::CommandStatus { .. } => "command_status",
+            Self::SentinelConnected { .. } => "sentinel_connected",
+            Self::SentinelDisconnected { .. } => "sentinel_disconnected",
+            Self::SentinelLog { .. } => "sentinel_log",
+        }
+    }
+
     pub fn to_message(&self) -> Message {
         Message::text(serde_json::to_string(self).unwrap())
     }
@@ -83,9 +150,12 @@ async fn ws_handler(
     let session_id =
         extract_session_id_from_cookies(cookie_header).ok_or(StatusCode::UNAUTHORIZED)?;
 
-    get_user_by_session(&state.db, session_id)
+    let user = get_user_by_session(&state.db, session_id)
         .await
         .ok_or(StatusCode::UNAUTHORIZED)?;
+    if !user.is_approved || !user.role.has(Permission::ViewDevices) {
+        return Err(StatusCode::FORBIDDEN);
+    }
 
     let rx = state.events.subscribe();
     Ok(ws.on_upgrade(move |socket| handle_socket(socket, rx)))
@@ -8,14 +8,100 @@ self.addEventListener('push', (event) => {
     }
   }
   const title = data.title || 'Panopticon';
+  const actions = data.actions || [];
   const options = {
     body: data.body || '',
     icon: '/favicon.png',
+    actions: actions.map(({ action, title }) => ({ action, title })),
+    // Signed tokens for the action buttons, keyed by action name
+    data: { tokens: Object.fromEntries(actions.map(({ action, token }) => [action, token])) },
   };
-  event.waitUntil(self.registration.showNotification(title, options));
+  const work = [self.registration.showNotification(title, options)];
+  // The server's VAPID key was rotated; move to the new one
+  if (data.resubscribe) work.push(resubscribe());
+  event.waitUntil(Promise.all(work));
 });
 
+function urlBase64ToUint8Array(base64String) {
+  const padding = '='.repeat((4 - (base64String.length % 4)) % 4);
+  const base64 = (base64String + padding).replace(/-/g, '+').replace(/_/g, '/');
+  return Uint8Array.from(atob(base64), (c) => c.charCodeAt(0));
+}
+
+async function saveSubscription(sub, replaces) {
+  const subJson = sub.toJSON();
+  await fetch('/api/push/subscribe', {
+    method: 'POST',
+    headers: { 'Content-Type': 'application/json' },
+    credentials: 'same-origin',
+    body: JSON.stringify({
+      endpoint: sub.endpoint,
+      p256dh: subJson.keys?.p256dh ?? '',
+      auth: subJson.keys?.auth ?? '',
+      replaces,
+    }),
+  });
+}
+
+// Subscribe again with the server's current VAPID key, replacing the old
+// subscription.
+async function resubscribe(oldEndpoint) {
+  try {
+    const res = await fetch('/api/push/vapid-key', { credentials: 'same-origin' });
+    if (!res.ok) return;
+    const { key } = await res.json();
+    const old = await self.registration.pushManager.getSubscription();
+    if (old) await old.unsubscribe();
+    const sub = await self.registration.pushManager.subscribe({
+      userVisibleOnly: true,
+      applicationServerKey: urlBase64ToUint8Array(key),
+    });
+    await saveSubscription(sub, oldEndpoint || (old && old.endpoint));
+  } catch (_) {
+    // The page retries when it is next opened
+  }
+}
+
+self.addEventListener('pushsubscriptionchange', (event) => {
+  const oldEndpoint = event.oldSubscription && event.oldSubscription.endpoint;
+  if (event.newSubscription) {
+    event.waitUntil(saveSubscription(event.newSubscription, oldEndpoint));
+  } else {
+    event.waitUntil(resubscribe(oldEndpoint));
+  }
+});
+
+async function performAction(action, token) {
+  let message;
+  try {
+    const res = await fetch('/api/push/actions', {
+      method: 'POST',
+      headers: { 'Content-Type': 'application/json' },
+      credentials: 'same-origin',
+      body: JSON.stringify({ token }),
+    });
+    if (res.ok) {
+      message = (await res.json()).message;
+    } else {
+      message = (await res.text()) || `Action failed (${res.status})`;
+    }
+  } catch (_) {
+    message = 'Could not reach Panopticon';
+  }
+  await self.registration.showNotification('Panopticon', {
+    body: message,
+    icon: '/favicon.png',
+    tag: `action-${action}`,
+  });
+}
+
 self.addEventListener('notificationclick', (event) => {
   event.notification.close();
-  event.waitUntil(clients.openWindow('/'));
+  const tokens = (event.notification.data && event.notification.data.tokens) || {};
+  const token = event.action && tokens[event.action];
+  if (token) {
+    event.waitUntil(performAction(event.action, token));
+  } else {
+    event.waitUntil(clients.openWindow('/'));
+  }
 });